@@ -0,0 +1,131 @@
+// Time and peak memory of the assembler on large generated sources. Run with "cargo bench". Memory
+// is counted by a global allocator that keeps track of the bytes in use, so the peak includes the
+// source, everything the passes keep, and the output. Each source is assembled both by assemble,
+// which returns the whole output, and by assemble_to, which streams it (here to nowhere).
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+struct Counting;
+
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let p = System.alloc(layout);
+        if !p.is_null() {
+            let used = IN_USE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(used, Ordering::Relaxed);
+        }
+        p
+    }
+
+    unsafe fn dealloc(&self, p: *mut u8, layout: Layout) {
+        System.dealloc(p, layout);
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+// Tables of data, 32 bytes to a line, as generated for graphics or levels. Each 16K bank at 8000
+// holds 512 lines.
+fn data_source(lines: usize) -> String {
+    let mut s = String::new();
+    for i in 0..lines {
+        if i % 0x200 == 0 {
+            s.push_str(&format!("bank {:x} origin 8000 size 4000\n", i / 0x200));
+        }
+        s.push_str("data ");
+        for j in 0..32 {
+            s.push_str(&format!("{:02x}", (i * 32 + j) as u8));
+        }
+        s.push_str(" ; row\n");
+    }
+    s
+}
+
+// Unrolled code with labels and comments, in 16K banks at 8000
+fn code_source(lines: usize) -> String {
+    let mut s = String::new();
+    for i in 0..lines {
+        if i % 0x2000 == 0 {
+            s.push_str(&format!("bank {:x} origin 8000 size 4000\n", i / 0x2000));
+        }
+        match i % 4 {
+            0 => s.push_str(&format!(".l{i}\n")),
+            1 => s.push_str("ldai 01 ; load\n"),
+            2 => s.push_str(&format!("staa {:04x}\n", 0x0200 + i % 0x100)),
+            _ => s.push_str(&format!("bne .l{}\n", i - 3)),
+        }
+    }
+    s
+}
+
+// Many labels: a label command and a code marker for each instruction, which refers to both
+fn label_source(labels: usize) -> String {
+    let mut s = String::from("org 0200\n");
+    for i in 0..labels {
+        s.push_str(&format!("label table_{i} {:04x}\n", 0x100 + i % 0x100));
+    }
+    for i in 0..labels {
+        s.push_str(&format!(".code_{i}\nldaa .table_{i}\n"));
+        if i % 0x10 == 0 {
+            s.push_str(&format!("bne .code_{i}\n"));
+        }
+    }
+    s
+}
+
+// Best time and peak memory of three runs of assembling the source in the format
+fn measure(source: &str, format: &str, streamed: bool) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut peak = 0;
+    for _ in 0..3 {
+        let args: Vec<String> = ["sasm", "-f", format]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let mut c = sasm2::Config::build(&args).unwrap();
+        c.itype = sasm2::config::IType::String(source.to_string());
+        c.otype = sasm2::config::OType::None;
+        let base = IN_USE.load(Ordering::Relaxed);
+        PEAK.store(base, Ordering::Relaxed);
+        let start = Instant::now();
+        match streamed {
+            true => sasm2::assemble_to(&mut c, &mut std::io::sink()).expect("assembly failed"),
+            false => drop(sasm2::assemble(&mut c).expect("assembly failed")),
+        }
+        best = best.min(start.elapsed());
+        peak = PEAK.load(Ordering::Relaxed) - base;
+    }
+    (best, peak)
+}
+
+fn bench(name: &str, source: &str) {
+    let mb = |n: usize| n as f64 / (1024.0 * 1024.0);
+    for format in ["hex", "listing"] {
+        let (time, returned) = measure(source, format, false);
+        let (_, streamed) = measure(source, format, true);
+        println!(
+            "{name:6} {format:7} {:7.2} MB source  {:7.2} MB peak returned  {:7.2} MB peak \
+             streamed  {time:?}",
+            mb(source.len()),
+            mb(returned),
+            mb(streamed)
+        );
+    }
+}
+
+fn main() {
+    for lines in [0x4000, 0x10000, 0x40000] {
+        bench("data", &data_source(lines / 0x10));
+        bench("code", &code_source(lines));
+    }
+    for labels in [5000, 10000, 20000] {
+        bench("labels", &label_source(labels));
+    }
+}
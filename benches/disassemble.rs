@@ -0,0 +1,53 @@
+// Timing of the disassembler on large inputs. Run with "cargo bench". This uses no benchmarking
+// framework, so it only reports the best of a few runs.
+
+use std::time::{Duration, Instant};
+
+// Pseudorandom bytes, which are a mix of runs of valid and invalid opcodes like a real ROM
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut seed: u64 = 0x2600;
+    (0..len)
+        .map(|_| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as u8
+        })
+        .collect()
+}
+
+// One long run of code, which is the worst case for finding code regions
+fn code_bytes(len: usize) -> Vec<u8> {
+    [0xa9, 0x01, 0x85, 0x80, 0xe8]
+        .iter()
+        .cycle()
+        .take(len)
+        .copied()
+        .collect()
+}
+
+fn bench(name: &str, bytes: &[u8], setup: impl Fn(&mut sasm2::Config)) {
+    let len = bytes.len();
+    let hex = hex::encode(bytes);
+    let mut best = Duration::MAX;
+    for _ in 0..5 {
+        let mut c = sasm2::Config::build_string_test(&hex);
+        c.addr = (0x10000 - len) as u16;
+        setup(&mut c);
+        let start = Instant::now();
+        sasm2::disassemble(&mut c).expect("disassembly failed");
+        best = best.min(start.elapsed());
+    }
+    println!("{name:30} {len:6} bytes  {best:?}");
+}
+
+fn main() {
+    // ROMs at the end of memory
+    for len in [0x1000, 0x4000, 0x8000] {
+        let random = random_bytes(len);
+        bench("random", &random, |_| ());
+        bench("random, max invalid 4", &random, |c| c.max_invalid = 4);
+        bench("random, illegal opcodes", &random, |c| c.illegal = true);
+        bench("code", &code_bytes(len), |_| ());
+    }
+}
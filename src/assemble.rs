@@ -1,23 +1,36 @@
-use std::collections::BTreeMap;
-use std::collections::HashMap;
-use std::io::Read;
+use alloc::collections::btree_map::Entry;
+use alloc::collections::BTreeMap;
+use alloc::collections::BTreeSet;
 
+use crate::ca65::Exporter;
+use crate::checksum::*;
 use crate::config::*;
 use crate::data::*;
+use crate::diagnostic::{Diagnostic, Location};
+use crate::emu::{Assertion, Cpu, Stop, Target, TestResult};
+use crate::error::*;
+use crate::flow::Report;
+use crate::host::*;
+use crate::object::*;
 use crate::output::*;
+use crate::prelude::*;
+use crate::ram::Ram;
+use crate::symbol::{SymbolId, SymbolTable};
 use crate::syntax::*;
+use crate::warning::*;
+use crate::zpm::{Zpm, ZP_EXHAUSTED};
 
-fn hex_to_uint(s: &str) -> Result<UInt, &str> {
+pub(crate) fn hex_to_uint(s: &str) -> Result<UInt, SasmError> {
     let num_hex_digits = s.len();
-    let em = "not a valid hexadecimal number";
+    let em = SasmError::syntax("not a valid hexadecimal number");
 
     match num_hex_digits {
-        1 | 2 => match u8::from_str_radix(&s, 16) {
+        1 | 2 => match u8::from_str_radix(s, 16) {
             Ok(n) => Ok(UInt::U8(n)),
             _ => Err(em),
         },
 
-        3 | 4 => match u16::from_str_radix(&s, 16) {
+        3 | 4 => match u16::from_str_radix(s, 16) {
             Ok(n) => Ok(UInt::U16(n)),
             _ => Err(em),
         },
@@ -26,12 +39,15 @@ fn hex_to_uint(s: &str) -> Result<UInt, &str> {
     }
 }
 
+// Error for a relative branch that cannot reach its target, which --long-branches fixes
+const BRANCH_TOO_FAR: &str = "relative branch is too far from target";
+
 // Compute x-y and return only if result fits in an i8.
 // However, return it as a u8 (same bits) so that it can be stored in a disassembly.
 // This is a bit tricky in Rust, so we write a separate function.
 fn compute_diff_u16_as_u8(x: u16, y: u16) -> Option<u8> {
     let diff: i32 = x as i32 - y as i32;
-    if diff > 127 || diff < -128 {
+    if !(-128..=127).contains(&diff) {
         return None;
     }
 
@@ -39,67 +55,336 @@ fn compute_diff_u16_as_u8(x: u16, y: u16) -> Option<u8> {
     return Some(diff.to_ne_bytes()[0]);
 }
 
-fn tokenize(line: &str) -> Result<SourceLine, &str> {
+// Error for a zero-page allocation that failed. Running out of zero page depends on every zbyte
+// before this one, so the message says how much was asked for.
+fn zp_error(e: &str, loc: &Location, size: u8) -> SasmError {
+    match e {
+        ZP_EXHAUSTED => {
+            let bytes = if size == 1 { "byte" } else { "bytes" };
+            let message = format!("{e} at line {} requesting {size} {bytes}", loc.line);
+            SasmError::range(&message).in_word(0)
+        }
+        _ => SasmError::range(e).in_word(0),
+    }
+}
+
+// Tokenize one item of a data, word, wordbe, or dword line
+fn tokenize_data_item(directive: &str, item: &str) -> Result<Rawdata, SasmError> {
+    let layout = match directive {
+        "wordbe" => Layout::WordBe,
+        "dword" => Layout::Dword,
+        _ => Layout::Word,
+    };
+    if let Some(l) = item.strip_prefix('.') {
+        return Ok(Rawdata::Label(l.to_string(), layout));
+    }
+
+    // Text is inserted as its ASCII bytes
+    if directive == "data" && item.starts_with('"') {
+        return match item.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+            Some(t) if t.contains('"') => Err(SasmError::syntax(
+                "data strings must be separated by spaces or commas",
+            )),
+            Some(t) if t.is_ascii() => Ok(Rawdata::Bytes(t.as_bytes().to_vec())),
+            Some(_) => Err(SasmError::syntax("data strings must be ASCII")),
+            None => Err(SasmError::syntax(
+                "data string is missing its closing quote",
+            )),
+        };
+    }
+    if directive == "data" {
+        return hex::decode(item)
+            .map(Rawdata::Bytes)
+            .map_err(|_| SasmError::syntax("data must be a valid hex string"));
+    }
+
+    // Values are hex with up to two digits per byte
+    match u32::from_str_radix(item, 16) {
+        Ok(n) if item.len() <= layout.size() * 2 => Ok(Rawdata::Bytes(layout.bytes(n))),
+        _ => Err(SasmError::syntax(&format!(
+            "{directive} must be a label or up to {} hex digits",
+            layout.size() * 2
+        ))),
+    }
+}
+
+fn tokenize(line: &str, comment_markers: &[String]) -> Result<SourceLine, SasmError> {
     // Remove comments
-    let words: Vec<&str> = line
-        .split(";")
-        .next()
-        .unwrap()
-        .split_ascii_whitespace()
-        .collect();
-    if words.len() == 0 {
+    let words: Vec<&str> = split_unquoted(strip_comment(line, comment_markers), |c| {
+        c.is_ascii_whitespace()
+    })
+    .into_iter()
+    .map(|(_, w)| w)
+    .collect();
+    if words.is_empty() {
         return Ok(SourceLine::Blank);
     }
 
     match words[0] {
-        "org" => {
+        // "org!" may go below the code address
+        "org" | "org!" => {
             if words.len() != 2 {
-                return Err("org takes one argument");
+                return Err(SasmError::syntax(&format!(
+                    "{} takes one argument",
+                    words[0]
+                )));
             }
-            match hex_to_uint(words[1])? {
-                UInt::U8(_) => Err("org must be a 2-byte address"),
-                UInt::U16(u) => Ok(SourceLine::Org(u)),
+            match hex_to_uint(words[1]).map_err(|e| e.in_word(1))? {
+                UInt::U8(_) => Err(SasmError::range("org must be a 2-byte address").in_word(1)),
+                UInt::U16(u) => Ok(SourceLine::Org(u, words[0] == "org!")),
             }
         }
 
+        "bank" => match words.as_slice() {
+            [_, n, "origin", o, "size", s] => {
+                let n = match hex_to_uint(n).map_err(|e| e.in_word(1))? {
+                    UInt::U8(u) => u as u16,
+                    UInt::U16(u) => u,
+                };
+                let origin = match hex_to_uint(o).map_err(|e| e.in_word(3))? {
+                    UInt::U8(_) => {
+                        return Err(
+                            SasmError::range("bank origin must be a 2-byte address").in_word(3)
+                        )
+                    }
+                    UInt::U16(u) => u,
+                };
+                let size = match hex_to_uint(s).map_err(|e| e.in_word(5))? {
+                    UInt::U8(u) => u as u16,
+                    UInt::U16(u) => u,
+                };
+                if size == 0 {
+                    return Err(SasmError::range("bank size must not be zero").in_word(5));
+                }
+                Ok(SourceLine::Bank(n, origin, size))
+            }
+            _ => Err(SasmError::syntax("expected bank N origin ADDR size SIZE")),
+        },
+
+        "segment" => match words.as_slice() {
+            [_, "code"] => Ok(SourceLine::Segment(Segment::Code)),
+            [_, "data"] => Ok(SourceLine::Segment(Segment::Data)),
+            [_, "bss"] => Ok(SourceLine::Segment(Segment::Bss)),
+            [_, _] => Err(SasmError::syntax("segment must be code, data, or bss").in_word(1)),
+            _ => Err(SasmError::syntax("segment takes one argument")),
+        },
+
         "label" => {
             if words.len() != 3 {
-                return Err("label takes two arguments");
+                return Err(SasmError::syntax("label takes two arguments"));
             }
 
-            match hex_to_uint(words[2])? {
+            match hex_to_uint(words[2]).map_err(|e| e.in_word(2))? {
                 UInt::U8(u) => Ok(SourceLine::Label(words[1].to_string(), UInt::U8(u))),
                 UInt::U16(u) => Ok(SourceLine::Label(words[1].to_string(), UInt::U16(u))),
             }
         }
 
-        "zbyte" => match words.len() {
-            2 => Ok(SourceLine::ZByte(words[1].to_string(), 1)),
-            3 => match hex_to_uint(words[2])? {
-                UInt::U8(u) => Ok(SourceLine::ZByte(words[1].to_string(), u)),
-                UInt::U16(_) => Err("zbyte array size must be a single byte (< 0x100)"),
+        "zbyte" => {
+            // Local bytes are freed at the next code marker
+            let (args, scope) = match words.split_last() {
+                Some((&"local", args)) if words.len() > 2 => (args, Scope::Local),
+                _ => (&words[..], Scope::Program),
+            };
+
+            // The address of the first byte can be made a multiple of an alignment
+            let (args, align) = match args {
+                [args @ .., "align", a] if args.len() >= 2 => {
+                    let pos = args.len() + 1;
+                    match hex_to_uint(a).map_err(|e| e.in_word(pos))? {
+                        UInt::U8(u) if u > 0 => (args, u),
+                        _ => {
+                            return Err(SasmError::range("zbyte alignment must be from 1 to ff")
+                                .in_word(pos))
+                        }
+                    }
+                }
+                _ => (args, 1),
+            };
+
+            let size = match args.len() {
+                2 => 1,
+                3 => match hex_to_uint(words[2]).map_err(|e| e.in_word(2))? {
+                    UInt::U8(0) => {
+                        return Err(SasmError::range("zbyte array size must not be zero").in_word(2))
+                    }
+                    UInt::U8(u) => u,
+                    UInt::U16(_) => {
+                        return Err(SasmError::range(
+                            "zbyte array size must be a single byte (< 0x100)",
+                        )
+                        .in_word(2))
+                    }
+                },
+                _ => {
+                    return Err(SasmError::syntax(
+                        "expected zbyte NAME, zbyte NAME SIZE, or either with align N or local",
+                    ))
+                }
+            };
+            Ok(SourceLine::ZByte(words[1].to_string(), size, align, scope))
+        }
+
+        "zword" => match words.as_slice() {
+            [_, name] => Ok(SourceLine::ZWord(name.to_string(), Scope::Program)),
+            [_, name, "local"] => Ok(SourceLine::ZWord(name.to_string(), Scope::Local)),
+            _ => Err(SasmError::syntax("expected zword NAME or zword NAME local")),
+        },
+
+        "var" => match words.len() {
+            2 => Ok(SourceLine::Var(words[1].to_string(), 1)),
+            3 => match hex_to_uint(words[2]).map_err(|e| e.in_word(2))? {
+                UInt::U8(u) => Ok(SourceLine::Var(words[1].to_string(), u as u16)),
+                UInt::U16(u) => Ok(SourceLine::Var(words[1].to_string(), u)),
             },
-            _ => Err("zbyte takes one or two arguments"),
+            _ => Err(SasmError::syntax("var takes one or two arguments")),
+        },
+
+        "test" => match words.len() {
+            2 => Ok(SourceLine::Test(words[1].to_string())),
+            _ => Err(SasmError::syntax("test takes one argument")),
+        },
+
+        // A register or "mem" and an address, then "==" or "!=" and a value
+        "assert" => {
+            let (checked, pos) = match words.get(1) {
+                Some(&"mem") if words.len() == 5 => match words[2].strip_prefix('.') {
+                    Some(l) => (Checked::Memory(Op::Label(l.to_string())), 3),
+                    None => {
+                        let addr = hex_to_uint(words[2]).map_err(|e| e.in_word(2))?;
+                        (Checked::Memory(Op::UInt(addr)), 3)
+                    }
+                },
+                Some(r) if words.len() == 4 && ["a", "x", "y", "s", "p"].contains(r) => {
+                    (Checked::Register(r.chars().next().unwrap_or('a')), 2)
+                }
+                _ => return Err(SasmError::syntax(
+                    "assert takes a, x, y, s, p, or mem and an address, then == or != and a value",
+                )),
+            };
+            let equal = match words[pos] {
+                "==" => true,
+                "!=" => false,
+                _ => {
+                    return Err(SasmError::syntax("assert must compare with == or !=").in_word(pos))
+                }
+            };
+            match hex_to_uint(words[pos + 1]).map_err(|e| e.in_word(pos + 1))? {
+                UInt::U8(v) => Ok(SourceLine::Assert(checked, v, equal)),
+                UInt::U16(_) => {
+                    Err(SasmError::range("assert value must be a single byte").in_word(pos + 1))
+                }
+            }
+        }
+
+        "zfree" => match words.len() {
+            2 => Ok(SourceLine::ZFree(words[1].to_string())),
+            _ => Err(SasmError::syntax("zfree takes one argument")),
         },
 
-        "data" => {
+        "zpreserve" => {
+            if words.len() != 3 {
+                return Err(SasmError::syntax("zpreserve takes two arguments"));
+            }
+            let mut range = [0; 2];
+            for i in 0..2 {
+                range[i] = match hex_to_uint(words[i + 1]).map_err(|e| e.in_word(i + 1))? {
+                    UInt::U8(u) => u,
+                    UInt::U16(_) => {
+                        return Err(
+                            SasmError::range("zpreserve takes zero-page addresses").in_word(i + 1)
+                        )
+                    }
+                };
+            }
+            if range[1] < range[0] {
+                return Err(SasmError::range("zpreserve range ends before it starts").in_word(2));
+            }
+            Ok(SourceLine::ZpReserve(range[0], range[1]))
+        }
+
+        "data" | "word" | "wordbe" | "dword" => {
+            // Items are separated by whitespace or commas
+            let items: Vec<(usize, &str)> = words
+                .iter()
+                .enumerate()
+                .skip(1)
+                .flat_map(|(i, w)| {
+                    split_unquoted(w, |c| c == ',')
+                        .into_iter()
+                        .map(move |(_, t)| (i, t))
+                })
+                .collect();
+            if items.is_empty() {
+                return Err(SasmError::syntax(&format!(
+                    "{} takes at least one argument",
+                    words[0]
+                )));
+            }
+
+            let mut data = Vec::new();
+            for (i, item) in items {
+                data.push((
+                    i,
+                    tokenize_data_item(words[0], item).map_err(|e| e.in_word(i))?,
+                ));
+            }
+            Ok(SourceLine::Data(data))
+        }
+
+        "end" => {
             if words.len() != 2 {
-                return Err("data takes one argument");
+                return Err(SasmError::syntax("end takes one argument"));
             }
-            if words[1].starts_with('.') {
-                Ok(SourceLine::Data(Rawdata::Label(words[1][1..].to_string())))
-            } else {
-                match hex::decode(words[1]) {
-                    Ok(v) => Ok(SourceLine::Data(Rawdata::Bytes(v))),
-                    Err(_) => Err("data must be a valid hex string"),
+            match words[1].strip_prefix('.') {
+                Some(l) => Ok(SourceLine::End(Op::Label(l.to_string()))),
+                None => match hex_to_uint(words[1]).map_err(|e| e.in_word(1))? {
+                    UInt::U8(_) => Err(SasmError::range("end must be a 2-byte address").in_word(1)),
+                    u => Ok(SourceLine::End(Op::UInt(u))),
+                },
+            }
+        }
+
+        "checksum" => {
+            if words.len() != 2 {
+                return Err(SasmError::syntax("checksum takes one argument"));
+            }
+            match Checksum::new(words[1]) {
+                Ok(c) => Ok(SourceLine::Checksum(c)),
+                Err(e) => Err(SasmError::syntax(e).in_word(1)),
+            }
+        }
+
+        // Each word is one or more bytes in hex, such as "ea ea" or "eaea"
+        "patch" => {
+            if words.len() < 3 {
+                return Err(SasmError::syntax("patch takes an address and bytes"));
+            }
+            let addr = match hex_to_uint(words[1]).map_err(|e| e.in_word(1))? {
+                UInt::U8(u) => u as u16,
+                UInt::U16(u) => u,
+            };
+            let mut bytes = Vec::new();
+            for (i, w) in words.iter().enumerate().skip(2) {
+                if w.len() % 2 != 0 || !w.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(
+                        SasmError::syntax("patch bytes must be pairs of hex digits").in_word(i)
+                    );
+                }
+                for pair in w.as_bytes().chunks(2) {
+                    let digits = core::str::from_utf8(pair).unwrap_or_default();
+                    bytes.push(u8::from_str_radix(digits, 16).unwrap_or_default());
                 }
             }
+            Ok(SourceLine::Patch(addr, bytes))
         }
 
         // Code markers
         cm if cm.starts_with('.') => {
             if words.len() != 1 {
-                return Err("code markers must be on a line by themselves");
+                return Err(
+                    SasmError::syntax("code markers must be on a line by themselves").in_word(1),
+                );
             }
             Ok(SourceLine::CodeMarker(words[0][1..].to_string()))
         }
@@ -111,20 +396,41 @@ fn tokenize(line: &str) -> Result<SourceLine, &str> {
             if words.len() > 1 {
                 op = if words[1].starts_with('.') {
                     Op::Label(words[1][1..].to_string())
+                } else if let Some(l) = words[1].strip_prefix("<.") {
+                    Op::Low(l.to_string())
+                } else if let Some(l) = words[1].strip_prefix(">.") {
+                    Op::High(l.to_string())
+                } else if let Some(h) = words[1].strip_prefix(['<', '>']) {
+                    let value = match hex_to_uint(h).map_err(|e| e.in_word(1))? {
+                        UInt::U8(u) => u as u16,
+                        UInt::U16(u) => u,
+                    };
+                    Op::UInt(UInt::U8(match words[1].starts_with('<') {
+                        true => value.to_le_bytes()[0],
+                        false => value.to_le_bytes()[1],
+                    }))
                 } else {
-                    Op::UInt(hex_to_uint(words[1])?)
+                    Op::UInt(hex_to_uint(words[1]).map_err(|e| e.in_word(1))?)
                 }
             }
 
             // Tokenize offset
-            let mut offset = Offset::U8(0);
+            let mut offset = Offset::Value(0);
             if words.len() > 2 {
                 offset = if words[2].starts_with('.') {
                     Offset::Label(words[2][1..].to_string())
                 } else {
-                    Offset::U8(match hex_to_uint(words[2])? {
-                        UInt::U8(u) => u,
-                        UInt::U16(_) => return Err("offset must be a single byte (< 0x100)"),
+                    // A minus sign subtracts the offset instead
+                    let (digits, sign) = match words[2].strip_prefix('-') {
+                        Some(d) => (d, -1),
+                        None => (words[2], 1),
+                    };
+                    Offset::Value(match hex_to_uint(digits).map_err(|e| e.in_word(2))? {
+                        UInt::U8(u) => sign * u as i16,
+                        UInt::U16(_) => {
+                            return Err(SasmError::range("offset must be a single byte (< 0x100)")
+                                .in_word(2))
+                        }
                     })
                 }
             }
@@ -134,299 +440,2451 @@ fn tokenize(line: &str) -> Result<SourceLine, &str> {
     }
 }
 
-// This parent function allows us to easily append the line number to any errors regardless of how
-// and where they are generated.
-pub fn assemble(config: &mut Config) -> Result<Code, String> {
-    let mut line_num = 0;
-    match run_internal(config, &mut line_num) {
-        Ok(c) => Ok(c),
-        Err(e) => Err(format!("{line_num}: {e}")),
+// Bytes at consecutive addresses in one segment, starting at an org or at the start of the segment
+struct Block {
+    segment: Segment,
+    addr: usize,
+    len: usize,
+
+    // Whether the address is relative to the end of the segment before, because the segment had
+    // no org before its first bytes. The first pass places these blocks once all sizes are known.
+    floating: bool,
+
+    // Index of the ROM bank that holds the block, if the program has banks
+    bank: Option<usize>,
+}
+
+// Block to start at a new org in a segment. If the org appears before any code in the segment, it
+// replaces the segment's first block, unless that block already has labels relative to it.
+fn org_block(
+    blocks: &mut Vec<Block>,
+    current_block: &mut BTreeMap<Segment, usize>,
+    floating_labels: &[(SymbolId, usize)],
+    segment: Segment,
+) -> usize {
+    let block = current_block[&segment];
+    let empty = blocks
+        .iter()
+        .filter(|b| b.segment == segment)
+        .all(|b| b.len == 0)
+        && !floating_labels.iter().any(|(_, b)| *b == block);
+    if empty {
+        return block;
     }
+    blocks.push(Block {
+        segment,
+        addr: 0,
+        len: 0,
+        floating: false,
+        bank: None,
+    });
+    current_block.insert(segment, blocks.len() - 1);
+    blocks.len() - 1
 }
 
-fn run_internal(config: &mut Config, line_num: &mut i32) -> Result<Code, String> {
-    let assembly = match config.itype {
-        IType::Stdin => {
-            let mut s = String::new();
-            std::io::stdin()
-                .read_to_string(&mut s)
-                .expect("Unable to read from stdin");
-            s
+// Instruction in its zero-page form, if it is absolute and its operand plus offset is already known
+// to be below 0100. A label stays in the operand as its low byte. Code markers may still move, so
+// they are never known.
+fn zero_page_instr(
+    mnemonic: String,
+    op: Op,
+    offset: Offset,
+    labels: &BTreeMap<SymbolId, UInt>,
+    symbols: &SymbolTable,
+    code_labels: &BTreeSet<SymbolId>,
+    isa: Isa,
+) -> (SourceLine, Form) {
+    let value = |l: &String| {
+        let id = symbols.get(l).filter(|id| !code_labels.contains(id))?;
+        match labels.get(&id) {
+            Some(UInt::U8(u)) => Some(*u as usize),
+            Some(UInt::U16(u)) => Some(*u as usize),
+            None => None,
         }
-        IType::String(ref s) => s.to_string(),
-        IType::File(ref f) => std::fs::read_to_string(f).expect("Unable to read input file"),
     };
+    let operand = match &op {
+        Op::UInt(UInt::U8(u)) => Some(*u as usize),
+        Op::UInt(UInt::U16(u)) => Some(*u as usize),
+        Op::Label(l) => value(l),
+        _ => None,
+    };
+    let offset_value = match &offset {
+        Offset::Value(v) => Some(*v as isize),
+        Offset::Label(l) => value(l).map(|v| v as isize),
+    };
+    let zp = match (operand, offset_value) {
+        (Some(o), Some(f)) if (0..=0xff).contains(&(o as isize + f)) => {
+            get_zero_page_form(&mnemonic, isa)
+        }
+        _ => None,
+    };
+    match zp {
+        Some(z) => (in_zero_page_form(z, op, offset), Form::ZeroPage),
+        None => (SourceLine::Instr(mnemonic, op, offset), Form::AsWritten),
+    }
+}
 
-    // Main data structures
-    // Vector of tokenized source lines
-    let mut source = Vec::new();
-
-    // Map of label names to value
-    let mut labels = HashMap::new();
-
-    // Current code address (address where the current byte will be stored in memory)
-    let mut code_addr: usize = 0;
-
-    // Current code position (position of current byte in assembly code, which is unchanged by
-    // "org" statements)
-    let mut code_pos: usize = 0;
-
-    // Map of org values to code positions
-    let mut org_to_code_pos = BTreeMap::new();
-
-    // Insert a default, initial org of 0000. Thus, an org statement is not required before code,
-    // although most programs should have one. (One exception is code for testing SASM itself.)
-    // If an org statement does appear before any code, this entry will be removed.
-    org_to_code_pos.insert(0, 0);
-
-    // First parser loop. Tokenizes source lines and collects labels.
-    *line_num = 0;
-    for line in assembly.lines() {
-        *line_num += 1;
-        let tokenized_line = tokenize(line)?;
-        match tokenized_line {
-            SourceLine::Blank => (),
-            SourceLine::Org(o) => {
-                if (o as usize) < code_addr {
-                    return Err("org smaller than code address".to_string());
-                }
+// Instruction with its zero-page mnemonic, whose operand is the low byte of the absolute one
+fn in_zero_page_form(mnemonic: String, op: Op, offset: Offset) -> SourceLine {
+    match op {
+        Op::UInt(u) => {
+            let u = match u {
+                UInt::U8(u) => u,
+                UInt::U16(u) => u as u8,
+            };
+            SourceLine::Instr(mnemonic, Op::UInt(UInt::U8(u)), offset)
+        }
+        Op::Label(l) => SourceLine::Instr(mnemonic, Op::Low(l), offset),
+        op => SourceLine::Instr(mnemonic, op, offset),
+    }
+}
 
-                // If org appears before any code, remove the default, initial org.
-                if code_pos == 0 {
-                    org_to_code_pos.clear();
-                }
+// Label and offset of a label in data. A label whose name has a "+" or "-" in it, such as the
+// second byte of a zword, is used as it is.
+fn data_label<'a>(
+    label: &'a str,
+    labels: &BTreeMap<SymbolId, UInt>,
+    symbols: &SymbolTable,
+) -> (&'a str, i32) {
+    match label_value(label, labels, symbols) {
+        Some(_) => (label, 0),
+        None => split_offset(label),
+    }
+}
 
-                org_to_code_pos.insert(o, code_pos);
-                code_addr = o as usize;
-            }
-            SourceLine::Label(ref s, u) => {
-                if labels.contains_key(s) {
-                    return Err("label repeated".to_string());
-                }
-                labels.insert(s.to_string(), u);
-            }
-            SourceLine::ZByte(ref s, size) => {
-                if labels.contains_key(s) {
-                    return Err("label repeated".to_string());
-                }
-                labels.insert(s.to_string(), UInt::U8(config.zpm.alloc(size)));
-            }
-            SourceLine::Data(ref d) => {
-                // Assume labels are two bytes, which is verified later in the second loop.
-                let mut data_size: usize = 2;
-                if let Rawdata::Bytes(b) = d {
-                    data_size = b.len();
-                }
+// Value of a label, found by its name, if it is defined
+fn label_value(
+    label: &str,
+    labels: &BTreeMap<SymbolId, UInt>,
+    symbols: &SymbolTable,
+) -> Option<UInt> {
+    symbols.get(label).and_then(|id| labels.get(&id)).copied()
+}
 
-                code_addr += data_size;
-                code_pos += data_size;
-            }
-            SourceLine::CodeMarker(ref s) => {
-                if labels.contains_key(s) {
-                    return Err("label repeated".to_string());
+// Second pass helper. Converts a single tokenized line to machine code and appends it to the
+// disassembly. The code address is the address of the first byte of the line as computed in the
+// first pass. Warnings are returned without a location, which the caller adds.
+fn assemble_line(
+    s: SourceLine,
+    code_addr: usize,
+    labels: &BTreeMap<SymbolId, UInt>,
+    symbols: &SymbolTable,
+    isa: Isa,
+    disassembly: &mut Vec<u8>,
+    warnings: &mut Vec<SasmWarning>,
+) -> Result<(), SasmError> {
+    match s {
+        // The opposite branch skips the jmpa that follows it
+        SourceLine::LongBranch(mnemonic, op, offset) => {
+            let opposite = get_opposite_branch(&mnemonic)
+                .ok_or_else(|| SasmError::internal("long branch is not a branch"))?;
+            let instr_info =
+                get_instr_info(opposite, isa).map_err(|e| SasmError::syntax(e).in_word(0))?;
+            disassembly.extend([instr_info.opcode, 3]);
+            let jmp = SourceLine::Instr("jmpa".to_string(), op, offset);
+            assemble_line(
+                jmp,
+                code_addr + 2,
+                labels,
+                symbols,
+                isa,
+                disassembly,
+                warnings,
+            )?;
+        }
+        SourceLine::Data(items) => {
+            for (word, item) in items {
+                match item {
+                    Rawdata::Label(l, layout) => {
+                        let (l, offset) = data_label(&l, labels, symbols);
+                        match label_value(l, labels, symbols) {
+                            Some(UInt::U8(_)) => {
+                                return Err(SasmError::label(
+                                    "labels used for data must be two bytes",
+                                )
+                                .in_word(word))
+                            }
+                            // Addresses wrap around, as they do when object files are linked
+                            Some(UInt::U16(u)) => {
+                                let value = u.wrapping_add_signed(offset as i16);
+                                disassembly.extend(layout.bytes(value as u32))
+                            }
+                            None => {
+                                return Err(SasmError::label(&format!("label {l} not defined"))
+                                    .in_word(word))
+                            }
+                        }
+                    }
+                    Rawdata::Bytes(b) => disassembly.extend(b),
                 }
-                labels.insert(s.to_string(), UInt::U16(code_addr as u16));
-            }
-            SourceLine::Instr(ref mnemonic, _, _) => {
-                code_addr += get_instr_size(mnemonic)? as usize;
-                code_pos += get_instr_size(mnemonic)? as usize;
             }
         }
 
-        // Store all source lines so that next loop can refer to input
-        // by line number.
-        source.push(tokenized_line);
-    }
+        // Placeholder bytes, which are replaced once the checksum is known
+        SourceLine::Checksum(c) => disassembly.extend(vec![0; c.size()]),
+        SourceLine::Instr(mnemonic, input_op, offset_type) => {
+            // Store opcode
+            let instr_info =
+                get_instr_info(&mnemonic, isa).map_err(|e| SasmError::syntax(e).in_word(0))?;
+            disassembly.push(instr_info.opcode);
 
-    // Second parser loop. Stores machine code in "disassembly" vector.
-    code_addr = 0;
-    *line_num = 0;
-    let mut disassembly: Vec<u8> = Vec::new();
-    for s in source {
-        *line_num += 1;
-        match s {
-            SourceLine::Org(o) => {
-                code_addr = o as usize;
-            }
-            SourceLine::Data(d) => match d {
-                Rawdata::Label(l) => match labels.get(&l) {
-                    Some(UInt::U8(_)) => {
-                        return Err("labels used for data must be two bytes".to_string())
+            // Compute offset
+            let offset: i16;
+            match offset_type {
+                Offset::Value(v) => offset = v,
+                Offset::Label(l) => match label_value(&l, labels, symbols) {
+                    Some(UInt::U8(u)) => offset = u as i16,
+                    Some(UInt::U16(_)) => {
+                        return Err(SasmError::label("offset must be a single byte").in_word(2))
                     }
-                    Some(UInt::U16(u)) => {
-                        let bytes = (*u).to_le_bytes();
-                        disassembly.push(bytes[0]);
-                        disassembly.push(bytes[1]);
+                    None => {
+                        return Err(SasmError::label(&format!("label {l} not defined")).in_word(2))
                     }
-                    None => panic!("Internal error: label {l} found in second pass but not first"),
                 },
-                Rawdata::Bytes(b) => disassembly.extend(b),
-            },
-            SourceLine::Instr(mnemonic, input_op, offset_type) => {
-                // Store opcode
-                let instr_info = get_instr_info(&mnemonic)?;
-                disassembly.push(instr_info.opcode);
-                code_addr += 1;
-
-                // Compute offset
-                let offset: u8;
-                match offset_type {
-                    Offset::U8(u) => offset = u,
-                    Offset::Label(l) => match labels.get(&l) {
-                        Some(UInt::U8(u)) => offset = *u,
-                        Some(UInt::U16(_)) => {
-                            return Err("offset must be a single byte".to_string())
-                        }
-                        None => panic!("Internal error: label {l} found in second pass but not first"),
-                    },
-                }
+            }
 
-                // Handle labelled op. Unwrap it and convert it to a non-label variant.
-                let input_op_unwrapped: Op;
-                if let Op::Label(l) = input_op {
-                    input_op_unwrapped = match labels.get(&l) {
-                        Some(u) => Op::UInt(*u),
-                        None => panic!("Internal error: label {l} found in second pass but not first"),
+            // Handle labelled op. Unwrap it and convert it to a non-label variant.
+            let input_op_unwrapped: Op;
+            if let Op::Label(l) = input_op {
+                input_op_unwrapped = match label_value(&l, labels, symbols) {
+                    Some(u) => Op::UInt(u),
+                    None => {
+                        return Err(SasmError::label(&format!("label {l} not defined")).in_word(1))
                     }
-                } else {
-                    input_op_unwrapped = input_op;
+                }
+            } else if let Op::Low(l) | Op::High(l) = &input_op {
+                // A single-byte label is its own low byte, with a high byte of zero
+                let value = match label_value(l, labels, symbols) {
+                    Some(UInt::U8(u)) => u as u16,
+                    Some(UInt::U16(u)) => u,
+                    None => {
+                        return Err(SasmError::label(&format!("label {l} not defined")).in_word(1))
+                    }
+                };
+                input_op_unwrapped = Op::UInt(UInt::U8(match input_op {
+                    Op::Low(_) => value.to_le_bytes()[0],
+                    _ => value.to_le_bytes()[1],
+                }));
+            } else {
+                input_op_unwrapped = input_op;
+            }
+
+            // Handle op
+            match input_op_unwrapped {
+                Op::Label(_) | Op::Low(_) | Op::High(_) => {
+                    return Err(SasmError::internal("label found for unwrapped op"))
                 }
 
-                // Handle op
-                match input_op_unwrapped {
-                    Op::Label(_) => panic!("Internal error: label found for unwrapped op"),
+                // No operand provided
+                Op::None => match instr_info.op {
+                    OpType::None => (),
+                    OpType::U8 => {
+                        return Err(
+                            SasmError::syntax("instruction requires a single-byte operand")
+                                .in_word(0),
+                        )
+                    }
+                    OpType::U16 => {
+                        return Err(
+                            SasmError::syntax("instruction requires a two-byte operand").in_word(0)
+                        )
+                    }
+                },
 
-                    // No operand provided
-                    Op::None => match instr_info.op {
-                        OpType::None => (),
-                        OpType::U8 => {
-                            return Err("instruction requires a single-byte operand".to_string())
+                // UInt op provided (recall that labels have already been unwrapped)
+                Op::UInt(ui_type) => match ui_type {
+                    // UInt op is a single byte
+                    UInt::U8(u) => match instr_info.op {
+                        OpType::None => {
+                            return Err(SasmError::syntax(
+                                "instruction does not require an operand",
+                            )
+                            .in_word(1))
                         }
+                        OpType::U8 => match u as i16 + offset {
+                            v if v > 0xff => {
+                                return Err(
+                                    SasmError::range("operand plus offset is > 0xff").in_word(1)
+                                )
+                            }
+                            v if v < 0 => {
+                                return Err(
+                                    SasmError::range("operand minus offset is < 0").in_word(1)
+                                )
+                            }
+                            v => disassembly.push(v as u8),
+                        },
                         OpType::U16 => {
-                            return Err("instruction requires a two-byte operand".to_string())
+                            return Err(SasmError::syntax(
+                                "instruction requires a two-byte operand",
+                            )
+                            .in_word(1))
                         }
                     },
 
-                    // UInt op provided (recall that labels have already been unwrapped)
-                    Op::UInt(ui_type) => match ui_type {
-                        // UInt op is a single byte
-                        UInt::U8(u) => match instr_info.op {
-                            OpType::None => {
-                                return Err("instruction does not require an operand".to_string())
-                            }
-                            OpType::U8 => {
-                                if u as u16 + offset as u16 > 0xff {
-                                    return Err("operand plus offset is > 0xff".to_string());
-                                } else {
-                                    disassembly.push(u + offset);
-                                    code_addr += 1;
-                                }
-                            }
-                            OpType::U16 => {
-                                return Err("instruction requires a two-byte operand".to_string())
-                            }
-                        },
+                    // UInt op is two bytes
+                    UInt::U16(u) => match instr_info.op {
+                        OpType::None => {
+                            return Err(SasmError::syntax(
+                                "instruction does not require an operand",
+                            )
+                            .in_word(1))
+                        }
+                        OpType::U8 => {
+                            // Special handling for relative branches. Allow them to have a
+                            // two-byte operand from which we compute the real, single-byte
+                            // operand (a code offset). Normally this will come from a label.
 
-                        // UInt op is two bytes
-                        UInt::U16(u) => match instr_info.op {
-                            OpType::None => {
-                                return Err("instruction does not require an operand".to_string())
-                            }
-                            OpType::U8 => {
-                                // Special handling for relative branches. Allow them to have a
-                                // two-byte operand from which we compute the real, single-byte
-                                // operand (a code offset). Normally this will come from a label.
-
-                                // Note that it is possible for the user to hardcode the relative
-                                // offset by giving a single-byte operand.
-                                if is_relative_branch_instruction(&mnemonic) {
-                                    // Not sure if it makes sense to support offsets here, but they are
-                                    // not forbidden anywhere else, so let's be consistent.
-                                    if u as u32 + offset as u32 > 0xffff {
-                                        return Err("operand plus offset is > 0xffff".to_string());
-                                    } else {
-                                        // Jump is from the end of the current instruction
-                                        // (code_addr + 1)
-                                        match compute_diff_u16_as_u8(
-                                            u + offset as u16,
-                                            (code_addr + 1) as u16,
-                                        ) {
-                                            Some(d) => {
-                                                disassembly.push(d);
-                                                code_addr += 1;
-                                            }
-                                            None => {
-                                                return Err(
-                                                    "relative branch is too far from target"
-                                                        .to_string(),
-                                                )
-                                            }
-                                        }
-                                    }
-                                } else {
-                                    return Err(
-                                        "instruction requires a single-byte operand".to_string()
+                            // Note that it is possible for the user to hardcode the relative
+                            // offset by giving a single-byte operand.
+                            if is_relative_branch_instruction(&mnemonic) {
+                                // Not sure if it makes sense to support offsets here, but they are
+                                // not forbidden anywhere else, so let's be consistent.
+                                if offset != 0 {
+                                    warnings.push(
+                                        SasmWarning::new(
+                                            WarningKind::BranchOffset,
+                                            "offset added to relative branch target",
+                                        )
+                                        .in_word(2),
                                     );
                                 }
-                            }
-                            OpType::U16 => {
-                                if u as u32 + offset as u32 > 0xffff {
-                                    return Err("operand plus offset is > 0xffff".to_string());
+                                if u as i32 + offset as i32 > 0xffff {
+                                    return Err(SasmError::range(
+                                        "operand plus offset is > 0xffff",
+                                    )
+                                    .in_word(1));
                                 } else {
-                                    let bytes = (u + offset as u16).to_le_bytes();
-                                    disassembly.push(bytes[0]);
-                                    disassembly.push(bytes[1]);
-                                    code_addr += 2;
+                                    // Jump is from the end of the current instruction
+                                    // (code_addr + 2)
+                                    match compute_diff_u16_as_u8(
+                                        u.wrapping_add_signed(offset),
+                                        (code_addr + 2) as u16,
+                                    ) {
+                                        Some(d) => {
+                                            disassembly.push(d);
+                                        }
+                                        None => {
+                                            return Err(SasmError::range(BRANCH_TOO_FAR).in_word(1))
+                                        }
+                                    }
                                 }
+                            } else {
+                                return Err(SasmError::syntax(
+                                    "instruction requires a single-byte operand",
+                                )
+                                .in_word(1));
                             }
-                        },
+                        }
+                        OpType::U16 => {
+                            // Subtracting wraps around below 0000, as it does when object files are
+                            // linked
+                            if u as i32 + offset as i32 > 0xffff {
+                                return Err(
+                                    SasmError::range("operand plus offset is > 0xffff").in_word(1)
+                                );
+                            } else {
+                                let bytes = u.wrapping_add_signed(offset).to_le_bytes();
+                                disassembly.push(bytes[0]);
+                                disassembly.push(bytes[1]);
+                            }
+                        }
                     },
+                },
+            }
+
+            // Taken branches cost an extra cycle if the target is on a different page than the
+            // next instruction.
+            if is_relative_branch_instruction(&mnemonic) {
+                if let Some(rel) = disassembly.last() {
+                    let next_addr = code_addr as isize + 2;
+                    let target_addr = next_addr + *rel as i8 as isize;
+                    if target_addr >> 8 != next_addr >> 8 {
+                        warnings.push(
+                            SasmWarning::new(
+                                WarningKind::BranchPage,
+                                &format!("branch to {:04x} crosses a page boundary", target_addr),
+                            )
+                            .in_word(1),
+                        );
+                    }
                 }
             }
+        }
 
-            // All other line types ignored in second pass
-            _ => (),
+        // All other line types ignored in second pass
+        _ => (),
+    }
+
+    Ok(())
+}
+
+// Text of the combined input, kept as one string with the range of each line in it. Lines and
+// their locations are found from these when needed, rather than kept as a string and a location
+// for every line, since generated sources can have millions of lines.
+struct SourceText {
+    text: String,
+    lines: Vec<core::ops::Range<usize>>,
+
+    // Name of each input file and the index of its first line. Input that is not from files has no
+    // file name.
+    files: Vec<(String, usize)>,
+}
+
+impl SourceText {
+    fn new(text: String, input_files: &[(String, usize)]) -> Self {
+        let start = text.as_ptr() as usize;
+        let lines = text
+            .lines()
+            .map(|l| {
+                let pos = l.as_ptr() as usize - start;
+                pos..pos + l.len()
+            })
+            .collect();
+        let mut first = 0;
+        let mut files = Vec::new();
+        for (f, n) in input_files {
+            files.push((f.to_string(), first));
+            first += n;
         }
+        SourceText { text, lines, files }
     }
 
-    // Create and write the final output
-    let code = bytes_to_output(&disassembly, org_to_code_pos, config.cformat);
-    write_code(&code, &config.otype)?;
+    fn len(&self) -> usize {
+        self.lines.len()
+    }
 
-    return Ok(code);
+    fn iter(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(|r| &self.text[r.clone()])
+    }
+
+    // Location of a line (as an index)
+    fn location(&self, line_idx: usize) -> Location {
+        match self.files.partition_point(|(_, first)| *first <= line_idx) {
+            0 => Location {
+                file: None,
+                line: line_idx + 1,
+            },
+            i => Location {
+                file: Some(self.files[i - 1].0.clone()),
+                line: line_idx - self.files[i - 1].1 + 1,
+            },
+        }
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl core::ops::Index<usize> for SourceText {
+    type Output = str;
 
-    #[test]
-    fn hex2_to_u8() {
-        match hex_to_uint("EB") {
-            Ok(UInt::U8(i)) => assert_eq!(i, 235),
-            _ => panic!("Unable to convert single-byte hex to int"),
+    fn index(&self, line_idx: usize) -> &str {
+        &self.text[self.lines[line_idx].clone()]
+    }
+}
+
+// What the first pass keeps of each line that the second pass assembles: its line (as an index),
+// address, and block, and the form that the first pass chose for an instruction. The second pass
+// tokenizes the line again, which keeps memory low for large sources, since tokenized lines such
+// as data take many times the space of their text.
+// The fields are 32 bits, which is plenty for them, to keep the records small.
+struct LineRecord {
+    line: u32,
+    addr: u32,
+    block: u32,
+    form: Form,
+}
+
+// Form of an instruction that the first pass chose, which depends on what it knew at the time
+#[derive(Clone, Copy, PartialEq)]
+enum Form {
+    AsWritten,
+    LongBranch,
+    ZeroPage,
+}
+
+// Error for a label defined a second time, which names the line of the first definition. Labels
+// without one were defined in the configuration.
+fn repeated_label(
+    label: &str,
+    definitions: &BTreeMap<SymbolId, usize>,
+    symbols: &SymbolTable,
+    lines: &SourceText,
+) -> SasmError {
+    let first = match symbols
+        .get(label)
+        .and_then(|id| definitions.get(&id))
+        .map(|i| lines.location(*i))
+    {
+        Some(Location {
+            file: Some(f),
+            line,
+        }) => format!("at {f}:{line}"),
+        Some(Location { file: None, line }) => format!("on line {line}"),
+        None => "in the configuration".to_string(),
+    };
+    SasmError::label(&format!("label {label} repeated (first defined {first})"))
+}
+
+// Record an error, passing it to the callback first
+fn report(config: &mut Config, errors: &mut Vec<SasmError>, e: SasmError) {
+    config.notify(Diagnostic::Error(&e));
+    errors.push(e);
+}
+
+// Result of both passes, from which every kind of output is made
+struct Assembly {
+    lines: SourceText,
+    disassembly: Vec<u8>,
+    org_to_code_pos: BTreeMap<u16, usize>,
+
+    // Value of each label, including those defined in the configuration
+    labels: BTreeMap<SymbolId, UInt>,
+
+    // Line number, address, and size of each line that generates code
+    line_map: Vec<(usize, usize, usize)>,
+
+    // Address and range of code positions of every line, for listings and statistics, and empty
+    // otherwise
+    line_code: Vec<(usize, core::ops::Range<usize>)>,
+
+    // Fewest cycles of every line, and whether it may take more, for statistics, and empty otherwise
+    line_cycles: Vec<(usize, bool)>,
+
+    // References to labels in other files, for object files
+    refs: Vec<(u16, RefKind, SymbolId)>,
+
+    // Line (as an index) that defines each label of the source, and the line and address of each
+    // reference to each label, for the cross-reference
+    definitions: BTreeMap<SymbolId, usize>,
+    uses: BTreeMap<SymbolId, Vec<(usize, usize)>>,
+
+    // Names of the labels in labels, definitions, and uses
+    symbols: SymbolTable,
+
+    // Addresses of operands that hold code marker addresses, for relocatable output
+    fixups: Vec<u16>,
+
+    stretches: Vec<Stretch>,
+
+    // Whether the program has ROM banks, so that its image is the banks one after another
+    banked: bool,
+
+    // Entry address given by the end directive
+    entry: Option<u16>,
+
+    // Tests for "sasm test"
+    tests: Vec<TestCase>,
+
+    // Line (as an index), address, and bytes of each patch directive
+    patches: Vec<(usize, u16, Vec<u8>)>,
+}
+
+// Test directive with its address and line (as an index), and the line and check of each of the
+// asserts after it
+struct TestCase {
+    name: String,
+    addr: u16,
+    line: usize,
+    asserts: Vec<(usize, Assertion)>,
+}
+
+// Assemble the input, collecting errors across the whole file rather than stopping at the first
+// one. Each error carries its location and source line.
+//
+// With --long-branches, branches that cannot reach their targets become long ones, which moves the
+// code after them, so the input is assembled again until every branch fits. Errors and warnings
+// are held back from the callback until the last attempt, which is the only one that counts.
+fn assemble_code(config: &mut Config) -> Result<Assembly, Vec<SasmError>> {
+    if !config.long_branches {
+        return assemble_attempt(config, &mut BTreeSet::new());
+    }
+
+    enum Found {
+        Error(SasmError),
+        Warning(SasmWarning),
+    }
+    let callback = config.on_diagnostic.take();
+    let found = alloc::rc::Rc::new(core::cell::RefCell::new(Vec::new()));
+    let sink = found.clone();
+    config.on_diagnostic = Some(Box::new(move |d| {
+        sink.borrow_mut().push(match d {
+            Diagnostic::Error(e) => Found::Error(e.clone()),
+            Diagnostic::Warning(w) => Found::Warning(w.clone()),
+        })
+    }));
+
+    let mut long_branches = BTreeSet::new();
+    let result = loop {
+        let num_long = long_branches.len();
+        found.borrow_mut().clear();
+        let result = assemble_attempt(config, &mut long_branches);
+        if long_branches.len() == num_long {
+            break result;
+        }
+        log::info!(
+            "{} branch(es) too far, assembling again",
+            long_branches.len() - num_long
+        );
+        config.reset();
+    };
+
+    config.on_diagnostic = callback;
+    for f in found.take() {
+        match f {
+            Found::Error(e) => config.notify(Diagnostic::Error(&e)),
+            Found::Warning(w) => config.notify(Diagnostic::Warning(&w)),
         }
     }
+    result
+}
 
-    #[test]
-    fn hex3_to_u16() {
-        match hex_to_uint("D80") {
-            Ok(UInt::U16(i)) => assert_eq!(i, 3456),
-            _ => panic!("Unable to convert 2-byte hex to int"),
+// One attempt at assembling the input. Branches on the lines in long_branches are long, and each
+// branch that turns out to be too far is added to them.
+fn assemble_attempt(
+    config: &mut Config,
+    long_branches: &mut BTreeSet<usize>,
+) -> Result<Assembly, Vec<SasmError>> {
+    // Name and number of lines of each input file, in order
+    let mut input_files = Vec::new();
+
+    // The ISA file is cloned, since errors are reported through the whole configuration
+    let isa_table = config.isa.clone();
+    let isa = Isa {
+        illegal: config.illegal,
+        table: isa_table.as_ref(),
+    };
+
+    let mut assembly = String::new();
+    for (name, bytes) in read_input(config).map_err(|e| vec![e])? {
+        let s = match (String::from_utf8(bytes), &name) {
+            (Ok(s), _) => s,
+            (Err(_), Some(f)) => {
+                let message = format!("Input file {f} is not valid text");
+                return Err(vec![SasmError::io(&message)]);
+            }
+            (Err(_), None) => return Err(vec![SasmError::io("Input is not valid text")]),
+        };
+        match name {
+            Some(f) => {
+                // Rebuild the text line by line, so that every file starts on a new line
+                for line in s.lines() {
+                    assembly.push_str(line);
+                    assembly.push('\n');
+                }
+                input_files.push((f, s.lines().count()));
+            }
+            // Input that is not from files is a single part, which is kept as it is
+            None if assembly.is_empty() => assembly = s,
+            None => assembly.push_str(&s),
         }
     }
 
-    #[test]
-    fn hex4_to_u16() {
-        match hex_to_uint("AD80") {
-            Ok(UInt::U16(i)) => assert_eq!(i, 44416),
-            _ => panic!("Unable to convert 2-byte hex to int"),
+    // Main data structures
+    // Record of each source line that the second pass assembles, with the code address at its start
+    let mut source: Vec<LineRecord> = Vec::new();
+
+    // Errors found so far
+    let mut errors = Vec::new();
+
+    // Label names, which the other tables refer to by number
+    let mut symbols = SymbolTable::new();
+
+    // Map of labels to value, starting with labels defined in the configuration
+    let mut labels: BTreeMap<SymbolId, UInt> = config
+        .defines
+        .iter()
+        .map(|(name, value)| (symbols.intern(name), *value))
+        .collect();
+
+    // Line (as an index) that defines each label of the source
+    let mut definitions: BTreeMap<SymbolId, usize> = BTreeMap::new();
+
+    // Zero-page ranges reserved in the configuration, which apply to every zbyte
+    for (start, end) in config.zp_reserved.clone() {
+        if let Err(e) = config.zpm.reserve(start, end) {
+            return Err(vec![SasmError::range(e)]);
         }
     }
 
-    #[test]
-    fn non_hex_to_err() {
-        let e = hex_to_uint("John");
-        assert!(e.is_err());
+    // Variables in main memory, if the system has RAM for them
+    let mut ram = Ram::new(config.zpm.system());
+
+    // Address and size of each zbyte that has not been freed, and the names of those that are local
+    let mut zbytes: BTreeMap<SymbolId, (u8, u8)> = BTreeMap::new();
+    let mut local_zbytes: Vec<SymbolId> = Vec::new();
+
+    // Label of the second byte of each zword, such as "ptr+1" for "ptr"
+    let mut zword_high: BTreeMap<SymbolId, SymbolId> = BTreeMap::new();
+
+    // Blocks of code in all segments. Code starts at a default org of 0000, so an org statement
+    // is not required before code, although most programs should have one. (One exception is code
+    // for testing SASM itself.) If an org statement appears before any code, it replaces the
+    // default.
+    let mut blocks = vec![Block {
+        segment: Segment::Code,
+        addr: 0,
+        len: 0,
+        floating: false,
+        bank: None,
+    }];
+
+    // Number, origin, and size of each ROM bank, in order of appearance
+    let mut banks: Vec<(u16, usize, usize)> = Vec::new();
+
+    // Current segment, and the current block of each segment
+    let mut segment = Segment::Code;
+    let mut current_block = BTreeMap::from([(Segment::Code, 0)]);
+
+    // Labels of code markers in floating blocks, which are relative until the blocks are placed
+    let mut floating_labels = Vec::new();
+
+    // Labels of code markers, which are the only labels that move with relocatable code
+    let mut code_labels = BTreeSet::new();
+
+    // Block and position within it of the embedded checksum, and its type, if any
+    let mut checksum_pos = None;
+
+    // Line and operand of the end directive, if any
+    let mut end_op = None;
+
+    // Names of the tests so far
+    let mut test_names = BTreeSet::new();
+
+    let lines = SourceText::new(assembly, &input_files);
+    log::info!("First pass: {} lines", lines.len());
+
+    // First parser loop. Tokenizes source lines and collects labels.
+    // Lines with errors are left out of the records, so that parsing can continue and the second
+    // loop does not report the same error again. So are blank lines, which generate nothing.
+    for (line_idx, line) in lines.iter().enumerate() {
+        let block = current_block[&segment];
+        let code_addr = blocks[block].addr + blocks[block].len;
+        let tokenized_line = match tokenize(line, &config.comment_markers) {
+            Ok(t) => t,
+            Err(e) => {
+                report(config, &mut errors, e.at(&lines.location(line_idx), line));
+                continue;
+            }
+        };
+        let line_addr = code_addr;
+        let (tokenized_line, form) = match tokenized_line {
+            SourceLine::Instr(m, op, offset) if long_branches.contains(&line_idx) => {
+                (SourceLine::LongBranch(m, op, offset), Form::LongBranch)
+            }
+            SourceLine::Instr(m, op, offset) if config.zp_optimize => {
+                zero_page_instr(m, op, offset, &labels, &symbols, &code_labels, isa)
+            }
+            t => (t, Form::AsWritten),
+        };
+
+        let result = match tokenized_line {
+            SourceLine::Blank => Ok(()),
+            SourceLine::Test(ref name) => match test_names.insert(name.clone()) {
+                true => Ok(()),
+                false => Err(SasmError::syntax(&format!("test {name} repeated")).in_word(1)),
+            },
+            SourceLine::Assert(..) if test_names.is_empty() => {
+                Err(SasmError::syntax("assert must follow a test directive"))
+            }
+            SourceLine::Assert(..) | SourceLine::Patch(..) => Ok(()),
+            SourceLine::End(ref op) => match end_op {
+                Some(_) => Err(SasmError::syntax("only one end directive is allowed")),
+                None => {
+                    end_op = Some((line_idx, op.clone()));
+                    Ok(())
+                }
+            },
+            SourceLine::Org(o, backwards) => {
+                if !backwards && !blocks[block].floating && (o as usize) < code_addr {
+                    Err(SasmError::range(
+                        "org smaller than code address (use org! for a separate block)",
+                    )
+                    .in_word(1))
+                } else {
+                    // An org stays in the bank it is in
+                    let bank = blocks[block].bank;
+                    let block =
+                        org_block(&mut blocks, &mut current_block, &floating_labels, segment);
+                    blocks[block].addr = o as usize;
+                    blocks[block].floating = false;
+                    blocks[block].bank = bank;
+                    Ok(())
+                }
+            }
+            SourceLine::Bank(n, origin, size) => {
+                if banks.iter().any(|b| b.0 == n) {
+                    Err(SasmError::range("bank repeated").in_word(1))
+                } else if origin as usize + size as usize > 0x10000 {
+                    Err(SasmError::range(&format!("bank {n:x} extends past ffff")).in_word(5))
+                } else {
+                    banks.push((n, origin as usize, size as usize));
+                    let block =
+                        org_block(&mut blocks, &mut current_block, &floating_labels, segment);
+                    blocks[block].addr = origin as usize;
+                    blocks[block].floating = false;
+                    blocks[block].bank = Some(banks.len() - 1);
+                    Ok(())
+                }
+            }
+            SourceLine::Segment(s) => {
+                segment = s;
+                current_block.entry(s).or_insert_with(|| {
+                    blocks.push(Block {
+                        segment: s,
+                        addr: 0,
+                        len: 0,
+                        floating: true,
+                        bank: None,
+                    });
+                    blocks.len() - 1
+                });
+                Ok(())
+            }
+            SourceLine::Label(ref s, u) => {
+                let id = symbols.intern(s);
+                match labels.entry(id) {
+                    Entry::Occupied(_) => {
+                        Err(repeated_label(s, &definitions, &symbols, &lines).in_word(1))
+                    }
+                    Entry::Vacant(v) => {
+                        v.insert(u);
+                        definitions.insert(id, line_idx);
+                        Ok(())
+                    }
+                }
+            }
+            SourceLine::ZByte(ref s, size, align, scope) => {
+                let id = symbols.intern(s);
+                match labels.entry(id) {
+                    Entry::Occupied(_) => {
+                        Err(repeated_label(s, &definitions, &symbols, &lines).in_word(1))
+                    }
+                    Entry::Vacant(v) => match config.zpm.alloc_aligned(size, align) {
+                        Ok(addr) => {
+                            log::debug!("zbyte {s}: {size} byte(s) allocated at {addr:02x}");
+                            v.insert(UInt::U8(addr));
+                            definitions.insert(id, line_idx);
+                            zbytes.insert(id, (addr, size));
+                            if scope == Scope::Local {
+                                local_zbytes.push(id);
+                            }
+                            Ok(())
+                        }
+                        Err(e) => Err(zp_error(e, &lines.location(line_idx), size)),
+                    },
+                }
+            }
+            // A pointer pair, with a label for each byte
+            SourceLine::ZWord(ref s, scope) => {
+                let id = symbols.intern(s);
+                let high = symbols.intern(&format!("{s}+1"));
+                let repeated = [id, high]
+                    .into_iter()
+                    .find(|l| labels.contains_key(l))
+                    .map(|l| repeated_label(symbols.name(l), &definitions, &symbols, &lines));
+                if let Some(e) = repeated {
+                    Err(e.in_word(1))
+                } else {
+                    match config.zpm.alloc(2) {
+                        Ok(addr) => {
+                            log::debug!("zword {s}: allocated at {addr:02x}");
+                            labels.insert(id, UInt::U8(addr));
+                            labels.insert(high, UInt::U8(addr + 1));
+                            definitions.insert(id, line_idx);
+                            definitions.insert(high, line_idx);
+                            zword_high.insert(id, high);
+                            zbytes.insert(id, (addr, 2));
+                            if scope == Scope::Local {
+                                local_zbytes.push(id);
+                            }
+                            Ok(())
+                        }
+                        Err(e) => Err(zp_error(e, &lines.location(line_idx), 2)),
+                    }
+                }
+            }
+            SourceLine::ZFree(ref s) => {
+                let id = symbols.get(s);
+                match id.and_then(|id| zbytes.remove(&id)) {
+                    Some((addr, size)) => {
+                        log::debug!("zbyte {s}: {size} byte(s) freed at {addr:02x}");
+                        config.zpm.free(addr, size);
+                        local_zbytes.retain(|l| Some(*l) != id);
+                        Ok(())
+                    }
+                    None => Err(SasmError::label("not an allocated zbyte").in_word(1)),
+                }
+            }
+            SourceLine::Var(ref s, size) => {
+                let id = symbols.intern(s);
+                match labels.entry(id) {
+                    Entry::Occupied(_) => {
+                        Err(repeated_label(s, &definitions, &symbols, &lines).in_word(1))
+                    }
+                    Entry::Vacant(v) => match ram.as_mut().map(|r| r.alloc(size)) {
+                        Some(Ok(addr)) => {
+                            log::debug!("var {s}: {size:x} byte(s) allocated at {addr:04x}");
+                            v.insert(UInt::U16(addr));
+                            definitions.insert(id, line_idx);
+                            Ok(())
+                        }
+                        Some(Err(e)) => Err(SasmError::range(e).in_word(0)),
+                        None => {
+                            Err(SasmError::range("this system has no RAM for variables").in_word(0))
+                        }
+                    },
+                }
+            }
+            SourceLine::ZpReserve(start, end) => config
+                .zpm
+                .reserve(start, end)
+                .map_err(|e| SasmError::range(e).in_word(0)),
+            SourceLine::Data(ref items) => {
+                // Assume labels are two-byte values, which is verified later in the second loop.
+                blocks[block].len += items.iter().map(|(_, d)| d.size()).sum::<usize>();
+                Ok(())
+            }
+            SourceLine::Checksum(_) if segment == Segment::Bss => {
+                Err(SasmError::syntax("bss segment cannot contain a checksum"))
+            }
+            SourceLine::Checksum(_) if config.emit_obj => Err(SasmError::syntax(
+                "checksum directive is not supported in object files",
+            )),
+            SourceLine::Checksum(c) => {
+                if checksum_pos.is_some() {
+                    Err(SasmError::syntax("only one checksum directive is allowed"))
+                } else {
+                    checksum_pos = Some((block, blocks[block].len, c));
+                    blocks[block].len += c.size();
+                    Ok(())
+                }
+            }
+            SourceLine::CodeMarker(ref s) => {
+                // Local zbytes are only used up to the next code marker
+                for l in local_zbytes.drain(..) {
+                    if let Some((addr, size)) = zbytes.remove(&l) {
+                        let l = symbols.name(l);
+                        log::debug!("zbyte {l}: {size} byte(s) freed at {addr:02x}");
+                        config.zpm.free(addr, size);
+                    }
+                }
+                let id = symbols.intern(s);
+                match labels.entry(id) {
+                    Entry::Occupied(_) => {
+                        Err(repeated_label(s, &definitions, &symbols, &lines).in_word(0))
+                    }
+                    Entry::Vacant(v) => {
+                        v.insert(UInt::U16(code_addr as u16));
+                        definitions.insert(id, line_idx);
+                        code_labels.insert(id);
+                        if blocks[block].floating {
+                            floating_labels.push((id, block));
+                        }
+                        Ok(())
+                    }
+                }
+            }
+            SourceLine::Instr(..) | SourceLine::LongBranch(..) if segment == Segment::Bss => {
+                Err(SasmError::syntax("bss segment cannot contain instructions").in_word(0))
+            }
+            SourceLine::LongBranch(..) => {
+                blocks[block].len += 5;
+                Ok(())
+            }
+            SourceLine::Instr(ref mnemonic, _, _) => match get_instr_size(mnemonic, isa) {
+                Ok(size) => {
+                    blocks[block].len += size as usize;
+                    Ok(())
+                }
+                Err(e) => Err(SasmError::syntax(e).in_word(0)),
+            },
+        };
+
+        // Data in the bss segment only takes up addresses
+        let tokenized_line = match tokenized_line {
+            SourceLine::Data(_) if segment == Segment::Bss => SourceLine::Blank,
+            t => t,
+        };
+
+        // Record the lines that the next loop assembles, which it finds again by line number
+        match result {
+            Ok(()) if matches!(tokenized_line, SourceLine::Blank) => (),
+            Ok(()) => source.push(LineRecord {
+                line: line_idx as u32,
+                addr: line_addr as u32,
+                block: block as u32,
+                form,
+            }),
+            Err(e) => report(config, &mut errors, e.at(&lines.location(line_idx), line)),
+        }
+    }
+
+    // Place the segments without an org after the segment before them, in the order code, data,
+    // and bss. Addresses in these blocks were relative to the start of the block until now.
+    let mut end = 0;
+    for s in [Segment::Code, Segment::Data, Segment::Bss] {
+        for b in blocks.iter_mut().filter(|b| b.segment == s) {
+            if b.floating {
+                b.addr = end;
+            }
+        }
+        if let Some(e) = blocks
+            .iter()
+            .filter(|b| b.segment == s)
+            .map(|b| b.addr + b.len)
+            .max()
+        {
+            end = e;
+        }
+    }
+    // A label past ffff is in a segment that extends past ffff, which is reported below, unless
+    // it is just after a segment that ends at ffff
+    for (id, block) in floating_labels {
+        let b = &blocks[block];
+        if let Some(UInt::U16(u)) = labels.get_mut(&id) {
+            match *u as usize + b.addr {
+                addr if addr > 0xffff && b.addr + b.len <= 0x10000 => {
+                    let message = format!("label {} at {addr:x} is past ffff", symbols.name(id));
+                    report(config, &mut errors, SasmError::range(&message));
+                }
+                addr if addr > 0xffff => (),
+                addr => *u = addr as u16,
+            }
+        }
+    }
+    for r in source.iter_mut() {
+        let b = &blocks[r.block as usize];
+        if b.floating {
+            r.addr += b.addr as u32;
+        }
+    }
+
+    // Entry address from the end directive, now that every label is known
+    let entry = match end_op {
+        Some((_, Op::UInt(UInt::U16(u)))) => Some(u),
+        Some((line_idx, Op::Label(l))) => match label_value(&l, &labels, &symbols) {
+            Some(UInt::U16(u)) => Some(u),
+            found => {
+                let e = match found {
+                    Some(_) => SasmError::label("end must be a 2-byte address"),
+                    None => SasmError::label(&format!("label {l} not defined")),
+                };
+                let e = e.in_word(1).at(&lines.location(line_idx), &lines[line_idx]);
+                report(config, &mut errors, e);
+                None
+            }
+        },
+        _ => None,
+    };
+
+    // Blocks in order of bank and then address, where no two in the same bank may overlap
+    let bank_number = |b: &Block| b.bank.map(|i| banks[i].0);
+    let mut placed: Vec<usize> = (0..blocks.len()).collect();
+    placed.sort_by_key(|b| (bank_number(&blocks[*b]), blocks[*b].addr, blocks[*b].len));
+    let mut end = 0;
+    let mut bank = None;
+    for b in placed.iter().map(|b| &blocks[*b]).filter(|b| b.len > 0) {
+        if b.bank != bank {
+            end = 0;
+            bank = b.bank;
+        }
+        if b.addr < end {
+            let message = format!("segments overlap at {:04x}", b.addr);
+            report(config, &mut errors, SasmError::range(&message));
+        }
+        end = end.max(b.addr + b.len);
+    }
+
+    // In a program with banks, every byte that is output must be inside a bank. The image has the
+    // banks in order of number, so the numbers must start at 0 and have no gaps.
+    let banked = !banks.is_empty();
+    for b in blocks
+        .iter()
+        .filter(|b| banked && b.len > 0 && b.segment != Segment::Bss)
+    {
+        let message = match b.bank.map(|i| banks[i]) {
+            None => format!("code at {:04x} is outside every bank", b.addr),
+            Some((n, origin, size)) if b.addr < origin || b.addr + b.len > origin + size => {
+                format!(
+                    "code at {:04x}-{:04x} is outside bank {n:x} ({:04x}-{:04x})",
+                    b.addr,
+                    b.addr + b.len - 1,
+                    origin,
+                    origin + size - 1
+                )
+            }
+            Some(_) => continue,
+        };
+        report(config, &mut errors, SasmError::range(&message));
+    }
+    let mut bank_numbers: Vec<u16> = banks.iter().map(|b| b.0).collect();
+    bank_numbers.sort();
+    if bank_numbers
+        .iter()
+        .enumerate()
+        .any(|(i, n)| *n as usize != i)
+    {
+        let message = "banks must be numbered from 0 without gaps";
+        report(config, &mut errors, SasmError::range(message));
+    }
+
+    // Position of each bank in the image
+    let bank_pos =
+        |i: usize| -> usize { banks.iter().filter(|b| b.0 < banks[i].0).map(|b| b.2).sum() };
+
+    // Addresses are 16 bits, so nothing may extend past ffff
+    for b in blocks.iter().filter(|b| b.addr + b.len > 0x10000) {
+        let message = format!("segment at {:04x} extends past ffff", b.addr);
+        report(config, &mut errors, SasmError::range(&message));
+    }
+
+    // Code position of each block that is output, which excludes the bss segment. The output
+    // has the blocks in order of address.
+    let mut block_pos = vec![None; blocks.len()];
+    let mut org_to_code_pos = BTreeMap::new();
+    let mut code_pos = 0;
+    for b in placed
+        .iter()
+        .filter(|b| blocks[**b].segment != Segment::Bss)
+    {
+        // A bank's blocks are at their offsets from its origin
+        if let Some(i) = blocks[*b].bank {
+            block_pos[*b] = Some(bank_pos(i) + blocks[*b].addr.saturating_sub(banks[i].1));
+            continue;
+        }
+        block_pos[*b] = Some(code_pos);
+        org_to_code_pos.insert(blocks[*b].addr as u16, code_pos);
+        code_pos += blocks[*b].len;
+    }
+
+    // The image of a program with banks is one block, which starts at the origin of bank 0
+    if let Some(b) = banks.iter().find(|b| b.0 == 0) {
+        org_to_code_pos = BTreeMap::from([(b.1 as u16, 0)]);
+    }
+    // The first pass rejects checksums in the bss segment, so their block always has a position
+    let checksum_pos = checksum_pos.and_then(|(b, pos, c)| block_pos[b].map(|p| (p + pos, c)));
+    source.sort_by_key(|r| block_pos[r.block as usize].unwrap_or(usize::MAX));
+
+    log::info!("Second pass: {} labels defined", labels.len());
+
+    // Second parser loop. Stores machine code in "disassembly" vector.
+    let mut disassembly: Vec<u8> = Vec::new();
+    let mut line_warnings = Vec::new();
+
+    // Line number, address, and size of each line that generates code
+    let mut line_map = Vec::new();
+
+    // Address and range of code positions of every line, for listings and the statistics report.
+    // Large sources have millions of lines, so these are only kept when they are needed.
+    let listing = matches!(config.cformat, CodeFormat::Listing) && config.custom_format.is_none();
+    let mut line_code = match listing || config.stats {
+        true => vec![(0, 0..0); lines.len()],
+        false => Vec::new(),
+    };
+
+    // Fewest cycles of every line, and whether it may take more, for the statistics report
+    let mut line_cycles = match config.stats {
+        true => vec![(0, false); lines.len()],
+        false => Vec::new(),
+    };
+
+    // Address, kind, and label of each reference to a label in another file, for object files
+    let mut refs = Vec::new();
+
+    // Address of each two-byte operand that holds the address of a code marker, for relocatable
+    // output
+    let mut fixups = Vec::new();
+
+    // Straight-line stretches of code, which start at each code marker and each block
+    let mut stretches: Vec<Stretch> = Vec::new();
+    let mut last_block = None;
+
+    // Line (as an index) and address of each reference to each label, and the line, kind, and
+    // name of each label that the source declares
+    let mut uses: BTreeMap<SymbolId, Vec<(usize, usize)>> = BTreeMap::new();
+    let mut declared = Vec::new();
+
+    // Tests and their asserts
+    let mut tests: Vec<TestCase> = Vec::new();
+    let mut patches = Vec::new();
+
+    for r in source {
+        let (line_idx, line_addr, block) = (r.line as usize, r.addr as usize, r.block as usize);
+        // The line tokenized in the first pass, so it does again, in the form chosen there
+        let s = match tokenize(&lines[line_idx], &config.comment_markers) {
+            Ok(s) => s,
+            Err(e) => {
+                report(
+                    config,
+                    &mut errors,
+                    e.at(&lines.location(line_idx), &lines[line_idx]),
+                );
+                continue;
+            }
+        };
+        let s = match (r.form, s) {
+            (Form::LongBranch, SourceLine::Instr(m, op, offset)) => {
+                SourceLine::LongBranch(m, op, offset)
+            }
+            (Form::ZeroPage, SourceLine::Instr(m, op, offset)) => {
+                match get_zero_page_form(&m, isa) {
+                    Some(z) => in_zero_page_form(z, op, offset),
+                    None => SourceLine::Instr(m, op, offset),
+                }
+            }
+            (_, s) => s,
+        };
+
+        // Fill the gaps in banks up to the line's position in the image
+        if let Some(p) = block_pos[block].filter(|_| banked) {
+            let pos = p + line_addr.saturating_sub(blocks[block].addr);
+            if disassembly.len() < pos {
+                disassembly.resize(pos, config.fill);
+            }
+        }
+        let line_start = disassembly.len();
+        match &s {
+            SourceLine::CodeMarker(l) => stretches.push(Stretch::new(Some(l), line_addr)),
+            _ if last_block != Some(block) => stretches.push(Stretch::new(None, line_addr)),
+            _ => (),
+        }
+        last_block = Some(block);
+        for l in s.label_refs() {
+            let refs = uses.entry(symbols.intern(l)).or_default();
+            if refs.last().map(|r| r.0) != Some(line_idx) {
+                refs.push((line_idx, line_addr));
+            }
+        }
+        match &s {
+            SourceLine::Label(l, _) => declared.push((line_idx, "label", symbols.intern(l))),
+            SourceLine::CodeMarker(l) => declared.push((line_idx, "label", symbols.intern(l))),
+            SourceLine::ZByte(l, ..) => declared.push((line_idx, "zbyte", symbols.intern(l))),
+            SourceLine::ZWord(l, _) => declared.push((line_idx, "zword", symbols.intern(l))),
+            SourceLine::Var(l, _) => declared.push((line_idx, "var", symbols.intern(l))),
+            _ => (),
+        }
+        // Tests start at the address of their lines. Labels in asserts are known by now.
+        match &s {
+            SourceLine::Patch(addr, bytes) => patches.push((line_idx, *addr, bytes.clone())),
+            SourceLine::Test(name) => tests.push(TestCase {
+                name: name.clone(),
+                addr: line_addr as u16,
+                line: line_idx,
+                asserts: Vec::new(),
+            }),
+            SourceLine::Assert(checked, value, equal) => {
+                let target = match checked {
+                    Checked::Register('a') => Ok(Target::A),
+                    Checked::Register('x') => Ok(Target::X),
+                    Checked::Register('y') => Ok(Target::Y),
+                    Checked::Register('s') => Ok(Target::S),
+                    Checked::Register(_) => Ok(Target::P),
+                    Checked::Memory(Op::UInt(UInt::U8(u))) => Ok(Target::Memory(*u as u16)),
+                    Checked::Memory(Op::UInt(UInt::U16(u))) => Ok(Target::Memory(*u)),
+                    Checked::Memory(op) => {
+                        let l = op.label().unwrap_or_default();
+                        match label_value(l, &labels, &symbols) {
+                            Some(UInt::U8(u)) => Ok(Target::Memory(u as u16)),
+                            Some(UInt::U16(u)) => Ok(Target::Memory(u)),
+                            None => Err(SasmError::label(&format!("label {l} not defined"))),
+                        }
+                    }
+                };
+                match (target, tests.last_mut()) {
+                    (Ok(target), Some(t)) => t.asserts.push((
+                        line_idx,
+                        Assertion {
+                            target,
+                            value: *value,
+                            equal: *equal,
+                        },
+                    )),
+                    (Ok(_), None) => (),
+                    (Err(e), _) => {
+                        let e = e.in_word(2).at(&lines.location(line_idx), &lines[line_idx]);
+                        report(config, &mut errors, e);
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        let timing = match &s {
+            SourceLine::Instr(m, _, _) => get_instr_info(m, isa).ok(),
+            _ => None,
+        };
+
+        // Labels in the line whose two-byte values can be referred to or fixed up, and where each
+        // value would be in the line's bytes
+        let is_instr = matches!(s, SourceLine::Instr(..) | SourceLine::LongBranch(..));
+        let line_labels: Vec<(SymbolId, usize)> = match &s {
+            SourceLine::Instr(_, Op::Label(l), _) => vec![(symbols.intern(l), 1)],
+            SourceLine::LongBranch(_, Op::Label(l), _) => vec![(symbols.intern(l), 3)],
+            SourceLine::Data(items) => {
+                let mut pos = 0;
+                let mut found = Vec::new();
+                for (_, d) in items {
+                    if let Rawdata::Label(l, Layout::Word) = d {
+                        found.push((symbols.intern(data_label(l, &labels, &symbols).0), pos));
+                    }
+                    pos += d.size();
+                }
+                found
+            }
+            _ => Vec::new(),
+        };
+
+        // An object file may refer to labels that another file defines. Assemble the line with a
+        // placeholder that leaves the offset (if any) in the operand, and let the linker add the
+        // label's value.
+        let externals: Vec<(SymbolId, usize)> = line_labels
+            .iter()
+            .copied()
+            .filter(|(l, _)| config.emit_obj && !labels.contains_key(l))
+            .collect();
+        for (l, pos) in &externals {
+            let (kind, placeholder) = match &s {
+                SourceLine::Instr(m, _, _) if is_relative_branch_instruction(m) => {
+                    (RefKind::Rel, UInt::U16((line_addr + 2) as u16))
+                }
+                SourceLine::Instr(m, _, _) if get_instr_size(m, isa) == Ok(2) => {
+                    (RefKind::Zp, UInt::U8(0))
+                }
+                _ => (RefKind::Abs, UInt::U16(0)),
+            };
+            labels.insert(*l, placeholder);
+            refs.push(((line_addr + pos) as u16, kind, *l));
+        }
+
+        if let Err(e) = assemble_line(
+            s,
+            line_addr,
+            &labels,
+            &symbols,
+            isa,
+            &mut disassembly,
+            &mut line_warnings,
+        ) {
+            if config.long_branches && e.message == BRANCH_TOO_FAR {
+                long_branches.insert(line_idx);
+            } else {
+                report(
+                    config,
+                    &mut errors,
+                    e.at(&lines.location(line_idx), &lines[line_idx]),
+                );
+            }
+        }
+        for (l, _) in &externals {
+            labels.remove(l);
+        }
+
+        // Code markers' addresses. Relative branches have a single-byte operand, which needs no
+        // fixup.
+        for (l, pos) in &line_labels {
+            if code_labels.contains(l) && (!is_instr || disassembly.len() - line_start == pos + 2) {
+                fixups.push((line_addr + pos) as u16);
+            }
+        }
+        for w in line_warnings.drain(..) {
+            config.warn(w.at(&lines.location(line_idx), &lines[line_idx]));
+        }
+        if disassembly.len() > line_start {
+            line_map.push((line_idx + 1, line_addr, disassembly.len() - line_start));
+        }
+        if let Some(c) = line_code.get_mut(line_idx) {
+            *c = (line_addr, line_start..disassembly.len());
+        }
+        if let (Some(c), Some(i)) = (line_cycles.get_mut(line_idx), timing) {
+            *c = (i.cycles as usize, i.page_penalty);
+        }
+        if let Some(st) = stretches.last_mut() {
+            st.bytes += disassembly.len() - line_start;
+            if let Some(i) = timing {
+                st.cycles += i.cycles as usize;
+                st.more |= i.page_penalty;
+            }
+        }
+    }
+    stretches.retain(|s| s.bytes > 0);
+
+    // Declarations that nothing refers to. The labels of object files are exported for other files
+    // to use, so they are never reported.
+    if !config.emit_obj {
+        for (line_idx, kind, id) in declared {
+            let high = zword_high.get(&id);
+            if uses.contains_key(&id) || high.is_some_and(|h| uses.contains_key(h)) {
+                continue;
+            }
+            let l = symbols.name(id);
+            let word = match lines[line_idx].trim_start().starts_with('.') {
+                true => 0,
+                false => 1,
+            };
+            let w = SasmWarning::new(
+                WarningKind::UnusedLabels,
+                &format!("{kind} {l} is never used"),
+            )
+            .in_word(word);
+            config.warn(w.at(&lines.location(line_idx), &lines[line_idx]));
+        }
+    }
+
+    // Every bank is filled to its size
+    let image_size: usize = banks.iter().map(|b| b.2).sum();
+    if disassembly.len() < image_size {
+        disassembly.resize(image_size, config.fill);
+    }
+
+    // Warnings stay in the config for the caller to report, unless they are treated as errors
+    if config.warnings.as_errors {
+        errors.extend(config.warnings.list.iter().map(SasmError::from_warning));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    log::info!(
+        "Assembled {} bytes in {} org block(s)",
+        disassembly.len(),
+        org_to_code_pos.len()
+    );
+
+    // Fill in the embedded checksum. It is computed over the whole image (including filler bytes)
+    // while its own bytes are still zero.
+    if let Some((pos, c)) = checksum_pos {
+        let value = c.compute(&binary_image(&disassembly, &org_to_code_pos, config.fill));
+        disassembly.splice(pos..pos + c.size(), c.to_le_bytes(value));
+    }
+
+    return Ok(Assembly {
+        lines,
+        disassembly,
+        org_to_code_pos,
+        labels,
+        line_map,
+        line_code,
+        line_cycles,
+        refs,
+        definitions,
+        uses,
+        symbols,
+        fixups,
+        stretches,
+        banked,
+        entry,
+        tests,
+        patches,
+    });
+}
+
+// Settings for assemble_str. Everything else is as for the command line's defaults.
+pub struct AsmOptions {
+    // System name, as for the --system flag
+    pub system: String,
+    pub illegal: bool,
+
+    // Filler byte for gaps between orgs in the image
+    pub fill: u8,
+}
+
+impl Default for AsmOptions {
+    fn default() -> Self {
+        AsmOptions {
+            system: "apple".to_string(),
+            illegal: false,
+            fill: 0xff,
+        }
+    }
+}
+
+// Straight-line stretch of code from a code marker (or an org) to the next one, for checking
+// timing budgets without an emulator
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stretch {
+    pub label: Option<String>,
+    pub addr: u16,
+    pub bytes: usize,
+
+    // Cycles if every instruction takes its fewest, and whether any may take more, such as when
+    // crossing a page or taking a branch
+    pub cycles: usize,
+    pub more: bool,
+}
+
+impl Stretch {
+    fn new(label: Option<&String>, addr: usize) -> Self {
+        Stretch {
+            label: label.cloned(),
+            addr: addr as u16,
+            bytes: 0,
+            cycles: 0,
+            more: false,
+        }
+    }
+}
+
+impl core::fmt::Display for Stretch {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let label = match &self.label {
+            Some(l) => format!(".{l}"),
+            None => "(org)".to_string(),
+        };
+        let plus = if self.more { "+" } else { "" };
+        let cycles = format!("{}{plus}", self.cycles);
+        write!(
+            f,
+            "{label:16} {:04x} {:5} bytes {cycles:>6} cycles",
+            self.addr, self.bytes
+        )
+    }
+}
+
+// Code assembled by assemble_str
+#[derive(Debug, PartialEq)]
+pub struct Assembled {
+    // Memory image from the first org to the end of the code, with filler bytes between orgs
+    pub bytes: Vec<u8>,
+
+    // Address and bytes of each org block
+    pub blocks: Vec<(u16, Vec<u8>)>,
+
+    // Value of every label, zbyte, and code marker
+    pub symbols: BTreeMap<String, u16>,
+
+    // Bytes and cycles between code markers
+    pub stretches: Vec<Stretch>,
+
+    pub warnings: Vec<SasmWarning>,
+}
+
+impl Assembled {
+    // Bytes of the org block at an address
+    pub fn block(&self, addr: u16) -> Option<&[u8]> {
+        self.blocks
+            .iter()
+            .find(|(a, _)| *a == addr)
+            .map(|(_, b)| b.as_slice())
+    }
+
+    // Value of a label, zbyte, or code marker
+    pub fn symbol(&self, name: &str) -> Option<u16> {
+        self.symbols.get(name).copied()
+    }
+}
+
+// Assembler that keeps its settings from one source to the next, for build servers and editors
+// that assemble many of them. Like assemble_str, it never reads or writes files or prints anything.
+pub struct Assembler {
+    config: Config,
+}
+
+impl Assembler {
+    pub fn new(options: &AsmOptions) -> Result<Self, SasmError> {
+        let mut config = Config::build_string_test("");
+        config.zpm = Zpm::new(&options.system, None).map_err(SasmError::syntax)?;
+        config.illegal = options.illegal;
+        config.fill = options.fill;
+        Ok(Assembler { config })
+    }
+
+    // Define a label for every later source, as with the --define flag. The value is in hex.
+    pub fn define(&mut self, name: &str, value: &str) -> Result<(), SasmError> {
+        let value = hex_to_uint(value)?;
+        self.config.defines.retain(|(n, _)| n != name);
+        self.config.defines.push((name.to_string(), value));
+        Ok(())
+    }
+
+    // Call a function with each error and warning as soon as it is found (see Config)
+    pub fn on_diagnostic(&mut self, f: impl FnMut(Diagnostic) + 'static) {
+        self.config.on_diagnostic = Some(Box::new(f));
+    }
+
+    // Assemble one source. Zero page and warnings start over each time. Only the first error is
+    // returned.
+    pub fn assemble(&mut self, source: &str) -> Result<Assembled, SasmError> {
+        self.config.reset();
+        self.config.itype = IType::String(source.to_string());
+
+        let a = assemble_code(&mut self.config).map_err(|mut errors| errors.remove(0))?;
+        let symbols = a
+            .labels
+            .iter()
+            .map(|(id, value)| match value {
+                UInt::U8(u) => (a.symbols.name(*id).to_string(), *u as u16),
+                UInt::U16(u) => (a.symbols.name(*id).to_string(), *u),
+            })
+            .collect();
+        return Ok(Assembled {
+            bytes: binary_image(&a.disassembly, &a.org_to_code_pos, self.config.fill),
+            blocks: org_blocks(&a.disassembly, &a.org_to_code_pos),
+            symbols,
+            stretches: a.stretches,
+            warnings: core::mem::take(&mut self.config.warnings.list),
+        });
+    }
+}
+
+// Assemble source in memory, for programs that use SASM2 as a library. Nothing is read or written,
+// and nothing is printed. Only the first error is returned.
+pub fn assemble_str(source: &str, options: &AsmOptions) -> Result<Assembled, SasmError> {
+    Assembler::new(options)?.assemble(source)
+}
+
+// Assemble the input and write the output in the configured format, or link object files if the
+// configuration says so
+pub fn assemble(config: &mut Config) -> Result<Code, Vec<SasmError>> {
+    assemble_into(config, None)
+}
+
+// Assemble the input like assemble, but write the output to out instead of to the output of the
+// configuration, and without returning it. The hex, binary, and listing formats are written a
+// piece at a time as they are made, so that large outputs are never held in memory whole.
+pub fn assemble_to(config: &mut Config, out: &mut dyn OutputWriter) -> Result<(), Vec<SasmError>> {
+    assemble_into(config, Some(out)).map(|_| ())
+}
+
+// Assemble the input and write the output to out, or else to the output of the configuration.
+// Output written to out is not returned.
+fn assemble_into(
+    config: &mut Config,
+    mut out: Option<&mut dyn OutputWriter>,
+) -> Result<Code, Vec<SasmError>> {
+    if config.link {
+        return link_objects(config, out);
+    }
+
+    let Assembly {
+        lines,
+        disassembly,
+        org_to_code_pos,
+        labels,
+        line_map,
+        line_code,
+        line_cycles,
+        refs,
+        definitions,
+        uses,
+        symbols,
+        fixups,
+        stretches,
+        banked,
+        entry,
+        patches,
+        ..
+    } = assemble_code(config)?;
+
+    // The image of banks only has addresses within each bank
+    if banked
+        && config.custom_format.is_none()
+        && (config.emit_obj
+            || !matches!(
+                config.cformat,
+                CodeFormat::Hex | CodeFormat::Binary | CodeFormat::Listing
+            ))
+    {
+        return Err(vec![SasmError::syntax(
+            "banks require hex, binary, or listing output",
+        )]);
+    }
+
+    if banked && (config.patch.is_some() || !patches.is_empty()) {
+        return Err(vec![SasmError::syntax("banks cannot be patched")]);
+    }
+
+    if config.report == Report::Cycles {
+        report_cycles(&stretches);
+    }
+
+    let (disassembly, org_to_code_pos) = match (&config.patch, patches.is_empty()) {
+        (None, true) => (disassembly, org_to_code_pos),
+        _ => patched_code(config, &lines, &patches, &disassembly, &org_to_code_pos)?,
+    };
+
+    // Location of every line, only for the reports that refer to lines
+    let locations: Vec<Location> = match config.stats
+        || config.source_map.is_some()
+        || config.xref.is_some()
+        || config.dbg_file.is_some()
+    {
+        true => (0..lines.len()).map(|i| lines.location(i)).collect(),
+        false => Vec::new(),
+    };
+
+    if config.stats {
+        let line_stats: Vec<LineStats> = line_code
+            .iter()
+            .zip(&line_cycles)
+            .enumerate()
+            .map(|(i, ((_, range), (cycles, more)))| {
+                (&locations[i], &lines[i], range.len(), *cycles, *more)
+            })
+            .collect();
+        report_stats(&stats(&line_stats));
+    }
+
+    if let Some(c) = config.checksum {
+        let value = c.compute(&binary_image(&disassembly, &org_to_code_pos, config.fill));
+        report_checksum(c, value);
+    }
+
+    // Labels by name, for the outputs that write them
+    let named = match config.emit_obj
+        || matches!(config.cformat, CodeFormat::Json)
+        || config.symfile.is_some()
+    {
+        true => named_labels(&labels, &symbols),
+        false => BTreeMap::new(),
+    };
+
+    // Binary output must be refused before any of it is written
+    check_binary_stdout(config).map_err(|e| vec![e])?;
+
+    // Create and write the final output
+    let streamed = !config.emit_obj
+        && config.custom_format.is_none()
+        && matches!(
+            config.cformat,
+            CodeFormat::Hex | CodeFormat::Binary | CodeFormat::Listing
+        );
+    let code = match config.cformat {
+        _ if streamed => {
+            let mut buf = Vec::new();
+            let written = match out.as_deref_mut() {
+                Some(o) => stream_output(
+                    config,
+                    o,
+                    &lines,
+                    &disassembly,
+                    &org_to_code_pos,
+                    &line_code,
+                ),
+                None => stream_output(
+                    config,
+                    &mut buf,
+                    &lines,
+                    &disassembly,
+                    &org_to_code_pos,
+                    &line_code,
+                ),
+            };
+            written.map_err(|e| vec![e])?;
+            match config.cformat.is_text() {
+                true => match String::from_utf8(buf) {
+                    Ok(s) => Code::String(s),
+                    Err(_) => return Err(vec![SasmError::internal("output is not text")]),
+                },
+                false => Code::Bytes(buf),
+            }
+        }
+        _ if config.emit_obj => {
+            let refs = refs
+                .into_iter()
+                .map(|(addr, kind, l)| (addr, kind, symbols.name(l).to_string()))
+                .collect();
+            Code::String(Object::new(&disassembly, &org_to_code_pos, &named, refs).to_text())
+        }
+        _ if config.custom_format.is_some() => {
+            image_output(config, &disassembly, &org_to_code_pos)?
+        }
+        CodeFormat::Json => json_output(
+            &disassembly,
+            &org_to_code_pos,
+            config.fill,
+            &named,
+            &line_map,
+        ),
+        CodeFormat::Reloc => reloc_output(&disassembly, &org_to_code_pos, config.fill, &fixups),
+        _ => image_output(config, &disassembly, &org_to_code_pos)?,
+    };
+    let code = with_run_command(config, code, entry);
+
+    // Labels are still needed after the second pass for the optional symbol file
+    let code = write_output(config, code, out, &named, &disassembly, &org_to_code_pos)?;
+
+    if let Some(ref f) = config.source_map {
+        let lines: Vec<(usize, usize, &Location)> = line_map
+            .iter()
+            .map(|(line, addr, size)| (*addr, *size, &locations[line - 1]))
+            .collect();
+        if let Err(e) = write_text(&source_map(&lines), f, config.overwrite) {
+            return Err(vec![e]);
+        }
+    }
+
+    if let Some(ref f) = config.dbg_file {
+        // Lines with code have spans, and lines that define labels are there for the symbols
+        let mut line_spans: BTreeMap<usize, Option<(usize, usize)>> = line_map
+            .iter()
+            .map(|(line, addr, size)| (line - 1, Some((*addr, *size))))
+            .collect();
+        for i in definitions.values() {
+            line_spans.entry(*i).or_insert(None);
+        }
+        let line_ids: BTreeMap<usize, usize> = line_spans
+            .keys()
+            .enumerate()
+            .map(|(id, i)| (*i, id))
+            .collect();
+        let dbg_lines: Vec<DebugLine> = line_spans
+            .iter()
+            .map(|(i, span)| (&locations[*i], *span))
+            .collect();
+
+        let mut files: Vec<(String, u64, u64)> = Vec::new();
+        for (location, _) in &dbg_lines {
+            let name = location.file.as_deref().unwrap_or("-");
+            if !files.iter().any(|(f, ..)| f == name) {
+                let (size, mtime) = file_info(name);
+                files.push((name.to_string(), size, mtime));
+            }
+        }
+        let segments: Vec<(u16, usize)> = org_blocks(&disassembly, &org_to_code_pos)
+            .iter()
+            .map(|(addr, bytes)| (*addr, bytes.len()))
+            .collect();
+        let mut syms: Vec<(&str, UInt, Option<usize>)> = labels
+            .iter()
+            .map(|(id, value)| {
+                let def = definitions.get(id).and_then(|i| line_ids.get(i).copied());
+                (symbols.name(*id), *value, def)
+            })
+            .collect();
+        syms.sort_by_key(|(name, ..)| *name);
+
+        let text = debug_info(&files, &segments, &dbg_lines, &syms);
+        if let Err(e) = write_text(&text, f, config.overwrite) {
+            return Err(vec![e]);
+        }
+    }
+
+    if let Some(ref f) = config.xref {
+        let mut ids: Vec<SymbolId> = labels.keys().chain(uses.keys()).copied().collect();
+        ids.sort_by_key(|id| symbols.name(*id));
+        ids.dedup();
+        let entries: Vec<XrefEntry> = ids
+            .into_iter()
+            .map(|id| {
+                let refs = uses.get(&id).map_or(Vec::new(), |r| {
+                    r.iter().map(|(i, addr)| (*addr, &locations[*i])).collect()
+                });
+                let definition = definitions.get(&id).map(|i| &locations[*i]);
+                (symbols.name(id), labels.get(&id).copied(), definition, refs)
+            })
+            .collect();
+        if let Err(e) = write_text(&xref(&entries), f, config.overwrite) {
+            return Err(vec![e]);
+        }
+    }
+
+    return Ok(code);
+}
+
+// Assemble the input and run it on the emulator from the entry address: the --entry flag, the end
+// directive, or else the first org. Nothing is written out.
+pub fn run(config: &mut Config) -> Result<(Cpu, Stop), Vec<SasmError>> {
+    if config.emit_obj || config.link {
+        return Err(vec![SasmError::syntax(
+            "object files cannot run on the emulator",
+        )]);
+    }
+    let Assembly {
+        disassembly,
+        org_to_code_pos,
+        banked,
+        entry,
+        ..
+    } = assemble_code(config)?;
+    let mut cpu = load_program(&disassembly, &org_to_code_pos, banked)?;
+    let first_org = org_to_code_pos.keys().next().copied();
+    let Some(start) = config.entries.first().copied().or(entry).or(first_org) else {
+        return Err(vec![SasmError::syntax("no code to run")]);
+    };
+
+    log::info!("Running from {start:04x}");
+    let stop = cpu.run(start, config.max_cycles);
+    return Ok((cpu, stop));
+}
+
+// Assemble the input and run each of its tests on the emulator, from the address of its test
+// directive with the program just loaded. A test fails if any of its asserts does not hold
+// afterwards, or if it does not stop by itself.
+pub fn test(config: &mut Config) -> Result<Vec<TestResult>, Vec<SasmError>> {
+    if config.emit_obj || config.link {
+        return Err(vec![SasmError::syntax(
+            "object files cannot run on the emulator",
+        )]);
+    }
+    let Assembly {
+        lines,
+        disassembly,
+        org_to_code_pos,
+        banked,
+        tests,
+        ..
+    } = assemble_code(config)?;
+    let program = load_program(&disassembly, &org_to_code_pos, banked)?;
+
+    let mut results = Vec::new();
+    for t in tests {
+        log::info!("Testing {} from {:04x}", t.name, t.addr);
+        let mut cpu = program.clone();
+        let stop = cpu.run(t.addr, config.max_cycles);
+        let mut failures = Vec::new();
+        if let Stop::Invalid(..) | Stop::CycleLimit = stop {
+            let e = SasmError::test(&format!("test did not stop: {stop}"));
+            failures.push(e.at(&lines.location(t.line), &lines[t.line]));
+        }
+        for (line_idx, a) in t.asserts {
+            if let Err(m) = a.check(&cpu) {
+                let e = SasmError::test(&m);
+                failures.push(e.at(&lines.location(line_idx), &lines[line_idx]));
+            }
+        }
+        results.push(TestResult {
+            name: t.name,
+            stop,
+            cycles: cpu.cycles,
+            failures,
+        });
+    }
+    return Ok(results);
+}
+
+// Assemble the input, disassemble the code, and assemble the disassembly again, which must give
+// the same bytes at the same addresses. The number of bytes compared is returned. The first byte
+// that differs is reported at both the source line and the disassembled line that produced it.
+pub fn verify(config: &mut Config) -> Result<usize, Vec<SasmError>> {
+    if config.emit_obj || config.link {
+        return Err(vec![SasmError::syntax("object files cannot be verified")]);
+    }
+    let original = assemble_code(config)?;
+    if original.banked {
+        return Err(vec![SasmError::syntax(
+            "programs with banks cannot be verified",
+        )]);
+    }
+
+    // The disassembler reads the code in the Apple II monitor format, which keeps the address of
+    // each org block. Every region it can decode is disassembled as code, to check as many
+    // instructions as possible.
+    let dump = match bytes_to_output(
+        &original.disassembly,
+        &original.org_to_code_pos,
+        CodeFormat::AppleSM,
+        config.fill,
+    ) {
+        Code::String(s) => s,
+        Code::Bytes(_) => return Err(vec![SasmError::internal("dump is not text")]),
+    };
+    let mut d = Config::build_string_test(&dump);
+    d.min_region_size = 0;
+    d.illegal = config.illegal;
+    d.isa = config.isa.clone();
+    let source = match crate::disassemble::disassemble(&mut d) {
+        Ok(Code::String(s)) => s,
+        Ok(Code::Bytes(_)) => return Err(vec![SasmError::internal("disassembly is not text")]),
+        Err(e) => return Err(vec![e]),
+    };
+
+    let mut r = Config::build_string_test(&source);
+    r.illegal = config.illegal;
+    r.isa = config.isa.clone();
+    let reassembled = match assemble_code(&mut r) {
+        Ok(a) => a,
+        Err(errors) => {
+            let mut errors: Vec<SasmError> = errors
+                .into_iter()
+                .map(|mut e| {
+                    e.message = format!("disassembly does not assemble: {}", e.message);
+                    e.file = Some("disassembly".to_string());
+                    e
+                })
+                .collect();
+            errors.truncate(1);
+            return Err(errors);
+        }
+    };
+    return compare_code(&original, &reassembled);
+}
+
+// Compare the code of the original assembly with that of its reassembled disassembly
+fn compare_code(original: &Assembly, reassembled: &Assembly) -> Result<usize, Vec<SasmError>> {
+    let memory = |a: &Assembly| -> BTreeMap<usize, u8> {
+        org_blocks(&a.disassembly, &a.org_to_code_pos)
+            .into_iter()
+            .flat_map(|(addr, bytes)| (addr as usize..).zip(bytes))
+            .collect()
+    };
+    let before = memory(original);
+    let after = memory(reassembled);
+    let mut addrs: Vec<&usize> = before.keys().chain(after.keys()).collect();
+    addrs.sort();
+    addrs.dedup();
+
+    let Some(addr) = addrs
+        .into_iter()
+        .copied()
+        .find(|a| before.get(a) != after.get(a))
+    else {
+        log::info!("Verified {} bytes", before.len());
+        return Ok(before.len());
+    };
+    let byte = |b: Option<&u8>| b.map_or("nothing".to_string(), |b| format!("{b:02x}"));
+    let message = format!(
+        "reassembled code differs at {addr:04x}: {} became {}",
+        byte(before.get(&addr)),
+        byte(after.get(&addr))
+    );
+
+    // Line of the source that produced the byte at the address, if any
+    let line_at = |a: &Assembly| {
+        a.line_map
+            .iter()
+            .find(|(_, start, size)| (*start..start + size).contains(&addr))
+            .map(|(line, _, _)| line - 1)
+    };
+    let mut errors = Vec::new();
+    if let Some(i) = line_at(original) {
+        let e = SasmError::test(&message);
+        errors.push(e.at(&original.lines.location(i), &original.lines[i]));
+    }
+    if let Some(i) = line_at(reassembled) {
+        let mut e = SasmError::test(&format!("{addr:04x} was disassembled as this line"));
+        e = e.at_line(i + 1, &reassembled.lines[i]);
+        e.file = Some("disassembly".to_string());
+        errors.push(e);
+    }
+    if errors.is_empty() {
+        errors.push(SasmError::test(&message));
+    }
+    return Err(errors);
+}
+
+// Translate the program into ca65 source for the cc65 toolchain ("sasm export"). The source is
+// assembled first, for the addresses given to zbytes and vars and the forms chosen for
+// instructions.
+pub fn export_ca65(config: &mut Config) -> Result<Code, Vec<SasmError>> {
+    if config.emit_obj || config.link {
+        return Err(vec![SasmError::syntax("object files cannot be exported")]);
+    }
+    let assembly = assemble_code(config)?;
+    let sizes: BTreeMap<usize, usize> = assembly
+        .line_map
+        .iter()
+        .map(|(line, _, size)| (line - 1, *size))
+        .collect();
+    let labels = named_labels(&assembly.labels, &assembly.symbols);
+    let mut exporter = Exporter::new(&labels, config.fill);
+    let cpu = match config.illegal {
+        true => "6502X",
+        false => "6502",
+    };
+    let mut text = format!("        .setcpu \"{cpu}\"\n");
+    let mut errors = Vec::new();
+    for (line_idx, line) in assembly.lines.iter().enumerate() {
+        let code = strip_comment(line, &config.comment_markers);
+
+        // Comments start with ';' in ca65, whatever their marker in SASM
+        let comment = config
+            .comment_markers
+            .iter()
+            .map(|m| m.as_str())
+            .chain([";"])
+            .find_map(|m| line[code.len()..].strip_prefix(m));
+        let size = sizes.get(&line_idx).copied().unwrap_or(0);
+        let result = tokenize(line, &config.comment_markers).and_then(|s| {
+            exporter
+                .line(&s, code, size)
+                .map_err(|e| SasmError::syntax(&e))
+        });
+        match result {
+            Ok(mut lines) => {
+                match (lines.last_mut(), comment) {
+                    (_, None) => (),
+                    (Some(l), Some(c)) => *l = format!("{l:23};{c}"),
+                    (None, Some(c)) => lines.push(format!(";{c}")),
+                }
+                lines.push(String::new());
+                text.push_str(&lines.join("\n"));
+            }
+            Err(e) => errors.push(e.at(&assembly.lines.location(line_idx), line)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // The text already ends with a newline, which printing it as a string would repeat
+    write_code(
+        &Code::Bytes(text.clone().into_bytes()),
+        &mut config.otype,
+        config.overwrite,
+    )
+    .map_err(|e| vec![e])?;
+    Ok(Code::String(text))
+}
+
+// Emulator with the program in its memory, which has no room for banks
+fn load_program(
+    disassembly: &[u8],
+    org_to_code_pos: &BTreeMap<u16, usize>,
+    banked: bool,
+) -> Result<Cpu, Vec<SasmError>> {
+    if banked {
+        let message = "programs with banks cannot run on the emulator";
+        return Err(vec![SasmError::syntax(message)]);
+    }
+    let mut cpu = Cpu::new();
+    for (addr, bytes) in org_blocks(disassembly, org_to_code_pos) {
+        cpu.load(addr, &bytes);
+    }
+    return Ok(cpu);
+}
+
+// Output of an image in a format that needs no more than the bytes and their addresses
+fn image_output(
+    config: &Config,
+    disassembly: &[u8],
+    org_to_code_pos: &BTreeMap<u16, usize>,
+) -> Result<Code, Vec<SasmError>> {
+    if let Some(f) = &config.custom_format {
+        return Ok(f.output(&org_blocks(disassembly, org_to_code_pos), config.fill));
+    }
+    let code = match config.cformat {
+        CodeFormat::Ines => match ines_output(disassembly, org_to_code_pos, config.fill) {
+            Ok(c) => c,
+            Err(e) => return Err(vec![e]),
+        },
+        CodeFormat::Prg => prg_output(disassembly, org_to_code_pos, config.fill),
+        CodeFormat::Xex => xex_output(disassembly, org_to_code_pos),
+        _ => bytes_to_output(disassembly, org_to_code_pos, config.cformat, config.fill),
+    };
+    return Ok(code);
+}
+
+// Bytes of the image and the code position of each org
+type PatchedCode = (Vec<u8>, BTreeMap<u16, usize>);
+
+// Org blocks of the code with the bytes of the patch directives added, written over the image of
+// --patch if there is one, which then becomes the only org block. Patches must not overlap the code
+// or each other.
+fn patched_code(
+    config: &Config,
+    lines: &SourceText,
+    patches: &[(usize, u16, Vec<u8>)],
+    disassembly: &[u8],
+    org_to_code_pos: &BTreeMap<u16, usize>,
+) -> Result<PatchedCode, Vec<SasmError>> {
+    let mut blocks = org_blocks(disassembly, org_to_code_pos);
+    let code_blocks = blocks.len();
+    let image_format = !config.emit_obj
+        && !matches!(
+            config.cformat,
+            CodeFormat::Json | CodeFormat::Listing | CodeFormat::Reloc
+        );
+    let mut errors = Vec::new();
+    for (line, addr, bytes) in patches {
+        let (start, end) = (*addr as usize, *addr as usize + bytes.len());
+        let overlap = blocks
+            .iter()
+            .position(|(a, b)| start < *a as usize + b.len() && end > *a as usize);
+        let message = match overlap {
+            _ if !image_format => "patch requires a memory image format".to_string(),
+            _ if end > 0x10000 => format!("patch at {addr:04x} extends past ffff"),
+            Some(i) if i < code_blocks => {
+                format!("patch at {addr:04x} overlaps code at {:04x}", blocks[i].0)
+            }
+            Some(i) => format!(
+                "patch at {addr:04x} overlaps the patch at {:04x}",
+                blocks[i].0
+            ),
+            None => {
+                blocks.push((*addr, bytes.clone()));
+                continue;
+            }
+        };
+        errors.push(SasmError::syntax(&message).at(&lines.location(*line), &lines[*line]));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    blocks.sort_by_key(|(addr, _)| *addr);
+
+    if let Some(f) = &config.patch {
+        let base = read_binary(f).map_err(|e| vec![e])?;
+        let image = patch_image(&base, config.addr, &blocks, config.fill).map_err(|e| vec![e])?;
+        return Ok((image, BTreeMap::from([(config.addr, 0)])));
+    }
+    let mut bytes = Vec::new();
+    let mut starts = BTreeMap::new();
+    for (addr, b) in blocks {
+        starts.insert(addr, bytes.len());
+        bytes.extend(b);
+    }
+    Ok((bytes, starts))
+}
+
+// Apple II monitor output ends with a command to run the code at the entry address, if there is
+// one. The --entry flag takes precedence over the end directive.
+fn with_run_command(config: &Config, code: Code, entry: Option<u16>) -> Code {
+    match (code, config.entries.first().copied().or(entry)) {
+        (Code::String(s), Some(e))
+            if matches!(config.cformat, CodeFormat::AppleSM)
+                && config.custom_format.is_none()
+                && !config.emit_obj =>
+        {
+            Code::String(format!("{s}{e:04x}G\n"))
+        }
+        (code, _) => code,
+    }
+}
+
+// Output file name with %04x replaced by the address of an org block, if the name has it
+fn block_file_name(otype: &OType, addr: u16) -> Option<String> {
+    match otype {
+        OType::File(f) if f.contains("%04x") => Some(f.replace("%04x", &format!("{addr:04x}"))),
+        _ => None,
+    }
+}
+
+// Write each org block to its own file in the configured format, without filler between them
+fn write_blocks(
+    config: &Config,
+    disassembly: &[u8],
+    org_to_code_pos: &BTreeMap<u16, usize>,
+) -> Result<(), Vec<SasmError>> {
+    if config.emit_obj
+        || config.custom_format.is_none()
+            && matches!(
+                config.cformat,
+                CodeFormat::Json | CodeFormat::Listing | CodeFormat::Reloc
+            )
+    {
+        let message = "an output file for each org block requires a memory image format";
+        return Err(vec![SasmError::syntax(message)]);
+    }
+    for (addr, bytes) in org_blocks(disassembly, org_to_code_pos) {
+        let code = image_output(config, &bytes, &BTreeMap::from([(addr, 0)]))?;
+        if let Some(f) = block_file_name(&config.otype, addr) {
+            write_code(&code, &mut OType::File(f), config.overwrite).map_err(|e| vec![e])?;
+        }
+    }
+    Ok(())
+}
+
+// Labels by name, for the outputs and the callers that work with names
+fn named_labels(
+    labels: &BTreeMap<SymbolId, UInt>,
+    symbols: &SymbolTable,
+) -> BTreeMap<String, UInt> {
+    labels
+        .iter()
+        .map(|(id, value)| (symbols.name(*id).to_string(), *value))
+        .collect()
+}
+
+// Write the output in a piece at a time: each line of a listing, or the image in pieces
+fn stream_output(
+    config: &Config,
+    out: &mut dyn OutputWriter,
+    lines: &SourceText,
+    disassembly: &[u8],
+    org_to_code_pos: &BTreeMap<u16, usize>,
+    line_code: &[(usize, core::ops::Range<usize>)],
+) -> Result<(), SasmError> {
+    if !matches!(config.cformat, CodeFormat::Listing) {
+        return write_image(
+            out,
+            disassembly,
+            org_to_code_pos,
+            config.cformat,
+            config.fill,
+        );
+    }
+    for (line, (addr, range)) in lines.iter().zip(line_code) {
+        let source = match config.cycles {
+            true => cycles_column(line, &config.comment_markers, config.instruction_set()) + line,
+            false => line.to_string(),
+        };
+        out.emit(listing_line(*addr, &disassembly[range.clone()], &source).as_bytes())?;
+    }
+    Ok(())
+}
+
+// Write the output and the optional symbol file. An address in the output file name writes the
+// code of each org block to its own file instead. Output for out may already be written, and the
+// rest of it is written there whole, ending text with a new line as for the other outputs.
+fn write_output(
+    config: &mut Config,
+    code: Code,
+    out: Option<&mut dyn OutputWriter>,
+    labels: &BTreeMap<String, UInt>,
+    disassembly: &[u8],
+    org_to_code_pos: &BTreeMap<u16, usize>,
+) -> Result<Code, Vec<SasmError>> {
+    if let Some(out) = out {
+        out.emit(code.as_bytes()).map_err(|e| vec![e])?;
+        if matches!(code, Code::String(_)) {
+            out.emit(b"\n").map_err(|e| vec![e])?;
+        }
+    } else if block_file_name(&config.otype, 0).is_some() {
+        write_blocks(config, disassembly, org_to_code_pos)?;
+    } else if let Err(e) = write_code(&code, &mut config.otype, config.overwrite) {
+        return Err(vec![e]);
+    }
+    if let Some(ref f) = config.symfile {
+        if let Err(e) = write_text(&config.symbol_format.write(labels), f, config.overwrite) {
+            return Err(vec![e]);
+        }
+    }
+
+    return Ok(code);
+}
+
+// Link object files given as input, instead of assembling source
+fn link_objects(
+    config: &mut Config,
+    out: Option<&mut dyn OutputWriter>,
+) -> Result<Code, Vec<SasmError>> {
+    let mut objects = Vec::new();
+    for f in config.input_files() {
+        let text = read_text(&f).map_err(|e| vec![e])?;
+        match Object::parse(&text) {
+            Ok(o) => objects.push((f, o)),
+            Err(e) => {
+                let message = format!("invalid object file {f}:{e}");
+                return Err(vec![SasmError::syntax(&message)]);
+            }
+        }
+    }
+    if objects.is_empty() {
+        return Err(vec![SasmError::io(
+            "Linking requires object files as input",
+        )]);
+    }
+
+    let mut warnings = Vec::new();
+    let linked = link(&objects, &mut warnings);
+    for w in warnings {
+        config.warn(w);
+    }
+    let (disassembly, org_to_code_pos, labels) = linked?;
+    if config.warnings.as_errors && !config.warnings.list.is_empty() {
+        return Err(config
+            .warnings
+            .list
+            .iter()
+            .map(SasmError::from_warning)
+            .collect());
+    }
+    log::info!(
+        "Linked {} bytes from {} object(s)",
+        disassembly.len(),
+        objects.len()
+    );
+
+    if let Some(c) = config.checksum {
+        let value = c.compute(&binary_image(&disassembly, &org_to_code_pos, config.fill));
+        report_checksum(c, value);
+    }
+
+    let code = match config.cformat {
+        _ if config.custom_format.is_some() => {
+            image_output(config, &disassembly, &org_to_code_pos)?
+        }
+        CodeFormat::Json => json_output(&disassembly, &org_to_code_pos, config.fill, &labels, &[]),
+        CodeFormat::Listing | CodeFormat::Reloc => {
+            let message = "listing and reloc output require source, not object files";
+            return Err(vec![SasmError::syntax(message)]);
+        }
+        _ => image_output(config, &disassembly, &org_to_code_pos)?,
+    };
+    let code = with_run_command(config, code, None);
+    check_binary_stdout(config).map_err(|e| vec![e])?;
+    write_output(config, code, out, &labels, &disassembly, &org_to_code_pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locations_in_files() {
+        let files = vec![("a.asm".to_string(), 2), ("b.asm".to_string(), 3)];
+        let text = SourceText::new("a1\na2\r\n\nb2\nb3\n".to_string(), &files);
+        assert_eq!(text.len(), 5);
+        assert_eq!((&text[1], &text[2], &text[4]), ("a2", "", "b3"));
+        let l: Vec<Location> = (0..5).map(|i| text.location(i)).collect();
+        assert_eq!((l[1].file.as_deref(), l[1].line), (Some("a.asm"), 2));
+        assert_eq!((l[2].file.as_deref(), l[2].line), (Some("b.asm"), 1));
+        assert_eq!(
+            SourceText::new("a\nb".to_string(), &[]).location(1),
+            Location {
+                file: None,
+                line: 2
+            }
+        );
+    }
+
+    #[test]
+    fn hex2_to_u8() {
+        match hex_to_uint("EB") {
+            Ok(UInt::U8(i)) => assert_eq!(i, 235),
+            _ => panic!("Unable to convert single-byte hex to int"),
+        }
+    }
+
+    #[test]
+    fn hex3_to_u16() {
+        match hex_to_uint("D80") {
+            Ok(UInt::U16(i)) => assert_eq!(i, 3456),
+            _ => panic!("Unable to convert 2-byte hex to int"),
+        }
+    }
+
+    #[test]
+    fn hex4_to_u16() {
+        match hex_to_uint("AD80") {
+            Ok(UInt::U16(i)) => assert_eq!(i, 44416),
+            _ => panic!("Unable to convert 2-byte hex to int"),
+        }
+    }
+
+    #[test]
+    fn non_hex_to_err() {
+        let e = hex_to_uint("John");
+        assert!(e.is_err());
+    }
+
+    #[test]
+    fn first_difference_at_both_lines() {
+        let assembly = |s: &str| assemble_code(&mut Config::build_string_test(s)).unwrap();
+        let original = assembly("org 0300\nnop\nldai 01\nrts\n");
+        assert_eq!(compare_code(&original, &original), Ok(4));
+
+        let errors = compare_code(&original, &assembly("org 0300\nnop\nldai 02\nrts\n"));
+        let errors: Vec<(Option<usize>, String)> = errors
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.line, e.message))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    Some(3),
+                    "reassembled code differs at 0302: 01 became 02".to_string()
+                ),
+                (Some(3), "0302 was disassembled as this line".to_string())
+            ]
+        );
+
+        let errors = compare_code(&original, &assembly("org 0300\nnop\nldai 01\n")).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "reassembled code differs at 0303: 60 became nothing"
+        );
+        assert_eq!(errors.len(), 1);
     }
 }
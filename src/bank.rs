@@ -0,0 +1,49 @@
+// Bank-switching schemes of Atari 2600 cartridges larger than 4K. Each 4K bank is mapped at f000
+// in turn, selected by accessing one of the hotspot addresses at the end of memory.
+
+use crate::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Banking {
+    // A single 4K bank, or memory that is not banked at all
+    None,
+
+    // 8K in 2 banks, with hotspots fff8-fff9
+    F8,
+
+    // 16K in 4 banks, with hotspots fff6-fff9
+    F6,
+}
+
+// Size of each bank and the address where it is mapped
+pub const BANK_SIZE: usize = 0x1000;
+pub const BANK_ADDR: u16 = 0xf000;
+
+impl Banking {
+    // Attempt to create a variant from a string
+    pub fn new(scheme: &str) -> Result<Self, &str> {
+        match scheme.to_ascii_lowercase().as_str() {
+            "none" => Ok(Banking::None),
+            "f8" => Ok(Banking::F8),
+            "f6" => Ok(Banking::F6),
+            _ => Err("banking must be none, f8, or f6"),
+        }
+    }
+
+    pub fn num_banks(&self) -> usize {
+        match self {
+            Banking::None => 1,
+            Banking::F8 => 2,
+            Banking::F6 => 4,
+        }
+    }
+
+    // Accessing the nth hotspot selects the nth bank
+    pub fn hotspots(&self) -> Vec<u16> {
+        match self {
+            Banking::None => Vec::new(),
+            Banking::F8 => vec![0xfff8, 0xfff9],
+            Banking::F6 => vec![0xfff6, 0xfff7, 0xfff8, 0xfff9],
+        }
+    }
+}
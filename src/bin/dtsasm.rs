@@ -5,13 +5,30 @@ fn main() {
     let args: Vec<String> = env::args().collect();
 
     // Configuration is the same for assembly and disassmbly, but disassembly
-    // ignores the -s (system) and -f (format) flags.
+    // only uses the listing format of the -f (format) flag. The -s (system) flag names the
+    // system's hardware addresses.
     let mut config = sasm2::Config::build(&args).unwrap_or_else(|err| {
-        println!("{err}");
+        eprintln!("{err}");
         process::exit(1);
     });
+    if let Some(text) = config.action.text() {
+        println!("{text}");
+        process::exit(0);
+    }
+    sasm2::logger::init(config.verbosity);
+
+    let result = sasm2::disassemble(&mut config);
+    let color = config.color.enabled();
+    let json = config.error_format == sasm2::ErrorFormat::Json;
 
-    if let Err(s) = sasm2::disassemble(&mut config) {
-        eprintln!("{s}");
+    if !config.warnings.as_errors && config.verbosity >= log::LevelFilter::Warn {
+        for w in &config.warnings.list {
+            eprintln!("{}", if json { w.to_json() } else { w.render(color) });
+        }
+    }
+
+    if let Err(s) = result {
+        eprintln!("{}", if json { s.to_json() } else { s.render(color) });
+        process::exit(1);
     }
 }
@@ -1,15 +1,230 @@
 use std::env;
+use std::io::Write;
 use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+// Print the warnings and errors of assembling, and return the result if there are no errors
+fn report<T>(config: &sasm2::Config, result: Result<T, Vec<sasm2::SasmError>>) -> Option<T> {
+    let color = config.color.enabled();
+    let json = config.error_format == sasm2::ErrorFormat::Json;
+
+    // Warnings treated as errors are already included in the errors
+    if !config.warnings.as_errors && config.verbosity >= log::LevelFilter::Warn {
+        for w in &config.warnings.list {
+            eprintln!("{}", if json { w.to_json() } else { w.render(color) });
+        }
+    }
+
+    match result {
+        Ok(r) => Some(r),
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{}", if json { e.to_json() } else { e.render(color) });
+            }
+            None
+        }
+    }
+}
+
+// Assemble once and print any warnings and errors. Output to STDOUT is written as it is made.
+fn run(config: &mut sasm2::Config) -> bool {
+    let result = match config.otype {
+        sasm2::config::OType::Stdout => {
+            let mut stdout = std::io::stdout().lock();
+            sasm2::assemble_to(config, &mut stdout).and_then(|_| {
+                stdout
+                    .flush()
+                    .map_err(|_| vec![sasm2::SasmError::io("Unable to write output")])
+            })
+        }
+        _ => sasm2::assemble(config).map(|_| ()),
+    };
+    report(config, result).is_some()
+}
+
+// Assemble and run the program on the emulator. What it writes goes to STDOUT, and how it
+// stopped, its registers, and its memory to STDERR.
+fn run_program(config: &mut sasm2::Config) -> ! {
+    let result = sasm2::run(config);
+    let Some((cpu, stop)) = report(config, result) else {
+        process::exit(1);
+    };
+    let mut stdout = std::io::stdout();
+    if stdout
+        .write_all(&cpu.output)
+        .and_then(|_| stdout.flush())
+        .is_err()
+    {
+        process::exit(1);
+    }
+    if !cpu.output.is_empty() && !cpu.output.ends_with(b"\n") {
+        eprintln!();
+    }
+    eprintln!("Stopped: {stop}");
+    eprintln!("{}", cpu.registers());
+    if let Some((start, end)) = config.dump {
+        eprint!("{}", cpu.dump(start, end));
+    }
+    process::exit(match stop {
+        sasm2::emu::Stop::Invalid(..) | sasm2::emu::Stop::CycleLimit => 1,
+        _ => 0,
+    });
+}
+
+// Latest modification time of the given files. Files that cannot be read are skipped, since
+// editors often replace a file rather than write to it in place.
+fn last_modified(files: &[String]) -> Option<SystemTime> {
+    files
+        .iter()
+        .filter_map(|f| std::fs::metadata(f).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+// Reassemble whenever an input file changes. Errors are reported, but the loop keeps going.
+fn watch(config: &mut sasm2::Config) -> ! {
+    let files = config.input_files();
+    if files.is_empty() {
+        eprintln!("Watch mode requires an input file");
+        process::exit(1);
+    }
+
+    let mut modified = last_modified(&files);
+    let mut wrote_output = run(config);
+    eprintln!("Watching {} for changes", files.join(", "));
+
+    loop {
+        thread::sleep(Duration::from_millis(500));
+
+        let m = last_modified(&files);
+        if m == modified {
+            continue;
+        }
+        modified = m;
+
+        // Once the output has been written, later builds replace it
+        if wrote_output && config.overwrite == sasm2::Overwrite::Never {
+            config.overwrite = sasm2::Overwrite::Force;
+        }
+
+        config.reset();
+        eprintln!("Reassembling");
+        wrote_output |= run(config);
+    }
+}
+
+// Assemble the program and run its tests on the emulator, with a line for each test and the
+// failures of those that fail. Exits with 1 if any test fails.
+fn run_tests(config: &mut sasm2::Config) -> ! {
+    let result = sasm2::test(config);
+    let Some(results) = report(config, result) else {
+        process::exit(1);
+    };
+    let color = config.color.enabled();
+    let json = config.error_format == sasm2::ErrorFormat::Json;
+    let mut failed = 0;
+    for r in &results {
+        match r.failures.is_empty() {
+            true => eprintln!("test {} ... ok ({} cycles)", r.name, r.cycles),
+            false => {
+                eprintln!("test {} ... FAILED ({})", r.name, r.stop);
+                for e in &r.failures {
+                    eprintln!("{}", if json { e.to_json() } else { e.render(color) });
+                }
+                failed += 1;
+            }
+        }
+    }
+    eprintln!("{} test(s), {failed} failed", results.len());
+    process::exit(if failed > 0 { 1 } else { 0 });
+}
+
+// Assemble the program, disassemble it, and check that the disassembly assembles to the same
+// bytes. Exits with 1 at the first byte that differs.
+fn verify(config: &mut sasm2::Config) -> ! {
+    let result = sasm2::verify(config);
+    let Some(size) = report(config, result) else {
+        process::exit(1);
+    };
+    eprintln!("Verified {size} bytes");
+    process::exit(0);
+}
+
+// Compare the disassemblies of two binaries, with a line for each change on STDOUT. Exits with 1
+// if they differ, like diff.
+fn diff(config: &mut sasm2::Config) -> ! {
+    let result = sasm2::diff(config).map_err(|e| vec![e]);
+    let Some(changes) = report(config, result) else {
+        process::exit(2);
+    };
+    for c in &changes {
+        println!("{c}");
+    }
+    process::exit(if changes.is_empty() { 0 } else { 1 });
+}
+
+// Translate ca65 source into SASM source. Exits with 1 if any line cannot be translated, without
+// writing anything.
+fn import(config: &mut sasm2::Config) -> ! {
+    let result = sasm2::import_ca65(config);
+    process::exit(if report(config, result).is_some() {
+        0
+    } else {
+        1
+    });
+}
+
+// Translate the program into ca65 source. Exits with 1 if it does not assemble or has lines
+// that ca65 has no equivalent for.
+fn export(config: &mut sasm2::Config) -> ! {
+    let result = sasm2::export_ca65(config);
+    process::exit(if report(config, result).is_some() {
+        0
+    } else {
+        1
+    });
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     let mut config = sasm2::Config::build(&args).unwrap_or_else(|err| {
-        println!("{err}");
+        eprintln!("{err}");
         process::exit(1);
     });
+    if let Some(text) = config.action.text() {
+        println!("{text}");
+        process::exit(0);
+    }
+    sasm2::logger::init(config.verbosity);
+
+    if config.run {
+        run_program(&mut config);
+    }
+
+    if config.test {
+        run_tests(&mut config);
+    }
+
+    if config.verify {
+        verify(&mut config);
+    }
 
-    if let Err(s) = sasm2::assemble(&mut config) {
-        eprintln!("{s}");
+    if config.diff {
+        diff(&mut config);
     }
+
+    if config.import {
+        import(&mut config);
+    }
+
+    if config.export {
+        export(&mut config);
+    }
+
+    if config.watch {
+        watch(&mut config);
+    }
+
+    process::exit(if run(&mut config) { 0 } else { 1 });
 }
@@ -0,0 +1,27 @@
+use std::env;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    // Configuration is the same as for assembly, but formatting only uses the input, the output,
+    // and the comment markers of the --comments flag
+    let mut config = sasm2::Config::build(&args).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+    if let Some(text) = config.action.text() {
+        println!("{text}");
+        process::exit(0);
+    }
+    sasm2::logger::init(config.verbosity);
+
+    let result = sasm2::format_source(&mut config);
+    let color = config.color.enabled();
+    let json = config.error_format == sasm2::ErrorFormat::Json;
+
+    if let Err(s) = result {
+        eprintln!("{}", if json { s.to_json() } else { s.render(color) });
+        process::exit(1);
+    }
+}
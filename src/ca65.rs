@@ -0,0 +1,669 @@
+// Import of source written for ca65, the assembler of cc65, into SASM source ("sasm import").
+// Instructions in the standard syntax, such as "LDA #$00" and "STA ($12),Y", become SASM
+// mnemonics with their addressing modes, and the directives whose meaning SASM shares become its
+// commands. Everything else, such as macros, conditionals, expressions, and unnamed labels, is
+// reported with its line rather than guessed at. Going the other way, a SASM program is exported
+// as ca65 source.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+
+use crate::config::Config;
+use crate::data::{get_instr_info, get_opposite_branch, is_relative_branch_instruction, Isa};
+use crate::diagnostic::Location;
+use crate::error::SasmError;
+use crate::host::{read_input, write_code};
+use crate::output::Code;
+use crate::prelude::*;
+use crate::standard::label;
+use crate::syntax::{
+    split_offset, split_unquoted, strip_comment, Layout, Offset, Op, Rawdata, Segment, SourceLine,
+    UInt,
+};
+
+// Directives that only matter to the cc65 linker or listing, which SASM can do without
+const IGNORED: [&str; 6] = [
+    ".export",
+    ".exportzp",
+    ".global",
+    ".globalzp",
+    ".p02",
+    ".debuginfo",
+];
+
+// Operand or item of data: a number, a label with an offset, or the low or high byte of a label
+#[derive(Debug, PartialEq)]
+enum Value {
+    Number(u32),
+    Label(String, i32),
+    Low(String),
+    High(String),
+}
+
+// Number in any of the notations of ca65: $hex, %binary, decimal, or a character in quotes
+fn number(text: &str) -> Option<u32> {
+    if let Some(h) = text.strip_prefix('$') {
+        return u32::from_str_radix(h, 16).ok();
+    }
+    if let Some(b) = text.strip_prefix('%') {
+        return u32::from_str_radix(b, 2).ok();
+    }
+    let chars: Vec<char> = text.chars().collect();
+    match chars.as_slice() {
+        ['\'', c, '\''] if c.is_ascii() => Some(*c as u32),
+        _ if text.chars().all(|c| c.is_ascii_digit()) => text.parse().ok(),
+        _ => None,
+    }
+}
+
+fn is_symbol(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '@')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+struct Importer {
+    // Symbols assigned values below 0100, whose operands take zero-page instructions as in ca65
+    zero_page: BTreeSet<String>,
+
+    // Last label that is not a cheap local (@name), which the cheap locals after it belong to
+    scope: String,
+}
+
+impl Importer {
+    // Cheap locals become labels of their own, named after the label they follow
+    fn symbol(&self, name: &str) -> String {
+        match name.strip_prefix('@') {
+            Some(local) => format!("{}_{local}", self.scope),
+            None => name.to_string(),
+        }
+    }
+
+    fn value(&self, text: &str) -> Result<Value, String> {
+        let text = text.trim();
+        let unsupported = || format!("expression {text} is not supported");
+        if let Some(l) = text.strip_prefix('<').filter(|l| is_symbol(l)) {
+            return Ok(Value::Low(self.symbol(l)));
+        }
+        if let Some(l) = text.strip_prefix('>').filter(|l| is_symbol(l)) {
+            return Ok(Value::High(self.symbol(l)));
+        }
+        if let Some(n) = number(text) {
+            return Ok(Value::Number(n));
+        }
+
+        // A label may have a number added or subtracted
+        let (name, offset) = match text.find(['+', '-']) {
+            Some(i) => {
+                let n = number(text[i + 1..].trim()).ok_or_else(unsupported)? as i32;
+                let sign = if text[i..].starts_with('-') { -1 } else { 1 };
+                (text[..i].trim(), sign * n)
+            }
+            None => (text, 0),
+        };
+        match is_symbol(name) {
+            true => Ok(Value::Label(self.symbol(name), offset)),
+            false => Err(unsupported()),
+        }
+    }
+
+    // Operand of an instruction after its mnemonic, as SASM writes it
+    fn operand(&self, value: &Value, wide: bool) -> Result<String, String> {
+        match value {
+            Value::Number(n) if wide && *n <= 0xffff => Ok(format!("{n:04x}")),
+            Value::Number(n) if *n <= 0xff => Ok(format!("{n:02x}")),
+            Value::Number(n) => Err(format!("${n:x} is too large for the operand")),
+            Value::Label(l, 0) => Ok(format!(".{l}")),
+            Value::Label(l, o) if o.unsigned_abs() <= 0xff => match *o < 0 {
+                true => Ok(format!(".{l} -{:02x}", o.unsigned_abs())),
+                false => Ok(format!(".{l} {o:02x}")),
+            },
+            Value::Label(..) => Err("offsets of labels must be below $100".to_string()),
+            Value::Low(l) => Ok(format!("<.{l}")),
+            Value::High(l) => Ok(format!(">.{l}")),
+        }
+    }
+
+    fn instruction(&self, mnemonic: &str, operand: &str) -> Result<String, String> {
+        let m = mnemonic.to_ascii_lowercase();
+        let isa = Isa {
+            illegal: false,
+            table: None,
+        };
+        let valid = |name: &str| get_instr_info(name, isa).is_ok();
+        let op = operand.trim();
+
+        // Implied and accumulator instructions, and branches, have no addressing mode to add
+        if op.is_empty() || op.eq_ignore_ascii_case("a") {
+            return match valid(&m) {
+                true => Ok(m),
+                false => Err(format!("{mnemonic} needs an operand")),
+            };
+        }
+        if is_relative_branch_instruction(&m) {
+            let target = self.operand(&self.value(op)?, true)?;
+            return Ok(format!("{m} {target}"));
+        }
+
+        let upper = op.to_ascii_uppercase();
+        let (mode, expr) = if let Some(e) = op.strip_prefix('#') {
+            ("i".to_string(), e)
+        } else if upper.starts_with('(') && upper.ends_with(",X)") {
+            ("nx".to_string(), &op[1..op.len() - 3])
+        } else if upper.starts_with('(') && upper.ends_with("),Y") {
+            ("ny".to_string(), &op[1..op.len() - 3])
+        } else if upper.starts_with('(') && upper.ends_with(')') {
+            ("n".to_string(), &op[1..op.len() - 1])
+        } else {
+            let (base, index) = match upper.rsplit_once(',') {
+                Some((_, "X")) => (&op[..op.len() - 2], "x"),
+                Some((_, "Y")) => (&op[..op.len() - 2], "y"),
+                _ => (op, ""),
+            };
+
+            // An address size of a: or z: forces absolute or zero-page addressing
+            let (base, forced) = match base.get(..2).map(|p| p.to_ascii_lowercase()) {
+                Some(p) if p == "a:" || p == "z:" => (&base[2..], Some(p == "z:")),
+                _ => (base, None),
+            };
+            let zero_page = forced.unwrap_or(match self.value(base)? {
+                Value::Number(n) => n <= 0xff,
+                Value::Label(l, _) => self.zero_page.contains(&l),
+                _ => false,
+            });
+            let size = match zero_page && valid(&format!("{m}z{index}")) {
+                true => "z",
+                false => "a",
+            };
+            (format!("{size}{index}"), base)
+        };
+
+        let name = format!("{m}{mode}");
+        if !valid(&name) {
+            return Err(format!("{mnemonic} {op} is not a 6502 instruction"));
+        }
+        let wide = mode.starts_with('a') || mode == "n";
+        Ok(format!(
+            "{name} {}",
+            self.operand(&self.value(expr)?, wide)?
+        ))
+    }
+
+    // Items of .byte, .word, or .dword, as the items of data, word, or dword
+    fn items(&self, args: &str, size: usize) -> Result<String, String> {
+        let mut items = Vec::new();
+        for (_, item) in split_unquoted(args, |c| c == ',') {
+            let item = item.trim();
+            if item.starts_with('"') {
+                items.push(item.to_string());
+                continue;
+            }
+            let max = (1u64 << (8 * size)) - 1;
+            match self.value(item)? {
+                Value::Number(n) if n as u64 <= max => {
+                    items.push(format!("{n:0width$x}", width = 2 * size))
+                }
+                Value::Number(n) => return Err(format!("${n:x} is too large for the data")),
+                Value::Label(l, 0) if size == 2 => items.push(format!(".{l}")),
+                Value::Label(l, o) if size == 2 => match o < 0 {
+                    true => items.push(format!(".{l}-{:x}", o.unsigned_abs())),
+                    false => items.push(format!(".{l}+{o:x}")),
+                },
+                _ => return Err(format!("{item} is not supported as data of this size")),
+            }
+        }
+        match items.is_empty() {
+            true => Err("data directives need at least one item".to_string()),
+            false => Ok(items.join(" ")),
+        }
+    }
+
+    fn directive(&mut self, directive: &str, args: &str) -> Result<Vec<String>, String> {
+        let d = directive.to_ascii_lowercase();
+        let args = args.trim();
+        let segment = |s: &str| Ok(vec![format!("segment {s}")]);
+        match d.as_str() {
+            ".org" => match self.value(args)? {
+                Value::Number(n) if n <= 0xffff => Ok(vec![format!("org {n:04x}")]),
+                _ => Err(".org needs an address".to_string()),
+            },
+            ".byte" | ".byt" => Ok(vec![format!("data {}", self.items(args, 1)?)]),
+            ".word" | ".addr" => Ok(vec![format!("word {}", self.items(args, 2)?)]),
+            ".dword" => Ok(vec![format!("dword {}", self.items(args, 4)?)]),
+            ".res" => {
+                let parts: Vec<&str> = args.split(',').collect();
+                let fill = match parts.get(1).map(|f| self.value(f)) {
+                    None => 0,
+                    Some(Ok(Value::Number(n))) if n <= 0xff => n,
+                    Some(_) => return Err(".res fills with a single byte".to_string()),
+                };
+                match self.value(parts[0])? {
+                    Value::Number(n) if n > 0 && n <= 0x10000 => Ok(vec![format!(
+                        "data {}",
+                        format!("{fill:02x}").repeat(n as usize)
+                    )]),
+                    _ => Err(".res needs a number of bytes".to_string()),
+                }
+            }
+            ".proc" if is_symbol(args) => {
+                self.scope = args.to_string();
+                Ok(vec![format!(".{args}")])
+            }
+            ".endproc" => Ok(Vec::new()),
+            ".code" => segment("code"),
+            ".rodata" | ".data" => segment("data"),
+            ".bss" => segment("bss"),
+            ".segment" => match args.trim_matches('"').to_ascii_uppercase().as_str() {
+                "CODE" => segment("code"),
+                "RODATA" | "DATA" => segment("data"),
+                "BSS" => segment("bss"),
+                s => Err(format!("segment {s} is not supported")),
+            },
+            ".setcpu" if args == "\"6502\"" => Ok(Vec::new()),
+            d if IGNORED.contains(&d) => Ok(Vec::new()),
+            _ => Err(format!("{directive} is not supported")),
+        }
+    }
+
+    // SASM lines for one line of ca65 source
+    fn line(&mut self, line: &str) -> Result<Vec<String>, String> {
+        let mut code = strip_comment(line, &[]).trim();
+        let comment = line[strip_comment(line, &[]).len()..].trim_end();
+        let mut lines = Vec::new();
+
+        // Labels, which in SASM are on lines of their own
+        while let Some((label, rest)) = code.split_once(':') {
+            if label.is_empty() {
+                return Err("unnamed labels are not supported".to_string());
+            }
+            if !is_symbol(label) || rest.starts_with(['=', ':']) {
+                break;
+            }
+            if !label.starts_with('@') {
+                self.scope = label.to_string();
+            }
+            lines.push(format!(".{}", self.symbol(label)));
+            code = rest.trim();
+        }
+
+        let (first, rest) = code
+            .split_once(|c: char| c.is_ascii_whitespace())
+            .unwrap_or((code, ""));
+        if let Some((name, value)) = code.split_once('=') {
+            let name = name.trim().trim_end_matches(':').trim();
+            match self.value(value)? {
+                Value::Number(n) if is_symbol(name) && n <= 0xff => {
+                    lines.push(format!("label {name} {n:02x}"))
+                }
+                Value::Number(n) if is_symbol(name) && n <= 0xffff => {
+                    lines.push(format!("label {name} {n:04x}"))
+                }
+                _ => return Err(format!("assignment to {name} is not supported")),
+            }
+        } else if first.starts_with('.') {
+            lines.extend(self.directive(first, rest)?);
+        } else if !first.is_empty() {
+            lines.push(self.instruction(first, rest)?);
+        }
+
+        // The comment goes after the last line, or on a line of its own
+        match (lines.last_mut(), comment.is_empty()) {
+            (_, true) => (),
+            (Some(l), false) => *l = format!("{l} {comment}"),
+            (None, false) => lines.push(comment.to_string()),
+        }
+        Ok(lines)
+    }
+}
+
+// Translate ca65 source into SASM source, with every line that cannot be translated as an error
+fn import_lines(source: &str, file: Option<&str>) -> Result<String, Vec<SasmError>> {
+    // Symbols assigned values below 0100 anywhere are zero page, even before their assignments
+    let zero_page = source
+        .lines()
+        .filter_map(|l| strip_comment(l, &[]).split_once('='))
+        .filter(|(n, v)| number(v.trim()).is_some_and(|v| v <= 0xff) && is_symbol(n.trim()))
+        .map(|(n, _)| n.trim().to_string())
+        .collect();
+    let mut importer = Importer {
+        zero_page,
+        scope: String::new(),
+    };
+
+    let mut text = String::new();
+    let mut errors = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        match importer.line(line) {
+            Ok(lines) if lines.is_empty() => text.push('\n'),
+            Ok(lines) => lines.iter().for_each(|l| text.push_str(&format!("{l}\n"))),
+            Err(e) => {
+                let location = Location {
+                    file: file.map(|f| f.to_string()),
+                    line: i + 1,
+                };
+                errors.push(SasmError::syntax(&e).at(&location, line));
+            }
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(text),
+        false => Err(errors),
+    }
+}
+
+// Translate ca65 source in memory
+pub fn import_ca65_str(source: &str) -> Result<String, Vec<SasmError>> {
+    import_lines(source, None)
+}
+
+// Translate the input file and write the SASM source to the output
+pub fn import_ca65(config: &mut Config) -> Result<Code, Vec<SasmError>> {
+    let parts = read_input(config).map_err(|e| vec![e])?;
+    if parts.len() != 1 {
+        return Err(vec![SasmError::io("Importing takes a single input file")]);
+    }
+    let Ok(source) = core::str::from_utf8(&parts[0].1) else {
+        return Err(vec![SasmError::io("Input is not valid text")]);
+    };
+    let text = import_lines(source, parts[0].0.as_deref())?;
+
+    // The text already ends with a newline, which printing it as a string would repeat
+    write_code(
+        &Code::Bytes(text.clone().into_bytes()),
+        &mut config.otype,
+        config.overwrite,
+    )
+    .map_err(|e| vec![e])?;
+    Ok(Code::String(text))
+}
+
+// Export of a SASM program as ca65 source ("sasm export"). Zbytes, zwords, and vars become symbols
+// with the addresses that the assembler gave them, and each instruction keeps the form that it was
+// assembled in, so that ca65 makes the same code.
+pub(crate) struct Exporter<'a> {
+    labels: &'a BTreeMap<String, UInt>,
+    fill: u8,
+
+    // Whether an org has been exported. Later orgs fill up to their addresses, as SASM does.
+    org: bool,
+}
+
+// Value in ca65 syntax, in as many digits as it was given
+fn hex(u: UInt) -> String {
+    match u {
+        UInt::U8(u) => format!("${u:02X}"),
+        UInt::U16(u) => format!("${u:04X}"),
+    }
+}
+
+// Bytes of data as .byte lines of at most 16 bytes each
+fn byte_lines(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(16)
+        .map(|c| {
+            let items: Vec<String> = c.iter().map(|b| format!("${b:02X}")).collect();
+            format!("        .byte {}", items.join(","))
+        })
+        .collect()
+}
+
+impl<'a> Exporter<'a> {
+    pub(crate) fn new(labels: &'a BTreeMap<String, UInt>, fill: u8) -> Self {
+        Exporter {
+            labels,
+            fill,
+            org: false,
+        }
+    }
+
+    // Assembled value of a symbol, as a definition
+    fn symbol(&self, name: &str) -> Result<String, String> {
+        match self.labels.get(name) {
+            Some(u) => Ok(format!("{} = {}", label(name), hex(*u))),
+            None => Err(format!("label {name} not defined")),
+        }
+    }
+
+    // Value of an operand without its offset, if it is known
+    fn value(&self, op: &Op) -> Option<u16> {
+        match op {
+            Op::UInt(UInt::U8(u)) => Some(*u as u16),
+            Op::UInt(UInt::U16(u)) => Some(*u),
+            Op::Label(l) => match self.labels.get(l) {
+                Some(UInt::U8(u)) => Some(*u as u16),
+                Some(UInt::U16(u)) => Some(*u),
+                None => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn operand(&self, op: &Op, offset: &Offset) -> String {
+        let op = match op {
+            Op::UInt(u) => hex(*u),
+            Op::Label(l) => label(l),
+            Op::Low(l) => format!("<{}", label(l)),
+            Op::High(l) => format!(">{}", label(l)),
+            Op::None => String::new(),
+        };
+        match offset {
+            Offset::Value(0) => op,
+            Offset::Value(v) if *v < 0 => format!("{op}-${:02X}", -v),
+            Offset::Value(v) => format!("{op}+${v:02X}"),
+            Offset::Label(l) => format!("{op}+{}", label(l)),
+        }
+    }
+
+    // Instruction in the form that it was assembled in, as told by its size. Absolute instructions
+    // of two bytes were made zero page, and branches of five bytes were made long.
+    fn instruction(&self, m: &str, op: &Op, offset: &Offset, size: usize) -> Vec<String> {
+        let (base, mode) = m.split_at(m.len().min(3));
+        let base = base.to_ascii_uppercase();
+        let operand = self.operand(op, offset);
+        if is_relative_branch_instruction(m) {
+            let target = match op {
+                // A single byte is the branch offset itself
+                Op::UInt(UInt::U8(u)) => format!("*{:+}", 2 + *u as i8 as i16),
+                _ => operand,
+            };
+            return match (size, get_opposite_branch(m)) {
+                (5, Some(opposite)) => vec![
+                    format!("        {} *+5", opposite.to_ascii_uppercase()),
+                    format!("        JMP {target}"),
+                ],
+                _ => vec![format!("        {base} {target}")],
+            };
+        }
+
+        let mode = match (mode, size) {
+            ("a", 2) => "z",
+            ("ax", 2) => "zx",
+            ("ay", 2) => "zy",
+            (mode, _) => mode,
+        };
+
+        // ca65 would choose zero page for addresses below 0100, and absolute for symbols that it
+        // has not seen yet, so both are made explicit
+        let prefix = match (mode, op) {
+            ("a" | "ax" | "ay", _) if self.value(op).is_some_and(|v| v <= 0xff) => "a:",
+            ("z" | "zx" | "zy", Op::Label(_)) => "z:",
+            _ => "",
+        };
+        let operand = match mode {
+            _ if matches!(op, Op::None) => return vec![format!("        {base}")],
+            "i" => format!("#{operand}"),
+            "a" | "z" => format!("{prefix}{operand}"),
+            "ax" | "zx" => format!("{prefix}{operand},X"),
+            "ay" | "zy" => format!("{prefix}{operand},Y"),
+            "n" => format!("({operand})"),
+            "nx" => format!("({operand},X)"),
+            "ny" => format!("({operand}),Y"),
+            _ => operand,
+        };
+        vec![format!("        {base} {operand}")]
+    }
+
+    // Translate one tokenized line with the text of its code and the size of the code that it
+    // made. Lines that only matter to SASM, such as tests, are kept as comments.
+    pub(crate) fn line(
+        &mut self,
+        s: &SourceLine,
+        code: &str,
+        size: usize,
+    ) -> Result<Vec<String>, String> {
+        let lines = match s {
+            SourceLine::Blank => Vec::new(),
+            SourceLine::CodeMarker(l) => vec![format!("{}:", label(l))],
+            SourceLine::Label(l, u) => vec![format!("{} = {}", label(l), hex(*u))],
+            SourceLine::ZByte(l, ..) | SourceLine::ZWord(l, _) | SourceLine::Var(l, _) => {
+                vec![self.symbol(l)?]
+            }
+            SourceLine::Org(_, true) => return Err("org! is not supported".to_string()),
+            SourceLine::Org(addr, false) if self.org => {
+                vec![format!("        .res ${addr:04X}-*, ${:02X}", self.fill)]
+            }
+            SourceLine::Org(addr, false) => {
+                self.org = true;
+                vec![format!("        .org ${addr:04X}")]
+            }
+            SourceLine::Segment(segment) => {
+                let name = match segment {
+                    Segment::Code => "CODE",
+                    Segment::Data => "DATA",
+                    Segment::Bss => "BSS",
+                };
+                vec![format!("        .segment \"{name}\"")]
+            }
+            SourceLine::Data(items) => {
+                let mut lines = Vec::new();
+                let mut bytes = Vec::new();
+                for (_, item) in items {
+                    match item {
+                        Rawdata::Bytes(b) => bytes.extend(b),
+                        Rawdata::Label(l, layout) => {
+                            lines.extend(byte_lines(&bytes));
+                            bytes.clear();
+
+                            // The second byte of a zword is a label of its own
+                            let (name, offset) = match self.labels.contains_key(l) {
+                                true => (l.as_str(), 0),
+                                false => split_offset(l),
+                            };
+                            let directive = match layout {
+                                Layout::Word => ".word",
+                                Layout::WordBe => ".dbyt",
+                                Layout::Dword => ".dword",
+                            };
+                            let offset = match offset {
+                                0 => String::new(),
+                                o if o < 0 => format!("-${:02X}", -o),
+                                o => format!("+${o:02X}"),
+                            };
+                            lines.push(format!("        {directive} {}{offset}", label(name)));
+                        }
+                    }
+                }
+                lines.extend(byte_lines(&bytes));
+                lines
+            }
+            SourceLine::Instr(m, op, offset) | SourceLine::LongBranch(m, op, offset) => {
+                self.instruction(m, op, offset, size)
+            }
+            SourceLine::End(_)
+            | SourceLine::ZFree(_)
+            | SourceLine::ZpReserve(..)
+            | SourceLine::Test(_)
+            | SourceLine::Assert(..) => vec![format!("; {}", code.trim())],
+            SourceLine::Bank(..) => return Err("banks are not supported".to_string()),
+            SourceLine::Checksum(_) => return Err("checksum is not supported".to_string()),
+            SourceLine::Patch(..) => return Err("patch is not supported".to_string()),
+        };
+        Ok(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::Scope;
+
+    #[test]
+    fn import_instructions_and_directives() {
+        let source = "ptr = $fb\n\
+                      .org $0300\n\
+                      start:  lda #$01   ; one\n\
+                      \x20       sta (ptr),Y\n\
+                      @loop:  inc counter+1\n\
+                      \x20       bne @loop\n\
+                      \x20       jmp (vector)\n\
+                      \x20       asl a\n\
+                      \x20       ldx a:$12,y\n\
+                      .proc sub\n\
+                      \x20       lda #<start\n\
+                      \x20       rts\n\
+                      .endproc\n\
+                      table:  .byte 1, $ff, \"Hi\"\n\
+                      \x20       .word start, table-1\n\
+                      \x20       .res 3, $ea\n";
+        let expected = "label ptr fb\norg 0300\n.start\nldai 01 ; one\nstany .ptr\n\
+                        .start_loop\ninca .counter 01\nbne .start_loop\njmpn .vector\nasl\n\
+                        ldxay 0012\n.sub\nldai <.start\nrts\n\n.table\ndata 01 ff \"Hi\"\n\
+                        word .start .table-1\ndata eaeaea\n";
+        assert_eq!(import_ca65_str(source), Ok(expected.to_string()));
+    }
+
+    #[test]
+    fn export_assembled_forms() {
+        let labels = BTreeMap::from([("p".to_string(), UInt::U8(0x10))]);
+        let mut exporter = Exporter::new(&labels, 0xff);
+        let mut export = |s: SourceLine, size| exporter.line(&s, "", size);
+        let abs = |m: &str, op| SourceLine::Instr(m.to_string(), op, Offset::Value(0));
+        let p = || Op::Label("p".to_string());
+        assert_eq!(
+            export(abs("ldaa", p()), 3),
+            Ok(vec!["        LDA a:p".to_string()])
+        );
+        assert_eq!(
+            export(abs("ldaax", p()), 2),
+            Ok(vec!["        LDA z:p,X".to_string()])
+        );
+        assert_eq!(
+            export(abs("bne", Op::Label("far".to_string())), 5),
+            Ok(vec![
+                "        BEQ *+5".to_string(),
+                "        JMP far".to_string()
+            ])
+        );
+        assert_eq!(
+            export(SourceLine::ZByte("p".to_string(), 1, 1, Scope::Program), 0),
+            Ok(vec!["p = $10".to_string()])
+        );
+        assert_eq!(
+            export(SourceLine::Org(0x300, false), 0),
+            Ok(vec!["        .org $0300".to_string()])
+        );
+        assert_eq!(
+            export(SourceLine::Org(0x400, false), 0),
+            Ok(vec!["        .res $0400-*, $FF".to_string()])
+        );
+        assert_eq!(
+            export(SourceLine::Patch(0x400, vec![0xea]), 0),
+            Err("patch is not supported".to_string())
+        );
+    }
+
+    #[test]
+    fn unsupported_lines_reported() {
+        let errors = import_ca65_str(".macro inc16\n:  nop\nlda #(x*2)\n")
+            .map_err(|e| e.iter().map(|e| e.to_string()).collect::<Vec<_>>());
+        assert_eq!(
+            errors,
+            Err(vec![
+                "1: .macro is not supported".to_string(),
+                "2: unnamed labels are not supported".to_string(),
+                "3: expression (x*2) is not supported".to_string(),
+            ])
+        );
+    }
+}
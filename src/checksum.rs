@@ -0,0 +1,109 @@
+// Checksums over the assembled image. These can be reported with the -C flag or embedded in the
+// code with the "checksum" directive.
+
+use crate::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Checksum {
+    // 8-bit sum of all bytes
+    Sum,
+
+    // CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xffff)
+    Crc16,
+
+    // Standard CRC-32 as used by zip, PNG, etc.
+    Crc32,
+}
+
+impl Checksum {
+    // Attempt to create a variant from a string
+    pub fn new(name: &str) -> Result<Self, &str> {
+        match name.to_ascii_lowercase().as_str() {
+            "sum" => Ok(Checksum::Sum),
+            "crc16" => Ok(Checksum::Crc16),
+            "crc32" => Ok(Checksum::Crc32),
+            _ => Err("checksum must be sum, crc16, or crc32"),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Checksum::Sum => "sum",
+            Checksum::Crc16 => "crc16",
+            Checksum::Crc32 => "crc32",
+        }
+    }
+
+    // Number of bytes needed to store the checksum
+    pub fn size(&self) -> usize {
+        match self {
+            Checksum::Sum => 1,
+            Checksum::Crc16 => 2,
+            Checksum::Crc32 => 4,
+        }
+    }
+
+    pub fn compute(&self, bytes: &[u8]) -> u32 {
+        match self {
+            Checksum::Sum => bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) as u32,
+            Checksum::Crc16 => {
+                let mut crc: u16 = 0xffff;
+                for b in bytes {
+                    crc ^= (*b as u16) << 8;
+                    for _ in 0..8 {
+                        crc = if crc & 0x8000 != 0 {
+                            (crc << 1) ^ 0x1021
+                        } else {
+                            crc << 1
+                        };
+                    }
+                }
+                crc as u32
+            }
+            Checksum::Crc32 => {
+                let mut crc: u32 = 0xffffffff;
+                for b in bytes {
+                    crc ^= *b as u32;
+                    for _ in 0..8 {
+                        crc = if crc & 1 != 0 {
+                            (crc >> 1) ^ 0xedb88320
+                        } else {
+                            crc >> 1
+                        };
+                    }
+                }
+                !crc
+            }
+        }
+    }
+
+    // Checksum in little-endian order, truncated to its size, for storing in the code
+    pub fn to_le_bytes(self, value: u32) -> Vec<u8> {
+        value.to_le_bytes()[..self.size()].to_vec()
+    }
+
+    // Checksum as hex digits, for reporting
+    pub fn to_hex_string(self, value: u32) -> String {
+        format!("{:0width$x}", value, width = self.size() * 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sum_wraps() {
+        assert_eq!(Checksum::Sum.compute(&[0xff, 0x02, 0x10]), 0x11);
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(Checksum::Crc16.compute(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(Checksum::Crc32.compute(b"123456789"), 0xcbf43926);
+    }
+}
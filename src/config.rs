@@ -1,88 +1,1054 @@
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+
+#[cfg(feature = "std")]
 use indoc::indoc;
+use log::LevelFilter;
 
-use crate::output::CodeFormat;
+#[cfg(feature = "std")]
+use crate::assemble::hex_to_uint;
+use crate::bank::Banking;
+use crate::checksum::Checksum;
+use crate::data::{Isa, IsaTable};
+use crate::diagnostic::{ColorChoice, Diagnostic, ErrorFormat};
+use crate::disassemble::DisassemblyWriter;
+use crate::emu::DEFAULT_MAX_CYCLES;
+use crate::flow::Report;
+use crate::hints::{Hints, LabelStyle};
+use crate::host::find_file;
+use crate::input::InputFormat;
+use crate::output::{CodeFormat, OutputFormat, Overwrite, SymbolFormat};
+use crate::prelude::*;
+use crate::standard::Syntax;
+use crate::syntax::UInt;
+#[cfg(feature = "std")]
+use crate::toml;
+#[cfg(feature = "std")]
+use crate::version::version;
+use crate::warning::{SasmWarning, Warnings};
 use crate::zpm::Zpm;
 
 pub enum IType {
     Stdin,
     String(String),
     File(String),
+
+    // Several source files assembled as one, in order
+    Files(Vec<String>),
+
+    // Any other source, such as a network stream or an in-memory buffer
+    #[cfg(feature = "std")]
+    Reader(Box<dyn Read>),
 }
 
+// Function that receives errors and warnings as they are found
+pub type DiagnosticCallback = Box<dyn FnMut(Diagnostic)>;
+
+// Name (for files) and contents of one part of the input
+pub type InputPart = (Option<String>, Vec<u8>);
+
 pub enum OType {
     Stdout,
     File(String),
     None,
+
+    // Any other destination
+    #[cfg(feature = "std")]
+    Writer(Box<dyn Write>),
+}
+
+// What the binaries do with the config
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    // Assemble, disassemble, or format as the other flags say
+    Build,
+
+    // Print the help message or the version on STDOUT instead (--help and --version)
+    PrintHelp,
+    PrintVersion,
+}
+
+impl Action {
+    // Text to print instead of building, if any
+    #[cfg(feature = "std")]
+    pub fn text(&self) -> Option<String> {
+        match self {
+            Action::Build => None,
+            Action::PrintHelp => Some(help().to_string()),
+            Action::PrintVersion => Some(version().to_string()),
+        }
+    }
 }
 
 pub struct Config {
+    pub action: Action,
     pub itype: IType,
     pub otype: OType,
     pub zpm: Zpm,
+
+    // Inclusive ranges of zero page that zbyte never allocates
+    pub zp_reserved: Vec<(u8, u8)>,
+
+    // Inclusive range of zero page that zbyte allocates from, instead of the system's default
+    pub zp_range: Option<(u8, u8)>,
     pub cformat: CodeFormat,
+    pub fill: u8,
+    pub symfile: Option<String>,
+    pub symbol_format: SymbolFormat,
+
+    // File with the source file and line of each address range of the code
+    pub source_map: Option<String>,
+
+    // Debug info in the format of the cc65 linker, for source-level debuggers
+    pub dbg_file: Option<String>,
+
+    // File with every label's value, the line that defines it, and the lines that refer to it
+    pub xref: Option<String>,
+
+    // Graphviz file for the control-flow graph of the disassembly
+    pub cfg_file: Option<String>,
+
+    // Report of subroutines in the disassembly
+    pub report: Report,
+
+    // How the disassembler makes labels for addresses without names
+    pub label_style: LabelStyle,
+
+    // Name the zero-page bytes that the disassembled code uses, such as z12 for 0012
+    pub zp_labels: bool,
+
+    // Label the addresses outside the disassembled input that the code uses, such as ext_fded
+    pub ext_labels: bool,
+    pub checksum: Option<Checksum>,
     pub addr: u16,
+
+    // Slice of the input to disassemble, in bytes. The starting address applies to the slice.
+    pub offset: usize,
+    pub length: Option<usize>,
+
+    // Bank-switching scheme of the input (disassembler only)
+    pub banking: Banking,
     pub min_region_size: usize,
+
+    // Further tuning of how the disassembler finds code: invalid opcodes a code region may skip,
+    // minimum percentage of its bytes that are instructions, and bytes subtracted from its size
+    // for each rare opcode
+    pub max_invalid: usize,
+    pub min_density: usize,
+    pub rare_penalty: usize,
+
+    // Percentage below which a guessed code region is left as data
+    pub min_confidence: usize,
+
+    // Second pass over the guessed code regions that follows their branches, jumps, and calls
+    pub refine_regions: bool,
+    pub illegal: bool,
+
+    // Instructions from an ISA file, which replace or extend the built-in ones
+    pub isa: Option<IsaTable>,
+
+    // Annotate listings and disassembly with the cycle count of each instruction
+    pub cycles: bool,
+
+    // Annotate the disassembly with the address and bytes of each line
+    pub show_bytes: bool,
+
+    // Report the bytes and cycles of each input file and source line on STDERR
+    pub stats: bool,
+
+    // Use the zero-page form of absolute instructions whose operands are known to fit in zero
+    // page by the time they are read
+    pub zp_optimize: bool,
+
+    // Assemble relative branches that cannot reach their targets as the opposite branch over a
+    // jmpa
+    pub long_branches: bool,
+
+    // Markers that start comments in addition to ';', such as "//" and "#"
+    pub comment_markers: Vec<String>,
+    pub warnings: Warnings,
+    pub defines: Vec<(String, UInt)>,
+    pub include_paths: Vec<String>,
+    pub overwrite: Overwrite,
+
+    // Write binary formats to STDOUT even when it is a terminal, which they would garble
+    pub force_binary_stdout: bool,
+    pub watch: bool,
+
+    // Assemble and run the program on the emulator instead of writing it out ("sasm run"), for up
+    // to the given number of cycles, then show the registers and an inclusive range of memory
+    pub run: bool,
+
+    // Run the program's tests instead ("sasm test")
+    pub test: bool,
+
+    // Check that the disassembly of the code assembles to the same code ("sasm verify")
+    pub verify: bool,
+
+    // Compare the disassemblies of two inputs instruction by instruction ("sasm diff")
+    pub diff: bool,
+
+    // Translate ca65 source into SASM source ("sasm import")
+    pub import: bool,
+
+    // Translate the program into ca65 source ("sasm export")
+    pub export: bool,
+    pub max_cycles: u64,
+    pub dump: Option<(u16, u16)>,
+
+    // Write an object file instead of the final output, or link object files given as input
+    pub emit_obj: bool,
+    pub link: bool,
+
+    // Existing image that the org blocks are written over, starting at the address of --addr
+    pub patch: Option<String>,
+    pub color: ColorChoice,
+    pub error_format: ErrorFormat,
+    pub verbosity: LevelFilter,
+    pub iformat: InputFormat,
+    pub entries: Vec<u16>,
+    pub hints: Hints,
+
+    // Name the hardware addresses of the system when disassembling. Only set when the system is
+    // given explicitly, so that plain disassemblies are unchanged.
+    pub system_addresses: bool,
+
+    pub syntax: Syntax,
+
+    // Called with each error and warning of a source line as the line is assembled, for editors
+    // that show them while the rest of the input is still being assembled
+    pub on_diagnostic: Option<DiagnosticCallback>,
+
+    // Output format of a library user, which replaces the one given by --format (assembler only)
+    pub custom_format: Option<Box<dyn OutputFormat>>,
+
+    // Disassembly format of a library user, which replaces the SASM source and the --format,
+    // --syntax, --cycles, and --report flags (disassembler only)
+    pub custom_writer: Option<Box<dyn DisassemblyWriter>>,
 }
 
+// Project file that the assembler reads from the current directory if no -c flag is given. The
+// disassembler and the formatter only read a project file given with -c, since settings for
+// assembling a project would silently change how they work on other files.
+#[cfg(feature = "std")]
+const DEFAULT_PROJECT_FILE: &str = "sasm.toml";
+
+// Flags to keep track of state while parsing the command line. Project file settings map to the
+// same flags, so that both are handled by the same code.
+#[cfg(feature = "std")]
+enum CLFlag {
+    Ifile,
+    Ofile,
+    Sys,
+    ZpReserve,
+    ZpRange,
+    Comments,
+    Format,
+    Fill,
+    Symfile,
+    SymbolFormat,
+    SourceMap,
+    DbgFile,
+    Xref,
+    CfgFile,
+    Report,
+    Labels,
+    Checksum,
+    Addr,
+    Offset,
+    Length,
+    Banking,
+    MinRegSize,
+    MaxInvalid,
+    MinDensity,
+    MinConfidence,
+    RarePenalty,
+    Warnings,
+    ProjectFile,
+    Define,
+    IncludePath,
+    Color,
+    ErrorFormat,
+    InputFormat,
+    Entry,
+    Hints,
+    Syntax,
+    Isa,
+    MaxCycles,
+    Dump,
+    Patch,
+    None,
+}
+
+#[cfg(feature = "std")]
 fn help() -> &'static str {
     return indoc! {"
-            Flags (all are optional):
-            -h: This help message
-            -i: Input  file (STDIN  is default)
-            -o: Output file (STDOUT is default)
-            -s: System: (assembler only)
-                apple: Apple II (default)
-                atari: Atari 2600
-            -f: Code output format: (assembler only)
-                hex:   String of hex digits (default)
-                apple: Apple II system monitor
-                bin:   Machine code
-            -a: Starting address in hex (disassembler only)
-                0x0000 is default. Must be < 0x10000.
-            -m: Minimum size for a code region (disassembler only)
-                10 is default.
+            Flags (all are optional). Long flags also accept --flag=value.
+            -h, --help:       This help message
+            -V, --version:    Version and supported CPUs, formats, and systems
+            -i, --input:      Input  file, or comma-separated files (STDIN  is default)
+            -o, --output:     Output file (STDOUT is default, or -). A name with %04x, such as
+                    out_%04x.bin, writes each org block to its own file named by its address
+                    (assembler only).
+            -s, --system:     System (the disassembler names the system's hardware addresses)
+                    apple: Apple II (default)
+                    atari: Atari 2600
+                    nes:   Nintendo Entertainment System
+                    c64:   Commodore 64
+                    vic20: Commodore VIC-20
+                    atari8: Atari 400 and 800
+                --zp-reserve: Comma-separated zero-page ranges in hex that zbyte never allocates,
+                    such as 00-1f (assembler only)
+                --zp-range:   Zero-page range in hex that zbyte allocates from, such as d0:ef
+                    (assembler only). All of zero page is default for apple and nes,
+                    80:ff for atari, 02 and fb:fe for c64, fb:fe for vic20, and cb:d1 for atari8.
+                --zp-optimize: Use zero-page instructions for absolute operands below 0100
+                    that are known when read, such as ldaz for ldaa (assembler only). Takes no
+                    argument.
+                --long-branches: Assemble a branch that is too far as the opposite branch
+                    over a jmpa, such as bne over jmpa for beq (assembler only). Takes no
+                    argument.
+                --comments:   Comma-separated markers that also start comments, such as //,#
+                    (assembler only). ';' always starts a comment.
+            -f, --format:     Code output format:
+                    hex:   String of hex digits (default)
+                    apple: Apple II system monitor
+                    bin:   Machine code
+                    json:  JSON with code, org blocks, symbols, and line map
+                    listing: Address, bytes, and source of each line
+                    ines:  iNES file with the code as PRG ROM (nes system only)
+                    prg:   Load address and machine code (c64 and vic20 systems only)
+                    xex:   Atari DOS executable (atari8 system only)
+                    reloc: Machine code with a table of operands to adjust when loaded elsewhere
+            -F, --fill:       Filler byte in hex for gaps between orgs (assembler only)
+                    ff is default.
+            -l, --symbols:    Symbol file for debuggers (assembler only)
+                --symbol-format: Format of the symbol file (assembler only)
+                    vice:     VICE monitor labels (default)
+                    applewin: AppleWin symbol table
+                    stella:   DASM symbol file, which Stella loads with the ROM
+                --source-map: Source map with the file and line of each address range
+                    (assembler only)
+                --dbg-file:   Debug info in the cc65 format, with the files, lines, and labels
+                    (assembler only)
+                --xref:       Cross-reference of each label with its value, the line that
+                    defines it, and the address and line of each reference (assembler only)
+            -C, --checksum:   Report a checksum of the code on STDERR (assembler only)
+                    sum:   8-bit sum of all bytes
+                    crc16: CRC-16/CCITT-FALSE
+                    crc32: CRC-32
+            -a, --addr:       Starting address in hex of the input (disassembler) or of the
+                    patched image (assembler). 0x0000 is default. Must be < 0x10000.
+                --offset:     Offset in hex of the first byte to disassemble (disassembler only)
+                --length:     Number of bytes in hex to disassemble (disassembler only)
+                    The starting address applies to the first byte. Input must be hex or bin.
+                --banking:    Atari 2600 bank switching (disassembler only)
+                    none:  No banks (default)
+                    f8:    8K in 2 banks
+                    f6:    16K in 4 banks
+            -m, --min-region: Minimum size for a code region (disassembler only)
+                    10 is default.
+                --max-invalid: Invalid opcodes a code region may skip (disassembler only)
+                    0 is default.
+                --min-density: Minimum percentage of instruction bytes in a code region
+                    (disassembler only). 0 is default.
+                --rare-penalty: Bytes subtracted from the size of a code region for each
+                    rare opcode, such as BRK (disassembler only). 0 is default.
+                --min-confidence: Minimum confidence in percent for a guessed code region,
+                    as shown by --report regions (disassembler only). 0 is default.
+                --refine:     Follow the branches, jumps, and calls of guessed code regions,
+                    leaving out regions that branch into the middle of an instruction of
+                    another and adding code at targets outside them (disassembler only). Takes
+                    no argument.
+                --input-format: Input format (disassembler only)
+                    auto:  Detected from the input (default)
+                    hex:   String of hex digits, whitespace allowed
+                    bin:   Machine code
+                    ihex:  Intel HEX
+                    srec:  Motorola S-records
+                    apple: Apple II system monitor memory dump
+                    dos:   Apple DOS 3.3 binary file with its 4-byte header
+            -e, --entry:      Comma-separated entry points in hex. The disassembler finds code by
+                    following jumps, branches, and subroutine calls from these addresses, and
+                    reads fffa, fffc, and fffe as interrupt vectors. Apple monitor output from the
+                    assembler ends with a command to run the first one.
+            -H, --hints:      Hints file with code and data ranges and names (disassembler only)
+                --cfg:        Graphviz DOT file for the control-flow graph (disassembler only)
+                --report:     Report of subroutines, their callers, and returns (disassembler only)
+                    none:  No report (default)
+                    after: After the assembly, as comments
+                    only:  Instead of the assembly
+                    cycles: Bytes and cycles between labels, on STDERR (assembler only)
+                    regions: Guessed code regions and the confidence in each, after the assembly
+                --stats:      Report the bytes and cycles of each input file and of each source
+                    line, largest first, on STDERR (assembler only). Takes no argument.
+                --labels:     Labels for addresses without names (disassembler only)
+                    addr:     The address, such as c00a (default)
+                    typed:    The address after sub_, loc_, or dat_ for subroutines, other
+                              code, and data
+                    numbered: Numbered in order of address, such as l1
+                --zp-labels:  Name each zero-page byte that the code uses, such as z12, with
+                    zbyte commands if a system is given (disassembler only). Takes no argument.
+                --ext-labels: Label each address outside the input that the code uses, such as
+                    ext_fded, with label commands (disassembler only). Takes no argument.
+                --syntax:     Syntax of the disassembly (disassembler only)
+                    sasm:     SASM source (default)
+                    standard: Standard 6502 syntax with ca65 directives, such as LDA #$00
+            -u, --illegal:    Allow undocumented (illegal) NMOS 6502 opcodes
+                    Takes no argument.
+                --isa:        ISA file in TOML that replaces or extends the instruction set
+                --cycles:     Show the cycle count of each instruction in listings and disassembly
+                    A + means one more cycle when crossing a page or taking a branch. Takes no
+                    argument.
+                --bytes:      Show the address and bytes of each line of disassembly as a comment
+                    (disassembler only). Takes no argument.
+            -W, --warn:       Warnings, as a comma-separated list of:
+                    all:   Enable all warnings
+                    none:  Disable all warnings
+                    error: Treat warnings as errors (also -Werror or --werror)
+                    NAME or no-NAME: Enable or disable a single warning (also -WNAME)
+                      branch-offset:     Offset added to a branch target (on)
+                      branch-page:       Branch crosses a page boundary (off)
+                      label-inside-line: Disassembler label inside a line (on)
+                      unused-labels:     Label or zbyte that is never used (off)
+                      duplicate-exports: Label defined in more than one linked object (on)
+            -c, --config:     Project file (sasm reads sasm.toml by default if it exists)
+            -D, --define:     Define a label as NAME=VALUE, with VALUE in hex
+            -I, --include:    Add a directory to search for input files
+                --force:      Overwrite output files that already exist
+                --backup:     Rename existing output files to NAME.bak first
+                --force-binary-stdout: Write a binary format to STDOUT even if it is a terminal
+            -w, --watch:      Reassemble whenever an input file changes (assembler only)
+                --emit-obj:   Write an object file, whose labels from other files are resolved
+                    by linking (assembler only). Takes no argument.
+                --link:       Link object files into the output (assembler only). Takes no
+                    argument.
+                --patch:      Write the org blocks over a copy of an existing binary image,
+                    which starts at the address of --addr, and write out the result
+                    (assembler only)
+                --color:      Color for errors and warnings: auto (default), always, or never
+                --error-format: Errors and warnings as human (default) or json (one per line)
+            -v, --verbose:    Report progress (-vv or repeat for more detail)
+            -q, --quiet:      Only report errors
+
+            sasm run [flags] assembles the program and runs it on the built-in 6502 emulator,
+            from the entry address (see --entry) or the first org. A write to f001 prints a
+            byte, and the run stops at brk, an rts from the entry routine, or a jump to itself.
+                --max-cycles: Cycles after which the run stops, in decimal (10000000 is default)
+                --dump:       Memory range in hex to show after the run (0000-00ff is default)
+
+            sasm test [flags] runs each test directive's test instead, with the same flags, and
+            fails if an assert does not hold or a test does not stop by itself.
+
+            sasm verify [flags] disassembles the assembled code and assembles the disassembly
+            again, and fails at the first byte that differs.
+
+            sasm diff A B [flags] disassembles two binaries with the disassembler's flags and
+            shows the instructions, operands, and data that differ, with their addresses.
+
+            sasm import [flags] translates ca65 source from the input into SASM source on the
+            output, and reports each line that it cannot translate.
+
+            sasm export [flags] assembles the input and writes it to the output as ca65 source,
+            with the addresses of zbytes, zwords, and vars as symbols.
+
+            Hex arguments may optionally start with 0x or $.
+            Flags override settings from the project file.
     "};
 }
 
+// Parse a hex command-line argument, allowing the common "0x" and "$" prefixes
+#[cfg(feature = "std")]
+fn parse_hex_arg<T: TryFrom<u32>>(a: &str) -> Option<T> {
+    let digits = a
+        .strip_prefix("0x")
+        .or_else(|| a.strip_prefix("0X"))
+        .or_else(|| a.strip_prefix('$'))
+        .unwrap_or(a);
+    match u32::from_str_radix(digits, 16) {
+        Ok(n) => T::try_from(n).ok(),
+        Err(_) => None,
+    }
+}
+
+// Parse a label definition of the form NAME=VALUE
+#[cfg(feature = "std")]
+fn parse_define(a: &str) -> Result<(String, UInt), String> {
+    match a.split_once('=') {
+        Some((name, value)) if !name.is_empty() => match hex_to_uint(value) {
+            Ok(u) => Ok((name.to_string(), u)),
+            Err(e) => Err(format!("Invalid value for {name}: {}", e.message)),
+        },
+        _ => Err(format!("Invalid definition: {a} (expected NAME=VALUE)")),
+    }
+}
+
+// Next log level after the given one, up to the most detailed
+#[cfg(feature = "std")]
+fn more_verbose(level: LevelFilter) -> LevelFilter {
+    match level {
+        LevelFilter::Off => LevelFilter::Error,
+        LevelFilter::Error => LevelFilter::Warn,
+        LevelFilter::Warn => LevelFilter::Info,
+        LevelFilter::Info => LevelFilter::Debug,
+        LevelFilter::Debug | LevelFilter::Trace => LevelFilter::Trace,
+    }
+}
+
 impl Config {
-    pub fn build(args: &[String]) -> Result<Config, String> {
-        // Flags to keep track of state while parsing the command line.
-        enum CLFlag {
-            Ifile,
-            Ofile,
-            Sys,
-            Format,
-            Addr,
-            MinRegSize,
-            None,
+    // Apply a single setting from either the command line or the project file
+    #[cfg(feature = "std")]
+    fn apply_setting(&mut self, flag: &CLFlag, a: &str) -> Result<(), String> {
+        match flag {
+            CLFlag::Ifile => {
+                // Several files are assembled (or linked) as one, in order
+                let files: Vec<String> = a.split(',').map(|f| f.to_string()).collect();
+                self.itype = match files.len() {
+                    1 => IType::File(a.to_string()),
+                    _ => IType::Files(files),
+                };
+            }
+            CLFlag::Ofile => {
+                self.otype = match a {
+                    "-" => OType::Stdout,
+                    _ => OType::File(a.to_string()),
+                }
+            }
+            CLFlag::Sys => {
+                self.zpm = Zpm::new(a, None)?;
+                self.system_addresses = true;
+            }
+            CLFlag::Format => self.cformat = CodeFormat::new(a)?,
+            CLFlag::Fill => {
+                self.fill = match parse_hex_arg(a) {
+                    Some(n) => n,
+                    None => return Err("Invalid filler byte".to_string()),
+                }
+            }
+            CLFlag::Symfile => self.symfile = Some(a.to_string()),
+            CLFlag::SymbolFormat => self.symbol_format = SymbolFormat::new(a)?,
+            CLFlag::SourceMap => self.source_map = Some(a.to_string()),
+            CLFlag::DbgFile => self.dbg_file = Some(a.to_string()),
+            CLFlag::Xref => self.xref = Some(a.to_string()),
+            CLFlag::CfgFile => self.cfg_file = Some(a.to_string()),
+            CLFlag::Report => self.report = Report::new(a)?,
+            CLFlag::Labels => self.label_style = LabelStyle::new(a)?,
+            CLFlag::Checksum => self.checksum = Some(Checksum::new(a)?),
+            CLFlag::Addr => {
+                self.addr = match parse_hex_arg(a) {
+                    Some(n) => n,
+                    None => return Err("Invalid starting address".to_string()),
+                }
+            }
+            CLFlag::Offset => {
+                self.offset = match parse_hex_arg(a) {
+                    Some(n) => n,
+                    None => return Err("Invalid offset".to_string()),
+                }
+            }
+            CLFlag::Length => {
+                self.length = match parse_hex_arg(a) {
+                    Some(n) => Some(n),
+                    None => return Err("Invalid length".to_string()),
+                }
+            }
+            CLFlag::Banking => self.banking = Banking::new(a)?,
+            CLFlag::MinRegSize => {
+                self.min_region_size = match a.parse() {
+                    Ok(n) => n,
+                    _ => return Err("Invalid minimum region size".to_string()),
+                }
+            }
+            CLFlag::MaxCycles => {
+                self.max_cycles = match a.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err("Invalid maximum number of cycles".to_string()),
+                }
+            }
+            CLFlag::Dump => {
+                let range = a
+                    .split_once('-')
+                    .and_then(|(s, e)| Some((parse_hex_arg::<u16>(s)?, parse_hex_arg::<u16>(e)?)));
+                match range {
+                    Some((s, e)) if s <= e => self.dump = Some((s, e)),
+                    _ => return Err(format!("Invalid memory range: {a}")),
+                }
+            }
+            CLFlag::MaxInvalid => {
+                self.max_invalid = match a.parse() {
+                    Ok(n) => n,
+                    _ => return Err("Invalid number of invalid opcodes".to_string()),
+                }
+            }
+            CLFlag::MinDensity => {
+                self.min_density = match a.parse() {
+                    Ok(n) if n <= 100 => n,
+                    _ => return Err("Invalid minimum density (must be 0-100)".to_string()),
+                }
+            }
+            CLFlag::MinConfidence => {
+                self.min_confidence = match a.parse() {
+                    Ok(n) if n <= 100 => n,
+                    _ => return Err("Invalid minimum confidence (must be 0-100)".to_string()),
+                }
+            }
+            CLFlag::RarePenalty => {
+                self.rare_penalty = match a.parse() {
+                    Ok(n) => n,
+                    _ => return Err("Invalid rare opcode penalty".to_string()),
+                }
+            }
+            CLFlag::Warnings => self.warnings.apply(a)?,
+            CLFlag::Define => {
+                let (name, value) = parse_define(a)?;
+                self.defines.retain(|(n, _)| *n != name);
+                self.defines.push((name, value));
+            }
+            CLFlag::IncludePath => self.include_paths.push(a.to_string()),
+            CLFlag::Color => self.color = ColorChoice::new(a)?,
+            CLFlag::ErrorFormat => self.error_format = ErrorFormat::new(a)?,
+            CLFlag::InputFormat => self.iformat = InputFormat::new(a)?,
+            CLFlag::Syntax => self.syntax = Syntax::new(a)?,
+            CLFlag::Hints => self.hints = Hints::load(a)?,
+            CLFlag::Isa => self.isa = Some(IsaTable::load(a)?),
+            CLFlag::ZpReserve => {
+                for r in a.split(',') {
+                    let range = r.split_once('-').and_then(|(s, e)| {
+                        Some((parse_hex_arg::<u8>(s)?, parse_hex_arg::<u8>(e)?))
+                    });
+                    match range {
+                        Some((s, e)) if s <= e => self.zp_reserved.push((s, e)),
+                        _ => return Err(format!("Invalid zero-page range: {r}")),
+                    }
+                }
+            }
+            CLFlag::Comments => {
+                for m in a.split(',') {
+                    if m.is_empty() || m.contains(|c: char| c.is_whitespace() || c == '"') {
+                        return Err(format!("Invalid comment marker: {m}"));
+                    }
+                    self.comment_markers.push(m.to_string());
+                }
+            }
+            CLFlag::ZpRange => {
+                let range = a
+                    .split_once(':')
+                    .and_then(|(s, e)| Some((parse_hex_arg::<u8>(s)?, parse_hex_arg::<u8>(e)?)));
+                match range {
+                    Some((s, e)) if s <= e => self.zp_range = Some((s, e)),
+                    _ => return Err(format!("Invalid zero-page range: {a}")),
+                }
+            }
+            CLFlag::Entry => {
+                for e in a.split(',') {
+                    match parse_hex_arg(e) {
+                        Some(n) => self.entries.push(n),
+                        None => return Err(format!("Invalid entry point: {e}")),
+                    }
+                }
+            }
+
+            // The project file is loaded before any other flags are processed
+            CLFlag::Patch => self.patch = Some(a.to_string()),
+            CLFlag::ProjectFile => (),
+            CLFlag::None => return Err(format!("Argument {a} must immediately follow a flag")),
         }
 
-        // Config with default values. Only zpm must be changed before build completes.
-        let mut config = Config {
-            itype: IType::Stdin,
-            otype: OType::Stdout,
-            zpm: Zpm::None, // Defaults to AppleII
+        Ok(())
+    }
+
+    // Load settings from a project file. Keys are the long flag names, with underscores instead
+    // of dashes. Labels are defined in a [defines] table.
+    #[cfg(feature = "std")]
+    fn apply_project_file(&mut self, path: &str) -> Result<(), String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(_) => return Err(format!("Unable to read project file {path}")),
+        };
+        let entries = toml::parse(&text).map_err(|e| format!("{path}:{e}"))?;
+
+        for entry in entries {
+            let location = format!("{path}:{}", entry.line);
+            if entry.table == "defines" {
+                let value = match entry.value {
+                    toml::Value::String(v) => v,
+                    _ => return Err(format!("{location}: define values must be hex strings")),
+                };
+                self.apply_setting(&CLFlag::Define, &format!("{}={value}", entry.key))
+                    .map_err(|e| format!("{location}: {e}"))?;
+                continue;
+            } else if !entry.table.is_empty() {
+                return Err(format!("{location}: unknown table [{}]", entry.table));
+            }
+
+            let flag = match entry.key.as_str() {
+                "input" => CLFlag::Ifile,
+                "output" => CLFlag::Ofile,
+                "system" => CLFlag::Sys,
+                "zp_reserve" => CLFlag::ZpReserve,
+                "zp_range" => CLFlag::ZpRange,
+                "comments" => CLFlag::Comments,
+                "format" => CLFlag::Format,
+                "fill" => CLFlag::Fill,
+                "symbols" => CLFlag::Symfile,
+                "symbol_format" => CLFlag::SymbolFormat,
+                "source_map" => CLFlag::SourceMap,
+                "dbg_file" => CLFlag::DbgFile,
+                "xref" => CLFlag::Xref,
+                "cfg" => CLFlag::CfgFile,
+                "report" => CLFlag::Report,
+                "labels" => CLFlag::Labels,
+                "checksum" => CLFlag::Checksum,
+                "addr" => CLFlag::Addr,
+                "offset" => CLFlag::Offset,
+                "length" => CLFlag::Length,
+                "banking" => CLFlag::Banking,
+                "min_region" => CLFlag::MinRegSize,
+                "max_invalid" => CLFlag::MaxInvalid,
+                "min_density" => CLFlag::MinDensity,
+                "min_confidence" => CLFlag::MinConfidence,
+                "rare_penalty" => CLFlag::RarePenalty,
+                "patch" => CLFlag::Patch,
+                "warn" => CLFlag::Warnings,
+                "include_paths" => CLFlag::IncludePath,
+                "color" => CLFlag::Color,
+                "error_format" => CLFlag::ErrorFormat,
+                "input_format" => CLFlag::InputFormat,
+                "entry" => CLFlag::Entry,
+                "hints" => CLFlag::Hints,
+                "max_cycles" => CLFlag::MaxCycles,
+                "dump" => CLFlag::Dump,
+                "syntax" => CLFlag::Syntax,
+                "isa" => CLFlag::Isa,
+                "illegal" => match entry.value {
+                    toml::Value::Boolean(b) => {
+                        self.illegal = b;
+                        continue;
+                    }
+                    _ => return Err(format!("{location}: illegal must be true or false")),
+                },
+                "bytes" => match entry.value {
+                    toml::Value::Boolean(b) => {
+                        self.show_bytes = b;
+                        continue;
+                    }
+                    _ => return Err(format!("{location}: bytes must be true or false")),
+                },
+                "cycles" => match entry.value {
+                    toml::Value::Boolean(b) => {
+                        self.cycles = b;
+                        continue;
+                    }
+                    _ => return Err(format!("{location}: cycles must be true or false")),
+                },
+                "stats" => match entry.value {
+                    toml::Value::Boolean(b) => {
+                        self.stats = b;
+                        continue;
+                    }
+                    _ => return Err(format!("{location}: stats must be true or false")),
+                },
+                "ext_labels" => match entry.value {
+                    toml::Value::Boolean(b) => {
+                        self.ext_labels = b;
+                        continue;
+                    }
+                    _ => return Err(format!("{location}: ext_labels must be true or false")),
+                },
+                "refine" => match entry.value {
+                    toml::Value::Boolean(b) => {
+                        self.refine_regions = b;
+                        continue;
+                    }
+                    _ => return Err(format!("{location}: refine must be true or false")),
+                },
+                "zp_labels" => match entry.value {
+                    toml::Value::Boolean(b) => {
+                        self.zp_labels = b;
+                        continue;
+                    }
+                    _ => return Err(format!("{location}: zp_labels must be true or false")),
+                },
+                "zp_optimize" => match entry.value {
+                    toml::Value::Boolean(b) => {
+                        self.zp_optimize = b;
+                        continue;
+                    }
+                    _ => return Err(format!("{location}: zp_optimize must be true or false")),
+                },
+                "long_branches" => match entry.value {
+                    toml::Value::Boolean(b) => {
+                        self.long_branches = b;
+                        continue;
+                    }
+                    _ => return Err(format!("{location}: long_branches must be true or false")),
+                },
+                "force" | "backup" => match entry.value {
+                    toml::Value::Boolean(b) => {
+                        if b {
+                            self.overwrite = match entry.key.as_str() {
+                                "force" => Overwrite::Force,
+                                _ => Overwrite::Backup,
+                            };
+                        }
+                        continue;
+                    }
+                    _ => return Err(format!("{location}: {} must be true or false", entry.key)),
+                },
+                k => return Err(format!("{location}: unknown setting {k}")),
+            };
+
+            match entry.value {
+                toml::Value::String(v) => self.apply_setting(&flag, &v),
+                toml::Value::Integer(n) => match flag {
+                    // Integers are decimal in TOML, but these settings are normally hex
+                    CLFlag::Fill | CLFlag::Addr | CLFlag::Offset | CLFlag::Length => {
+                        self.apply_setting(&flag, &format!("{n:x}"))
+                    }
+                    _ => self.apply_setting(&flag, &n.to_string()),
+                },
+                toml::Value::Array(values) => match flag {
+                    CLFlag::Ifile => {
+                        self.itype = IType::Files(values);
+                        Ok(())
+                    }
+                    CLFlag::IncludePath => {
+                        self.include_paths.extend(values);
+                        Ok(())
+                    }
+                    CLFlag::Entry | CLFlag::ZpReserve | CLFlag::Comments => {
+                        self.apply_setting(&flag, &values.join(","))
+                    }
+                    _ => Err(format!("{} does not accept a list", entry.key)),
+                },
+                toml::Value::Boolean(_) => Err(format!("{} does not accept true/false", entry.key)),
+            }
+            .map_err(|e| format!("{location}: {e}"))?;
+        }
+
+        Ok(())
+    }
+
+    // Config with default values for everything but the input, the output, and the system
+    fn with_defaults(itype: IType, otype: OType, zpm: Zpm) -> Config {
+        Config {
+            action: Action::Build,
+            itype,
+            otype,
+            zpm,
+            zp_reserved: Vec::new(),
+            zp_range: None,
             cformat: CodeFormat::Hex,
+            fill: 0xff,
+            symfile: None,
+            symbol_format: SymbolFormat::Vice,
+            source_map: None,
+            dbg_file: None,
+            xref: None,
+            cfg_file: None,
+            report: Report::None,
+            label_style: LabelStyle::Addr,
+            zp_labels: false,
+            ext_labels: false,
+            checksum: None,
             addr: 0,
+            offset: 0,
+            length: None,
+            banking: Banking::None,
             min_region_size: 10,
+            max_invalid: 0,
+            min_density: 0,
+            min_confidence: 0,
+            refine_regions: false,
+            rare_penalty: 0,
+            illegal: false,
+            isa: None,
+            cycles: false,
+            show_bytes: false,
+            stats: false,
+            zp_optimize: false,
+            long_branches: false,
+            comment_markers: Vec::new(),
+            warnings: Warnings::default(),
+            defines: Vec::new(),
+            include_paths: Vec::new(),
+            overwrite: Overwrite::Never,
+            force_binary_stdout: false,
+            watch: false,
+            run: false,
+            test: false,
+            verify: false,
+            diff: false,
+            import: false,
+            export: false,
+            max_cycles: DEFAULT_MAX_CYCLES,
+            dump: Some((0x0000, 0x00ff)),
+            emit_obj: false,
+            link: false,
+            patch: None,
+            color: ColorChoice::Auto,
+            error_format: ErrorFormat::Human,
+            verbosity: LevelFilter::Warn,
+            iformat: InputFormat::Auto,
+            entries: Vec::new(),
+            hints: Hints::default(),
+            system_addresses: false,
+            syntax: Syntax::Sasm,
+            on_diagnostic: None,
+            custom_format: None,
+            custom_writer: None,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn build(args: &[String]) -> Result<Config, String> {
+        // Config with default values. Only zpm must be changed before build completes.
+        let mut config = Config::with_defaults(IType::Stdin, OType::Stdout, Zpm::None);
+
+        // "sasm run" runs the program instead of writing it out, "sasm test" runs its tests, and
+        // "sasm verify" checks it against its disassembly. The rest are flags as usual.
+        config.run = args.get(1).is_some_and(|a| a == "run");
+        config.test = args.get(1).is_some_and(|a| a == "test");
+        config.verify = args.get(1).is_some_and(|a| a == "verify");
+        config.diff = args.get(1).is_some_and(|a| a == "diff");
+        config.import = args.get(1).is_some_and(|a| a == "import");
+        config.export = args.get(1).is_some_and(|a| a == "export");
+
+        // "sasm diff" takes the two files to compare before its flags
+        let diff_files: Vec<String> = match config.diff {
+            true => args
+                .iter()
+                .skip(2)
+                .take_while(|a| !a.starts_with('-'))
+                .take(2)
+                .cloned()
+                .collect(),
+            false => Vec::new(),
         };
 
+        // Long flags may be given as "--flag=value", which is split into the flag and its argument.
+        let mut expanded_args = Vec::new();
+        let subcommand = config.run
+            || config.test
+            || config.verify
+            || config.diff
+            || config.import
+            || config.export;
+        for a in args
+            .iter()
+            .skip(if subcommand { 2 + diff_files.len() } else { 1 })
+        {
+            match a.split_once('=') {
+                Some((flag, value)) if a.starts_with("--") => {
+                    expanded_args.push(flag.to_string());
+                    expanded_args.push(value.to_string());
+                }
+                _ => expanded_args.push(a.to_string()),
+            }
+        }
+
+        // The project file is loaded first so that flags can override its settings
+        let is_assembler = args
+            .first()
+            .and_then(|a| std::path::Path::new(a).file_stem())
+            .is_some_and(|s| s == "sasm");
+        let project_file = match expanded_args
+            .iter()
+            .position(|a| a == "-c" || a == "--config")
+        {
+            Some(i) => match expanded_args.get(i + 1) {
+                Some(f) => Some(f.to_string()),
+                None => return Err(format!("Flag {} requires an argument", expanded_args[i])),
+            },
+            None if is_assembler && std::path::Path::new(DEFAULT_PROJECT_FILE).exists() => {
+                Some(DEFAULT_PROJECT_FILE.to_string())
+            }
+            None => None,
+        };
+        if let Some(f) = project_file {
+            config.apply_project_file(&f)?;
+        }
+        if !diff_files.is_empty() {
+            config.itype = IType::Files(diff_files);
+        }
+
         // Simple but strict argument parser. All flags are optional.
         let mut current_flag = CLFlag::None;
-        let mut args_iter = args.iter();
-        _ = args_iter.next();
-        for a in args_iter {
-            // Process flags
-            if a.starts_with('-') {
+        for a in expanded_args.iter() {
+            // Process flags. A lone "-" is an argument meaning STDOUT.
+            if a.starts_with('-') && a != "-" {
                 if let CLFlag::None = current_flag {
                     match a.as_str() {
-                        "-h" => return Err(help().to_string()),
-                        "-i" => current_flag = CLFlag::Ifile,
-                        "-o" => current_flag = CLFlag::Ofile,
-                        "-s" => current_flag = CLFlag::Sys,
-                        "-f" => current_flag = CLFlag::Format,
-                        "-a" => current_flag = CLFlag::Addr,
-                        "-m" => current_flag = CLFlag::MinRegSize,
+                        "-h" | "--help" => {
+                            config.action = Action::PrintHelp;
+                            return Ok(config);
+                        }
+                        "-V" | "--version" => {
+                            config.action = Action::PrintVersion;
+                            return Ok(config);
+                        }
+                        "-i" | "--input" => current_flag = CLFlag::Ifile,
+                        "-o" | "--output" => current_flag = CLFlag::Ofile,
+                        "-s" | "--system" => current_flag = CLFlag::Sys,
+                        "--zp-reserve" => current_flag = CLFlag::ZpReserve,
+                        "--zp-range" => current_flag = CLFlag::ZpRange,
+                        "--comments" => current_flag = CLFlag::Comments,
+                        "-f" | "--format" => current_flag = CLFlag::Format,
+                        "-F" | "--fill" => current_flag = CLFlag::Fill,
+                        "-l" | "--symbols" => current_flag = CLFlag::Symfile,
+                        "--symbol-format" => current_flag = CLFlag::SymbolFormat,
+                        "--source-map" => current_flag = CLFlag::SourceMap,
+                        "--dbg-file" => current_flag = CLFlag::DbgFile,
+                        "--xref" => current_flag = CLFlag::Xref,
+                        "--cfg" => current_flag = CLFlag::CfgFile,
+                        "--report" => current_flag = CLFlag::Report,
+                        "--labels" => current_flag = CLFlag::Labels,
+                        "-C" | "--checksum" => current_flag = CLFlag::Checksum,
+                        "-a" | "--addr" => current_flag = CLFlag::Addr,
+                        "--offset" => current_flag = CLFlag::Offset,
+                        "--length" => current_flag = CLFlag::Length,
+                        "--banking" => current_flag = CLFlag::Banking,
+                        "-m" | "--min-region" => current_flag = CLFlag::MinRegSize,
+                        "--max-invalid" => current_flag = CLFlag::MaxInvalid,
+                        "--min-density" => current_flag = CLFlag::MinDensity,
+                        "--min-confidence" => current_flag = CLFlag::MinConfidence,
+                        "--rare-penalty" => current_flag = CLFlag::RarePenalty,
+                        "--refine" => config.refine_regions = true,
+                        "-u" | "--illegal" => config.illegal = true,
+                        "--cycles" => config.cycles = true,
+                        "--bytes" => config.show_bytes = true,
+                        "--stats" => config.stats = true,
+                        "--zp-optimize" => config.zp_optimize = true,
+                        "--zp-labels" => config.zp_labels = true,
+                        "--ext-labels" => config.ext_labels = true,
+                        "--long-branches" => config.long_branches = true,
+                        "-W" | "--warn" => current_flag = CLFlag::Warnings,
+                        "-Werror" | "--werror" => config.warnings.as_errors = true,
+                        w if w.len() > 2 && w.starts_with("-W") => {
+                            config.warnings.apply(&w[2..])?
+                        }
+                        "-c" | "--config" => current_flag = CLFlag::ProjectFile,
+                        "-D" | "--define" => current_flag = CLFlag::Define,
+                        "-I" | "--include" => current_flag = CLFlag::IncludePath,
+                        "--force" => config.overwrite = Overwrite::Force,
+                        "--backup" => config.overwrite = Overwrite::Backup,
+                        "--force-binary-stdout" => config.force_binary_stdout = true,
+                        "-w" | "--watch" => config.watch = true,
+                        "--max-cycles" => current_flag = CLFlag::MaxCycles,
+                        "--dump" => current_flag = CLFlag::Dump,
+                        "--emit-obj" => config.emit_obj = true,
+                        "--link" => config.link = true,
+                        "--patch" => current_flag = CLFlag::Patch,
+                        "--color" => current_flag = CLFlag::Color,
+                        "--error-format" => current_flag = CLFlag::ErrorFormat,
+                        "--input-format" => current_flag = CLFlag::InputFormat,
+                        "-e" | "--entry" => current_flag = CLFlag::Entry,
+                        "-H" | "--hints" => current_flag = CLFlag::Hints,
+                        "--syntax" => current_flag = CLFlag::Syntax,
+                        "--isa" => current_flag = CLFlag::Isa,
+                        "-q" | "--quiet" => config.verbosity = LevelFilter::Error,
+                        "-v" | "--verbose" => config.verbosity = more_verbose(config.verbosity),
+                        "-vv" => config.verbosity = more_verbose(more_verbose(config.verbosity)),
                         _ => return Err(format!("Invalid flag: {a}")),
                     }
                 } else {
@@ -91,27 +1057,7 @@ impl Config {
 
             // Process arguments
             } else {
-                match current_flag {
-                    CLFlag::Ifile => config.itype = IType::File(a.to_string()),
-                    CLFlag::Ofile => config.otype = OType::File(a.to_string()),
-                    CLFlag::Sys => config.zpm = Zpm::new(a)?,
-                    CLFlag::Format => config.cformat = CodeFormat::new(a)?,
-                    CLFlag::Addr => {
-                        config.addr = match u16::from_str_radix(&a, 16) {
-                            Ok(n) => n,
-                            _ => return Err("Invalid starting address".to_string()),
-                        }
-                    }
-                    CLFlag::MinRegSize => {
-                        config.min_region_size = match a.parse() {
-                            Ok(n) => n,
-                            _ => return Err("Invalid minimum region size".to_string()),
-                        }
-                    }
-                    CLFlag::None => {
-                        return Err(format!("Argument {a} must immediately follow a flag"))
-                    }
-                }
+                config.apply_setting(&current_flag, a)?;
 
                 current_flag = CLFlag::None;
             }
@@ -122,28 +1068,221 @@ impl Config {
             config.zpm = Zpm::new_for_apple();
         }
 
+        // The zero-page range applies to whichever system is set, so it waits for all flags
+        if let Some(range) = config.zp_range {
+            config.zpm = Zpm::new(config.zpm.system(), Some(range))?;
+        }
+
         // Check for illegal combinations
-        match config.zpm {
-            Zpm::Atari2600 { .. } => match config.cformat {
-                CodeFormat::AppleSM => {
-                    return Err("Apple System Monitor output not compatible with Atari".to_string())
-                }
-                _ => (),
-            },
+        if config.emit_obj && config.link {
+            return Err("Cannot both write and link object files".to_string());
+        }
+        if config.patch.is_some()
+            && (config.emit_obj
+                || matches!(
+                    config.cformat,
+                    CodeFormat::Json | CodeFormat::Listing | CodeFormat::Reloc
+                ))
+        {
+            return Err("Patching requires a memory image format".to_string());
+        }
+        match (&config.zpm, config.cformat) {
+            (Zpm::Apple { .. }, CodeFormat::AppleSM)
+            | (Zpm::Nes { .. }, CodeFormat::Ines)
+            | (Zpm::C64 { .. } | Zpm::Vic20 { .. }, CodeFormat::Prg)
+            | (Zpm::Atari8 { .. }, CodeFormat::Xex) => (),
+            (Zpm::Atari2600 { .. }, CodeFormat::AppleSM) => {
+                return Err("Apple System Monitor output not compatible with Atari".to_string());
+            }
+            (_, CodeFormat::AppleSM) => {
+                return Err(format!(
+                    "Apple System Monitor output not compatible with {}",
+                    config.zpm.system()
+                ));
+            }
+            (_, CodeFormat::Ines) => return Err("iNES output requires the NES system".to_string()),
+            (_, CodeFormat::Prg) => {
+                return Err("PRG output requires the C64 or VIC-20 system".to_string());
+            }
+            (_, CodeFormat::Xex) => {
+                return Err("XEX output requires the Atari 8-bit system".to_string());
+            }
             _ => (),
         }
 
-        return Ok(config);
+        if let CLFlag::None = current_flag {
+            return Ok(config);
+        }
+        return Err(format!(
+            "Flag {} requires an argument",
+            expanded_args.last().map_or("", |s| s.as_str())
+        ));
     }
 
-    pub fn build_string_test(input_string: &str) -> Config {
-        Config {
-            itype: IType::String(input_string.to_string()),
-            otype: OType::None,
-            zpm: Zpm::new_for_apple(),
-            cformat: CodeFormat::Hex,
-            addr: 0,
-            min_region_size: 10,
+    // Find an input file, searching the include paths if it is not found as given
+    pub fn resolve_input(&self, f: &str) -> String {
+        find_file(f, &self.include_paths)
+    }
+
+    // Instruction set to assemble or disassemble with
+    pub fn instruction_set(&self) -> Isa<'_> {
+        Isa {
+            illegal: self.illegal,
+            table: self.isa.as_ref(),
+        }
+    }
+
+    // Input files, with include paths applied. Empty if input is not from files.
+    pub fn input_files(&self) -> Vec<String> {
+        match self.itype {
+            IType::File(ref f) => vec![self.resolve_input(f)],
+            IType::Files(ref files) => files.iter().map(|f| self.resolve_input(f)).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // Pass an error or warning to the callback, if there is one
+    pub fn notify(&mut self, d: Diagnostic) {
+        if let Some(f) = self.on_diagnostic.as_mut() {
+            f(d);
         }
     }
+
+    // Record a warning, unless it is disabled
+    pub fn warn(&mut self, w: SasmWarning) {
+        if self.warnings.is_enabled(w.kind) {
+            self.notify(Diagnostic::Warning(&w));
+        }
+        self.warnings.warn(w);
+    }
+
+    // Clear state left over from a previous assembly, so that the config can be reused
+    pub fn reset(&mut self) {
+        self.zpm.reset();
+        self.warnings.list.clear();
+    }
+
+    pub fn build_string_test(input_string: &str) -> Config {
+        Config::with_defaults(
+            IType::String(input_string.to_string()),
+            OType::None,
+            Zpm::new_for_apple(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_from(args: &[&str]) -> Result<Config, String> {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        Config::build(&args)
+    }
+
+    #[test]
+    fn long_flags() {
+        let c =
+            build_from(&["sasm", "--input", "in.s", "--format=bin", "--addr", "0x300"]).unwrap();
+        assert!(matches!(c.itype, IType::File(ref f) if f == "in.s"));
+        assert!(matches!(c.cformat, CodeFormat::Binary));
+        assert_eq!(c.addr, 0x300);
+    }
+
+    #[test]
+    fn help_and_version() {
+        let c = build_from(&["sasm", "-i", "in.s", "--version"]).unwrap();
+        assert_eq!(c.action, Action::PrintVersion);
+        assert_eq!(c.action.text(), Some(version().to_string()));
+        let c = build_from(&["dtsasm", "-h"]).unwrap();
+        assert_eq!(c.action, Action::PrintHelp);
+        assert_eq!(build_from(&["sasm"]).unwrap().action.text(), None);
+    }
+
+    #[test]
+    fn hex_argument_prefixes() {
+        assert_eq!(parse_hex_arg::<u16>("$c000"), Some(0xc000));
+        assert_eq!(parse_hex_arg::<u16>("0x300"), Some(0x300));
+        assert_eq!(parse_hex_arg::<u8>("ea"), Some(0xea));
+        assert_eq!(parse_hex_arg::<u8>("100"), None);
+    }
+
+    #[test]
+    fn missing_argument() {
+        assert_eq!(
+            build_from(&["sasm", "--output"]).err(),
+            Some("Flag --output requires an argument".to_string())
+        );
+    }
+
+    #[test]
+    fn dash_output_is_stdout() {
+        let config = build_from(&["sasm", "-o", "-", "--force-binary-stdout"]).unwrap();
+        assert!(matches!(config.otype, OType::Stdout));
+        assert!(config.force_binary_stdout);
+    }
+
+    #[test]
+    fn project_file_with_overrides() {
+        let path = std::env::temp_dir().join("sasm2_project_file_test.toml");
+        std::fs::write(
+            &path,
+            "input = [\"a.asm\", \"b.asm\"]\nformat = \"bin\"\nfill = \"ea\"\n\
+             [defines]\nscreen = \"0400\"\n",
+        )
+        .unwrap();
+
+        let c = build_from(&[
+            "sasm",
+            "-c",
+            path.to_str().unwrap(),
+            "-F",
+            "00",
+            "-D",
+            "x=1",
+        ]);
+        std::fs::remove_file(&path).unwrap();
+        let c = c.unwrap();
+
+        assert!(matches!(c.itype, IType::Files(ref f) if f.len() == 2));
+        assert!(matches!(c.cformat, CodeFormat::Binary));
+        assert_eq!(c.fill, 0);
+        assert_eq!(c.defines.len(), 2);
+        assert!(matches!(c.defines[0], (ref n, UInt::U16(0x400)) if n == "screen"));
+    }
+
+    #[test]
+    fn zp_range_after_system() {
+        let mut c = build_from(&["sasm", "--zp-range", "90:9f", "-s", "atari"]).unwrap();
+        assert_eq!(c.zpm.alloc(1), Ok(0x90));
+        assert_eq!(
+            build_from(&["sasm", "--zp-range", "40:9f", "-s", "atari"]).err(),
+            Some("Atari 2600 zero-page range must be within 80-ff".to_string())
+        );
+    }
+
+    #[test]
+    fn verbosity_flags() {
+        assert_eq!(build_from(&["sasm"]).unwrap().verbosity, LevelFilter::Warn);
+        assert_eq!(
+            build_from(&["sasm", "-v"]).unwrap().verbosity,
+            LevelFilter::Info
+        );
+        assert_eq!(
+            build_from(&["sasm", "-vv", "-v"]).unwrap().verbosity,
+            LevelFilter::Trace
+        );
+        assert_eq!(
+            build_from(&["sasm", "-q"]).unwrap().verbosity,
+            LevelFilter::Error
+        );
+    }
+
+    #[test]
+    fn unknown_long_flag() {
+        assert_eq!(
+            build_from(&["sasm", "--bogus=1"]).err(),
+            Some("Invalid flag: --bogus".to_string())
+        );
+    }
 }
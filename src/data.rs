@@ -1,31 +1,100 @@
-use std::collections::HashMap;
-use std::sync::LazyLock;
+use alloc::collections::BTreeMap;
+use spin::Lazy;
 
-pub fn get_instr_info(mnemonic: &str) -> Result<&InstrInfo, &str> {
-    match ISA_BY_MNEMONIC.get(mnemonic) {
-        Some(i) => Ok(i),
+use crate::prelude::*;
+use crate::toml::{self, Value};
+
+// Instruction set in use: the built-in instructions, the undocumented ones if they are allowed,
+// and those loaded from an ISA file
+#[derive(Clone, Copy, Default)]
+pub struct Isa<'a> {
+    pub illegal: bool,
+    pub table: Option<&'a IsaTable>,
+}
+
+// Undocumented (illegal) NMOS opcodes are only recognized when explicitly allowed. They are kept
+// in a separate table so that the standard instruction set is unaffected by default.
+pub fn get_instr_info<'a>(mnemonic: &str, isa: Isa<'a>) -> Result<&'a InstrInfo, &'static str> {
+    if let Some(t) = isa.table {
+        if let Some(i) = t.instrs.get(mnemonic) {
+            return Ok(i);
+        }
+        if t.replace {
+            return Err("mnemonic not found");
+        }
+    }
+    if let Some(i) = ISA_BY_MNEMONIC.get(mnemonic) {
+        return Ok(i);
+    }
+
+    match ILLEGAL_BY_MNEMONIC.get(mnemonic) {
+        Some(i) if isa.illegal => Ok(i),
+        Some(_) => Err("undocumented instruction (enable with -u)"),
         // TODO: Detailed errors about unsupported or missing flags
         None => Err("mnemonic not found"),
     }
 }
 
-pub fn get_instr_size(mnemonic: &str) -> Result<u8, &str> {
-    match ISA_BY_MNEMONIC.get(mnemonic) {
-        Some(i) => match i.op {
-            OpType::None => Ok(1),
-            OpType::U8 => Ok(2),
-            OpType::U16 => Ok(3),
-        },
-        None => Err("mnemonic not found"),
+// Cycle count of an instruction for annotating output, such as "4+" when crossing a page adds one
+pub fn get_instr_cycles(mnemonic: &str, isa: Isa) -> Option<String> {
+    let i = get_instr_info(mnemonic, isa).ok()?;
+    match i.page_penalty {
+        true => Some(format!("{}+", i.cycles)),
+        false => Some(i.cycles.to_string()),
     }
 }
 
-pub fn get_instr_info_from_opcode(opcode: u8) -> Option<&'static InstrInfo> {
-    return ISA_BY_OPCODE[opcode as usize];
+pub fn get_instr_size(mnemonic: &str, isa: Isa) -> Result<u8, &'static str> {
+    let i = get_instr_info(mnemonic, isa)?;
+    match i.op {
+        OpType::None => Ok(1),
+        OpType::U8 => Ok(2),
+        OpType::U16 => Ok(3),
+    }
+}
+
+// Zero-page form of an absolute-mode instruction, such as "ldazx" for "ldaax", if the instruction
+// set has one. Forms are related by the mode letters after the three-letter operation.
+pub fn get_zero_page_form(mnemonic: &str, isa: Isa) -> Option<String> {
+    let mode = match mnemonic.get(3..)? {
+        "a" => "z",
+        "ax" => "zx",
+        "ay" => "zy",
+        _ => return None,
+    };
+    let zp = format!("{}{mode}", &mnemonic[..3]);
+    match (get_instr_info(mnemonic, isa), get_instr_info(&zp, isa)) {
+        (Ok(a), Ok(z)) if matches!((&a.op, &z.op), (OpType::U16, OpType::U8)) => Some(zp),
+        _ => None,
+    }
+}
+
+pub fn get_instr_info_from_opcode(opcode: u8, isa: Isa<'_>) -> Option<&InstrInfo> {
+    if let Some(t) = isa.table {
+        if let Some(i) = t.opcodes.get(&opcode).and_then(|m| t.instrs.get(m)) {
+            return Some(i);
+        }
+        if t.replace {
+            return None;
+        }
+    }
+    let i = match isa.illegal {
+        true => ISA_WITH_ILLEGAL_BY_OPCODE[opcode as usize],
+        false => ISA_BY_OPCODE[opcode as usize],
+    }?;
+
+    // A built-in instruction given a new opcode in the ISA file no longer has its old one
+    match isa
+        .table
+        .is_some_and(|t| t.instrs.contains_key(&i.mnemonic))
+    {
+        true => None,
+        false => Some(i),
+    }
 }
 
-pub fn get_instr_size_from_opcode(opcode: u8) -> Option<u8> {
-    match ISA_BY_OPCODE[opcode as usize] {
+pub fn get_instr_size_from_opcode(opcode: u8, isa: Isa) -> Option<u8> {
+    match get_instr_info_from_opcode(opcode, isa) {
         Some(i) => match i.op {
             OpType::None => Some(1),
             OpType::U8 => Some(2),
@@ -34,187 +103,849 @@ pub fn get_instr_size_from_opcode(opcode: u8) -> Option<u8> {
         None => None,
     }
 }
+// Whether an opcode is rarely found in real code, which makes it more likely to be data. These are
+// the undocumented opcodes, BRK, SED, CLV, and the (zero page,X) addressing mode.
+pub fn is_rare_opcode(opcode: u8, isa: Isa) -> bool {
+    let documented = Isa {
+        illegal: false,
+        ..isa
+    };
+    match get_instr_info_from_opcode(opcode, documented) {
+        Some(i) => {
+            // The (zero page,X) mode, but not inx
+            ["brk", "sed", "clv"].contains(&i.mnemonic.as_str())
+                || i.mnemonic.get(3..) == Some("nx")
+        }
+        None => isa.illegal && get_instr_info_from_opcode(opcode, isa).is_some(),
+    }
+}
+
 pub fn is_relative_branch_instruction(mnemonic: &str) -> bool {
     let instrs = ["bpl", "bmi", "bvc", "bvs", "bcc", "bcs", "bne", "beq"];
     return instrs.contains(&mnemonic.to_lowercase().as_str());
 }
 
+// Branch taken in exactly the cases that the given one is not, such as "beq" for "bne"
+pub fn get_opposite_branch(mnemonic: &str) -> Option<&'static str> {
+    let pairs = [
+        ("bpl", "bmi"),
+        ("bvc", "bvs"),
+        ("bcc", "bcs"),
+        ("bne", "beq"),
+    ];
+    pairs.iter().find_map(|(a, b)| match mnemonic {
+        m if m == *a => Some(*b),
+        m if m == *b => Some(*a),
+        _ => None,
+    })
+}
+
+// Whether an instruction only writes to its address (including the undocumented "sax")
+pub fn is_store_instruction(mnemonic: &str) -> bool {
+    let instrs = ["sta", "stx", "sty", "sax"];
+    return instrs
+        .iter()
+        .any(|i| mnemonic.to_lowercase().starts_with(i));
+}
+
+// Whether an instruction uses zero-page addressing, possibly indexed, but not indirect
+pub fn is_zero_page_instruction(mnemonic: &str) -> bool {
+    return mnemonic.len() > 3 && mnemonic[3..].to_lowercase().starts_with('z');
+}
+
+// Whether the operand of an instruction is a zero-page address, used directly or as a pointer
+pub fn is_zero_page_operand(mnemonic: &str) -> bool {
+    is_zero_page_instruction(mnemonic) || matches!(mnemonic.get(3..), Some("nx" | "ny"))
+}
+
+#[derive(Clone, Debug)]
 pub struct InstrInfo {
     pub mnemonic: String,
     pub opcode: u8,
     pub op: OpType,
+
+    // Cycles taken, and whether one more is taken when a page boundary is crossed. For branches,
+    // this means one more when the branch is taken and another when it also crosses a page.
+    pub cycles: u8,
+    pub page_penalty: bool,
 }
 
+#[derive(Clone, Debug)]
 pub enum OpType {
     U8,
     U16,
     None,
 }
 
-fn new_instr(mnemonic: &str, opcode: u8, op: OpType) -> (String, InstrInfo) {
-    (mnemonic.to_string(), InstrInfo { mnemonic: mnemonic.to_string(), opcode, op })
-}
-
-static ISA_BY_MNEMONIC: LazyLock<HashMap<String, InstrInfo>> = LazyLock::new(|| {
-    HashMap::from([
-        new_instr("adci", 0x69, OpType::U8),
-        new_instr("adcz", 0x65, OpType::U8),
-        new_instr("adczx", 0x75, OpType::U8),
-        new_instr("adca", 0x6d, OpType::U16),
-        new_instr("adcax", 0x7d, OpType::U16),
-        new_instr("adcay", 0x79, OpType::U16),
-        new_instr("adcnx", 0x61, OpType::U8),
-        new_instr("adcny", 0x71, OpType::U8),
-        new_instr("andi", 0x29, OpType::U8),
-        new_instr("andz", 0x25, OpType::U8),
-        new_instr("andzx", 0x35, OpType::U8),
-        new_instr("anda", 0x2d, OpType::U16),
-        new_instr("andax", 0x3d, OpType::U16),
-        new_instr("anday", 0x39, OpType::U16),
-        new_instr("andnx", 0x21, OpType::U8),
-        new_instr("andny", 0x31, OpType::U8),
-        new_instr("asl", 0x0a, OpType::None),
-        new_instr("aslz", 0x06, OpType::U8),
-        new_instr("aslzx", 0x16, OpType::U8),
-        new_instr("asla", 0x0e, OpType::U16),
-        new_instr("aslax", 0x1e, OpType::U16),
-        new_instr("bitz", 0x24, OpType::U8),
-        new_instr("bita", 0x2c, OpType::U16),
-        new_instr("bpl", 0x10, OpType::U8),
-        new_instr("bmi", 0x30, OpType::U8),
-        new_instr("bvc", 0x50, OpType::U8),
-        new_instr("bvs", 0x70, OpType::U8),
-        new_instr("bcc", 0x90, OpType::U8),
-        new_instr("bcs", 0xb0, OpType::U8),
-        new_instr("bne", 0xd0, OpType::U8),
-        new_instr("beq", 0xf0, OpType::U8),
-        new_instr("brk", 0x00, OpType::None),
-        new_instr("cmpi", 0xc9, OpType::U8),
-        new_instr("cmpz", 0xc5, OpType::U8),
-        new_instr("cmpzx", 0xd5, OpType::U8),
-        new_instr("cmpa", 0xcd, OpType::U16),
-        new_instr("cmpax", 0xdd, OpType::U16),
-        new_instr("cmpay", 0xd9, OpType::U16),
-        new_instr("cmpnx", 0xc1, OpType::U8),
-        new_instr("cmpny", 0xd1, OpType::U8),
-        new_instr("cpxi", 0xe0, OpType::U8),
-        new_instr("cpxz", 0xe4, OpType::U8),
-        new_instr("cpxa", 0xec, OpType::U16),
-        new_instr("cpyi", 0xc0, OpType::U8),
-        new_instr("cpyz", 0xc4, OpType::U8),
-        new_instr("cpya", 0xcc, OpType::U16),
-        new_instr("decz", 0xc6, OpType::U8),
-        new_instr("deczx", 0xd6, OpType::U8),
-        new_instr("deca", 0xce, OpType::U16),
-        new_instr("decax", 0xde, OpType::U16),
-        new_instr("eori", 0x49, OpType::U8),
-        new_instr("eorz", 0x45, OpType::U8),
-        new_instr("eorzx", 0x55, OpType::U8),
-        new_instr("eora", 0x4d, OpType::U16),
-        new_instr("eorax", 0x5d, OpType::U16),
-        new_instr("eoray", 0x59, OpType::U16),
-        new_instr("eornx", 0x41, OpType::U8),
-        new_instr("eorny", 0x51, OpType::U8),
-        new_instr("clc", 0x18, OpType::None),
-        new_instr("sec", 0x38, OpType::None),
-        new_instr("cli", 0x58, OpType::None),
-        new_instr("sei", 0x78, OpType::None),
-        new_instr("clv", 0xb8, OpType::None),
-        new_instr("cld", 0xd8, OpType::None),
-        new_instr("sed", 0xf8, OpType::None),
-        new_instr("incz", 0xe6, OpType::U8),
-        new_instr("inczx", 0xf6, OpType::U8),
-        new_instr("inca", 0xee, OpType::U16),
-        new_instr("incax", 0xfe, OpType::U16),
-        new_instr("jmpa", 0x4c, OpType::U16),
-        new_instr("jmpn", 0x6c, OpType::U8),
-        new_instr("jsra", 0x20, OpType::U16),
-        new_instr("ldai", 0xa9, OpType::U8),
-        new_instr("ldaz", 0xa5, OpType::U8),
-        new_instr("ldazx", 0xb5, OpType::U8),
-        new_instr("ldaa", 0xad, OpType::U16),
-        new_instr("ldaax", 0xbd, OpType::U16),
-        new_instr("ldaay", 0xb9, OpType::U16),
-        new_instr("ldanx", 0xa1, OpType::U8),
-        new_instr("ldany", 0xb1, OpType::U8),
-        new_instr("ldxi", 0xa2, OpType::U8),
-        new_instr("ldxz", 0xa6, OpType::U8),
-        new_instr("ldxzy", 0xb6, OpType::U8),
-        new_instr("ldxa", 0xae, OpType::U16),
-        new_instr("ldxay", 0xbe, OpType::U16),
-        new_instr("ldyi", 0xa0, OpType::U8),
-        new_instr("ldyz", 0xa4, OpType::U8),
-        new_instr("ldyzx", 0xb4, OpType::U8),
-        new_instr("ldya", 0xac, OpType::U16),
-        new_instr("ldyax", 0xbc, OpType::U16),
-        new_instr("lsr", 0x4a, OpType::None),
-        new_instr("lsrz", 0x46, OpType::U8),
-        new_instr("lsrzx", 0x56, OpType::U8),
-        new_instr("lsra", 0x4e, OpType::U16),
-        new_instr("lsrax", 0x5e, OpType::U16),
-        new_instr("nop", 0xea, OpType::None),
-        new_instr("orai", 0x09, OpType::U8),
-        new_instr("oraz", 0x05, OpType::U8),
-        new_instr("orazx", 0x15, OpType::U8),
-        new_instr("oraa", 0x0d, OpType::U16),
-        new_instr("oraax", 0x1d, OpType::U16),
-        new_instr("oraay", 0x19, OpType::U16),
-        new_instr("oranx", 0x01, OpType::U8),
-        new_instr("orany", 0x11, OpType::U8),
-        new_instr("tax", 0xaa, OpType::None),
-        new_instr("txa", 0x8a, OpType::None),
-        new_instr("dex", 0xca, OpType::None),
-        new_instr("inx", 0xe8, OpType::None),
-        new_instr("tay", 0xa8, OpType::None),
-        new_instr("tya", 0x98, OpType::None),
-        new_instr("dey", 0x88, OpType::None),
-        new_instr("iny", 0xc8, OpType::None),
-        new_instr("rol", 0x2a, OpType::None),
-        new_instr("rolz", 0x26, OpType::U8),
-        new_instr("rolzx", 0x36, OpType::U8),
-        new_instr("rola", 0x2e, OpType::U16),
-        new_instr("rolax", 0x3e, OpType::U16),
-        new_instr("ror", 0x6a, OpType::None),
-        new_instr("rorz", 0x66, OpType::U8),
-        new_instr("rorzx", 0x76, OpType::U8),
-        new_instr("rora", 0x6e, OpType::U16),
-        new_instr("rorax", 0x7e, OpType::U16),
-        new_instr("rti", 0x40, OpType::None),
-        new_instr("rts", 0x60, OpType::None),
-        new_instr("sbci", 0xe9, OpType::U8),
-        new_instr("sbcz", 0xe5, OpType::U8),
-        new_instr("sbczx", 0xf5, OpType::U8),
-        new_instr("sbca", 0xed, OpType::U16),
-        new_instr("sbcax", 0xfd, OpType::U16),
-        new_instr("sbcay", 0xf9, OpType::U16),
-        new_instr("sbcnx", 0xe1, OpType::U8),
-        new_instr("sbcny", 0xf1, OpType::U8),
-        new_instr("staz", 0x85, OpType::U8),
-        new_instr("stazx", 0x95, OpType::U8),
-        new_instr("staa", 0x8d, OpType::U16),
-        new_instr("staax", 0x9d, OpType::U16),
-        new_instr("staay", 0x99, OpType::U16),
-        new_instr("stanx", 0x81, OpType::U8),
-        new_instr("stany", 0x91, OpType::U8),
-        new_instr("txs", 0x9a, OpType::None),
-        new_instr("tsx", 0xba, OpType::None),
-        new_instr("pha", 0x48, OpType::None),
-        new_instr("pla", 0x68, OpType::None),
-        new_instr("php", 0x08, OpType::None),
-        new_instr("plp", 0x28, OpType::None),
-        new_instr("stxz", 0x86, OpType::U8),
-        new_instr("stxzy", 0x96, OpType::U8),
-        new_instr("stxa", 0x8e, OpType::U16),
-        new_instr("styz", 0x84, OpType::U8),
-        new_instr("styzx", 0x94, OpType::U8),
-        new_instr("stya", 0x8c, OpType::U16),
+fn new_instr(
+    mnemonic: &str,
+    opcode: u8,
+    op: OpType,
+    cycles: u8,
+    page_penalty: bool,
+) -> (String, InstrInfo) {
+    (
+        mnemonic.to_string(),
+        InstrInfo {
+            mnemonic: mnemonic.to_string(),
+            opcode,
+            op,
+            cycles,
+            page_penalty,
+        },
+    )
+}
+
+// Instructions loaded from an ISA file, which replace the built-in instruction set or extend it.
+// The file is in TOML, with a line for each instruction in an [instructions] table:
+//   MNEMONIC = "OPCODE OPERAND_BYTES CYCLES"
+// The opcode is in hex, the operand is 0, 1, or 2 bytes, and the cycles may end in '+' for one
+// more when a page is crossed. A top-level "replace = true" drops the built-in instructions.
+#[derive(Clone, Debug, Default)]
+pub struct IsaTable {
+    instrs: BTreeMap<String, InstrInfo>,
+    opcodes: BTreeMap<u8, String>,
+    replace: bool,
+}
+
+fn parse_instr(mnemonic: &str, value: &str) -> Result<InstrInfo, String> {
+    let fields: Vec<&str> = value.split_ascii_whitespace().collect();
+    let [opcode, operand, cycles] = fields.as_slice() else {
+        return Err(format!(
+            "{mnemonic} must be \"OPCODE OPERAND_BYTES CYCLES\", such as \"a9 1 2\""
+        ));
+    };
+    let opcode = match u8::from_str_radix(opcode, 16) {
+        Ok(o) => o,
+        Err(_) => return Err(format!("invalid opcode {opcode} for {mnemonic}")),
+    };
+    let op = match *operand {
+        "0" => OpType::None,
+        "1" => OpType::U8,
+        "2" => OpType::U16,
+        _ => return Err(format!("operand of {mnemonic} must be 0, 1, or 2 bytes")),
+    };
+    let (count, page_penalty) = match cycles.strip_suffix('+') {
+        Some(c) => (c, true),
+        None => (*cycles, false),
+    };
+    let cycles = match count.parse() {
+        Ok(c) => c,
+        Err(_) => return Err(format!("invalid cycle count {cycles} for {mnemonic}")),
+    };
+    Ok(new_instr(mnemonic, opcode, op, cycles, page_penalty).1)
+}
+
+impl IsaTable {
+    pub fn parse(text: &str) -> Result<IsaTable, String> {
+        let mut table = IsaTable::default();
+        let mut lines = BTreeMap::new();
+
+        for entry in toml::parse(text)? {
+            let line_num = entry.line;
+            match (entry.table.as_str(), entry.key.as_str(), entry.value) {
+                ("", "replace", Value::Boolean(b)) => table.replace = b,
+                ("", "replace", _) => {
+                    return Err(format!("{line_num}: replace must be true or false"))
+                }
+                ("instructions", m, Value::String(v)) => {
+                    if !m.starts_with(|c: char| c.is_ascii_lowercase())
+                        || !m
+                            .chars()
+                            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+                    {
+                        return Err(format!(
+                            "{line_num}: mnemonic {m} must be lowercase letters and digits"
+                        ));
+                    }
+                    let instr = parse_instr(m, &v).map_err(|e| format!("{line_num}: {e}"))?;
+                    if table.instrs.contains_key(m) {
+                        return Err(format!("{line_num}: {m} is defined twice"));
+                    }
+                    if let Some(other) = table.opcodes.get(&instr.opcode) {
+                        return Err(format!(
+                            "{line_num}: opcode {:02x} of {m} is already used by {other}",
+                            instr.opcode
+                        ));
+                    }
+                    table.opcodes.insert(instr.opcode, m.to_string());
+                    table.instrs.insert(m.to_string(), instr);
+                    lines.insert(m.to_string(), line_num);
+                }
+                ("instructions", m, _) => {
+                    return Err(format!("{line_num}: {m} must be a string"));
+                }
+                ("", k, _) => return Err(format!("{line_num}: unknown key {k}")),
+                (t, _, _) => return Err(format!("{line_num}: unknown table [{t}]")),
+            }
+        }
+
+        // Opcodes must not clash with built-in instructions that are kept
+        if !table.replace {
+            for (opcode, m) in &table.opcodes {
+                if let Some(i) = ISA_BY_OPCODE[*opcode as usize]
+                    .filter(|i| i.mnemonic != *m && !table.instrs.contains_key(&i.mnemonic))
+                {
+                    return Err(format!(
+                        "{}: opcode {opcode:02x} of {m} is already used by {}",
+                        lines[m], i.mnemonic
+                    ));
+                }
+            }
+        }
+
+        Ok(table)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &str) -> Result<IsaTable, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => IsaTable::parse(&text).map_err(|e| format!("{path}:{e}")),
+            Err(_) => Err(format!("Unable to read ISA file {path}")),
+        }
+    }
+}
+
+static ISA_BY_MNEMONIC: Lazy<BTreeMap<String, InstrInfo>> = Lazy::new(|| {
+    BTreeMap::from([
+        new_instr("adci", 0x69, OpType::U8, 2, false),
+        new_instr("adcz", 0x65, OpType::U8, 3, false),
+        new_instr("adczx", 0x75, OpType::U8, 4, false),
+        new_instr("adca", 0x6d, OpType::U16, 4, false),
+        new_instr("adcax", 0x7d, OpType::U16, 4, true),
+        new_instr("adcay", 0x79, OpType::U16, 4, true),
+        new_instr("adcnx", 0x61, OpType::U8, 6, false),
+        new_instr("adcny", 0x71, OpType::U8, 5, true),
+        new_instr("andi", 0x29, OpType::U8, 2, false),
+        new_instr("andz", 0x25, OpType::U8, 3, false),
+        new_instr("andzx", 0x35, OpType::U8, 4, false),
+        new_instr("anda", 0x2d, OpType::U16, 4, false),
+        new_instr("andax", 0x3d, OpType::U16, 4, true),
+        new_instr("anday", 0x39, OpType::U16, 4, true),
+        new_instr("andnx", 0x21, OpType::U8, 6, false),
+        new_instr("andny", 0x31, OpType::U8, 5, true),
+        new_instr("asl", 0x0a, OpType::None, 2, false),
+        new_instr("aslz", 0x06, OpType::U8, 5, false),
+        new_instr("aslzx", 0x16, OpType::U8, 6, false),
+        new_instr("asla", 0x0e, OpType::U16, 6, false),
+        new_instr("aslax", 0x1e, OpType::U16, 7, false),
+        new_instr("bitz", 0x24, OpType::U8, 3, false),
+        new_instr("bita", 0x2c, OpType::U16, 4, false),
+        new_instr("bpl", 0x10, OpType::U8, 2, true),
+        new_instr("bmi", 0x30, OpType::U8, 2, true),
+        new_instr("bvc", 0x50, OpType::U8, 2, true),
+        new_instr("bvs", 0x70, OpType::U8, 2, true),
+        new_instr("bcc", 0x90, OpType::U8, 2, true),
+        new_instr("bcs", 0xb0, OpType::U8, 2, true),
+        new_instr("bne", 0xd0, OpType::U8, 2, true),
+        new_instr("beq", 0xf0, OpType::U8, 2, true),
+        new_instr("brk", 0x00, OpType::None, 7, false),
+        new_instr("cmpi", 0xc9, OpType::U8, 2, false),
+        new_instr("cmpz", 0xc5, OpType::U8, 3, false),
+        new_instr("cmpzx", 0xd5, OpType::U8, 4, false),
+        new_instr("cmpa", 0xcd, OpType::U16, 4, false),
+        new_instr("cmpax", 0xdd, OpType::U16, 4, true),
+        new_instr("cmpay", 0xd9, OpType::U16, 4, true),
+        new_instr("cmpnx", 0xc1, OpType::U8, 6, false),
+        new_instr("cmpny", 0xd1, OpType::U8, 5, true),
+        new_instr("cpxi", 0xe0, OpType::U8, 2, false),
+        new_instr("cpxz", 0xe4, OpType::U8, 3, false),
+        new_instr("cpxa", 0xec, OpType::U16, 4, false),
+        new_instr("cpyi", 0xc0, OpType::U8, 2, false),
+        new_instr("cpyz", 0xc4, OpType::U8, 3, false),
+        new_instr("cpya", 0xcc, OpType::U16, 4, false),
+        new_instr("decz", 0xc6, OpType::U8, 5, false),
+        new_instr("deczx", 0xd6, OpType::U8, 6, false),
+        new_instr("deca", 0xce, OpType::U16, 6, false),
+        new_instr("decax", 0xde, OpType::U16, 7, false),
+        new_instr("eori", 0x49, OpType::U8, 2, false),
+        new_instr("eorz", 0x45, OpType::U8, 3, false),
+        new_instr("eorzx", 0x55, OpType::U8, 4, false),
+        new_instr("eora", 0x4d, OpType::U16, 4, false),
+        new_instr("eorax", 0x5d, OpType::U16, 4, true),
+        new_instr("eoray", 0x59, OpType::U16, 4, true),
+        new_instr("eornx", 0x41, OpType::U8, 6, false),
+        new_instr("eorny", 0x51, OpType::U8, 5, true),
+        new_instr("clc", 0x18, OpType::None, 2, false),
+        new_instr("sec", 0x38, OpType::None, 2, false),
+        new_instr("cli", 0x58, OpType::None, 2, false),
+        new_instr("sei", 0x78, OpType::None, 2, false),
+        new_instr("clv", 0xb8, OpType::None, 2, false),
+        new_instr("cld", 0xd8, OpType::None, 2, false),
+        new_instr("sed", 0xf8, OpType::None, 2, false),
+        new_instr("incz", 0xe6, OpType::U8, 5, false),
+        new_instr("inczx", 0xf6, OpType::U8, 6, false),
+        new_instr("inca", 0xee, OpType::U16, 6, false),
+        new_instr("incax", 0xfe, OpType::U16, 7, false),
+        new_instr("jmpa", 0x4c, OpType::U16, 3, false),
+        new_instr("jmpn", 0x6c, OpType::U16, 5, false),
+        new_instr("jsra", 0x20, OpType::U16, 6, false),
+        new_instr("ldai", 0xa9, OpType::U8, 2, false),
+        new_instr("ldaz", 0xa5, OpType::U8, 3, false),
+        new_instr("ldazx", 0xb5, OpType::U8, 4, false),
+        new_instr("ldaa", 0xad, OpType::U16, 4, false),
+        new_instr("ldaax", 0xbd, OpType::U16, 4, true),
+        new_instr("ldaay", 0xb9, OpType::U16, 4, true),
+        new_instr("ldanx", 0xa1, OpType::U8, 6, false),
+        new_instr("ldany", 0xb1, OpType::U8, 5, true),
+        new_instr("ldxi", 0xa2, OpType::U8, 2, false),
+        new_instr("ldxz", 0xa6, OpType::U8, 3, false),
+        new_instr("ldxzy", 0xb6, OpType::U8, 4, false),
+        new_instr("ldxa", 0xae, OpType::U16, 4, false),
+        new_instr("ldxay", 0xbe, OpType::U16, 4, true),
+        new_instr("ldyi", 0xa0, OpType::U8, 2, false),
+        new_instr("ldyz", 0xa4, OpType::U8, 3, false),
+        new_instr("ldyzx", 0xb4, OpType::U8, 4, false),
+        new_instr("ldya", 0xac, OpType::U16, 4, false),
+        new_instr("ldyax", 0xbc, OpType::U16, 4, true),
+        new_instr("lsr", 0x4a, OpType::None, 2, false),
+        new_instr("lsrz", 0x46, OpType::U8, 5, false),
+        new_instr("lsrzx", 0x56, OpType::U8, 6, false),
+        new_instr("lsra", 0x4e, OpType::U16, 6, false),
+        new_instr("lsrax", 0x5e, OpType::U16, 7, false),
+        new_instr("nop", 0xea, OpType::None, 2, false),
+        new_instr("orai", 0x09, OpType::U8, 2, false),
+        new_instr("oraz", 0x05, OpType::U8, 3, false),
+        new_instr("orazx", 0x15, OpType::U8, 4, false),
+        new_instr("oraa", 0x0d, OpType::U16, 4, false),
+        new_instr("oraax", 0x1d, OpType::U16, 4, true),
+        new_instr("oraay", 0x19, OpType::U16, 4, true),
+        new_instr("oranx", 0x01, OpType::U8, 6, false),
+        new_instr("orany", 0x11, OpType::U8, 5, true),
+        new_instr("tax", 0xaa, OpType::None, 2, false),
+        new_instr("txa", 0x8a, OpType::None, 2, false),
+        new_instr("dex", 0xca, OpType::None, 2, false),
+        new_instr("inx", 0xe8, OpType::None, 2, false),
+        new_instr("tay", 0xa8, OpType::None, 2, false),
+        new_instr("tya", 0x98, OpType::None, 2, false),
+        new_instr("dey", 0x88, OpType::None, 2, false),
+        new_instr("iny", 0xc8, OpType::None, 2, false),
+        new_instr("rol", 0x2a, OpType::None, 2, false),
+        new_instr("rolz", 0x26, OpType::U8, 5, false),
+        new_instr("rolzx", 0x36, OpType::U8, 6, false),
+        new_instr("rola", 0x2e, OpType::U16, 6, false),
+        new_instr("rolax", 0x3e, OpType::U16, 7, false),
+        new_instr("ror", 0x6a, OpType::None, 2, false),
+        new_instr("rorz", 0x66, OpType::U8, 5, false),
+        new_instr("rorzx", 0x76, OpType::U8, 6, false),
+        new_instr("rora", 0x6e, OpType::U16, 6, false),
+        new_instr("rorax", 0x7e, OpType::U16, 7, false),
+        new_instr("rti", 0x40, OpType::None, 6, false),
+        new_instr("rts", 0x60, OpType::None, 6, false),
+        new_instr("sbci", 0xe9, OpType::U8, 2, false),
+        new_instr("sbcz", 0xe5, OpType::U8, 3, false),
+        new_instr("sbczx", 0xf5, OpType::U8, 4, false),
+        new_instr("sbca", 0xed, OpType::U16, 4, false),
+        new_instr("sbcax", 0xfd, OpType::U16, 4, true),
+        new_instr("sbcay", 0xf9, OpType::U16, 4, true),
+        new_instr("sbcnx", 0xe1, OpType::U8, 6, false),
+        new_instr("sbcny", 0xf1, OpType::U8, 5, true),
+        new_instr("staz", 0x85, OpType::U8, 3, false),
+        new_instr("stazx", 0x95, OpType::U8, 4, false),
+        new_instr("staa", 0x8d, OpType::U16, 4, false),
+        new_instr("staax", 0x9d, OpType::U16, 5, false),
+        new_instr("staay", 0x99, OpType::U16, 5, false),
+        new_instr("stanx", 0x81, OpType::U8, 6, false),
+        new_instr("stany", 0x91, OpType::U8, 6, false),
+        new_instr("txs", 0x9a, OpType::None, 2, false),
+        new_instr("tsx", 0xba, OpType::None, 2, false),
+        new_instr("pha", 0x48, OpType::None, 3, false),
+        new_instr("pla", 0x68, OpType::None, 4, false),
+        new_instr("php", 0x08, OpType::None, 3, false),
+        new_instr("plp", 0x28, OpType::None, 4, false),
+        new_instr("stxz", 0x86, OpType::U8, 3, false),
+        new_instr("stxzy", 0x96, OpType::U8, 4, false),
+        new_instr("stxa", 0x8e, OpType::U16, 4, false),
+        new_instr("styz", 0x84, OpType::U8, 3, false),
+        new_instr("styzx", 0x94, OpType::U8, 4, false),
+        new_instr("stya", 0x8c, OpType::U16, 4, false),
     ])
 });
 
-static ISA_BY_OPCODE: LazyLock<[Option<&InstrInfo>; 256]> = LazyLock::new(|| {
+// Stable undocumented NMOS 6502 opcodes, using the same mnemonic modifiers as the standard set.
+// Unstable opcodes (XAA, AHX, TAS, etc.), JAM, and the duplicate encodings of NOP, ANC, and SBC
+// are not included.
+static ILLEGAL_BY_MNEMONIC: Lazy<BTreeMap<String, InstrInfo>> = Lazy::new(|| {
+    BTreeMap::from([
+        new_instr("sloz", 0x07, OpType::U8, 5, false),
+        new_instr("slozx", 0x17, OpType::U8, 6, false),
+        new_instr("sloa", 0x0f, OpType::U16, 6, false),
+        new_instr("sloax", 0x1f, OpType::U16, 7, false),
+        new_instr("sloay", 0x1b, OpType::U16, 7, false),
+        new_instr("slonx", 0x03, OpType::U8, 8, false),
+        new_instr("slony", 0x13, OpType::U8, 8, false),
+        new_instr("rlaz", 0x27, OpType::U8, 5, false),
+        new_instr("rlazx", 0x37, OpType::U8, 6, false),
+        new_instr("rlaa", 0x2f, OpType::U16, 6, false),
+        new_instr("rlaax", 0x3f, OpType::U16, 7, false),
+        new_instr("rlaay", 0x3b, OpType::U16, 7, false),
+        new_instr("rlanx", 0x23, OpType::U8, 8, false),
+        new_instr("rlany", 0x33, OpType::U8, 8, false),
+        new_instr("srez", 0x47, OpType::U8, 5, false),
+        new_instr("srezx", 0x57, OpType::U8, 6, false),
+        new_instr("srea", 0x4f, OpType::U16, 6, false),
+        new_instr("sreax", 0x5f, OpType::U16, 7, false),
+        new_instr("sreay", 0x5b, OpType::U16, 7, false),
+        new_instr("srenx", 0x43, OpType::U8, 8, false),
+        new_instr("sreny", 0x53, OpType::U8, 8, false),
+        new_instr("rraz", 0x67, OpType::U8, 5, false),
+        new_instr("rrazx", 0x77, OpType::U8, 6, false),
+        new_instr("rraa", 0x6f, OpType::U16, 6, false),
+        new_instr("rraax", 0x7f, OpType::U16, 7, false),
+        new_instr("rraay", 0x7b, OpType::U16, 7, false),
+        new_instr("rranx", 0x63, OpType::U8, 8, false),
+        new_instr("rrany", 0x73, OpType::U8, 8, false),
+        new_instr("saxz", 0x87, OpType::U8, 3, false),
+        new_instr("saxzy", 0x97, OpType::U8, 4, false),
+        new_instr("saxa", 0x8f, OpType::U16, 4, false),
+        new_instr("saxnx", 0x83, OpType::U8, 6, false),
+        new_instr("laxz", 0xa7, OpType::U8, 3, false),
+        new_instr("laxzy", 0xb7, OpType::U8, 4, false),
+        new_instr("laxa", 0xaf, OpType::U16, 4, false),
+        new_instr("laxay", 0xbf, OpType::U16, 4, true),
+        new_instr("laxnx", 0xa3, OpType::U8, 6, false),
+        new_instr("laxny", 0xb3, OpType::U8, 5, true),
+        new_instr("dcpz", 0xc7, OpType::U8, 5, false),
+        new_instr("dcpzx", 0xd7, OpType::U8, 6, false),
+        new_instr("dcpa", 0xcf, OpType::U16, 6, false),
+        new_instr("dcpax", 0xdf, OpType::U16, 7, false),
+        new_instr("dcpay", 0xdb, OpType::U16, 7, false),
+        new_instr("dcpnx", 0xc3, OpType::U8, 8, false),
+        new_instr("dcpny", 0xd3, OpType::U8, 8, false),
+        new_instr("iscz", 0xe7, OpType::U8, 5, false),
+        new_instr("isczx", 0xf7, OpType::U8, 6, false),
+        new_instr("isca", 0xef, OpType::U16, 6, false),
+        new_instr("iscax", 0xff, OpType::U16, 7, false),
+        new_instr("iscay", 0xfb, OpType::U16, 7, false),
+        new_instr("iscnx", 0xe3, OpType::U8, 8, false),
+        new_instr("iscny", 0xf3, OpType::U8, 8, false),
+        new_instr("anci", 0x0b, OpType::U8, 2, false),
+        new_instr("alri", 0x4b, OpType::U8, 2, false),
+        new_instr("arri", 0x6b, OpType::U8, 2, false),
+        new_instr("sbxi", 0xcb, OpType::U8, 2, false),
+        new_instr("nopi", 0x80, OpType::U8, 2, false),
+        new_instr("nopz", 0x04, OpType::U8, 3, false),
+        new_instr("nopzx", 0x14, OpType::U8, 4, false),
+        new_instr("nopa", 0x0c, OpType::U16, 4, false),
+        new_instr("nopax", 0x1c, OpType::U16, 4, true),
+    ])
+});
+
+static ISA_BY_OPCODE: Lazy<[Option<&InstrInfo>; 256]> = Lazy::new(|| {
     let mut a = [None; 256];
     for (_, instr) in ISA_BY_MNEMONIC.iter() {
         a[instr.opcode as usize] = Some(instr);
     }
     a
 });
+
+static ISA_WITH_ILLEGAL_BY_OPCODE: Lazy<[Option<&InstrInfo>; 256]> = Lazy::new(|| {
+    let mut a = *ISA_BY_OPCODE;
+    for (_, instr) in ILLEGAL_BY_MNEMONIC.iter() {
+        a[instr.opcode as usize] = Some(instr);
+    }
+    a
+});
+
+// Well-known Apple II addresses: soft switches and I/O locations in $C000-$C0FF, and entry points
+// of monitor ROM routines. Names follow Apple's own documentation, in lowercase.
+pub const APPLE_ADDRESSES: &[(u16, &str)] = &[
+    (0xc000, "kbd"),
+    (0xc010, "kbdstrb"),
+    (0xc020, "tapeout"),
+    (0xc030, "spkr"),
+    (0xc040, "strobe"),
+    (0xc050, "txtclr"),
+    (0xc051, "txtset"),
+    (0xc052, "mixclr"),
+    (0xc053, "mixset"),
+    (0xc054, "lowscr"),
+    (0xc055, "hiscr"),
+    (0xc056, "lores"),
+    (0xc057, "hires"),
+    (0xc058, "setan0"),
+    (0xc059, "clran0"),
+    (0xc05a, "setan1"),
+    (0xc05b, "clran1"),
+    (0xc05c, "setan2"),
+    (0xc05d, "clran2"),
+    (0xc05e, "setan3"),
+    (0xc05f, "clran3"),
+    (0xc060, "tapein"),
+    (0xc061, "butn0"),
+    (0xc062, "butn1"),
+    (0xc063, "butn2"),
+    (0xc064, "paddl0"),
+    (0xc065, "paddl1"),
+    (0xc066, "paddl2"),
+    (0xc067, "paddl3"),
+    (0xc070, "ptrig"),
+    (0xf800, "plot"),
+    (0xf819, "hline"),
+    (0xf828, "vline"),
+    (0xf832, "clrscr"),
+    (0xf836, "clrtop"),
+    (0xf85f, "nextcol"),
+    (0xf864, "setcol"),
+    (0xf871, "scrn"),
+    (0xf941, "prntax"),
+    (0xf948, "prblnk"),
+    (0xf94a, "prbl2"),
+    (0xfb1e, "pread"),
+    (0xfb2f, "init"),
+    (0xfb39, "settxt"),
+    (0xfb40, "setgr"),
+    (0xfbdd, "bell1"),
+    (0xfc22, "vtab"),
+    (0xfc42, "clreop"),
+    (0xfc58, "home"),
+    (0xfc62, "cr"),
+    (0xfc66, "lf"),
+    (0xfc70, "scroll"),
+    (0xfc9c, "clreol"),
+    (0xfca8, "wait"),
+    (0xfd0c, "rdkey"),
+    (0xfd1b, "keyin"),
+    (0xfd35, "rdchar"),
+    (0xfd67, "getlnz"),
+    (0xfd6a, "getln"),
+    (0xfd8e, "crout"),
+    (0xfdda, "prbyte"),
+    (0xfde3, "prhex"),
+    (0xfded, "cout"),
+    (0xfdf0, "cout1"),
+    (0xfe2c, "move"),
+    (0xfe80, "setinv"),
+    (0xfe84, "setnorm"),
+    (0xff2d, "prerr"),
+    (0xff3a, "bell"),
+    (0xff3f, "restore"),
+    (0xff4a, "save"),
+    (0xff59, "oldrst"),
+    (0xff65, "mon"),
+    (0xff69, "monz"),
+];
+
+// Atari 2600 TIA registers in zero page and RIOT registers at $280-$297, with their names for
+// reads and for writes, which often reach different registers at the same address. An empty name
+// means the register cannot be read or written. Names follow the standard vcs.h, in lowercase.
+pub const ATARI_REGISTERS: &[(u16, &str, &str)] = &[
+    (0x00, "cxm0p", "vsync"),
+    (0x01, "cxm1p", "vblank"),
+    (0x02, "cxp0fb", "wsync"),
+    (0x03, "cxp1fb", "rsync"),
+    (0x04, "cxm0fb", "nusiz0"),
+    (0x05, "cxm1fb", "nusiz1"),
+    (0x06, "cxblpf", "colup0"),
+    (0x07, "cxppmm", "colup1"),
+    (0x08, "inpt0", "colupf"),
+    (0x09, "inpt1", "colubk"),
+    (0x0a, "inpt2", "ctrlpf"),
+    (0x0b, "inpt3", "refp0"),
+    (0x0c, "inpt4", "refp1"),
+    (0x0d, "inpt5", "pf0"),
+    (0x0e, "", "pf1"),
+    (0x0f, "", "pf2"),
+    (0x10, "", "resp0"),
+    (0x11, "", "resp1"),
+    (0x12, "", "resm0"),
+    (0x13, "", "resm1"),
+    (0x14, "", "resbl"),
+    (0x15, "", "audc0"),
+    (0x16, "", "audc1"),
+    (0x17, "", "audf0"),
+    (0x18, "", "audf1"),
+    (0x19, "", "audv0"),
+    (0x1a, "", "audv1"),
+    (0x1b, "", "grp0"),
+    (0x1c, "", "grp1"),
+    (0x1d, "", "enam0"),
+    (0x1e, "", "enam1"),
+    (0x1f, "", "enabl"),
+    (0x20, "", "hmp0"),
+    (0x21, "", "hmp1"),
+    (0x22, "", "hmm0"),
+    (0x23, "", "hmm1"),
+    (0x24, "", "hmbl"),
+    (0x25, "", "vdelp0"),
+    (0x26, "", "vdelp1"),
+    (0x27, "", "vdelbl"),
+    (0x28, "", "resmp0"),
+    (0x29, "", "resmp1"),
+    (0x2a, "", "hmove"),
+    (0x2b, "", "hmclr"),
+    (0x2c, "", "cxclr"),
+    (0x0280, "swcha", "swcha"),
+    (0x0281, "swacnt", "swacnt"),
+    (0x0282, "swchb", "swchb"),
+    (0x0283, "swbcnt", "swbcnt"),
+    (0x0284, "intim", ""),
+    (0x0285, "timint", ""),
+    (0x0294, "", "tim1t"),
+    (0x0295, "", "tim8t"),
+    (0x0296, "", "tim64t"),
+    (0x0297, "", "t1024t"),
+];
+
+// Commodore 64 KERNAL jump table, which the VIC-20 shares. Names follow the Commodore 64
+// Programmer's Reference Guide, in lowercase.
+pub const KERNAL_ADDRESSES: &[(u16, &str)] = &[
+    (0xff81, "cint"),
+    (0xff84, "ioinit"),
+    (0xff87, "ramtas"),
+    (0xff8a, "restor"),
+    (0xff8d, "vector"),
+    (0xff90, "setmsg"),
+    (0xff93, "second"),
+    (0xff96, "tksa"),
+    (0xff99, "memtop"),
+    (0xff9c, "membot"),
+    (0xff9f, "scnkey"),
+    (0xffa2, "settmo"),
+    (0xffa5, "acptr"),
+    (0xffa8, "ciout"),
+    (0xffab, "untlk"),
+    (0xffae, "unlsn"),
+    (0xffb1, "listen"),
+    (0xffb4, "talk"),
+    (0xffb7, "readst"),
+    (0xffba, "setlfs"),
+    (0xffbd, "setnam"),
+    (0xffc0, "open"),
+    (0xffc3, "close"),
+    (0xffc6, "chkin"),
+    (0xffc9, "chkout"),
+    (0xffcc, "clrchn"),
+    (0xffcf, "chrin"),
+    (0xffd2, "chrout"),
+    (0xffd5, "load"),
+    (0xffd8, "save"),
+    (0xffdb, "settim"),
+    (0xffde, "rdtim"),
+    (0xffe1, "stop"),
+    (0xffe4, "getin"),
+    (0xffe7, "clall"),
+    (0xffea, "udtim"),
+    (0xffed, "screen"),
+    (0xfff0, "plot"),
+    (0xfff3, "iobase"),
+];
+
+// Atari 400 and 800 GTIA, POKEY, PIA, and ANTIC registers at $d000-$d40f, with their names for
+// reads and for writes as for the Atari 2600, and the OS vectors for I/O and vertical blank
+// interrupts at $e456-$e462. Names follow the Atari OS equates, in lowercase.
+pub const ATARI8_REGISTERS: &[(u16, &str, &str)] = &[
+    (0xd000, "m0pf", "hposp0"),
+    (0xd001, "m1pf", "hposp1"),
+    (0xd002, "m2pf", "hposp2"),
+    (0xd003, "m3pf", "hposp3"),
+    (0xd004, "p0pf", "hposm0"),
+    (0xd005, "p1pf", "hposm1"),
+    (0xd006, "p2pf", "hposm2"),
+    (0xd007, "p3pf", "hposm3"),
+    (0xd008, "m0pl", "sizep0"),
+    (0xd009, "m1pl", "sizep1"),
+    (0xd00a, "m2pl", "sizep2"),
+    (0xd00b, "m3pl", "sizep3"),
+    (0xd00c, "p0pl", "sizem"),
+    (0xd00d, "p1pl", "grafp0"),
+    (0xd00e, "p2pl", "grafp1"),
+    (0xd00f, "p3pl", "grafp2"),
+    (0xd010, "trig0", "grafp3"),
+    (0xd011, "trig1", "grafm"),
+    (0xd012, "trig2", "colpm0"),
+    (0xd013, "trig3", "colpm1"),
+    (0xd014, "pal", "colpm2"),
+    (0xd015, "", "colpm3"),
+    (0xd016, "", "colpf0"),
+    (0xd017, "", "colpf1"),
+    (0xd018, "", "colpf2"),
+    (0xd019, "", "colpf3"),
+    (0xd01a, "", "colbk"),
+    (0xd01b, "", "prior"),
+    (0xd01c, "", "vdelay"),
+    (0xd01d, "", "gractl"),
+    (0xd01e, "", "hitclr"),
+    (0xd01f, "consol", "consol"),
+    (0xd200, "pot0", "audf1"),
+    (0xd201, "pot1", "audc1"),
+    (0xd202, "pot2", "audf2"),
+    (0xd203, "pot3", "audc2"),
+    (0xd204, "pot4", "audf3"),
+    (0xd205, "pot5", "audc3"),
+    (0xd206, "pot6", "audf4"),
+    (0xd207, "pot7", "audc4"),
+    (0xd208, "allpot", "audctl"),
+    (0xd209, "kbcode", "stimer"),
+    (0xd20a, "random", "skres"),
+    (0xd20b, "", "potgo"),
+    (0xd20d, "serin", "serout"),
+    (0xd20e, "irqst", "irqen"),
+    (0xd20f, "skstat", "skctl"),
+    (0xd300, "porta", "porta"),
+    (0xd301, "portb", "portb"),
+    (0xd302, "pactl", "pactl"),
+    (0xd303, "pbctl", "pbctl"),
+    (0xd400, "", "dmactl"),
+    (0xd401, "", "chactl"),
+    (0xd402, "", "dlistl"),
+    (0xd403, "", "dlisth"),
+    (0xd404, "", "hscrol"),
+    (0xd405, "", "vscrol"),
+    (0xd407, "", "pmbase"),
+    (0xd409, "", "chbase"),
+    (0xd40a, "", "wsync"),
+    (0xd40b, "vcount", ""),
+    (0xd40c, "penh", ""),
+    (0xd40d, "penv", ""),
+    (0xd40e, "", "nmien"),
+    (0xd40f, "nmist", "nmires"),
+    (0xe456, "ciov", "ciov"),
+    (0xe459, "siov", "siov"),
+    (0xe45c, "setvbv", "setvbv"),
+    (0xe45f, "sysvbv", "sysvbv"),
+    (0xe462, "xitvbv", "xitvbv"),
+];
+
+// NES PPU registers at $2000-$2007 and APU and I/O registers at $4000-$4017, with their names for
+// reads and for writes as for the Atari 2600. Names follow the NESdev wiki, in lowercase.
+pub const NES_REGISTERS: &[(u16, &str, &str)] = &[
+    (0x2000, "", "ppuctrl"),
+    (0x2001, "", "ppumask"),
+    (0x2002, "ppustatus", ""),
+    (0x2003, "", "oamaddr"),
+    (0x2004, "oamdata", "oamdata"),
+    (0x2005, "", "ppuscroll"),
+    (0x2006, "", "ppuaddr"),
+    (0x2007, "ppudata", "ppudata"),
+    (0x4000, "", "sq1_vol"),
+    (0x4001, "", "sq1_sweep"),
+    (0x4002, "", "sq1_lo"),
+    (0x4003, "", "sq1_hi"),
+    (0x4004, "", "sq2_vol"),
+    (0x4005, "", "sq2_sweep"),
+    (0x4006, "", "sq2_lo"),
+    (0x4007, "", "sq2_hi"),
+    (0x4008, "", "tri_linear"),
+    (0x400a, "", "tri_lo"),
+    (0x400b, "", "tri_hi"),
+    (0x400c, "", "noise_vol"),
+    (0x400e, "", "noise_lo"),
+    (0x400f, "", "noise_hi"),
+    (0x4010, "", "dmc_freq"),
+    (0x4011, "", "dmc_raw"),
+    (0x4012, "", "dmc_start"),
+    (0x4013, "", "dmc_len"),
+    (0x4014, "", "oamdma"),
+    (0x4015, "snd_chn", "snd_chn"),
+    (0x4016, "joy1", "joy1"),
+    (0x4017, "joy2", "apu_frame"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isa_file_extends_and_replaces() {
+        let table =
+            IsaTable::parse("[instructions]\nphx = \"da 0 3\"\nldaay = \"b9 2 5+\"\n").unwrap();
+        let isa = Isa {
+            illegal: false,
+            table: Some(&table),
+        };
+        assert_eq!(get_instr_size("phx", isa), Ok(1));
+        assert_eq!(get_instr_cycles("ldaay", isa), Some("5+".to_string()));
+        assert_eq!(get_instr_size("rts", isa), Ok(1));
+        assert!(get_instr_info_from_opcode(0xda, isa).is_some());
+
+        let table = IsaTable::parse("replace = true\n[instructions]\nphx = \"da 0 3\"\n").unwrap();
+        let isa = Isa {
+            illegal: false,
+            table: Some(&table),
+        };
+        assert_eq!(get_instr_size("rts", isa), Err("mnemonic not found"));
+        assert!(get_instr_info_from_opcode(0x60, isa).is_none());
+    }
+
+    #[test]
+    fn isa_file_errors() {
+        let parse = |text: &str| IsaTable::parse(text).map(|_| ());
+        assert_eq!(
+            parse("[instructions]\nphx = \"da 0 3\"\nplx = \"da 0 4\"\n"),
+            Err("3: opcode da of plx is already used by phx".to_string())
+        );
+        assert_eq!(
+            parse("[instructions]\nstz = \"8d 2 4\"\n"),
+            Err("2: opcode 8d of stz is already used by staa".to_string())
+        );
+        assert_eq!(
+            parse("[instructions]\nphx = \"da 3 3\"\n"),
+            Err("2: operand of phx must be 0, 1, or 2 bytes".to_string())
+        );
+        assert_eq!(
+            parse("[instructions]\nphx = \"da 0\"\n"),
+            Err("2: phx must be \"OPCODE OPERAND_BYTES CYCLES\", such as \"a9 1 2\"".to_string())
+        );
+    }
+
+    // Addressing mode of an opcode, from the bits of the NMOS 6502 opcode matrix (aaabbbcc)
+    fn mode_from_opcode(opcode: u8) -> &'static str {
+        let (a, b, c) = (opcode >> 5, (opcode >> 2) & 7, opcode & 3);
+        match (c, b) {
+            // Stores and loads of X use Y for indexing instead of X
+            (2 | 3, 5) if a == 4 || a == 5 => "zy",
+            (2 | 3, 7) if a == 5 => "ay",
+            (1 | 3, _) => ["nx", "z", "i", "a", "ny", "zx", "ay", "ax"][b as usize],
+            (0, 0) if a == 1 => "a",
+            (0, 0) if a < 4 => "",
+            (_, 0) => "i",
+            (_, 1) => "z",
+            (0, 3) if a == 3 => "n",
+            (_, 3) => "a",
+            (0, 4) => "",
+            (_, 5) => "zx",
+            (_, 7) => "ax",
+            _ => "",
+        }
+    }
+
+    #[test]
+    fn instrs_match_opcode_matrix() {
+        let isa = Isa {
+            illegal: true,
+            table: None,
+        };
+        let mut count = 0;
+        for opcode in 0..=255 {
+            let Some(i) = get_instr_info_from_opcode(opcode, isa) else {
+                continue;
+            };
+            count += 1;
+            let mode = mode_from_opcode(opcode);
+            assert_eq!(&i.mnemonic[3..], mode, "mode of {opcode:02x}");
+            let size = match mode {
+                "" if is_relative_branch_instruction(&i.mnemonic) => 2,
+                "" => 1,
+                "a" | "ax" | "ay" | "n" => 3,
+                _ => 2,
+            };
+            assert_eq!(get_instr_size_from_opcode(opcode, isa), Some(size));
+            assert_eq!(
+                get_instr_info(&i.mnemonic, isa).map(|i| i.opcode),
+                Ok(opcode)
+            );
+        }
+        assert_eq!(count, ISA_BY_MNEMONIC.len() + ILLEGAL_BY_MNEMONIC.len());
+    }
+
+    #[test]
+    fn zero_page_forms() {
+        let isa = Isa::default();
+        assert_eq!(get_zero_page_form("ldaa", isa), Some("ldaz".to_string()));
+        assert_eq!(get_zero_page_form("ldxay", isa), Some("ldxzy".to_string()));
+        assert_eq!(get_zero_page_form("ldaay", isa), None);
+        assert_eq!(get_zero_page_form("jmpa", isa), None);
+        assert_eq!(get_zero_page_form("ldaz", isa), None);
+    }
+}
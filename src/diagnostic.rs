@@ -0,0 +1,192 @@
+// Rendering of errors and warnings for the terminal. Each diagnostic is printed as usual, followed
+// by its source line with the offending text underlined.
+
+#[cfg(feature = "std")]
+use std::io::IsTerminal;
+
+use crate::error::SasmError;
+use crate::json;
+use crate::prelude::*;
+use crate::syntax::{split_unquoted, strip_comment};
+use crate::warning::SasmWarning;
+
+// ANSI escape sequences
+pub const RED: &str = "\x1b[31m";
+pub const YELLOW: &str = "\x1b[33m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+// Where a diagnostic comes from: the input file, if known, and the line number (starting at 1)
+// within that file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub file: Option<String>,
+    pub line: usize,
+}
+
+// An error or warning as soon as it is found, for the callback in the configuration
+#[derive(Debug, PartialEq)]
+pub enum Diagnostic<'a> {
+    Error(&'a SasmError),
+    Warning(&'a SasmWarning),
+}
+
+// When to use color, as set by the --color flag
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorChoice {
+    // Only when stderr is a terminal and NO_COLOR is not set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    // Attempt to create a variant from a string
+    pub fn new(choice: &str) -> Result<Self, &str> {
+        match choice.to_ascii_lowercase().as_str() {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err("color must be auto, always, or never"),
+        }
+    }
+
+    // Whether diagnostics written to stderr should be colored
+    #[cfg(feature = "std")]
+    pub fn enabled(&self) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none() && std::io::stderr().is_terminal()
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+// How errors and warnings are printed, as set by the --error-format flag
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorFormat {
+    // Message followed by the underlined source line
+    Human,
+
+    // One JSON object per line
+    Json,
+}
+
+impl ErrorFormat {
+    // Attempt to create a variant from a string
+    pub fn new(format: &str) -> Result<Self, &str> {
+        match format.to_ascii_lowercase().as_str() {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err("error format must be human or json"),
+        }
+    }
+}
+
+// Column (starting at 1) and length of a word in a source line. Without a word, the span covers
+// everything before the comment.
+pub fn span(source: &str, word: Option<usize>) -> Option<(usize, usize)> {
+    let code = strip_comment(source, &[]);
+    let words: Vec<(usize, usize)> = split_unquoted(code, |c| c.is_ascii_whitespace())
+        .into_iter()
+        .map(|(s, w)| (code[..s].chars().count() + 1, w.chars().count()))
+        .collect();
+
+    match word.and_then(|w| words.get(w)) {
+        Some(&w) => Some(w),
+        None => {
+            let (first, _) = *words.first()?;
+            let (last, len) = *words.last()?;
+            Some((first, last + len - first))
+        }
+    }
+}
+
+pub fn render(
+    message: &str,
+    source: Option<&str>,
+    span: Option<(usize, usize)>,
+    highlight: &str,
+    color: bool,
+) -> String {
+    let (start, end) = if color {
+        (format!("{BOLD}{highlight}"), RESET)
+    } else {
+        (String::new(), "")
+    };
+
+    let mut out = format!("{start}{message}{end}");
+    if let (Some(source), Some((column, len))) = (source, span) {
+        // Copy tabs so that the underline lines up with the source however tabs are displayed
+        let indent: String = source
+            .chars()
+            .take(column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        out.push_str(&format!(
+            "\n    {source}\n    {indent}{start}{}{end}",
+            "^".repeat(len)
+        ));
+    }
+
+    out
+}
+
+// Unknown values are null
+pub fn json(
+    file: Option<&str>,
+    line: Option<usize>,
+    span: Option<(usize, usize)>,
+    severity: &str,
+    message: &str,
+    code: &str,
+) -> String {
+    let null = || "null".to_string();
+    format!(
+        "{{\"file\": {}, \"line\": {}, \"column\": {}, \"severity\": {}, \"message\": {}, \"code\": {}}}",
+        file.map_or_else(null, json::string),
+        line.map_or_else(null, |l| l.to_string()),
+        span.map_or_else(null, |(c, _)| c.to_string()),
+        json::string(severity),
+        json::string(message),
+        json::string(code)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn span_of_word() {
+        assert_eq!(span("  ldai  .color ; load", Some(1)), Some((9, 6)));
+        assert_eq!(span("  ldai  .color ; load", None), Some((3, 12)));
+        assert_eq!(span("; only a comment", Some(0)), None);
+        assert_eq!(span("data \"A; B\" zz ; text", Some(2)), Some((13, 2)));
+    }
+
+    #[test]
+    fn json_with_unknown_location() {
+        assert_eq!(
+            json(None, Some(2), None, "error", "bad \"x\"", "syntax"),
+            "{\"file\": null, \"line\": 2, \"column\": null, \"severity\": \"error\", \
+             \"message\": \"bad \\\"x\\\"\", \"code\": \"syntax\"}"
+        );
+    }
+
+    #[test]
+    fn render_plain() {
+        assert_eq!(
+            render(
+                "3: mnemonic not found",
+                Some("\tbogus 01"),
+                Some((2, 5)),
+                RED,
+                false
+            ),
+            "3: mnemonic not found\n    \tbogus 01\n    \t^^^^^"
+        );
+    }
+}
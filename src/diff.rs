@@ -0,0 +1,275 @@
+// Comparison of two binaries by their disassemblies ("sasm diff"). Each input is decoded as the
+// disassembler decodes it, the instructions, pointers, and bytes of data of both are aligned so that
+// code that moved or was inserted lines up with the rest, and what differs is reported as changed
+// instructions and operands rather than bytes.
+
+use core::fmt;
+
+use crate::config::{Config, IType};
+use crate::data::is_relative_branch_instruction;
+use crate::disassemble::{get_items, get_segments, Item, Operand};
+use crate::error::SasmError;
+use crate::host::read_input_bytes;
+use crate::prelude::*;
+
+// One instruction, pointer, or byte of data, with its address. Operands are compared by the
+// addresses they refer to, since labels are named differently in each disassembly.
+#[derive(Debug, PartialEq)]
+struct Unit {
+    addr: u16,
+    mnemonic: Option<String>,
+    text: String,
+}
+
+impl Unit {
+    fn same(&self, other: &Unit) -> bool {
+        self.mnemonic == other.mnemonic && self.text == other.text
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    // Same instruction with another operand, at its address in each input
+    Operand(u16, u16, String, String),
+
+    // Another instruction, pointer, or data at the same place in the code
+    Replaced(u16, u16, String, String),
+
+    // Only in the first input
+    Removed(u16, String),
+
+    // Only in the second input
+    Added(u16, String),
+}
+
+// Address in both inputs, written once if the code did not move
+fn addresses(a: u16, b: u16) -> String {
+    match a == b {
+        true => format!("{a:04x}"),
+        false => format!("{a:04x}/{b:04x}"),
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Operand(a, b, old, new) => {
+                write!(f, "{}: operand {old} -> {new}", addresses(*a, *b))
+            }
+            Change::Replaced(a, b, old, new) => {
+                write!(f, "{}: instruction {old} -> {new}", addresses(*a, *b))
+            }
+            Change::Removed(a, old) => write!(f, "{a:04x}: removed {old}"),
+            Change::Added(b, new) => write!(f, "{b:04x}: added {new}"),
+        }
+    }
+}
+
+// Instructions, pointers, and bytes of data of a disassembly in order. Labels and orgs are left
+// out, since the addresses are kept with each unit.
+fn get_units(items: &[Item]) -> Vec<Unit> {
+    let mut units = Vec::new();
+    for item in items {
+        match item {
+            Item::Instr {
+                addr,
+                bytes,
+                mnemonic,
+                operand,
+            } => {
+                let wide = bytes.len() > 2 || is_relative_branch_instruction(mnemonic);
+                let text = match operand {
+                    Operand::None => mnemonic.clone(),
+                    Operand::Byte(b) => format!("{mnemonic} {b:02x}"),
+                    Operand::Addr(a) | Operand::Label(_, a) if wide => {
+                        format!("{mnemonic} {a:04x}")
+                    }
+                    Operand::Addr(a) | Operand::Label(_, a) => format!("{mnemonic} {a:02x}"),
+                };
+                units.push(Unit {
+                    addr: *addr,
+                    mnemonic: Some(mnemonic.clone()),
+                    text,
+                });
+            }
+            Item::Pointer { addr, target, .. } => units.push(Unit {
+                addr: *addr,
+                mnemonic: None,
+                text: format!("word {target:04x}"),
+            }),
+            // Each byte of data is a unit, so that a changed byte in a table is only that byte
+            Item::Data { addr, bytes } => {
+                for (i, b) in bytes.iter().enumerate() {
+                    units.push(Unit {
+                        addr: addr.wrapping_add(i as u16),
+                        mnemonic: None,
+                        text: format!("data {b:02x}"),
+                    });
+                }
+            }
+            Item::Bank(_)
+            | Item::Name(..)
+            | Item::ZpReserve(..)
+            | Item::ZByte(..)
+            | Item::Org(_)
+            | Item::Label(..) => (),
+        }
+    }
+    units
+}
+
+// Positions in a and b of the units that both have, in order, as many as possible (Myers' diff).
+// Only the diagonals reached at each step are kept, so memory grows with the number of
+// differences rather than with the size of the inputs.
+fn common_units(a: &[Unit], b: &[Unit]) -> Vec<(usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let mut trace = Vec::new();
+
+    // Furthest position on diagonal k after d differences, from the diagonals after d - 1
+    let next = |v: &[isize], k: isize, d: isize| match k == -d
+        || (k != d && v[(offset + k - 1) as usize] < v[(offset + k + 1) as usize])
+    {
+        true => k + 1,
+        false => k - 1,
+    };
+
+    let mut last = 0;
+    'search: for d in 0..=max {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let from = next(&v, k, d);
+            let mut x = match from == k + 1 {
+                true => v[(offset + from) as usize],
+                false => v[(offset + from) as usize] + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize].same(&b[y as usize]) {
+                x += 1;
+                y += 1;
+            }
+            v[(offset + k) as usize] = x;
+            if x >= n && y >= m {
+                last = d;
+                break 'search;
+            }
+        }
+    }
+
+    // Walk back through the steps, collecting the diagonal runs
+    let mut pairs = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (1..=last).rev() {
+        let snapshot = &trace[d as usize];
+        let at = |k: isize| snapshot[(k + d) as usize];
+        let k = x - y;
+        let from = match k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            true => k + 1,
+            false => k - 1,
+        };
+        let (from_x, from_y) = (at(from), at(from) - from);
+        while x > from_x && y > from_y {
+            x -= 1;
+            y -= 1;
+            pairs.push((x as usize, y as usize));
+        }
+        (x, y) = (from_x, from_y);
+    }
+    while x > 0 && y > 0 {
+        x -= 1;
+        y -= 1;
+        pairs.push((x as usize, y as usize));
+    }
+    pairs.reverse();
+    pairs
+}
+
+// Changes between two disassemblies. Between units that line up, the units of each side are paired
+// in order as changed operands or instructions, and the rest are removed or added.
+fn changes(a: &[Unit], b: &[Unit]) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let ends = [(a.len(), b.len())];
+    for (next_i, next_j) in common_units(a, b).into_iter().chain(ends) {
+        let paired = (next_i - i).min(next_j - j);
+        for (old, new) in a[i..i + paired].iter().zip(&b[j..j + paired]) {
+            let (old_text, new_text) = (old.text.clone(), new.text.clone());
+            changes.push(
+                match old.mnemonic.is_some() && old.mnemonic == new.mnemonic {
+                    true => Change::Operand(old.addr, new.addr, old_text, new_text),
+                    false => Change::Replaced(old.addr, new.addr, old_text, new_text),
+                },
+            );
+        }
+        for old in &a[i + paired..next_i] {
+            changes.push(Change::Removed(old.addr, old.text.clone()));
+        }
+        for new in &b[j + paired..next_j] {
+            changes.push(Change::Added(new.addr, new.text.clone()));
+        }
+        (i, j) = (next_i + 1, next_j + 1);
+    }
+    changes
+}
+
+// Disassemble two inputs with the same settings and return what differs between them
+pub fn diff(config: &mut Config) -> Result<Vec<Change>, SasmError> {
+    let files = config.input_files();
+    if files.len() != 2 {
+        return Err(SasmError::io("diff takes two input files"));
+    }
+
+    let mut units = Vec::new();
+    for f in files {
+        config.itype = IType::File(f);
+        let input = read_input_bytes(config)?;
+        let segments = get_segments(config, &input)?;
+        let (items, _, _) = get_items(config, &segments)?;
+        units.push(get_units(&items));
+    }
+    Ok(changes(&units[0], &units[1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instr(addr: u16, mnemonic: &str, text: &str) -> Unit {
+        Unit {
+            addr,
+            mnemonic: Some(mnemonic.to_string()),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn aligned_changes() {
+        let a = [
+            instr(0x300, "ldai", "ldai 01"),
+            instr(0x302, "staa", "staa 0400"),
+            instr(0x305, "inx", "inx"),
+            instr(0x306, "rts", "rts"),
+        ];
+        let b = [
+            instr(0x300, "ldai", "ldai 02"),
+            instr(0x302, "nop", "nop"),
+            instr(0x303, "staa", "staa 0400"),
+            instr(0x306, "rts", "rts"),
+        ];
+        assert_eq!(
+            changes(&a, &b),
+            vec![
+                Change::Operand(0x300, 0x300, "ldai 01".to_string(), "ldai 02".to_string()),
+                Change::Added(0x302, "nop".to_string()),
+                Change::Removed(0x305, "inx".to_string()),
+            ]
+        );
+        assert_eq!(changes(&a, &a), Vec::new());
+        assert_eq!(
+            Change::Replaced(0x302, 0x303, "inx".to_string(), "iny".to_string()).to_string(),
+            "0302/0303: instruction inx -> iny"
+        );
+    }
+}
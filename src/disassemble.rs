@@ -1,93 +1,651 @@
-use std::collections::BTreeSet;
-use std::io::Read;
+use alloc::borrow::Cow;
+use alloc::collections::{BTreeMap, BTreeSet};
 
+use crate::bank::*;
 use crate::config::*;
 use crate::data::*;
+use crate::error::*;
+use crate::flow::*;
+use crate::hints::{Hints, LabelStyle};
+use crate::host::*;
+use crate::input::InputFormat;
 use crate::output::*;
+use crate::prelude::*;
+use crate::standard::{self, Syntax};
+use crate::warning::*;
+use crate::zpm::{ranges_outside, Zpm};
 
 // Maps bytes to their instruction sizes
 // Either 1-3 or 0 if byte is not a legal instruction
-fn get_instr_sizes_for_bytes(bytes: &Vec<u8>) -> Vec<u8> {
+fn get_instr_sizes_for_bytes(bytes: &[u8], isa: Isa) -> Vec<u8> {
     let mut byte_to_instr_size = vec![0; bytes.len()];
     for i in 0..bytes.len() {
-        if let Some(s) = get_instr_size_from_opcode(bytes[i]) {
+        if let Some(s) = get_instr_size_from_opcode(bytes[i], isa) {
             byte_to_instr_size[i] = s;
         }
     }
     byte_to_instr_size
 }
 
-fn get_code_regions(instr_sizes: &Vec<u8>, min_region_size: usize) -> Vec<(usize, usize)> {
+// Position in the segment that the branch, jump, or subroutine call at pos goes to, if it is inside
+// the segment
+fn get_target(bytes: &[u8], pos: usize, addr: u16, isa: Isa) -> Option<usize> {
+    let size = get_instr_size_from_opcode(bytes[pos], isa)? as usize;
+    let target = match get_instr_info_from_opcode(bytes[pos], isa)?
+        .mnemonic
+        .as_str()
+    {
+        m if is_relative_branch_instruction(m) && size == 2 => {
+            pos as isize + 2 + bytes[pos + 1] as i8 as isize
+        }
+        "jsra" | "jmpa" if size == 3 => {
+            u16::from_le_bytes([bytes[pos + 1], bytes[pos + 2]]) as isize - addr as isize
+        }
+        _ => return None,
+    };
+    match (0..bytes.len() as isize).contains(&target) {
+        true => Some(target as usize),
+        false => None,
+    }
+}
+
+// How likely a region guessed to be code is code, with the evidence for it, for the regions
+// report and --min-confidence
+#[derive(Debug, PartialEq)]
+pub(crate) struct Confidence {
+    // Positions of the region in its segment, including invalid opcodes that it skips
+    start: usize,
+    end: usize,
+
+    // Instructions, and how many of them have rare opcodes
+    num_instrs: usize,
+    num_rare: usize,
+
+    // Targets of branches, jumps, and subroutine calls inside the segment, and how many of them
+    // are the start of an instruction of the guessed code
+    num_targets: usize,
+    num_on_instrs: usize,
+
+    // Last instruction, if it returns or jumps rather than running into what follows
+    terminator: Option<String>,
+
+    // Percentage from 0 to 100
+    score: usize,
+}
+
+impl Confidence {
+    // Product of the share of common opcodes, the share of targets on instructions, and half for a
+    // region that does not end in a return or jump
+    fn new(
+        bytes: &[u8],
+        addr: u16,
+        span: (usize, usize),
+        parts: &[(usize, usize)],
+        instr_starts: &BTreeSet<usize>,
+        isa: Isa,
+    ) -> Self {
+        let mut confidence = Confidence {
+            start: span.0,
+            end: span.1,
+            num_instrs: 0,
+            num_rare: 0,
+            num_targets: 0,
+            num_on_instrs: 0,
+            terminator: None,
+            score: 0,
+        };
+        let mut last = None;
+        for (start, end) in parts {
+            let mut pos = *start;
+            while pos < *end {
+                let Some(info) = get_instr_info_from_opcode(bytes[pos], isa) else {
+                    break;
+                };
+                let size = get_instr_size_from_opcode(bytes[pos], isa).unwrap_or(1) as usize;
+                confidence.num_instrs += 1;
+                confidence.num_rare += is_rare_opcode(bytes[pos], isa) as usize;
+                if let Some(t) = get_target(bytes, pos, addr, isa) {
+                    confidence.num_targets += 1;
+                    confidence.num_on_instrs += instr_starts.contains(&t) as usize;
+                }
+                last = Some(info.mnemonic.as_str());
+                pos += size;
+            }
+        }
+        confidence.terminator = last
+            .filter(|m| ["rts", "rti", "jmpa", "jmpn"].contains(m))
+            .map(|m| m.to_string());
+
+        let common =
+            100 * (confidence.num_instrs - confidence.num_rare) / confidence.num_instrs.max(1);
+        let consistent = match confidence.num_targets {
+            0 => 100,
+            n => 100 * confidence.num_on_instrs / n,
+        };
+        let ending = match confidence.terminator {
+            Some(_) => 100,
+            None => 50,
+        };
+        confidence.score = common * consistent * ending / 10000;
+        confidence
+    }
+
+    // Line of the regions report, such as
+    // "; 0300-031f: 95% (1 of 20 opcodes rare, 3 of 3 targets on code, ends in rts)"
+    fn report_line(&self, addr: u16, min_confidence: usize) -> String {
+        let ending = match &self.terminator {
+            Some(m) => format!("ends in {m}"),
+            None => "runs into data".to_string(),
+        };
+        let kept = match self.score < min_confidence {
+            true => ", left as data",
+            false => "",
+        };
+        format!(
+            "; {:04x}-{:04x}: {}% ({} of {} opcodes rare, {} of {} targets on code, {ending}){kept}\n",
+            addr as usize + self.start,
+            addr as usize + self.end - 1,
+            self.score,
+            self.num_rare,
+            self.num_instrs,
+            self.num_on_instrs,
+            self.num_targets,
+        )
+    }
+}
+
+// Code regions and the confidence in each one
+type ScoredRegions = (Vec<(usize, usize)>, Vec<Confidence>);
+
+// Candidate code regions are scored by their size, minus a penalty for each rare opcode. A region
+// may skip over a limited number of invalid opcodes, which are then left as data between its
+// parts. Only regions that score above the minimum size and are dense enough in instructions are
+// kept, preferring those with the highest score. With --refine, a second pass follows the branches,
+// jumps, and calls of the kept regions (see refine_regions). The kept regions are then rated by
+// how much they look like code, and those below the minimum confidence are left as data.
+fn get_code_regions(bytes: &[u8], instr_sizes: &[u8], addr: u16, config: &Config) -> ScoredRegions {
+    let len = instr_sizes.len();
+    let isa = config.instruction_set();
+
+    // A candidate region starts at every byte. Regions starting at different bytes often run
+    // into the same chain of instructions, so each is computed from the one after its first
+    // instruction (or invalid opcode) instead of decoding the whole chain again. The chain
+    // starting at each byte is summarized by the end of its last part (if any), the number of
+    // rare opcodes, and the number of instruction bytes. Chains are computed for each number of
+    // invalid opcodes that may still be skipped, up to the maximum.
+    #[derive(Clone, Copy, Default)]
+    struct Chain {
+        end: Option<usize>,
+        num_rare: usize,
+        instr_bytes: usize,
+    }
+    let mut chains = vec![Chain::default(); len];
+    let mut fewer_skips: Vec<Chain> = Vec::new();
+    for skips in 0..=config.max_invalid {
+        for pos in (0..len).rev() {
+            let size = instr_sizes[pos] as usize;
+            chains[pos] = if size == 0 {
+                match skips {
+                    0 => Chain::default(),
+                    _ => fewer_skips.get(pos + 1).copied().unwrap_or_default(),
+                }
+            } else {
+                let next = chains.get(pos + size).copied().unwrap_or_default();
+                Chain {
+                    end: Some(next.end.unwrap_or(pos + size)),
+                    num_rare: next.num_rare + is_rare_opcode(bytes[pos], isa) as usize,
+                    instr_bytes: next.instr_bytes + size,
+                }
+            };
+        }
+        fewer_skips = chains.clone();
+    }
+
+    // Score and span of each candidate region. Invalid opcodes at the end are not part of it.
+    let mut candidates = Vec::new();
+    for (start_pos, chain) in chains.iter().enumerate() {
+        let span = match chain.end {
+            Some(end) => (start_pos, end),
+            None => continue,
+        };
+        let score = (span.1 - span.0).saturating_sub(chain.num_rare * config.rare_penalty);
+        if score > config.min_region_size
+            && chain.instr_bytes * 100 >= config.min_density * (span.1 - span.0)
+        {
+            candidates.push((score, span));
+        }
+    }
+
+    // Sort regions from highest to lowest score
+    candidates.sort_by_key(|c| core::cmp::Reverse(c.0));
+
+    // Only keep regions that do not overlap (or touch) a region with a higher score. Kept regions
+    // never overlap each other, so only the last one starting before a region ends can overlap it.
+    let mut selected = BTreeMap::new();
+    for (_, span) in candidates {
+        match selected.range(..=span.1).next_back() {
+            Some((_, end)) if *end >= span.0 => (),
+            _ => {
+                selected.insert(span.0, span.1);
+            }
+        }
+    }
+    if config.refine_regions {
+        let ends: Vec<Option<usize>> = chains.iter().map(|c| c.end).collect();
+        refine_regions(bytes, instr_sizes, addr, isa, &ends, &mut selected);
+    }
+
+    // Split each region into its parts, which are separated by invalid opcodes
+    let mut split = Vec::new();
+    for (start_pos, end_pos) in selected {
+        let mut regions = Vec::new();
+        let mut part_start = start_pos;
+        let mut pos = start_pos;
+        while pos < end_pos {
+            if instr_sizes[pos] == 0 {
+                if pos > part_start {
+                    regions.push((part_start, pos));
+                }
+                pos += 1;
+                part_start = pos;
+            } else {
+                pos += instr_sizes[pos] as usize;
+            }
+        }
+        if pos > part_start {
+            regions.push((part_start, pos));
+        }
+        split.push(((start_pos, end_pos), regions));
+    }
+
+    // Branches and calls into any of the regions are consistent with the guess
+    let mut instr_starts = BTreeSet::new();
+    for (start, end) in split.iter().flat_map(|(_, parts)| parts) {
+        let mut pos = *start;
+        while pos < *end {
+            instr_starts.insert(pos);
+            pos += instr_sizes[pos].max(1) as usize;
+        }
+    }
+
     let mut regions = Vec::new();
+    let mut confidences = Vec::new();
+    for (span, parts) in split {
+        let confidence = Confidence::new(bytes, addr, span, &parts, &instr_starts, isa);
+        if confidence.score >= config.min_confidence {
+            regions.extend(parts);
+        }
+        confidences.push(confidence);
+    }
+    (regions, confidences)
+}
 
-    // Compute possible code region starting from each byte
-    for start_pos in 0..instr_sizes.len() {
-        let mut end_pos = start_pos;
+// Second pass over the regions chosen by get_code_regions, as a map from start to end, seeded by
+// the targets of their branches, jumps, and subroutine calls. A region with a target in the middle
+// of an instruction of another region cannot be code, so it is left as data. A target outside every
+// region starts code, so the chain of instructions from it (its end in chain_ends) is made a region
+// even if it is too small to have been guessed. This repeats until no region is added or left out,
+// and a region that was left out is never added again.
+fn refine_regions(
+    bytes: &[u8],
+    instr_sizes: &[u8],
+    addr: u16,
+    isa: Isa,
+    chain_ends: &[Option<usize>],
+    regions: &mut BTreeMap<usize, usize>,
+) {
+    let len = instr_sizes.len();
+    let mut rejected = BTreeSet::new();
+    loop {
+        // Start of the region of each instruction byte, and the bytes that start instructions
+        let mut owners = vec![None; len];
+        let mut instr_starts = vec![false; len];
+        let mut targets = Vec::new();
+        for (start, end) in regions.iter() {
+            let mut pos = *start;
+            while pos < *end {
+                let size = instr_sizes[pos] as usize;
+                if size == 0 {
+                    pos += 1;
+                    continue;
+                }
+                owners[pos..pos + size].fill(Some(*start));
+                instr_starts[pos] = true;
+                if let Some(t) = get_target(bytes, pos, addr, isa) {
+                    targets.push((*start, t));
+                }
+                pos += size;
+            }
+        }
 
-        // Compute length of code region
-        while end_pos < instr_sizes.len() {
-            if instr_sizes[end_pos] == 0 {
-                break;
+        let num_rejected = rejected.len();
+        for (start, t) in &targets {
+            if matches!(owners[*t], Some(o) if o != *start) && !instr_starts[*t] {
+                rejected.insert(*start);
             }
-            end_pos += instr_sizes[end_pos] as usize;
         }
+        regions.retain(|start, _| !rejected.contains(start));
 
-        if end_pos - start_pos > min_region_size {
-            regions.push((start_pos, end_pos));
+        let mut added = false;
+        for (start, t) in targets {
+            if rejected.contains(&start) || rejected.contains(&t) || instr_sizes[t] == 0 {
+                continue;
+            }
+            let Some(end) = chain_ends[t] else {
+                continue;
+            };
+            match regions.range(..=end).next_back() {
+                Some((_, e)) if *e >= t => (),
+                _ => {
+                    log::debug!("Code region {:04x} found from a target", addr as usize + t);
+                    regions.insert(t, end);
+                    added = true;
+                }
+            }
+        }
+        if !added && rejected.len() == num_rejected {
+            break;
         }
     }
+}
 
-    // Sort regions from largest to smallest
-    regions.sort_by(|a, b| (b.1 - b.0).cmp(&(a.1 - a.0)));
+// Addresses of the NMI, reset, and IRQ vectors
+const VECTORS: [usize; 3] = [0xfffa, 0xfffc, 0xfffe];
 
-    // Helper function
-    fn regions_overlap(r1: (usize, usize), r2: (usize, usize)) -> bool {
-        if r1.1 < r2.0 {
-            return false;
-        } else if r2.1 < r1.0 {
-            return false;
+// Commands that the disassembly may have besides instructions and data, which take no bytes
+const COMMANDS: [&str; 3] = ["label", "zpreserve", "zbyte"];
+
+// Alternative to get_code_regions that finds code by following the flow of control from a set of
+// entry points, rather than guessing. Entry points at vector addresses are read as vectors, and
+// the code they point to is followed instead. Returns regions in the same form.
+fn get_code_regions_from_entries(
+    bytes: &[u8],
+    start_addr: u16,
+    entries: &[u16],
+    hinted: &[bool],
+    isa: Isa,
+) -> Vec<(usize, usize)> {
+    let to_pos = |addr: isize| {
+        let pos = addr - start_addr as isize;
+        if pos >= 0 && (pos as usize) < bytes.len() {
+            Some(pos as usize)
         } else {
-            return true;
+            None
+        }
+    };
+
+    // Addresses still to be followed
+    let mut pending = Vec::new();
+    for e in entries {
+        let e = *e as usize;
+        match to_pos(e as isize) {
+            Some(p) if VECTORS.contains(&e) && p + 1 < bytes.len() => {
+                pending.push(u16::from_le_bytes([bytes[p], bytes[p + 1]]) as isize)
+            }
+            _ => pending.push(e as isize),
         }
     }
 
-    // Only keep regions that do not overlap a larger region
-    let mut num_selected_regions = 0;
-    for i in 0..regions.len() {
-        let mut keep_region = true;
+    // Size of the instruction starting at each byte, or 0 if no instruction starts there
+    let mut instr_sizes = vec![0u8; bytes.len()];
+
+    // Whether each byte is part of an instruction
+    let mut is_code = vec![false; bytes.len()];
+
+    while let Some(addr) = pending.pop() {
+        let mut pos = match to_pos(addr) {
+            Some(p) => p,
+            None => continue,
+        };
 
-        for j in 0..num_selected_regions {
-            if regions_overlap(regions[i], regions[j]) {
-                keep_region = false;
+        // Follow instructions until control leaves this path or reaches code already found
+        while pos < bytes.len() && instr_sizes[pos] == 0 {
+            let instr_info = match get_instr_info_from_opcode(bytes[pos], isa) {
+                Some(i) => i,
+                None => break,
+            };
+            let instr_size = match get_instr_size_from_opcode(bytes[pos], isa) {
+                Some(s) => s as usize,
+                None => break,
+            };
+            if pos + instr_size > bytes.len()
+                || is_code[pos..pos + instr_size].contains(&true)
+                || hinted[pos..pos + instr_size].contains(&true)
+            {
                 break;
             }
+            instr_sizes[pos] = instr_size as u8;
+            is_code[pos..pos + instr_size].fill(true);
+
+            let mnemonic = instr_info.mnemonic.as_str();
+            let next_addr = start_addr as isize + (pos + instr_size) as isize;
+            if is_relative_branch_instruction(mnemonic) {
+                pending.push(next_addr + bytes[pos + 1] as i8 as isize);
+            }
+            let target = match instr_size {
+                3 => u16::from_le_bytes([bytes[pos + 1], bytes[pos + 2]]) as isize,
+                _ => 0,
+            };
+            match mnemonic {
+                "jmpa" => {
+                    pending.push(target);
+                    break;
+                }
+                "jsra" => pending.push(target),
+
+                // Indirect jumps cannot be followed
+                "jmpn" | "rts" | "rti" | "brk" => break,
+                _ => (),
+            }
+
+            pos += instr_size;
         }
+    }
 
-        if keep_region {
-            regions[num_selected_regions] = regions[i];
-            num_selected_regions += 1;
+    // Join consecutive instructions into regions
+    let mut regions = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        if instr_sizes[pos] == 0 {
+            pos += 1;
+            continue;
         }
+        let start_pos = pos;
+        while pos < bytes.len() && instr_sizes[pos] != 0 {
+            pos += instr_sizes[pos] as usize;
+        }
+        regions.push((start_pos, pos));
     }
 
-    // Remove non-selected regions and sort by starting value
-    regions.resize(num_selected_regions, (0, 0));
-    regions.sort_by(|a, b| a.0.cmp(&b.0));
+    return regions;
+}
+
+// Positions in bytes covered by an inclusive address range, if any
+fn range_to_positions(range: (u16, u16), start_addr: u16, len: usize) -> Option<(usize, usize)> {
+    let start = (range.0 as usize).max(start_addr as usize);
+    let end = (range.1 as usize + 1).min(start_addr as usize + len);
+    if start < end {
+        Some((start - start_addr as usize, end - start_addr as usize))
+    } else {
+        None
+    }
+}
+
+// Bytes that the hints have already declared to be code or data
+fn get_hinted_bytes(len: usize, start_addr: u16, hints: &Hints) -> Vec<bool> {
+    let mut hinted = vec![false; len];
+    for r in hints.code.iter().chain(&hints.data) {
+        if let Some((start, end)) = range_to_positions(*r, start_addr, len) {
+            hinted[start..end].fill(true);
+        }
+    }
+    hinted
+}
+
+// Code regions declared in the hints. Each is decoded from its first byte, stopping early at an
+// illegal opcode or an instruction that does not fit in the range.
+fn get_hinted_code_regions(
+    bytes: &[u8],
+    start_addr: u16,
+    hints: &Hints,
+    isa: Isa,
+) -> Vec<(usize, usize)> {
+    let mut regions: Vec<(usize, usize)> = Vec::new();
+    for r in &hints.code {
+        if let Some((start, end)) = range_to_positions(*r, start_addr, bytes.len()) {
+            let mut pos = start;
+            while pos < end {
+                match get_instr_size_from_opcode(bytes[pos], isa) {
+                    Some(s) if pos + s as usize <= end => pos += s as usize,
+                    _ => break,
+                }
+            }
+            if pos > start {
+                regions.push((start, pos));
+            }
+        }
+    }
+
+    // Overlapping ranges cannot both be code, so keep the first
+    regions.sort_by_key(|r| r.0);
+    let mut last_end = 0;
+    regions.retain(|r| {
+        let keep = r.0 >= last_end;
+        if keep {
+            last_end = r.1;
+        }
+        keep
+    });
 
     return regions;
 }
 
+// Minimum number of consecutive words that make a pointer table
+const MIN_POINTER_TABLE: usize = 2;
+
+// One line of a disassembly, given to a DisassemblyWriter. Addresses inside the input that
+// operands and pointers refer to have labels, and those labels come with their addresses.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Item {
+    // Start of a bank of a banked ROM
+    Bank(usize),
+
+    // Named address outside the input, defined with a label command
+    Name(String, usize),
+
+    // Range of zero page kept from zbyte, and a zero-page byte allocated with zbyte, with the
+    // address that it gets (--zp-labels with a system)
+    ZpReserve(u8, u8),
+    ZByte(String, u8),
+
+    // Start of a segment
+    Org(u16),
+
+    // Label of the next instruction or data, and its address
+    Label(String, usize),
+
+    Instr {
+        addr: u16,
+        bytes: Vec<u8>,
+        mnemonic: String,
+        operand: Operand,
+    },
+
+    // Run of bytes that are not code
+    Data {
+        addr: u16,
+        bytes: Vec<u8>,
+    },
+
+    // Two bytes of data holding the address of a label
+    Pointer {
+        addr: u16,
+        label: String,
+        target: usize,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    None,
+
+    // Immediate value or zero-page address without a name
+    Byte(u8),
+
+    // Address without a name
+    Addr(usize),
+
+    // Label and the address it stands for
+    Label(String, usize),
+}
+
+// Disassembly format that library users can add without changing SASM2, such as HTML with links
+// from operands to their labels. It is given every line of the disassembly in order.
+pub trait DisassemblyWriter {
+    fn write(&self, items: &[Item]) -> Code;
+}
+
+// Positions of the little-endian words in bytes[start..end] that point to instructions, in runs
+// of at least MIN_POINTER_TABLE. Single words point to code too often by chance.
+fn get_pointer_positions(
+    bytes: &[u8],
+    start: usize,
+    end: usize,
+    instr_addrs: &BTreeSet<usize>,
+) -> Vec<usize> {
+    let is_pointer = |p: usize| {
+        p + 1 < end
+            && instr_addrs.contains(&(u16::from_le_bytes([bytes[p], bytes[p + 1]]) as usize))
+    };
+
+    let mut positions = Vec::new();
+    let mut pos = start;
+    while pos + 1 < end {
+        let run = (0..).take_while(|i| is_pointer(pos + 2 * i)).count();
+        if run >= MIN_POINTER_TABLE {
+            positions.extend((0..run).map(|i| pos + 2 * i));
+            pos += 2 * run;
+        } else {
+            pos += 1;
+        }
+    }
+
+    positions
+}
+
+#[allow(clippy::too_many_arguments)]
 fn get_assembly_from_bytes(
-    bytes: &Vec<u8>,
-    regions: &Vec<(usize, usize)>,
+    bytes: &[u8],
+    regions: &[(usize, usize)],
     start_addr: u16,
-) -> Code {
-    struct SourceLine(u16, String);
+    first_line: usize,
+    hints: &mut Hints,
+    defined_names: &mut BTreeSet<(usize, String)>,
+    isa: Isa,
+    warnings: &mut Warnings,
+) -> Result<Vec<Item>, SasmError> {
+    // Address, text, and the address inside the input whose label ends the line, if any. Labels
+    // are added once all labeled addresses are known, since the label style may depend on them.
+    struct SourceLine(u16, String, Option<usize>);
 
     // First disassembly loop. This loop does the majority of the work, creating the output source
     // lines (minus labels) and also finding and storing labels.
     let mut last_region_end_byte = 0;
     let mut source = Vec::new();
-    let mut labeled_addrs = BTreeSet::new();
+
+    // Named addresses are always labeled, since other segments may refer to them
+    let mut labeled_addrs: BTreeSet<usize> = hints
+        .names
+        .keys()
+        .map(|a| *a as usize)
+        .filter(|a| *a >= start_addr as usize && *a < start_addr as usize + bytes.len())
+        .collect();
+
+    // Named addresses outside the input that are used here
+    let mut external_names = BTreeSet::new();
+
+    // Targets of subroutine calls inside the input
+    let mut subroutines = BTreeSet::new();
 
     for (start_byte_ref, end_byte_ref) in regions {
         let start_byte = *start_byte_ref;
@@ -99,19 +657,31 @@ fn get_assembly_from_bytes(
             source.push(SourceLine(
                 last_region_end_byte as u16 + start_addr,
                 format!("data  {hex}"),
+                None,
             ));
         }
 
         // Write code in region
-        let err_string = "Internal error: found invalid opcode while creating assembly";
+        let err = || SasmError::internal("found invalid opcode while creating assembly");
         let mut current_byte = start_byte;
         while current_byte < end_byte {
-            let instr_info = get_instr_info_from_opcode(bytes[current_byte]).expect(err_string);
-            let instr_size: usize = get_instr_size_from_opcode(bytes[current_byte])
-                .expect(err_string)
+            let instr_info =
+                get_instr_info_from_opcode(bytes[current_byte], isa).ok_or_else(err)?;
+            let instr_size: usize = get_instr_size_from_opcode(bytes[current_byte], isa)
+                .ok_or_else(err)?
                 .into();
             let mnemonic = &instr_info.mnemonic;
             let padding = " ".repeat(6 - mnemonic.len());
+            let write = is_store_instruction(mnemonic);
+
+            // Zero-page operands outside the program may have a name, such as a hardware register.
+            // Pointers are read, even by stores.
+            let zero_page_name = match is_zero_page_operand(mnemonic) {
+                true => Some(bytes[current_byte + 1] as u16)
+                    .filter(|a| *a < start_addr || *a as usize >= start_addr as usize + bytes.len())
+                    .and_then(|a| hints.name(a, write && is_zero_page_instruction(mnemonic))),
+                false => None,
+            };
 
             // Write a single instruction
 
@@ -122,45 +692,88 @@ fn get_assembly_from_bytes(
                         // relative address
                         let instr_addr = start_addr as usize + current_byte + instr_size;
                         let abs_addr = instr_addr as isize + bytes[current_byte + 1] as i8 as isize;
-                        assert!(
-                            abs_addr >= 0,
-                            "Error: relative address has absolute address less than 0"
-                        );
+                        if abs_addr < 0 {
+                            let message = format!(
+                                "branch at {:04x} is to an address below 0000",
+                                instr_addr - instr_size
+                            );
+                            return Err(SasmError::range(&message));
+                        }
                         abs_addr as usize
                     }
                     3 => {
                         // absolute address
                         bytes[current_byte + 2] as usize * 256 + bytes[current_byte + 1] as usize
                     }
-                    _ => panic!("Internal error: impossible size for branch instruction"),
+                    _ => {
+                        return Err(SasmError::internal(
+                            "impossible size for branch instruction",
+                        ))
+                    }
                 };
 
-                // Do not use a label for addresses outside the program's address space
-                // Currently, the label is the address prepended with a dot, so just remove the
-                // dot to insert the explicit address.
-                let mut optional_dot = ".";
-                if new_addr < start_addr as usize || new_addr >= start_addr as usize + bytes.len() {
-                    optional_dot = "";
-                } else if !labeled_addrs.contains(&new_addr) {
+                // Do not use a label for addresses outside the program's address space, unless
+                // they have a name. Named addresses outside the program are defined with label
+                // commands. Zero-page names are single bytes, so they cannot be used here.
+                let mut target = None;
+                let operand = if let Some(n) = u16::try_from(new_addr)
+                    .ok()
+                    .and_then(|a| hints.shared_names.get(&a))
+                {
+                    external_names.insert((new_addr, n.to_string()));
+                    format!(".{n}")
+                } else if new_addr >= start_addr as usize
+                    && new_addr < start_addr as usize + bytes.len()
+                {
                     labeled_addrs.insert(new_addr);
-                }
+                    if mnemonic == "jsra" {
+                        subroutines.insert(new_addr);
+                    }
+                    target = Some(new_addr);
+                    String::new()
+                } else if let Some(n) = u16::try_from(new_addr)
+                    .ok()
+                    .filter(|a| *a > 0xff)
+                    .and_then(|a| hints.name(a, write))
+                {
+                    external_names.insert((new_addr, n.to_string()));
+                    format!(".{n}")
+                } else if let Some(n) = external_label(new_addr, hints) {
+                    let operand = format!(".{n}");
+                    external_names.insert((new_addr, n));
+                    operand
+                } else {
+                    format!("{:04x}", new_addr)
+                };
+                source.push(SourceLine(
+                    current_byte as u16 + start_addr,
+                    format!("{mnemonic}{padding}{operand}"),
+                    target,
+                ));
+
+            // Case 2: instruction has a zero-page address with a name, such as a hardware register
+            } else if let Some(n) = zero_page_name {
+                external_names.insert((bytes[current_byte + 1] as usize, n.to_string()));
                 source.push(SourceLine(
                     current_byte as u16 + start_addr,
-                    format!("{mnemonic}{padding}{optional_dot}{:04x}", new_addr),
+                    format!("{mnemonic}{padding}.{n}"),
+                    None,
                 ));
 
-            // Case 2: instruction has a single operand that is not an address
+            // Case 3: instruction has a single operand that is not an address or has no name
             } else if instr_size > 1 {
                 source.push(SourceLine(
                     current_byte as u16 + start_addr,
                     format!("{mnemonic}{padding}{:02x}", bytes[current_byte + 1]),
+                    None,
                 ));
 
-            // Case 3: instruction has no operands
+            // Case 4: instruction has no operands
             } else {
                 source.push(SourceLine(
                     current_byte as u16 + start_addr,
-                    format!("{mnemonic}"),
+                    mnemonic.to_string(),
+                    None,
                 ));
             }
 
@@ -176,113 +789,798 @@ fn get_assembly_from_bytes(
         source.push(SourceLine(
             last_region_end_byte as u16 + start_addr,
             format!("data  {hex}"),
+            None,
         ));
     }
 
+    // Interrupt vectors at the end of memory point to code, so they are written as labels. This
+    // splits the data line that covers them, which is always the last line.
+    let has_vectors = start_addr as usize + bytes.len() == 0x10000 && bytes.len() >= 6;
+    if let Some(SourceLine(a, line, _)) = source.last().filter(|_| has_vectors) {
+        if line.starts_with("data ") && (*a as usize) <= VECTORS[0] {
+            let a = *a as usize;
+            let hex = line
+                .split_ascii_whitespace()
+                .nth(1)
+                .unwrap_or("")
+                .to_string();
+            source.pop();
+            if a < VECTORS[0] {
+                source.push(SourceLine(
+                    a as u16,
+                    format!("data  {}", &hex[..2 * (VECTORS[0] - a)]),
+                    None,
+                ));
+            }
+
+            for v in VECTORS {
+                let pos = v - start_addr as usize;
+                let target = bytes[pos + 1] as usize * 256 + bytes[pos] as usize;
+                if target >= start_addr as usize && target < VECTORS[0] {
+                    labeled_addrs.insert(target);
+                    source.push(SourceLine(v as u16, "data ".to_string(), Some(target)));
+                } else if let Some(n) = Some(target as u16)
+                    .filter(|a| *a > 0xff)
+                    .and_then(|a| hints.name(a, false))
+                {
+                    external_names.insert((target, n.to_string()));
+                    source.push(SourceLine(v as u16, format!("data .{n}"), None));
+                } else if let Some(n) = external_label(target, hints) {
+                    source.push(SourceLine(v as u16, format!("data .{n}"), None));
+                    external_names.insert((target, n));
+                } else {
+                    let hex = hex::encode(&bytes[pos..pos + 2]);
+                    source.push(SourceLine(v as u16, format!("data {hex}"), None));
+                }
+            }
+        }
+    }
+
+    // Tables of pointers to code in data are written as labels, so that the code can still move
+    let mut instr_addrs = BTreeSet::new();
+    for (start, end) in regions {
+        let mut pos = *start;
+        while pos < *end {
+            instr_addrs.insert(start_addr as usize + pos);
+            pos += get_instr_size_from_opcode(bytes[pos], isa)
+                .ok_or_else(|| SasmError::internal("found invalid opcode in a code region"))?
+                as usize;
+        }
+    }
+    let mut with_pointers = Vec::new();
+    for SourceLine(a, line, target) in source {
+        if !line.starts_with("data ") || line.contains('.') || target.is_some() {
+            with_pointers.push(SourceLine(a, line, target));
+            continue;
+        }
+        let start = (a - start_addr) as usize;
+        let end = start + line.split_ascii_whitespace().nth(1).unwrap_or("").len() / 2;
+        let mut pos = start;
+        for p in get_pointer_positions(bytes, start, end, &instr_addrs) {
+            if p > pos {
+                let hex = hex::encode(&bytes[pos..p]);
+                with_pointers.push(SourceLine(
+                    pos as u16 + start_addr,
+                    format!("data  {hex}"),
+                    None,
+                ));
+            }
+            let target = u16::from_le_bytes([bytes[p], bytes[p + 1]]) as usize;
+            labeled_addrs.insert(target);
+            with_pointers.push(SourceLine(
+                p as u16 + start_addr,
+                "data ".to_string(),
+                Some(target),
+            ));
+            pos = p + 2;
+        }
+        if pos < end {
+            let hex = hex::encode(&bytes[pos..end]);
+            with_pointers.push(SourceLine(
+                pos as u16 + start_addr,
+                format!("data  {hex}"),
+                None,
+            ));
+        }
+    }
+    let source = with_pointers;
+
+    // Make labels for addresses without names in the selected style
+    let unnamed = labeled_addrs
+        .iter()
+        .filter(|a| !u16::try_from(**a).is_ok_and(|a| hints.names.contains_key(&a)));
+    hints.made_labels = match hints.label_style {
+        LabelStyle::Addr => BTreeMap::new(),
+        LabelStyle::Typed => unnamed
+            .map(|a| {
+                let prefix = if subroutines.contains(a) {
+                    "sub"
+                } else if instr_addrs.contains(a) {
+                    "loc"
+                } else {
+                    "dat"
+                };
+                (*a, format!("{prefix}_{a:04x}"))
+            })
+            .collect(),
+        // Numbers already used by names are skipped
+        LabelStyle::Numbered => {
+            let mut numbers = (1..)
+                .map(|n| format!("l{n}"))
+                .filter(|l| !hints.names.values().any(|n| n == l));
+            unnamed
+                .map(|a| (*a, numbers.next().unwrap_or_default()))
+                .collect()
+        }
+    };
+
     // Second disassembly loop. Join source lines, inserting labels at the proper locations.
-    let mut assembly = String::new();
-    let mut current_line = 1;
+    let mut items = Vec::new();
 
-    // Labeled addresses are sorted. Add a sentinel value to avoid handling NONEs
-    let addr_error = "Internal error: ran out of labeled addresses";
-    labeled_addrs.insert(0x10000);
-    let mut labeled_addr_iter = labeled_addrs.iter();
-    let mut next_labeled_addr = *labeled_addr_iter.next().expect(addr_error);
+    // Labeled addresses are sorted. Once they run out, the next one is past every line.
+    let mut labeled_addr_iter = labeled_addrs.iter().copied();
+    let mut next_labeled = move || labeled_addr_iter.next().unwrap_or(usize::MAX);
+    let mut next_labeled_addr = next_labeled();
 
-    // First line is the starting address
-    assembly.push_str(&format!("org   {:04x}\n", start_addr));
-    current_line += 1;
+    // Define names used here that are not defined elsewhere
+    for (a, n) in external_names {
+        if defined_names.insert((a, n.clone())) {
+            items.push(Item::Name(n, a));
+        }
+    }
+
+    // Next line is the starting address
+    items.push(Item::Org(start_addr));
 
     for s in source {
-        // Check for skipped labels that reference inside the previous line.
+        // Check for skipped labels that reference inside the previous line. Each item is a line.
         while (s.0 as usize) > next_labeled_addr {
-            eprintln!(
-                "Warning: address {:04x} inside line {}",
-                next_labeled_addr,
-                current_line - 1
-            );
-            next_labeled_addr = *labeled_addr_iter.next().expect(addr_error);
+            warnings.warn(SasmWarning::new(
+                WarningKind::LabelInsideLine,
+                &format!(
+                    "address {:04x} inside line {}",
+                    next_labeled_addr,
+                    first_line + items.len() - 1
+                ),
+            ));
+            next_labeled_addr = next_labeled();
         }
+        let pos = (s.0 - start_addr) as usize;
 
-        // Handle data region
-        if s.1.starts_with("data ") {
-            let data_string =
+        // Handle data region. Vectors and pointers refer to labels and are handled like
+        // instructions.
+        if s.1.starts_with("data ") && !s.1.contains('.') && s.2.is_none() {
+            let size =
                 s.1.split_ascii_whitespace()
-                    .skip(1)
-                    .next()
-                    .expect("Internal error: empty data region found");
-            let mut start_byte = 0;
+                    .nth(1)
+                    .ok_or_else(|| SasmError::internal("empty data region found"))?
+                    .len()
+                    / 2;
+            let mut start_byte = pos;
 
             // Print data sub-regions as defined by labels
-            while next_labeled_addr < s.0 as usize + data_string.len() / 2 {
-                let end_byte = 2 * (next_labeled_addr - s.0 as usize);
+            while next_labeled_addr < s.0 as usize + size {
+                let end_byte = next_labeled_addr - start_addr as usize;
 
                 // Print data region only if not empty
                 if end_byte > start_byte {
-                    assembly.push_str("data ");
-                    assembly.push_str(&data_string[start_byte..end_byte]);
-                    assembly.push_str("\n");
-                    current_line += 1;
+                    items.push(Item::Data {
+                        addr: start_byte as u16 + start_addr,
+                        bytes: bytes[start_byte..end_byte].to_vec(),
+                    });
                 }
 
                 // Print label
-                assembly.push_str(&format!(".{:04x}\n", next_labeled_addr));
-                current_line += 1;
+                items.push(Item::Label(
+                    hints.label(next_labeled_addr),
+                    next_labeled_addr,
+                ));
 
-                next_labeled_addr = *labeled_addr_iter.next().expect(addr_error);
+                next_labeled_addr = next_labeled();
                 start_byte = end_byte;
             }
 
             // Print remaining data
-            assembly.push_str("data ");
-            assembly.push_str(&data_string[start_byte..data_string.len()]);
-            assembly.push_str("\n");
-            current_line += 1;
+            items.push(Item::Data {
+                addr: start_byte as u16 + start_addr,
+                bytes: bytes[start_byte..pos + size].to_vec(),
+            });
 
         // Instruction
         } else {
             // Insert label if needed
             if s.0 as usize == next_labeled_addr {
-                assembly.push_str(&format!(".{:04x}\n", next_labeled_addr));
-                current_line += 1;
-                next_labeled_addr = *labeled_addr_iter.next().expect(addr_error);
+                items.push(Item::Label(
+                    hints.label(next_labeled_addr),
+                    next_labeled_addr,
+                ));
+                next_labeled_addr = next_labeled();
+            }
+
+            // Pointers are data, written as the label they hold
+            let mut words = s.1.split_ascii_whitespace();
+            let mnemonic = words.next().unwrap_or("").to_string();
+            let size = match mnemonic.as_str() {
+                "data" => 2,
+                m => get_instr_size(m, isa)
+                    .map_err(|_| SasmError::internal("invalid mnemonic in disassembly"))?
+                    as usize,
+            };
+            let operand = match (words.next(), s.2) {
+                (_, Some(t)) => Operand::Label(hints.label(t), t),
+                (Some(n), None) if n.starts_with('.') => {
+                    let a = match mnemonic.as_str() {
+                        "data" => u16::from_le_bytes([bytes[pos], bytes[pos + 1]]) as usize,
+                        m => instr_operand(&bytes[pos..pos + size], s.0, m),
+                    };
+                    Operand::Label(n[1..].to_string(), a)
+                }
+                (Some(h), None) if h.len() == 2 => Operand::Byte(bytes[pos + 1]),
+                (Some(h), None) => Operand::Addr(
+                    usize::from_str_radix(h, 16)
+                        .map_err(|_| SasmError::internal("invalid operand in disassembly"))?,
+                ),
+                (None, None) => Operand::None,
+            };
+            items.push(match (mnemonic.as_str(), operand) {
+                ("data", Operand::Label(label, target)) => Item::Pointer {
+                    addr: s.0,
+                    label,
+                    target,
+                },
+                (_, operand) => Item::Instr {
+                    addr: s.0,
+                    bytes: bytes[pos..pos + size].to_vec(),
+                    mnemonic,
+                    operand,
+                },
+            });
+        }
+    }
+
+    Ok(items)
+}
+
+// Label of an address outside the input without a name, as given by --ext-labels. Zero page is
+// left out, since its labels are single bytes that absolute operands cannot use.
+fn external_label(addr: usize, hints: &Hints) -> Option<String> {
+    match hints.external_labels && (0x100..=0xffff).contains(&addr) {
+        true => Some(format!("ext_{addr:04x}")),
+        false => None,
+    }
+}
+
+// Name of a zero-page byte without any other name, as given by --zp-labels
+fn zero_page_label(addr: u8) -> String {
+    format!("z{addr:02x}")
+}
+
+// Zero-page bytes that the instructions of the code regions use as addresses
+fn zero_page_operands(bytes: &[u8], regions: &[(usize, usize)], isa: Isa) -> BTreeSet<u8> {
+    let mut addrs = BTreeSet::new();
+    for (start, end) in regions {
+        let mut pos = *start;
+        while pos < *end {
+            let Some(info) = get_instr_info_from_opcode(bytes[pos], isa) else {
+                break;
+            };
+            if is_zero_page_operand(&info.mnemonic) && pos + 1 < bytes.len() {
+                addrs.insert(bytes[pos + 1]);
             }
+            pos += get_instr_size_from_opcode(bytes[pos], isa).unwrap_or(1) as usize;
+        }
+    }
+    addrs
+}
+
+// Address that the operand of an instruction refers to, for operands written as names
+fn instr_operand(bytes: &[u8], addr: u16, mnemonic: &str) -> usize {
+    match bytes.len() {
+        3 => u16::from_le_bytes([bytes[1], bytes[2]]) as usize,
+        _ if is_relative_branch_instruction(mnemonic) => {
+            (addr as isize + 2 + bytes[1] as i8 as isize) as usize
+        }
+        _ => bytes[1] as usize,
+    }
+}
+
+// Disassembly in SASM syntax, one line for each item
+fn get_sasm(items: &[Item]) -> String {
+    let mut assembly = String::new();
+    for item in items {
+        let line = match item {
+            Item::Bank(b) => format!("; bank {b}"),
+            Item::Name(n, a) => {
+                let width = if *a > 0xff { 4 } else { 2 };
+                format!("label {n} {a:0width$x}")
+            }
+            Item::ZpReserve(start, end) => format!("zpreserve {start:02x} {end:02x}"),
+            Item::ZByte(n, _) => format!("zbyte {n}"),
+            Item::Org(a) => format!("org   {:04x}", a),
+            Item::Label(l, _) => format!(".{l}"),
+            Item::Data { bytes, .. } => format!("data {}", hex::encode(bytes)),
+            Item::Pointer { label, .. } => format!("data .{label}"),
+            Item::Instr {
+                mnemonic, operand, ..
+            } => {
+                let operand = match operand {
+                    Operand::None => String::new(),
+                    Operand::Byte(b) => format!("{:02x}", b),
+                    Operand::Addr(a) => format!("{:04x}", a),
+                    Operand::Label(l, _) => format!(".{l}"),
+                };
+                format!("{mnemonic:6}{operand}").trim_end().to_string()
+            }
+        };
+        assembly.push_str(&line);
+        assembly.push('\n');
+    }
+    assembly
+}
+
+// Source in the chosen syntax
+fn get_source_line(line: &str, syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Sasm => line.to_string(),
+        Syntax::Standard => standard::translate(line),
+    }
+}
+
+// Comment with the cycle count after an instruction, such as "ldaax 0600      ; 4+". The count
+// goes before the bytes of --bytes, in a column of its own.
+fn add_cycles(line: &str, isa: Isa) -> String {
+    let mnemonic = line.split_ascii_whitespace().next().unwrap_or("");
+    match (get_instr_cycles(mnemonic, isa), line.split_once(" ; ")) {
+        (Some(c), Some((code, bytes))) => format!("{:15} ; {c:3} {bytes}", code.trim_end()),
+        (Some(c), None) => format!("{line:15} ; {c}"),
+        (None, _) => line.to_string(),
+    }
+}
+
+// Comment with the address and bytes after each line of the disassembly that has bytes, such as
+// "ldai  00        ; 0300: a9 00", to check the decoding against
+fn add_bytes(assembly: &str, items: &[Item]) -> String {
+    let mut with_bytes = String::new();
+    for (line, item) in assembly.lines().zip(items) {
+        let (addr, bytes) = match item {
+            Item::Instr { addr, bytes, .. } | Item::Data { addr, bytes } => (*addr, bytes.clone()),
+            Item::Pointer { addr, target, .. } => (*addr, (*target as u16).to_le_bytes().to_vec()),
+            _ => {
+                with_bytes.push_str(&format!("{line}\n"));
+                continue;
+            }
+        };
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+        with_bytes.push_str(&format!("{line:15} ; {addr:04x}: {}\n", hex.join(" ")));
+    }
+    with_bytes
+}
+
+// Listing with the address and bytes of each line in front of the source, like a monitor listing.
+// Data lines show only their first three bytes. Lines match the source one to one, so that
+// warnings refer to the same line numbers.
+fn get_listing(
+    assembly: &str,
+    segments: &[(u16, Cow<[u8]>)],
+    isa: Isa,
+    syntax: Syntax,
+    cycles: bool,
+) -> Result<String, SasmError> {
+    let mut listing = String::new();
+    let mut segment: &[u8] = &[];
+    let mut start_addr = 0;
+    let mut addr = 0;
+
+    for line in assembly.lines() {
+        let words: Vec<&str> = line.split_ascii_whitespace().collect();
+        let size = match words.as_slice() {
+            ["org", a] => {
+                start_addr = usize::from_str_radix(a, 16)
+                    .map_err(|_| SasmError::internal("invalid org in disassembly"))?;
+                addr = start_addr;
+                segment = segments
+                    .iter()
+                    .find(|(a, _)| *a as usize == start_addr)
+                    .map(|(_, b)| &b[..])
+                    .ok_or_else(|| SasmError::internal("org without segment in disassembly"))?;
+                0
+            }
+            ["data", d] if d.starts_with('.') => 2,
+            ["data", d] => d.len() / 2,
+            [m, ..] if !m.starts_with(['.', ';']) && !COMMANDS.contains(m) => {
+                get_instr_size(m, isa)
+                    .map_err(|_| SasmError::internal("invalid mnemonic in disassembly"))?
+                    .into()
+            }
+            _ => 0,
+        };
+
+        let source = match cycles {
+            true => cycles_column(line, &[], isa) + &get_source_line(line, syntax),
+            false => get_source_line(line, syntax),
+        };
+        let bytes = segment
+            .get(addr - start_addr..addr - start_addr + size)
+            .ok_or_else(|| SasmError::internal("line outside its segment in disassembly"))?;
+        listing.push_str(&listing_line(addr, bytes, &source));
+        addr += size;
+    }
+
+    Ok(listing)
+}
+
+// Address and bytes of each part of the input that is disassembled on its own
+pub(crate) type Segments<'a> = Vec<(u16, Cow<'a, [u8]>)>;
+
+// Bytes of each segment of the input at its address. Binary input is borrowed rather than copied,
+// and only the window of --offset and --length is kept. Banked ROMs have a segment for each bank.
+pub(crate) fn get_segments<'a>(
+    config: &Config,
+    input: &'a [u8],
+) -> Result<Segments<'a>, SasmError> {
+    // Strings have always been hex, so never treat them as binary
+    let iformat = match (&config.itype, config.iformat.detect(input)) {
+        (IType::String(_), InputFormat::Binary) if config.iformat == InputFormat::Auto => {
+            InputFormat::Hex
+        }
+        (_, f) => f,
+    };
+    // Binary input is disassembled where it is, and other formats from their decoded bytes
+    let mut segments: Segments = match iformat {
+        InputFormat::Binary => vec![(config.addr, Cow::Borrowed(input))],
+        f => f
+            .decode(input, config.addr)?
+            .into_iter()
+            .map(|(a, b)| (a, Cow::Owned(b)))
+            .collect(),
+    };
+
+    // Only disassemble a slice of the input. Formats with their own addresses have no single
+    // offset, so they are not supported.
+    if config.offset > 0 || config.length.is_some() {
+        if !matches!(iformat, InputFormat::Hex | InputFormat::Binary) {
+            return Err(SasmError::syntax(
+                "--offset and --length require hex or binary input",
+            ));
+        }
+        let size = segments[0].1.len();
+        let end = config.offset + config.length.unwrap_or(size.saturating_sub(config.offset));
+        if end <= config.offset || end > size {
+            let message = format!(
+                "offset {:x} and length {:x} do not fit in {:x} bytes of input",
+                config.offset,
+                end.saturating_sub(config.offset),
+                size
+            );
+            return Err(SasmError::range(&message));
+        }
+        segments[0].1 = match core::mem::take(&mut segments[0].1) {
+            Cow::Borrowed(b) => Cow::Borrowed(&b[config.offset..end]),
+            Cow::Owned(b) => Cow::Owned(b[config.offset..end].to_vec()),
+        };
+    }
+
+    // Addresses are 16 bits, so the input must not extend past ffff
+    if let Some((a, _)) = segments
+        .iter()
+        .find(|(a, b)| *a as usize + b.len() > 0x10000)
+    {
+        let message = format!("input at {:04x} extends past ffff", a);
+        return Err(SasmError::range(&message));
+    }
 
-            // Insert source line
-            assembly.push_str(&s.1);
-            assembly.push_str("\n");
-            current_line += 1;
+    // Banked ROMs are split into banks, which are all mapped at the same address
+    if config.banking != Banking::None {
+        let size = config.banking.num_banks() * BANK_SIZE;
+        match segments.as_slice() {
+            [(_, bytes)] if bytes.len() == size => (),
+            _ => {
+                let message = format!(
+                    "{:?} banking requires {}K of input",
+                    config.banking,
+                    size / 1024
+                );
+                return Err(SasmError::range(&message));
+            }
         }
+        segments = match core::mem::take(&mut segments[0].1) {
+            Cow::Borrowed(b) => b
+                .chunks(BANK_SIZE)
+                .map(|b| (BANK_ADDR, Cow::Borrowed(b)))
+                .collect(),
+            Cow::Owned(b) => b
+                .chunks(BANK_SIZE)
+                .map(|b| (BANK_ADDR, Cow::Owned(b.to_vec())))
+                .collect(),
+        };
     }
 
-    Code::String(assembly)
+    Ok(segments)
 }
 
-pub fn disassemble(config: &mut Config) -> Result<Code, String> {
-    let bytes = match config.itype {
-        IType::Stdin => {
-            let mut b: Vec<u8> = Vec::new();
-            match std::io::stdin().read_to_end(&mut b) {
-                Ok(_) => b,
-                Err(_) => return Err("Unable to read from stdin".to_string()),
+// Lines of the disassembly of every segment, with the control-flow graph in DOT and the subroutine
+// or regions report when they are asked for
+pub(crate) fn get_items(
+    config: &mut Config,
+    segments: &[(u16, Cow<[u8]>)],
+) -> Result<(Vec<Item>, String, String), SasmError> {
+    // Name the hardware addresses of the selected system, unless the hints already name them
+    let mut hints = config.hints.clone();
+    hints.label_style = config.label_style;
+    hints.external_labels = config.ext_labels;
+    if config.system_addresses {
+        match config.zpm {
+            Zpm::Apple { .. } | Zpm::C64 { .. } | Zpm::Vic20 { .. } => {
+                let addresses = match config.zpm {
+                    Zpm::Apple { .. } => APPLE_ADDRESSES,
+                    _ => KERNAL_ADDRESSES,
+                };
+                for (a, n) in addresses {
+                    hints.names.entry(*a).or_insert(n.to_string());
+                }
+            }
+            Zpm::Atari2600 { .. } | Zpm::Nes { .. } | Zpm::Atari8 { .. } => {
+                let registers = match config.zpm {
+                    Zpm::Nes { .. } => NES_REGISTERS,
+                    Zpm::Atari8 { .. } => ATARI8_REGISTERS,
+                    _ => ATARI_REGISTERS,
+                };
+                for (a, r, w) in registers {
+                    if config.hints.names.contains_key(a) {
+                        continue;
+                    }
+                    if !r.is_empty() {
+                        hints.names.insert(*a, r.to_string());
+                    }
+                    if !w.is_empty() && w != r {
+                        hints.write_names.insert(*a, w.to_string());
+                    }
+                }
             }
+            Zpm::None => (),
         }
+    }
 
-        IType::String(ref s) => match hex::decode(s) {
-            Ok(b) => b,
-            _ => return Err("Cannot decode input string".to_string()),
-        },
+    // Accessing a hotspot selects a bank, so the hotspots are named after their banks
+    for (bank, a) in config.banking.hotspots().iter().enumerate() {
+        if !config.hints.names.contains_key(a) {
+            hints.shared_names.insert(*a, format!("bank{bank}"));
+        }
+    }
 
-        IType::File(ref f) => match std::fs::read(f) {
-            Ok(b) => b,
-            Err(_) => return Err("Unable to read input file".to_string()),
-        },
+    // Code reached from the interrupt vectors is named after them, unless it already has a name
+    for (addr, bytes) in segments {
+        if *addr as usize + bytes.len() != 0x10000 || bytes.len() < 6 {
+            continue;
+        }
+        for (v, name) in [(0xfffc, "reset"), (0xfffa, "nmi"), (0xfffe, "irq")] {
+            let pos = v - *addr as usize;
+            let target = u16::from_le_bytes([bytes[pos], bytes[pos + 1]]);
+            if target > 0xff && !hints.names.values().any(|n| n == name) {
+                hints.names.entry(target).or_insert(name.to_string());
+            }
+        }
+    }
+
+    // Zero page outside the input is named by address, except where the hints or the system
+    // already name it, so that the disassembly uses a name for each byte
+    let mut made_names = BTreeSet::new();
+    if config.zp_labels {
+        for a in 0..=0xffu8 {
+            let in_input = segments.iter().any(|(addr, bytes)| {
+                a as usize >= *addr as usize && (a as usize) < *addr as usize + bytes.len()
+            });
+            let named = [&hints.names, &hints.write_names]
+                .iter()
+                .any(|n| n.contains_key(&(a as u16)));
+            if !in_input && !named {
+                hints.names.insert(a as u16, zero_page_label(a));
+                made_names.insert(a);
+            }
+        }
+    }
+
+    // Named addresses inside the input are labeled where they appear. The rest are defined with
+    // label commands before the first segment that uses them.
+    let mut defined_names: BTreeSet<(usize, String)> = hints
+        .names
+        .iter()
+        .map(|(a, n)| (*a as usize, n.to_string()))
+        .filter(|(a, _)| {
+            segments
+                .iter()
+                .any(|(addr, bytes)| *a >= *addr as usize && *a < *addr as usize + bytes.len())
+        })
+        .collect();
+    let mut names_in_input = defined_names.clone();
+    let mut items = Vec::new();
+    let mut dot = String::new();
+    let mut report = String::new();
+
+    // Fields are borrowed separately, since the warnings are updated along the way
+    let isa = Isa {
+        illegal: config.illegal,
+        table: config.isa.as_ref(),
     };
 
-    let bytes_to_instr_size = get_instr_sizes_for_bytes(&bytes);
-    let code_regions = get_code_regions(&bytes_to_instr_size, config.min_region_size);
-    let assembly = get_assembly_from_bytes(&bytes, &code_regions, config.addr);
-    write_code(&assembly, &config.otype)?;
+    let (regions, confidences): (Vec<_>, Vec<_>) = segments
+        .iter()
+        .map(|(addr, bytes)| get_segment_regions(config, bytes, *addr, &hints, isa))
+        .unzip();
+
+    // With a system, the zero-page bytes that the code uses are allocated with zbyte instead. The
+    // rest of zero page is reserved first, so that each name is allocated the byte it stands for.
+    if config.zp_labels && config.system_addresses && config.syntax == Syntax::Sasm {
+        let used: BTreeSet<u8> = segments
+            .iter()
+            .zip(&regions)
+            .flat_map(|((_, bytes), regions)| zero_page_operands(bytes, regions, isa))
+            .filter(|a| made_names.contains(a))
+            .collect();
+        let order = config.zpm.alloc_order(&used);
+        let allocated = order.iter().copied().collect();
+        for (start, end) in ranges_outside(&allocated) {
+            items.push(Item::ZpReserve(start, end));
+        }
+        for a in order {
+            let name = zero_page_label(a);
+            defined_names.insert((a as usize, name.clone()));
+            names_in_input.insert((a as usize, name.clone()));
+            items.push(Item::ZByte(name, a));
+        }
+    }
+
+    // Each segment is disassembled separately and starts with its own org
+    for (bank, (addr, bytes)) in segments.iter().map(|(a, b)| (*a, b)).enumerate() {
+        // Each bank is a section of its own, with its own labels
+        if config.banking != Banking::None {
+            items.push(Item::Bank(bank));
+            hints.label_prefix = format!("b{bank}_");
+            defined_names = names_in_input.clone();
+        }
+
+        let code_regions = &regions[bank];
+        items.extend(get_assembly_from_bytes(
+            bytes,
+            code_regions,
+            addr,
+            items.len() + 1,
+            &mut hints,
+            &mut defined_names,
+            isa,
+            &mut config.warnings,
+        )?);
+
+        if config.cfg_file.is_some() || matches!(config.report, Report::After | Report::Only) {
+            let blocks = get_blocks(bytes, addr, code_regions, isa)?;
+            log::debug!("Found {} basic block(s)", blocks.len());
+            if config.cfg_file.is_some() {
+                dot.push_str(&get_dot(&blocks, addr, bytes.len(), &hints));
+            }
+            report.push_str(&get_subroutine_report(&blocks, addr, bytes.len(), &hints));
+        }
+        if config.report == Report::Regions {
+            for c in &confidences[bank] {
+                report.push_str(&c.report_line(addr, config.min_confidence));
+            }
+        }
+    }
+    Ok((items, dot, report))
+}
+
+// Code regions of a segment, as positions in its bytes, in order, and the confidence in each region
+// that was guessed
+fn get_segment_regions(
+    config: &Config,
+    bytes: &[u8],
+    addr: u16,
+    hints: &Hints,
+    isa: Isa,
+) -> ScoredRegions {
+    log::info!("Disassembling {} bytes at {:04x}", bytes.len(), addr);
+    let mut hinted = get_hinted_bytes(bytes.len(), addr, hints);
+    let mut confidences = Vec::new();
+    let mut code_regions = if config.entries.is_empty() {
+        // Code reached from the interrupt vectors is certain, so only the rest is guessed
+        let mut regions = Vec::new();
+        if addr as usize + bytes.len() == 0x10000 && bytes.len() >= 6 {
+            let vectors = VECTORS.map(|v| v as u16);
+            regions = get_code_regions_from_entries(bytes, addr, &vectors, &hinted, isa);
+            for (start, end) in &regions {
+                hinted[*start..*end].fill(true);
+            }
+            hinted[bytes.len() - 6..].fill(true);
+        }
+
+        let mut bytes_to_instr_size = get_instr_sizes_for_bytes(bytes, isa);
+
+        // Instructions may not overlap bytes covered by hints
+        for (pos, size) in bytes_to_instr_size.iter_mut().enumerate() {
+            let end = pos + *size as usize;
+            if end > bytes.len() || hinted[pos..end].contains(&true) {
+                *size = 0;
+            }
+        }
+        let (guessed, scored) = get_code_regions(bytes, &bytes_to_instr_size, addr, config);
+        regions.extend(guessed);
+        confidences = scored;
+        regions
+    } else {
+        get_code_regions_from_entries(bytes, addr, &config.entries, &hinted, isa)
+    };
+    code_regions.extend(get_hinted_code_regions(bytes, addr, hints, isa));
+    code_regions.sort_by_key(|r| r.0);
+
+    log::info!("Found {} code region(s)", code_regions.len());
+    for (start, end) in &code_regions {
+        log::debug!(
+            "Code region {:04x}-{:04x}",
+            addr as usize + start,
+            addr as usize + end - 1
+        );
+    }
+    (code_regions, confidences)
+}
+
+pub fn disassemble(config: &mut Config) -> Result<Code, SasmError> {
+    // Multiple files are disassembled as one contiguous block of memory. A large file is mapped
+    // rather than read, so that disassembling a window of it with --offset and --length, or one
+    // bank at a time, only loads those bytes.
+    let input = read_input_bytes(config)?;
+    let segments = get_segments(config, &input)?;
+    let (items, dot, report) = get_items(config, &segments)?;
+    let isa = Isa {
+        illegal: config.illegal,
+        table: config.isa.as_ref(),
+    };
+    let mut assembly = get_sasm(&items);
+
+    // Listings already show the bytes of each line
+    if config.show_bytes && !matches!(config.cformat, CodeFormat::Listing) {
+        assembly = add_bytes(&assembly, &items);
+    }
+    match config.report {
+        Report::None | Report::Cycles => (),
+        Report::Regions => assembly.push_str(&format!("\n; Code regions\n{report}")),
+        Report::After => assembly.push_str(&format!("\n; Subroutines\n{report}")),
+        Report::Only => assembly = format!("; Subroutines\n{report}"),
+    }
+    let assembly = match (&config.custom_writer, config.cformat) {
+        (Some(w), _) => w.write(&items),
+        (None, CodeFormat::Listing) => Code::String(get_listing(
+            &assembly,
+            &segments,
+            isa,
+            config.syntax,
+            config.cycles,
+        )?),
+        (None, _) if config.cycles => Code::String(
+            assembly
+                .lines()
+                .map(|l| get_source_line(&add_cycles(l, isa), config.syntax) + "\n")
+                .collect(),
+        ),
+        (None, _) => Code::String(match config.syntax {
+            Syntax::Sasm => assembly,
+            Syntax::Standard => assembly
+                .lines()
+                .map(|l| get_source_line(l, config.syntax) + "\n")
+                .collect(),
+        }),
+    };
+
+    // Disassembly produces at most one error, so report the first warning
+    if config.warnings.as_errors {
+        if let Some(w) = config.warnings.list.first() {
+            return Err(SasmError::from_warning(w));
+        }
+    }
+
+    write_code(&assembly, &mut config.otype, config.overwrite)?;
+    if let Some(ref f) = config.cfg_file {
+        let graph = format!("digraph cfg {{\n    node [shape=box, fontname=monospace];\n{dot}}}\n");
+        write_text(&graph, f, config.overwrite)?;
+    }
 
     Ok(assembly)
 }
@@ -295,6 +1593,474 @@ mod tests {
     fn convert_bytes_to_instr_sizes() {
         let bytes: Vec<u8> = vec![0x00, 0x22, 0xc0, 0xfe, 0xaa, 0xff];
         let sizes: Vec<u8> = vec![1, 0, 2, 3, 1, 0];
-        assert_eq!(get_instr_sizes_for_bytes(&bytes), sizes);
+        assert_eq!(get_instr_sizes_for_bytes(&bytes, Isa::default()), sizes);
+    }
+
+    #[test]
+    fn convert_bytes_to_instr_sizes_with_illegal() {
+        let bytes: Vec<u8> = vec![0xa7, 0xaf, 0x02, 0xcb];
+        assert_eq!(
+            get_instr_sizes_for_bytes(&bytes, Isa::default()),
+            vec![0, 0, 0, 0]
+        );
+        assert_eq!(
+            get_instr_sizes_for_bytes(
+                &bytes,
+                Isa {
+                    illegal: true,
+                    table: None,
+                }
+            ),
+            vec![2, 3, 0, 2]
+        );
+    }
+
+    #[test]
+    fn round_trip_apple_sm_output() {
+        let source = "org 0300\nldai 01\nrts\norg 4000\nnop\n";
+        let mut c = Config::build_string_test(source);
+        c.cformat = CodeFormat::AppleSM;
+        let dump = match crate::assemble(&mut c) {
+            Ok(Code::String(s)) => s,
+            _ => panic!("assembly failed"),
+        };
+
+        let mut c = Config::build_string_test(&dump);
+        c.min_region_size = 0;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "org   0300\nldai  01\nrts\norg   4000\nnop\n".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn follow_entry_points() {
+        // c000: jsr c007; jmp c00a; data; c007: rts; data; c00a: bne c00a; brk
+        // fffc: reset vector to c000
+        let mut bytes = vec![
+            0x20, 0x07, 0xc0, 0x4c, 0x0a, 0xc0, 0xff, 0x60, 0xa9, 0xff, 0xd0, 0xfe, 0x00,
+        ];
+        bytes.resize(0x3ffc, 0xff);
+        bytes.extend([0x00, 0xc0, 0xff, 0xff]);
+        assert_eq!(
+            get_code_regions_from_entries(
+                &bytes,
+                0xc000,
+                &[0xfffc],
+                &[false; 0x4000],
+                Isa::default()
+            ),
+            vec![(0, 6), (7, 8), (10, 13)]
+        );
+    }
+
+    #[test]
+    fn hints_force_regions_and_names() {
+        // 0300: jsr fded; lda #01; rts, followed by two bytes that look like code
+        let mut c = Config::build_string_test("20edfda90160a901");
+        c.addr = 0x300;
+        c.hints =
+            Hints::parse("code 0300-0305\ndata 0306-0307\nname fded cout\nname 0300 main").unwrap();
+        c.min_region_size = 0;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "label cout fded\norg   0300\n.main\njsra  .cout\nldai  01\nrts\ndata a901\n"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn pointer_table_as_labels() {
+        // 0300: lda #01; rts; rts, followed by pointers to both returns and a lone byte
+        let mut c = Config::build_string_test("a90160600203030300");
+        c.addr = 0x300;
+        c.hints = Hints::parse("code 0300-0303\ndata 0304-0308").unwrap();
+        c.min_region_size = 0;
+        let assembly = [
+            "org   0300",
+            "ldai  01",
+            ".0302",
+            "rts",
+            ".0303",
+            "rts",
+            "data .0302",
+            "data .0303",
+            "data 00",
+        ];
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(assembly.join("\n") + "\n"))
+        );
+    }
+
+    #[test]
+    fn label_styles() {
+        // c000: jsr c00b; lda c00c; bne c000; jmp fded; c00b: rts; c00c: data
+        let mut c = Config::build_string_test("200bc0ad0cc0d0f84cedfd600102");
+        c.addr = 0xc000;
+        c.entries = vec![0xc000];
+        c.label_style = LabelStyle::Typed;
+        let assembly = [
+            "org   c000",
+            ".loc_c000",
+            "jsra  .sub_c00b",
+            "ldaa  .dat_c00c",
+            "bne   .loc_c000",
+            "jmpa  fded",
+            ".sub_c00b",
+            "rts",
+            ".dat_c00c",
+            "data 0102",
+        ];
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(assembly.join("\n") + "\n"))
+        );
+
+        // Numbers used by names are skipped
+        c.label_style = LabelStyle::Numbered;
+        c.hints = Hints::parse("name c00c l2").unwrap();
+        let assembly = [
+            "org   c000",
+            ".l1",
+            "jsra  .l3",
+            "ldaa  .l2",
+            "bne   .l1",
+            "jmpa  fded",
+            ".l3",
+            "rts",
+            ".l2",
+            "data 0102",
+        ];
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(assembly.join("\n") + "\n"))
+        );
+    }
+
+    #[test]
+    fn apple_addresses_named() {
+        // 0300: sta c030; jmp fded
+        let mut c = Config::build_string_test("8d30c04cedfd");
+        c.addr = 0x300;
+        c.zpm = Zpm::new("apple", None).unwrap();
+        c.system_addresses = true;
+        c.min_region_size = 0;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "label spkr c030\nlabel cout fded\norg   0300\nstaa  .spkr\njmpa  .cout\n"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn zero_page_labels() {
+        // 0300: lda 12; sta (fb),y; inc 12; rts
+        let mut c = Config::build_string_test("a51291fbe61260");
+        c.addr = 0x300;
+        c.min_region_size = 0;
+        c.zp_labels = true;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "label z12 12\nlabel zfb fb\norg   0300\nldaz  .z12\nstany .zfb\nincz  .z12\nrts\n"
+                    .to_string()
+            ))
+        );
+
+        // The Atari 2600 allocates from 80, so the lowest byte comes first
+        let mut c = Config::build_string_test("a58191fbe68160");
+        c.addr = 0xf000;
+        c.zpm = Zpm::new("atari", None).unwrap();
+        c.system_addresses = true;
+        c.min_region_size = 0;
+        c.zp_labels = true;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "zpreserve 00 80\nzpreserve 82 fa\nzpreserve fc ff\nzbyte z81\nzbyte zfb\n\
+                 org   f000\nldaz  .z81\nstany .zfb\nincz  .z81\nrts\n"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn bytes_as_comments() {
+        // 0300: lda #00; sta 0400; then data
+        let mut c = Config::build_string_test("a9008d000403");
+        c.addr = 0x300;
+        c.min_region_size = 0;
+        c.show_bytes = true;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "org   0300\nldai  00        ; 0300: a9 00\nstaa  0400      ; 0302: 8d 00 04\n\
+                 data 03         ; 0305: 03\n"
+                    .to_string()
+            ))
+        );
+
+        let mut c = Config::build_string_test("a900");
+        c.addr = 0x300;
+        c.min_region_size = 0;
+        c.show_bytes = true;
+        c.cycles = true;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "org   0300\nldai  00        ; 2   0300: a9 00\n".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn external_labels() {
+        // 0300: jsr fc58; sta c030; jmp e000; lda 12
+        let mut c = Config::build_string_test("2058fc8d30c04c00e0a512");
+        c.addr = 0x300;
+        c.min_region_size = 0;
+        c.ext_labels = true;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "label ext_c030 c030\nlabel ext_e000 e000\nlabel ext_fc58 fc58\norg   0300\n\
+                 jsra  .ext_fc58\nstaa  .ext_c030\njmpa  .ext_e000\nldaz  12\n"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn atari_registers_named() {
+        // f000: sta wsync; bit inpt4; lda intim; sta tim64t; lda #02
+        let mut c = Config::build_string_test("8502240cad84028d9602a902");
+        c.addr = 0xf000;
+        c.zpm = Zpm::new("atari", None).unwrap();
+        c.system_addresses = true;
+        c.min_region_size = 0;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "label wsync 02\nlabel inpt4 0c\nlabel intim 0284\nlabel tim64t 0296\n\
+                 org   f000\nstaz  .wsync\nbitz  .inpt4\nldaa  .intim\nstaa  .tim64t\nldai  02\n"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn nes_registers_named() {
+        // c000: bit ppustatus; sta ppuctrl; sta oamdma
+        let mut c = Config::build_string_test("2c02208d00208d1440");
+        c.addr = 0xc000;
+        c.zpm = Zpm::new("nes", None).unwrap();
+        c.system_addresses = true;
+        c.min_region_size = 0;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "label ppuctrl 2000\nlabel ppustatus 2002\nlabel oamdma 4014\n\
+                 org   c000\nbita  .ppustatus\nstaa  .ppuctrl\nstaa  .oamdma\n"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn listing_output() {
+        // 0300: jsr 0306; rts; data; 0306: rts
+        let mut c = Config::build_string_test("200603600102600a");
+        c.addr = 0x300;
+        c.cformat = CodeFormat::Listing;
+        c.hints = Hints::parse("code 0300-0303\ncode 0306-0306").unwrap();
+        let listing = [
+            "                org   0300",
+            "0300  20 06 03  jsra  .0306",
+            "0303  60        rts",
+            "0304  01 02     data 0102",
+            "                .0306",
+            "0306  60        rts",
+            "0307  0a        data 0a",
+        ];
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(listing.join("\n") + "\n"))
+        );
+    }
+
+    #[test]
+    fn tune_code_regions() {
+        // lda #01; invalid; lda #02; rts
+        let mut c = Config::build_string_test("a90102a90260");
+        c.min_region_size = 5;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String("org   0000\ndata a90102a90260\n".to_string()))
+        );
+
+        c = Config::build_string_test("a90102a90260");
+        c.min_region_size = 5;
+        c.max_invalid = 1;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "org   0000\nldai  01\ndata 02\nldai  02\nrts\n".to_string()
+            ))
+        );
+
+        c = Config::build_string_test("a90102a90260");
+        c.min_region_size = 5;
+        c.max_invalid = 1;
+        c.min_density = 90;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String("org   0000\ndata a90102a90260\n".to_string()))
+        );
+
+        // Six BRKs
+        c = Config::build_string_test("000000000000");
+        c.min_region_size = 5;
+        c.rare_penalty = 1;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String("org   0000\ndata 000000000000\n".to_string()))
+        );
+    }
+
+    #[test]
+    fn region_confidence() {
+        // 0300: ldx #00; inx; bne 0302; rts; two invalid opcodes, then six BRKs and a branch to
+        // the invalid opcodes
+        let code = "a200e8d0fd600202000000000000d0f6";
+        let mut c = Config::build_string_test(code);
+        c.addr = 0x300;
+        c.min_region_size = 5;
+        c.report = Report::Regions;
+        let report = "\n; Code regions\n\
+                      ; 0300-0305: 100% (0 of 4 opcodes rare, 1 of 1 targets on code, ends in rts)\n\
+                      ; 0308-030f: 0% (6 of 7 opcodes rare, 0 of 1 targets on code, runs into data)\n";
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "org   0300\nldxi  00\n.0302\ninx\nbne   .0302\nrts\n.0306\ndata 0202\n\
+                 brk\nbrk\nbrk\nbrk\nbrk\nbrk\nbne   .0306\n"
+                    .to_string()
+                    + report
+            ))
+        );
+
+        let mut c = Config::build_string_test(code);
+        c.addr = 0x300;
+        c.min_region_size = 5;
+        c.min_confidence = 50;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "org   0300\nldxi  00\n.0302\ninx\nbne   .0302\nrts\ndata 0202000000000000d0f6\n"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn refined_regions() {
+        // 0300: ldx #00; jsr 030c; inx; bne 0302; rts; three invalid opcodes; 030c: iny; rts; an
+        // invalid opcode; four NOPs and a branch into the middle of ldx #00; rts
+        let code = "a200200c03e8d0fa60020202c86002eaeaeaead0ec60";
+        let mut c = Config::build_string_test(code);
+        c.addr = 0x300;
+        c.min_region_size = 5;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "org   0300\nldxi  00\n.0302\njsra  .030c\ninx\nbne   .0302\nrts\n\
+                 data 020202\n.030c\ndata c86002\nnop\nnop\nnop\nnop\nbne   .0301\nrts\n"
+                    .to_string()
+            ))
+        );
+
+        let mut c = Config::build_string_test(code);
+        c.addr = 0x300;
+        c.min_region_size = 5;
+        c.refine_regions = true;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "org   0300\nldxi  00\n.0302\njsra  .030c\ninx\nbne   .0302\nrts\n\
+                 data 020202\n.030c\niny\nrts\ndata 02eaeaeaead0ec60\n"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn interrupt_vectors() {
+        // fff0: sei; cld; jmp fff0; data; vectors all pointing to fff0
+        let mut c = Config::build_string_test("78d84cf0ff0000000000f0fff0fff0ff");
+        c.addr = 0xfff0;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String(
+                "org   fff0\n.reset\nsei\ncld\njmpa  .reset\ndata 0000000000\n\
+                 data .reset\ndata .reset\ndata .reset\n"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn disassemble_slice() {
+        // nop; nop; lda #01; rts; nop
+        let mut c = Config::build_string_test("eaeaa90160ea");
+        c.addr = 0x300;
+        c.offset = 2;
+        c.length = Some(3);
+        c.min_region_size = 0;
+        assert_eq!(
+            disassemble(&mut c),
+            Ok(Code::String("org   0300\nldai  01\nrts\n".to_string()))
+        );
+
+        c.offset = 4;
+        assert_eq!(
+            disassemble(&mut c).map_err(|e| e.message),
+            Err("offset 4 and length 3 do not fit in 6 bytes of input".to_string())
+        );
+    }
+
+    #[test]
+    fn input_past_ffff() {
+        // jsr ffff; rts
+        let mut c = Config::build_string_test("20ffff60");
+        c.addr = 0xfffe;
+        assert_eq!(
+            disassemble(&mut c).map_err(|e| e.message),
+            Err("input at fffe extends past ffff".to_string())
+        );
+    }
+
+    #[test]
+    fn f8_banks() {
+        // Each bank: lda of the other bank's hotspot; jmp f000; filler; vectors to f000
+        let bank = |hotspot: &str| format!("ad{hotspot}ff4c00f0{}00f000f000f0", "ff".repeat(4084));
+        let mut c = Config::build_string_test(&(bank("f9") + &bank("f8")));
+        c.banking = Banking::F8;
+        let assembly = match disassemble(&mut c) {
+            Ok(Code::String(s)) => s,
+            r => panic!("unexpected result {r:?}"),
+        };
+        assert!(assembly.starts_with(
+            "; bank 0\nlabel bank1 fff9\norg   f000\n.b0_reset\nldaa  .bank1\njmpa  .b0_reset\n"
+        ));
+        assert!(assembly.contains(
+            "; bank 1\nlabel bank0 fff8\norg   f000\n.b1_reset\nldaa  .bank0\njmpa  .b1_reset\n"
+        ));
     }
 }
@@ -0,0 +1,619 @@
+// A cycle-counting NMOS 6502 for trying out small routines without leaving SASM2. Memory is a flat
+// 64K of RAM with two I/O addresses: a write to f001 sends a byte to the output, and a read of
+// f004 takes the next byte of the input (00 when there is none). Instructions are decoded with
+// the same tables as the assembler, so their sizes and cycle counts always agree with listings.
+
+use alloc::collections::VecDeque;
+
+use crate::data::*;
+use crate::error::SasmError;
+use crate::prelude::*;
+
+// Memory-mapped I/O
+pub const PUTC: u16 = 0xf001;
+pub const GETC: u16 = 0xf004;
+
+// Cycles a run may take when no limit is given, so that a program that never stops still ends
+pub const DEFAULT_MAX_CYCLES: u64 = 10_000_000;
+
+// Status flags
+const C: u8 = 0x01;
+const Z: u8 = 0x02;
+const I: u8 = 0x04;
+const D: u8 = 0x08;
+const B: u8 = 0x10;
+const U: u8 = 0x20;
+const V: u8 = 0x40;
+const N: u8 = 0x80;
+
+// Why a run ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    // rts with nothing on the stack, which returns from the routine at the entry address
+    Return,
+
+    // brk at the address, which is not executed
+    Brk(u16),
+
+    // Jump or branch to itself at the address, the usual way for a program to halt
+    Loop(u16),
+
+    // Opcode at the address that is not a documented 6502 instruction
+    Invalid(u8, u16),
+
+    // Cycle limit reached
+    CycleLimit,
+}
+
+impl core::fmt::Display for Stop {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Stop::Return => write!(f, "returned from the entry routine"),
+            Stop::Brk(a) => write!(f, "brk at {a:04x}"),
+            Stop::Loop(a) => write!(f, "loop at {a:04x}"),
+            Stop::Invalid(o, a) => write!(f, "invalid opcode {o:02x} at {a:04x}"),
+            Stop::CycleLimit => write!(f, "cycle limit reached"),
+        }
+    }
+}
+
+// Register or byte of memory that an assert directive checks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    A,
+    X,
+    Y,
+    S,
+    P,
+    Memory(u16),
+}
+
+// Check after a test runs that the target holds the value, or that it does not
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Assertion {
+    pub target: Target,
+    pub value: u8,
+    pub equal: bool,
+}
+
+impl Assertion {
+    // What the target holds instead, if the check fails
+    pub fn check(&self, cpu: &Cpu) -> Result<(), String> {
+        let (name, actual) = match self.target {
+            Target::A => ("a".to_string(), cpu.a),
+            Target::X => ("x".to_string(), cpu.x),
+            Target::Y => ("y".to_string(), cpu.y),
+            Target::S => ("s".to_string(), cpu.s),
+            Target::P => ("p".to_string(), cpu.p),
+            Target::Memory(addr) => (format!("mem {addr:04x}"), cpu.memory[addr as usize]),
+        };
+        match (actual == self.value, self.equal) {
+            (true, true) | (false, false) => Ok(()),
+            _ => Err(format!("assert failed: {name} is {actual:02x}")),
+        }
+    }
+}
+
+// Outcome of one test: how it stopped, the cycles it took, and its failures, which are errors at
+// the lines of the asserts that failed (or of the test, if it did not stop by itself)
+#[derive(Debug, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    pub stop: Stop,
+    pub cycles: u64,
+    pub failures: Vec<SasmError>,
+}
+
+#[derive(Clone)]
+pub struct Cpu {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub s: u8,
+    pub p: u8,
+    pub pc: u16,
+    pub cycles: u64,
+    pub memory: Vec<u8>,
+
+    // Bytes for reads of GETC, and bytes written to PUTC
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cpu {
+    // Empty memory and an empty stack, with interrupts disabled as after a reset
+    pub fn new() -> Cpu {
+        Cpu {
+            a: 0,
+            x: 0,
+            y: 0,
+            s: 0xff,
+            p: U | I,
+            pc: 0,
+            cycles: 0,
+            memory: vec![0; 0x10000],
+            input: VecDeque::new(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn load(&mut self, addr: u16, bytes: &[u8]) {
+        let start = addr as usize;
+        let end = (start + bytes.len()).min(self.memory.len());
+        self.memory[start..end].copy_from_slice(&bytes[..end - start]);
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        match addr {
+            GETC => self.input.pop_front().unwrap_or(0),
+            _ => self.memory[addr as usize],
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            PUTC => self.output.push(value),
+            _ => self.memory[addr as usize] = value,
+        }
+    }
+
+    // Two bytes, low byte first. The high byte of a zero-page pointer wraps around within zero
+    // page, and so does that of jmpn within its page.
+    fn read_word(&mut self, addr: u16, wrap_page: bool) -> u16 {
+        let high = match wrap_page {
+            true => (addr & 0xff00) | (addr.wrapping_add(1) & 0x00ff),
+            false => addr.wrapping_add(1),
+        };
+        u16::from_le_bytes([self.read(addr), self.read(high)])
+    }
+
+    fn push(&mut self, value: u8) {
+        self.write(0x0100 | self.s as u16, value);
+        self.s = self.s.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.s = self.s.wrapping_add(1);
+        self.read(0x0100 | self.s as u16)
+    }
+
+    fn set_flag(&mut self, flag: u8, on: bool) {
+        match on {
+            true => self.p |= flag,
+            false => self.p &= !flag,
+        }
+    }
+
+    fn set_nz(&mut self, value: u8) {
+        self.set_flag(Z, value == 0);
+        self.set_flag(N, value & 0x80 != 0);
+    }
+
+    fn adc(&mut self, m: u8) {
+        let (a, m16, c) = (self.a as u16, m as u16, (self.p & C) as u16);
+        let binary = a + m16 + c;
+        if self.p & D == 0 {
+            self.set_flag(C, binary > 0xff);
+            self.set_flag(V, !(a ^ m16) & (a ^ binary) & 0x80 != 0);
+            self.a = binary as u8;
+            self.set_nz(self.a);
+            return;
+        }
+
+        // Decimal mode on the NMOS 6502 takes Z from the binary sum, and N and V from the sum
+        // before its high digit is adjusted
+        let mut low = (a & 0x0f) + (m16 & 0x0f) + c;
+        if low > 9 {
+            low += 6;
+        }
+        let carry = if low > 0x0f { 0x10 } else { 0 };
+        let mut sum = (a & 0xf0) + (m16 & 0xf0) + carry + (low & 0x0f);
+        self.set_flag(Z, binary & 0xff == 0);
+        self.set_flag(N, sum & 0x80 != 0);
+        self.set_flag(V, !(a ^ m16) & (a ^ sum) & 0x80 != 0);
+        if sum > 0x9f {
+            sum += 0x60;
+        }
+        self.set_flag(C, sum > 0xff);
+        self.a = sum as u8;
+    }
+
+    fn sbc(&mut self, m: u8) {
+        let (a, borrow) = (self.a, 1 - (self.p & C) as i16);
+        let decimal = self.p & D != 0;
+
+        // Flags always come from the binary difference
+        self.p &= !D;
+        self.adc(!m);
+        if !decimal {
+            return;
+        }
+        self.p |= D;
+
+        let mut low = (a & 0x0f) as i16 - (m & 0x0f) as i16 - borrow;
+        let mut high = (a >> 4) as i16 - (m >> 4) as i16;
+        if low < 0 {
+            low -= 6;
+            high -= 1;
+        }
+        if high < 0 {
+            high -= 6;
+        }
+        self.a = ((high << 4) | (low & 0x0f)) as u8;
+    }
+
+    fn compare(&mut self, register: u8, m: u8) {
+        self.set_flag(C, register >= m);
+        self.set_nz(register.wrapping_sub(m));
+    }
+
+    // Shifts and rotates, which work on the accumulator or on memory
+    fn shift(&mut self, op: &str, value: u8) -> u8 {
+        let carry_in = self.p & C;
+        let (result, carry_out) = match op {
+            "asl" => (value << 1, value & 0x80),
+            "lsr" => (value >> 1, value & 0x01),
+            "rol" => (value << 1 | carry_in, value & 0x80),
+            _ => (value >> 1 | carry_in << 7, value & 0x01),
+        };
+        self.set_flag(C, carry_out != 0);
+        self.set_nz(result);
+        result
+    }
+
+    // Run one instruction, unless it stops the program
+    pub fn step(&mut self) -> Option<Stop> {
+        let addr = self.pc;
+        let opcode = self.memory[addr as usize];
+        let Some(info) = get_instr_info_from_opcode(opcode, Isa::default()) else {
+            return Some(Stop::Invalid(opcode, addr));
+        };
+        let (op, mode) = info.mnemonic.split_at(3);
+        let mut cycles = info.cycles as u64;
+        if op == "brk" {
+            return Some(Stop::Brk(addr));
+        }
+        if op == "rts" && self.s == 0xff {
+            return Some(Stop::Return);
+        }
+
+        // Operand bytes, read without I/O since they may not belong to the instruction
+        let b1 = self.memory[addr.wrapping_add(1) as usize];
+        let word = u16::from_le_bytes([b1, self.memory[addr.wrapping_add(2) as usize]]);
+        self.pc = addr.wrapping_add(match info.op {
+            OpType::None => 1,
+            OpType::U8 => 2,
+            OpType::U16 => 3,
+        });
+
+        // Effective address of the operand, with one more cycle for crossing a page if the
+        // instruction takes it
+        let indexed = |base: u16, index: u8, cycles: &mut u64| {
+            let a = base.wrapping_add(index as u16);
+            if info.page_penalty && a & 0xff00 != base & 0xff00 {
+                *cycles += 1;
+            }
+            a
+        };
+        let target = match mode {
+            "z" => Some(b1 as u16),
+            "zx" => Some(b1.wrapping_add(self.x) as u16),
+            "zy" => Some(b1.wrapping_add(self.y) as u16),
+            "a" => Some(word),
+            "ax" => Some(indexed(word, self.x, &mut cycles)),
+            "ay" => Some(indexed(word, self.y, &mut cycles)),
+            "n" => Some(self.read_word(word, true)),
+            "nx" => Some(self.read_word(b1.wrapping_add(self.x) as u16, true)),
+            "ny" => {
+                let base = self.read_word(b1 as u16, true);
+                Some(indexed(base, self.y, &mut cycles))
+            }
+            _ => None,
+        };
+        let operand = |cpu: &mut Cpu| match target {
+            Some(t) => cpu.read(t),
+            None => b1,
+        };
+
+        match op {
+            "lda" => {
+                self.a = operand(self);
+                self.set_nz(self.a);
+            }
+            "ldx" => {
+                self.x = operand(self);
+                self.set_nz(self.x);
+            }
+            "ldy" => {
+                self.y = operand(self);
+                self.set_nz(self.y);
+            }
+            "sta" | "stx" | "sty" => {
+                let value = match op {
+                    "sta" => self.a,
+                    "stx" => self.x,
+                    _ => self.y,
+                };
+                self.write(target.unwrap_or_default(), value);
+            }
+            "adc" => {
+                let m = operand(self);
+                self.adc(m);
+            }
+            "sbc" => {
+                let m = operand(self);
+                self.sbc(m);
+            }
+            "and" | "ora" | "eor" => {
+                let m = operand(self);
+                self.a = match op {
+                    "and" => self.a & m,
+                    "ora" => self.a | m,
+                    _ => self.a ^ m,
+                };
+                self.set_nz(self.a);
+            }
+            "cmp" => {
+                let m = operand(self);
+                self.compare(self.a, m);
+            }
+            "cpx" => {
+                let m = operand(self);
+                self.compare(self.x, m);
+            }
+            "cpy" => {
+                let m = operand(self);
+                self.compare(self.y, m);
+            }
+            "bit" => {
+                let m = operand(self);
+                self.set_flag(Z, self.a & m == 0);
+                self.set_flag(N, m & N != 0);
+                self.set_flag(V, m & V != 0);
+            }
+            "asl" | "lsr" | "rol" | "ror" => match target {
+                Some(t) => {
+                    let value = self.read(t);
+                    let result = self.shift(op, value);
+                    self.write(t, result);
+                }
+                None => self.a = self.shift(op, self.a),
+            },
+            "inc" | "dec" => {
+                let t = target.unwrap_or_default();
+                let value = match op {
+                    "inc" => self.read(t).wrapping_add(1),
+                    _ => self.read(t).wrapping_sub(1),
+                };
+                self.write(t, value);
+                self.set_nz(value);
+            }
+            "inx" | "dex" => {
+                self.x = match op {
+                    "inx" => self.x.wrapping_add(1),
+                    _ => self.x.wrapping_sub(1),
+                };
+                self.set_nz(self.x);
+            }
+            "iny" | "dey" => {
+                self.y = match op {
+                    "iny" => self.y.wrapping_add(1),
+                    _ => self.y.wrapping_sub(1),
+                };
+                self.set_nz(self.y);
+            }
+            "tax" | "tay" | "txa" | "tya" | "tsx" => {
+                let value = match op {
+                    "tax" | "tay" => self.a,
+                    "txa" => self.x,
+                    "tya" => self.y,
+                    _ => self.s,
+                };
+                match op {
+                    "tax" | "tsx" => self.x = value,
+                    "tay" => self.y = value,
+                    _ => self.a = value,
+                }
+                self.set_nz(value);
+            }
+            "txs" => self.s = self.x,
+            "pha" => self.push(self.a),
+            "php" => self.push(self.p | B | U),
+            "pla" => {
+                self.a = self.pull();
+                self.set_nz(self.a);
+            }
+            "plp" => self.p = (self.pull() & !B) | U,
+            "clc" | "cld" | "cli" | "clv" | "sec" | "sed" | "sei" => {
+                let flag = match &op[2..] {
+                    "c" => C,
+                    "d" => D,
+                    "i" => I,
+                    _ => V,
+                };
+                self.set_flag(flag, op.starts_with('s'));
+            }
+            "jmp" => self.pc = target.unwrap_or_default(),
+            "jsr" => {
+                let [low, high] = self.pc.wrapping_sub(1).to_le_bytes();
+                self.push(high);
+                self.push(low);
+                self.pc = word;
+            }
+            "rts" => {
+                let low = self.pull();
+                let high = self.pull();
+                self.pc = u16::from_le_bytes([low, high]).wrapping_add(1);
+            }
+            "rti" => {
+                self.p = (self.pull() & !B) | U;
+                let low = self.pull();
+                let high = self.pull();
+                self.pc = u16::from_le_bytes([low, high]);
+            }
+            _ if is_relative_branch_instruction(op) => {
+                let (flag, set) = match op {
+                    "bpl" => (N, false),
+                    "bmi" => (N, true),
+                    "bvc" => (V, false),
+                    "bvs" => (V, true),
+                    "bcc" => (C, false),
+                    "bcs" => (C, true),
+                    "bne" => (Z, false),
+                    _ => (Z, true),
+                };
+                if (self.p & flag != 0) == set {
+                    let t = self.pc.wrapping_add(b1 as i8 as u16);
+                    cycles += if t & 0xff00 != self.pc & 0xff00 { 2 } else { 1 };
+                    self.pc = t;
+                }
+            }
+            _ => (),
+        }
+        self.cycles += cycles;
+
+        match self.pc == addr {
+            true => Some(Stop::Loop(addr)),
+            false => None,
+        }
+    }
+
+    // Run from an address until the program stops or takes the given number of cycles
+    pub fn run(&mut self, entry: u16, max_cycles: u64) -> Stop {
+        self.pc = entry;
+        while self.cycles < max_cycles {
+            if let Some(stop) = self.step() {
+                return stop;
+            }
+        }
+        Stop::CycleLimit
+    }
+
+    // Registers and flags, with set flags in capitals
+    pub fn registers(&self) -> String {
+        let flags: String = "nv-bdizc"
+            .chars()
+            .enumerate()
+            .map(|(i, f)| match self.p & (0x80 >> i) != 0 {
+                true => f.to_ascii_uppercase(),
+                false => f,
+            })
+            .collect();
+        format!(
+            "pc={:04x} a={:02x} x={:02x} y={:02x} s={:02x} p={:02x} ({flags}) cycles={}",
+            self.pc, self.a, self.x, self.y, self.s, self.p, self.cycles
+        )
+    }
+
+    // Memory in an inclusive range, 16 bytes to a line after the address of the first
+    pub fn dump(&self, start: u16, end: u16) -> String {
+        let mut text = String::new();
+        for line in (start as usize..=end as usize).step_by(16) {
+            let bytes: Vec<String> = (line..=(line + 15).min(end as usize))
+                .map(|a| format!("{:02x}", self.memory[a]))
+                .collect();
+            text.push_str(&format!("{line:04x}: {}\n", bytes.join(" ")));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run code at 0600 until it stops
+    fn run_code(bytes: &[u8]) -> (Cpu, Stop) {
+        let mut cpu = Cpu::new();
+        cpu.load(0x0600, bytes);
+        let stop = cpu.run(0x0600, DEFAULT_MAX_CYCLES);
+        (cpu, stop)
+    }
+
+    #[test]
+    fn loop_with_cycles() {
+        // ldxi 05; .loop; dex; bne .loop; rts
+        let (cpu, stop) = run_code(&[0xa2, 0x05, 0xca, 0xd0, 0xfd, 0x60]);
+        assert_eq!(stop, Stop::Return);
+        assert_eq!(cpu.x, 0);
+        assert_eq!(cpu.p & Z, Z);
+
+        // 2 for ldxi, then 2 for each dex and 3 for each taken bne, and 2 for the last
+        assert_eq!(cpu.cycles, 2 + 5 * 2 + 4 * 3 + 2);
+    }
+
+    #[test]
+    fn subroutine_and_output() {
+        // ldai 48; jsra .put; ldai 49; jsra .put; brk; .put; staa f001; rts
+        let code = [
+            0xa9, 0x48, 0x20, 0x0b, 0x06, 0xa9, 0x49, 0x20, 0x0b, 0x06, 0x00, 0x8d, 0x01, 0xf0,
+            0x60,
+        ];
+        let (cpu, stop) = run_code(&code);
+        assert_eq!(stop, Stop::Brk(0x060a));
+        assert_eq!(cpu.output, b"HI");
+        assert_eq!(cpu.s, 0xff);
+    }
+
+    #[test]
+    fn arithmetic_flags() {
+        // clc; ldai 50; adci 50
+        let (cpu, _) = run_code(&[0x18, 0xa9, 0x50, 0x69, 0x50, 0x60]);
+        assert_eq!((cpu.a, cpu.p & (N | V | C)), (0xa0, N | V));
+
+        // sec; ldai 00; sbci 01
+        let (cpu, _) = run_code(&[0x38, 0xa9, 0x00, 0xe9, 0x01, 0x60]);
+        assert_eq!((cpu.a, cpu.p & (N | C)), (0xff, N));
+
+        // sed; clc; ldai 58; adci 46
+        let (cpu, _) = run_code(&[0xf8, 0x18, 0xa9, 0x58, 0x69, 0x46, 0x60]);
+        assert_eq!((cpu.a, cpu.p & C), (0x04, C));
+
+        // sed; sec; ldai 12; sbci 21
+        let (cpu, _) = run_code(&[0xf8, 0x38, 0xa9, 0x12, 0xe9, 0x21, 0x60]);
+        assert_eq!((cpu.a, cpu.p & C), (0x91, 0));
+    }
+
+    #[test]
+    fn indirect_modes() {
+        // ldai 34; staz 10; ldai 12; staz 11; ldyi 05; ldany 10; jmpn 0010
+        let code = [
+            0xa9, 0x34, 0x85, 0x10, 0xa9, 0x12, 0x85, 0x11, 0xa0, 0x05, 0xb1, 0x10, 0x6c, 0x10,
+            0x00,
+        ];
+        let mut cpu = Cpu::new();
+        cpu.load(0x0600, &code);
+        cpu.load(0x1239, &[0x77]);
+        cpu.load(0x1234, &[0x4c, 0x34, 0x12]);
+        assert_eq!(cpu.run(0x0600, DEFAULT_MAX_CYCLES), Stop::Loop(0x1234));
+        assert_eq!(cpu.a, 0x77);
+    }
+
+    #[test]
+    fn stops() {
+        assert_eq!(run_code(&[0x02]).1, Stop::Invalid(0x02, 0x0600));
+        assert_eq!(run_code(&[0x4c, 0x00, 0x06]).1, Stop::Loop(0x0600));
+
+        // .loop; jmpa .next; .next; jmpa .loop
+        let mut cpu = Cpu::new();
+        cpu.load(0x0600, &[0x4c, 0x03, 0x06, 0x4c, 0x00, 0x06]);
+        assert_eq!(cpu.run(0x0600, 100), Stop::CycleLimit);
+        assert_eq!(cpu.cycles, 102);
+    }
+
+    #[test]
+    fn registers_and_dump() {
+        let (cpu, _) = run_code(&[0xa9, 0x80, 0x60]);
+        assert_eq!(
+            cpu.registers(),
+            "pc=0602 a=80 x=00 y=00 s=ff p=a4 (Nv-bdIzc) cycles=2"
+        );
+        assert_eq!(cpu.dump(0x0600, 0x0602), "0600: a9 80 60\n");
+    }
+}
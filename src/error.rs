@@ -0,0 +1,172 @@
+use core::fmt;
+
+use crate::diagnostic;
+use crate::diagnostic::Location;
+use crate::prelude::*;
+use crate::warning::SasmWarning;
+
+// Broad categories of errors so that library users can react to them without parsing messages
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    // Reading input or writing output failed
+    Io,
+
+    // Malformed source line, unknown mnemonic, wrong number of arguments, etc.
+    Syntax,
+
+    // A value does not fit where it is used (operand too large, branch too far, etc.)
+    Range,
+
+    // Undefined, repeated, or misused labels
+    Label,
+
+    // A bug in SASM itself
+    Internal,
+
+    // A warning treated as an error
+    Warning,
+
+    // An assert directive that does not hold after its test runs, a test that does not stop, or
+    // code that assembles differently after disassembling it ("sasm verify")
+    Test,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SasmError {
+    pub kind: ErrorKind,
+    pub message: String,
+
+    // Input file, line number (starting at 1), and text of the offending source line, if known
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub source_line: Option<String>,
+
+    // Index of the offending word in the source line, if known. Used to underline it.
+    pub word: Option<usize>,
+}
+
+impl ErrorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::Io => "io",
+            ErrorKind::Syntax => "syntax",
+            ErrorKind::Range => "range",
+            ErrorKind::Label => "label",
+            ErrorKind::Internal => "internal",
+            ErrorKind::Warning => "warning",
+            ErrorKind::Test => "test",
+        }
+    }
+}
+
+impl SasmError {
+    pub fn new(kind: ErrorKind, message: &str) -> Self {
+        SasmError {
+            kind,
+            message: message.to_string(),
+            file: None,
+            line: None,
+            source_line: None,
+            word: None,
+        }
+    }
+
+    // Shorthands for each kind of error
+    pub fn io(message: &str) -> Self {
+        Self::new(ErrorKind::Io, message)
+    }
+
+    pub fn syntax(message: &str) -> Self {
+        Self::new(ErrorKind::Syntax, message)
+    }
+
+    pub fn range(message: &str) -> Self {
+        Self::new(ErrorKind::Range, message)
+    }
+
+    pub fn label(message: &str) -> Self {
+        Self::new(ErrorKind::Label, message)
+    }
+
+    pub fn internal(message: &str) -> Self {
+        Self::new(ErrorKind::Internal, message)
+    }
+
+    pub fn test(message: &str) -> Self {
+        Self::new(ErrorKind::Test, message)
+    }
+
+    pub fn from_warning(w: &SasmWarning) -> Self {
+        SasmError {
+            kind: ErrorKind::Warning,
+            message: w.message.clone(),
+            file: w.file.clone(),
+            line: w.line,
+            source_line: w.source_line.clone(),
+            word: w.word,
+        }
+    }
+
+    // Attach the location of the error. Errors are usually created deep inside the assembler,
+    // where the line is not known, so this is added by the caller on the way out.
+    pub fn at_line(mut self, line: usize, source: &str) -> Self {
+        self.line = Some(line);
+        self.source_line = Some(source.to_string());
+        self
+    }
+
+    // Attach the location of the error when the input may be several files
+    pub fn at(mut self, location: &Location, source: &str) -> Self {
+        self.file = location.file.clone();
+        self.line = Some(location.line);
+        self.source_line = Some(source.to_string());
+        self
+    }
+
+    pub fn in_word(mut self, word: usize) -> Self {
+        self.word = Some(word);
+        self
+    }
+
+    // Column (starting at 1) and length of the offending text in the source line, if known
+    pub fn span(&self) -> Option<(usize, usize)> {
+        diagnostic::span(self.source_line.as_deref()?, self.word)
+    }
+
+    // The error followed by the source line with the offending text underlined
+    pub fn render(&self, color: bool) -> String {
+        diagnostic::render(
+            &self.to_string(),
+            self.source_line.as_deref(),
+            self.span(),
+            diagnostic::RED,
+            color,
+        )
+    }
+
+    // A single-line JSON object for editors and other tools
+    pub fn to_json(&self) -> String {
+        diagnostic::json(
+            self.file.as_deref(),
+            self.line,
+            self.span(),
+            "error",
+            &self.message,
+            self.kind.name(),
+        )
+    }
+}
+
+// Errors print as "line: message", with the file first when input comes from files
+impl fmt::Display for SasmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(l)) => write!(f, "{file}:{l}: {}", self.message),
+            (None, Some(l)) => write!(f, "{l}: {}", self.message),
+            (Some(file), None) => write!(f, "{file}: {}", self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl core::error::Error for SasmError {}
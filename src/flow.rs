@@ -0,0 +1,351 @@
+// Basic blocks of disassembled code and the flow of control between them. A basic block is a run
+// of instructions that control can only enter at the top and leave at the bottom, apart from
+// subroutine calls, which return to the same block.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+
+use crate::data::*;
+use crate::error::SasmError;
+use crate::hints::Hints;
+use crate::prelude::*;
+
+// Which report to print, as set by the --report flag
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Report {
+    None,
+
+    // After the assembly, as comments
+    After,
+
+    // Instead of the assembly
+    Only,
+
+    // Bytes and cycles between labels of assembled code, on STDERR (assembler only)
+    Cycles,
+
+    // Code regions that the disassembler guessed and its confidence in each, after the assembly
+    // as comments (disassembler only)
+    Regions,
+}
+
+impl Report {
+    // Attempt to create a variant from a string
+    pub fn new(report: &str) -> Result<Self, &str> {
+        match report.to_ascii_lowercase().as_str() {
+            "none" => Ok(Report::None),
+            "after" => Ok(Report::After),
+            "only" => Ok(Report::Only),
+            "cycles" => Ok(Report::Cycles),
+            "regions" => Ok(Report::Regions),
+            _ => Err("report must be none, after, only, cycles, or regions"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgeKind {
+    // Falling through to the next instruction
+    Next,
+
+    // Taken relative branch
+    Branch,
+
+    // Absolute jump
+    Jump,
+
+    // Subroutine call
+    Call,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Block {
+    // Address of the first instruction and the address after the last one
+    pub start: usize,
+    pub end: usize,
+
+    // Mnemonic and target address (if any) of the last instruction
+    pub last: String,
+    pub target: Option<usize>,
+
+    // Target address and kind of each way control leaves the block
+    pub edges: Vec<(usize, EdgeKind)>,
+
+    // Address and target address of each subroutine call in the block
+    pub calls: Vec<(usize, usize)>,
+}
+
+// Split code regions into basic blocks. A block ends at a branch, jump, or return, or where another
+// block starts because control can reach it from elsewhere.
+pub fn get_blocks(
+    bytes: &[u8],
+    start_addr: u16,
+    regions: &[(usize, usize)],
+    isa: Isa,
+) -> Result<Vec<Block>, SasmError> {
+    // Address, size, mnemonic, and target address of each instruction
+    let mut instrs = Vec::new();
+    for (start, end) in regions {
+        let mut pos = *start;
+        while pos < *end {
+            let err = || SasmError::internal("found invalid opcode in a code region");
+            let mnemonic = &get_instr_info_from_opcode(bytes[pos], isa)
+                .ok_or_else(err)?
+                .mnemonic;
+            let size = get_instr_size_from_opcode(bytes[pos], isa).ok_or_else(err)? as usize;
+            let addr = start_addr as usize + pos;
+            let target = if is_relative_branch_instruction(mnemonic) {
+                usize::try_from((addr + size) as isize + bytes[pos + 1] as i8 as isize).ok()
+            } else if mnemonic == "jmpa" || mnemonic == "jsra" {
+                Some(u16::from_le_bytes([bytes[pos + 1], bytes[pos + 2]]) as usize)
+            } else {
+                None
+            };
+            instrs.push((addr, size, mnemonic.as_str(), target));
+            pos += size;
+        }
+    }
+    let instr_addrs: BTreeSet<usize> = instrs.iter().map(|i| i.0).collect();
+
+    // Addresses where blocks start
+    let mut leaders = BTreeSet::new();
+    for (addr, size, mnemonic, target) in &instrs {
+        if let Some(t) = target.filter(|t| instr_addrs.contains(t)) {
+            leaders.insert(t);
+        }
+        if is_relative_branch_instruction(mnemonic) || ends_flow(mnemonic) {
+            leaders.insert(addr + size);
+        }
+    }
+
+    let mut blocks: Vec<Block> = Vec::new();
+    for (addr, size, mnemonic, target) in instrs {
+        match blocks.last_mut() {
+            Some(b) if b.end == addr && !leaders.contains(&addr) => {
+                b.end = addr + size;
+                b.last = mnemonic.to_string();
+                b.target = target;
+            }
+            _ => blocks.push(Block {
+                start: addr,
+                end: addr + size,
+                last: mnemonic.to_string(),
+                target,
+                edges: Vec::new(),
+                calls: Vec::new(),
+            }),
+        }
+        if let (Some(t), "jsra", Some(block)) = (target, mnemonic, blocks.last_mut()) {
+            block.edges.push((t, EdgeKind::Call));
+            block.calls.push((addr, t));
+        }
+    }
+
+    // Edges from the last instruction of each block
+    for block in &mut blocks {
+        match (block.last.as_str(), block.target) {
+            (m, target) if is_relative_branch_instruction(m) => {
+                if let Some(t) = target {
+                    block.edges.push((t, EdgeKind::Branch));
+                }
+                block.edges.push((block.end, EdgeKind::Next));
+            }
+            ("jmpa", Some(t)) => block.edges.push((t, EdgeKind::Jump)),
+            (m, _) if ends_flow(m) => (),
+            _ if instr_addrs.contains(&block.end) => block.edges.push((block.end, EdgeKind::Next)),
+            _ => (),
+        }
+    }
+
+    Ok(blocks)
+}
+
+// Whether control never continues to the next instruction
+fn ends_flow(mnemonic: &str) -> bool {
+    ["jmpa", "jmpn", "rts", "rti", "brk"].contains(&mnemonic)
+}
+
+// Label of an address, whether inside the segment or not
+fn get_name(addr: usize, start_addr: u16, len: usize, hints: &Hints) -> String {
+    match u16::try_from(addr)
+        .ok()
+        .and_then(|a| hints.shared_names.get(&a))
+    {
+        Some(n) => n.to_string(),
+        None if addr >= start_addr as usize && addr < start_addr as usize + len => {
+            hints.label(addr)
+        }
+        None => match u16::try_from(addr).ok().and_then(|a| hints.names.get(&a)) {
+            Some(n) => n.to_string(),
+            None => format!("{:04x}", addr),
+        },
+    }
+}
+
+// Graphviz nodes and edges for the blocks of one segment, without the enclosing "digraph". Nodes
+// are named by their labels. Targets that do not start a block are drawn as ellipses.
+pub fn get_dot(blocks: &[Block], start_addr: u16, len: usize, hints: &Hints) -> String {
+    let node = |addr: usize| get_name(addr, start_addr, len, hints);
+
+    let mut dot = String::new();
+    let starts: BTreeSet<usize> = blocks.iter().map(|b| b.start).collect();
+    for b in blocks {
+        let n = node(b.start);
+        dot.push_str(&format!(
+            "    \"{n}\" [label=\"{n}\\n{:04x}-{:04x}\"];\n",
+            b.start,
+            b.end - 1
+        ));
+    }
+
+    let mut others = BTreeSet::new();
+    for b in blocks {
+        for (target, kind) in &b.edges {
+            if !starts.contains(target) && others.insert(*target) {
+                dot.push_str(&format!("    \"{}\" [shape=ellipse];\n", node(*target)));
+            }
+            let attributes = match kind {
+                EdgeKind::Next => "",
+                EdgeKind::Branch => " [label=\"branch\"]",
+                EdgeKind::Jump => " [label=\"jmp\"]",
+                EdgeKind::Call => " [label=\"jsr\", style=dashed]",
+            };
+            dot.push_str(&format!(
+                "    \"{}\" -> \"{}\"{attributes};\n",
+                node(b.start),
+                node(*target)
+            ));
+        }
+    }
+
+    dot
+}
+
+// Report of the subroutines called with JSR in one segment, as comment lines. A subroutine covers
+// the blocks reachable from its start without following calls.
+pub fn get_subroutine_report(
+    blocks: &[Block],
+    start_addr: u16,
+    len: usize,
+    hints: &Hints,
+) -> String {
+    let name = |addr: usize| get_name(addr, start_addr, len, hints);
+    let block_at: BTreeMap<usize, &Block> = blocks.iter().map(|b| (b.start, b)).collect();
+
+    // Addresses of the calls to each subroutine
+    let mut callers: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for (addr, target) in blocks.iter().flat_map(|b| &b.calls) {
+        callers.entry(*target).or_default().push(*addr);
+    }
+
+    let mut report = String::new();
+    for (sub, calls) in callers {
+        let called_from: Vec<String> = calls.iter().map(|c| format!("{:04x}", c)).collect();
+        let called_from = called_from.join(", ");
+        if !block_at.contains_key(&sub) {
+            report.push_str(&format!(
+                "; {}: outside the code, called from {called_from}\n",
+                name(sub)
+            ));
+            continue;
+        }
+
+        // Follow the flow of control within the subroutine
+        let mut reached = BTreeSet::new();
+        let mut pending = vec![sub];
+        while let Some(a) = pending.pop() {
+            if let Some(b) = block_at.get(&a).filter(|_| reached.insert(a)) {
+                for (target, kind) in &b.edges {
+                    if *kind != EdgeKind::Call {
+                        pending.push(*target);
+                    }
+                }
+            }
+        }
+        let returns: BTreeSet<&str> = reached
+            .iter()
+            .map(|a| block_at[a].last.as_str())
+            .filter(|m| *m == "rts" || *m == "rti")
+            .collect();
+        let returns = match returns.is_empty() {
+            true => "does not end in rts or rti".to_string(),
+            false => format!(
+                "ends in {}",
+                returns.into_iter().collect::<Vec<_>>().join(" and ")
+            ),
+        };
+        let end = reached
+            .iter()
+            .map(|a| block_at[a].end)
+            .max()
+            .unwrap_or(sub + 1);
+        report.push_str(&format!(
+            "; {}: {sub:04x}-{:04x}, {returns}, called from {called_from}\n",
+            name(sub),
+            end - 1
+        ));
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_into_blocks() {
+        // c000: jsr c008; beq c000; jmp c008; c008: rts
+        let bytes = [0x20, 0x08, 0xc0, 0xf0, 0xfb, 0x4c, 0x08, 0xc0, 0x60];
+        let blocks = get_blocks(&bytes, 0xc000, &[(0, 9)], Isa::default()).unwrap();
+        let ranges: Vec<(usize, usize)> = blocks.iter().map(|b| (b.start, b.end)).collect();
+        assert_eq!(
+            ranges,
+            vec![(0xc000, 0xc005), (0xc005, 0xc008), (0xc008, 0xc009)]
+        );
+        assert_eq!(
+            blocks[0].edges,
+            vec![
+                (0xc008, EdgeKind::Call),
+                (0xc000, EdgeKind::Branch),
+                (0xc005, EdgeKind::Next)
+            ]
+        );
+        assert_eq!(blocks[1].edges, vec![(0xc008, EdgeKind::Jump)]);
+        assert_eq!(blocks[2].edges, vec![]);
+    }
+
+    #[test]
+    fn report_subroutines() {
+        // c000: jsr c007; jsr fded; rts; c007: bne c00a; rts; c00a: rti
+        let bytes = [
+            0x20, 0x07, 0xc0, 0x20, 0xed, 0xfd, 0x60, 0xd0, 0x01, 0x60, 0x40,
+        ];
+        let blocks = get_blocks(&bytes, 0xc000, &[(0, 11)], Isa::default()).unwrap();
+        let hints = Hints::parse("name fded cout").unwrap();
+        let report = [
+            "; c007: c007-c00a, ends in rti and rts, called from c000",
+            "; cout: outside the code, called from c003",
+        ];
+        assert_eq!(
+            get_subroutine_report(&blocks, 0xc000, bytes.len(), &hints),
+            report.join("\n") + "\n"
+        );
+    }
+
+    #[test]
+    fn dot_with_named_target() {
+        // c000: jsr fded; rts
+        let bytes = [0x20, 0xed, 0xfd, 0x60];
+        let blocks = get_blocks(&bytes, 0xc000, &[(0, 4)], Isa::default()).unwrap();
+        let hints = Hints::parse("name fded cout").unwrap();
+        let dot = [
+            "    \"c000\" [label=\"c000\\nc000-c003\"];",
+            "    \"cout\" [shape=ellipse];",
+            "    \"c000\" -> \"cout\" [label=\"jsr\", style=dashed];",
+        ];
+        assert_eq!(
+            get_dot(&blocks, 0xc000, bytes.len(), &hints),
+            dot.join("\n") + "\n"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+// Formatting of SASM source in the layout that the disassembler writes: mnemonics and orgs padded
+// to six columns, one space between the other words of a line, and comments after code lined up
+// in one column. Mnemonics, commands, and the hex values of instructions and orgs are lowercase.
+// Only spacing and case change, and comments are kept as they are, so formatted source assembles
+// to the same bytes.
+
+use crate::config::Config;
+use crate::error::SasmError;
+use crate::host::{read_input, write_code};
+use crate::output::Code;
+use crate::prelude::*;
+use crate::syntax::{split_unquoted, strip_comment};
+
+// Commands whose words are separated by single spaces, unlike instructions
+const COMMANDS: [&str; 18] = [
+    "bank",
+    "segment",
+    "label",
+    "zbyte",
+    "zword",
+    "var",
+    "test",
+    "assert",
+    "zfree",
+    "zpreserve",
+    "data",
+    "word",
+    "wordbe",
+    "dword",
+    "end",
+    "checksum",
+    "patch",
+    "org!",
+];
+
+// Width of a mnemonic or org with the spaces after it, as in disassembly
+const MNEMONIC_WIDTH: usize = 6;
+
+// Column (starting at 0) of comments after code
+const COMMENT_COLUMN: usize = 16;
+
+// Hex values of instructions and orgs, including offsets and low and high bytes, are lowercase
+fn lowercase_hex(word: &str) -> String {
+    let digits = word.trim_start_matches(['-', '<', '>']);
+    match !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => word.to_ascii_lowercase(),
+        false => word.to_string(),
+    }
+}
+
+// Format one line. Comments start with ';' or any of the other markers, as when assembling.
+pub fn format_line(line: &str, comment_markers: &[String]) -> String {
+    let code = strip_comment(line, comment_markers);
+    let comment = line[code.len()..].trim_end();
+    let words: Vec<&str> = split_unquoted(code, |c| c.is_ascii_whitespace())
+        .into_iter()
+        .map(|(_, w)| w)
+        .collect();
+
+    let code = match words.split_first() {
+        None => String::new(),
+        Some((first, _)) if first.starts_with('.') => words.join(" "),
+        Some((first, rest)) => {
+            let first = first.to_ascii_lowercase();
+            match COMMANDS.contains(&first.as_str()) {
+                true => [first.as_str()]
+                    .iter()
+                    .chain(rest)
+                    .copied()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                false => {
+                    let rest: Vec<String> = rest.iter().map(|w| lowercase_hex(w)).collect();
+                    format!("{first:MNEMONIC_WIDTH$}{}", rest.join(" "))
+                        .trim_end()
+                        .to_string()
+                }
+            }
+        }
+    };
+
+    match (code.is_empty(), comment.is_empty()) {
+        (true, _) => comment.to_string(),
+        (false, true) => code,
+        (false, false) => format!("{code:width$} {comment}", width = COMMENT_COLUMN - 1),
+    }
+}
+
+// Format source in memory, with ';' as the only comment marker
+pub fn format_str(source: &str) -> String {
+    format_lines(source, &[])
+}
+
+fn format_lines(source: &str, comment_markers: &[String]) -> String {
+    source
+        .lines()
+        .map(|l| format_line(l, comment_markers) + "\n")
+        .collect()
+}
+
+// Format the input file and write it to the output, which may be the same file with --force
+pub fn format_source(config: &mut Config) -> Result<Code, SasmError> {
+    let parts = read_input(config)?;
+    if parts.len() != 1 {
+        return Err(SasmError::io("Formatting takes a single input file"));
+    }
+    let text = match String::from_utf8(parts[0].1.clone()) {
+        Ok(t) => t,
+        Err(_) => return Err(SasmError::io("Input is not valid text")),
+    };
+    let formatted = format_lines(&text, &config.comment_markers);
+
+    // The text already ends with a newline, which printing it as a string would repeat
+    write_code(
+        &Code::Bytes(formatted.clone().into_bytes()),
+        &mut config.otype,
+        config.overwrite,
+    )?;
+    Ok(Code::String(formatted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_lines_like_disassembly() {
+        assert_eq!(format_line("  LDAI   0A", &[]), "ldai  0a");
+        assert_eq!(format_line("org 4000", &[]), "org   4000");
+        assert_eq!(format_line("  .loop", &[]), ".loop");
+        assert_eq!(format_line("staax .Table   -1", &[]), "staax .Table -1");
+        assert_eq!(format_line("label   Top   FF", &[]), "label Top FF");
+        assert_eq!(format_line("   ; a comment  ", &[]), "; a comment");
+        assert_eq!(format_line("rts;done", &[]), "rts             ;done");
+        assert_eq!(
+            format_line("data  \"A;  B\"   00 ; text", &[]),
+            "data \"A;  B\" 00 ; text"
+        );
+        assert_eq!(
+            format_line("inx // next", &["//".to_string()]),
+            "inx             // next"
+        );
+    }
+}
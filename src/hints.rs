@@ -0,0 +1,162 @@
+// Hints that tell the disassembler what it cannot work out for itself. Each line of a hints file
+// is one of the following, with addresses in hex and comments starting with ';' as in SASM source:
+//   code START-END   Disassemble these bytes as code
+//   data START-END   Never disassemble these bytes as code
+//   name ADDR NAME   Use NAME as the label for this address
+
+use alloc::collections::BTreeMap;
+
+use crate::prelude::*;
+
+// How labels without names are made, as set by the --labels flag
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LabelStyle {
+    // The address, such as "c00a"
+    #[default]
+    Addr,
+
+    // The address after a prefix for how it is used: "sub_" for subroutines, "loc_" for other
+    // code, and "dat_" for data
+    Typed,
+
+    // Numbered in order of address, such as "l1"
+    Numbered,
+}
+
+impl LabelStyle {
+    // Attempt to create a variant from a string
+    pub fn new(style: &str) -> Result<Self, &str> {
+        match style.to_ascii_lowercase().as_str() {
+            "addr" => Ok(LabelStyle::Addr),
+            "typed" => Ok(LabelStyle::Typed),
+            "numbered" => Ok(LabelStyle::Numbered),
+            _ => Err("labels must be addr, typed, or numbered"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Hints {
+    // Inclusive address ranges
+    pub code: Vec<(u16, u16)>,
+    pub data: Vec<(u16, u16)>,
+
+    pub names: BTreeMap<u16, String>,
+
+    // Names for stores, where they differ from the names for loads. Hardware registers often
+    // differ this way, but hints files cannot give them.
+    pub write_names: BTreeMap<u16, String>,
+
+    // Names that are always defined with label commands, even for addresses inside the input.
+    // These are addresses that banks share, such as bank-switching hotspots.
+    pub shared_names: BTreeMap<u16, String>,
+
+    // Prefix for labels of addresses inside the input, which keeps the labels of banks apart
+    pub label_prefix: String,
+
+    // Labels made for addresses inside the input without names, unless the style is Addr
+    pub label_style: LabelStyle,
+    pub made_labels: BTreeMap<usize, String>,
+
+    // Whether addresses outside the input without names get labels such as ext_fded
+    pub external_labels: bool,
+}
+
+fn parse_addr(a: &str) -> Result<u16, String> {
+    match u16::from_str_radix(a, 16) {
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("invalid address {a}")),
+    }
+}
+
+fn parse_range(r: &str) -> Result<(u16, u16), String> {
+    let (start, end) = match r.split_once('-') {
+        Some((s, e)) => (parse_addr(s)?, parse_addr(e)?),
+        None => return Err(format!("invalid range {r} (expected START-END)")),
+    };
+    if end < start {
+        return Err(format!("range {r} ends before it starts"));
+    }
+    Ok((start, end))
+}
+
+impl Hints {
+    pub fn parse(text: &str) -> Result<Hints, String> {
+        let mut hints = Hints::default();
+
+        for (line_idx, line) in text.lines().enumerate() {
+            let line_num = line_idx + 1;
+            let words: Vec<&str> = line
+                .split(';')
+                .next()
+                .unwrap_or("")
+                .split_ascii_whitespace()
+                .collect();
+
+            let result = match words.as_slice() {
+                [] => Ok(()),
+                ["code", r] => parse_range(r).map(|r| hints.code.push(r)),
+                ["data", r] => parse_range(r).map(|r| hints.data.push(r)),
+                ["name", a, n] if !n.starts_with('.') => parse_addr(a).map(|a| {
+                    hints.names.insert(a, n.to_string());
+                }),
+                ["name", _, _] => Err("names must not start with '.'".to_string()),
+                _ => Err("expected code START-END, data START-END, or name ADDR NAME".to_string()),
+            };
+            result.map_err(|e| format!("{line_num}: {e}"))?;
+        }
+
+        Ok(hints)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &str) -> Result<Hints, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Hints::parse(&text).map_err(|e| format!("{path}:{e}")),
+            Err(_) => Err(format!("Unable to read hints file {path}")),
+        }
+    }
+
+    // Name of an address for an instruction that writes to it or reads from it
+    pub fn name(&self, addr: u16, write: bool) -> Option<&String> {
+        match write {
+            true => self.write_names.get(&addr).or(self.names.get(&addr)),
+            false => self.names.get(&addr),
+        }
+    }
+
+    // Label for an address inside the input, which is its name if it has one
+    pub fn label(&self, addr: usize) -> String {
+        match u16::try_from(addr).ok().and_then(|a| self.names.get(&a)) {
+            Some(n) => format!("{}{n}", self.label_prefix),
+            None => match self.made_labels.get(&addr) {
+                Some(l) => format!("{}{l}", self.label_prefix),
+                None => format!("{}{:04x}", self.label_prefix, addr),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hints() {
+        let hints =
+            Hints::parse("; ROM\ncode c000-c0ff\ndata fffa-ffff\nname c000 reset ; entry\n")
+                .unwrap();
+        assert_eq!(hints.code, vec![(0xc000, 0xc0ff)]);
+        assert_eq!(hints.data, vec![(0xfffa, 0xffff)]);
+        assert_eq!(hints.label(0xc000), "reset");
+        assert_eq!(hints.label(0xc001), "c001");
+    }
+
+    #[test]
+    fn parse_error_has_line() {
+        assert_eq!(
+            Hints::parse("code c000-c0ff\ndata c100\n"),
+            Err("2: invalid range c100 (expected START-END)".to_string())
+        );
+    }
+}
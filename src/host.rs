@@ -0,0 +1,271 @@
+// Input and output through the host system: files, STDIN, STDOUT, and STDERR. The rest of the
+// crate only works on data in memory, so that everything that depends on an operating system is
+// found here. Without the std feature, "memory_host.rs" takes its place.
+
+use std::io::{IsTerminal, Read, Write};
+
+use crate::assemble::Stretch;
+use crate::checksum::Checksum;
+use crate::config::{Config, IType, InputPart, OType};
+use crate::error::SasmError;
+use crate::output::{Code, OutputWriter, Overwrite};
+
+// Path of a file, searched for in the include paths if it is not found as given
+pub fn find_file(f: &str, include_paths: &[String]) -> String {
+    let path = std::path::Path::new(f);
+    if !path.exists() && path.is_relative() {
+        for dir in include_paths {
+            let candidate = std::path::Path::new(dir).join(path);
+            if candidate.exists() {
+                return candidate.to_string_lossy().to_string();
+            }
+        }
+    }
+    f.to_string()
+}
+
+// Read each part of the input in order
+pub fn read_input(config: &mut Config) -> Result<Vec<InputPart>, SasmError> {
+    let mut b = Vec::new();
+    match config.itype {
+        IType::Stdin => {
+            if let Err(e) = std::io::stdin().read_to_end(&mut b) {
+                return Err(SasmError::io(&format!("Unable to read from stdin: {e}")));
+            }
+        }
+        IType::String(ref s) => b = s.as_bytes().to_vec(),
+        IType::Reader(ref mut r) => {
+            if let Err(e) = r.read_to_end(&mut b) {
+                return Err(SasmError::io(&format!("Unable to read input: {e}")));
+            }
+        }
+        IType::File(_) | IType::Files(_) => {
+            let mut parts = Vec::new();
+            for f in config.input_files() {
+                match std::fs::read(&f) {
+                    Ok(b) => parts.push((Some(f), b)),
+                    Err(e) => {
+                        let message = format!("Unable to read input file {f}: {e}");
+                        return Err(SasmError::io(&message));
+                    }
+                }
+            }
+            return Ok(parts);
+        }
+    }
+    Ok(vec![(None, b)])
+}
+
+// Input as one block of bytes, which a large file maps into memory instead of reading, so that
+// only the pages that are used are loaded
+pub enum InputBytes {
+    Read(Vec<u8>),
+    Mapped(memmap2::Mmap),
+}
+
+impl std::ops::Deref for InputBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            InputBytes::Read(b) => b,
+            InputBytes::Mapped(m) => m,
+        }
+    }
+}
+
+// Smallest input file that is mapped rather than read, which is larger than any 6502 address space
+pub const MAP_THRESHOLD: u64 = 0x10000;
+
+// Read every part of the input as one block. A single large file is mapped into memory.
+pub fn read_input_bytes(config: &mut Config) -> Result<InputBytes, SasmError> {
+    if let [f] = config.input_files().as_slice() {
+        let file = match std::fs::File::open(f) {
+            Ok(file) => file,
+            Err(e) => {
+                return Err(SasmError::io(&format!(
+                    "Unable to read input file {f}: {e}"
+                )));
+            }
+        };
+        if file.metadata().is_ok_and(|m| m.len() >= MAP_THRESHOLD) {
+            // Safety: the map is only read, and changing a file while it is being disassembled
+            // is no more expected than for any other input
+            match unsafe { memmap2::Mmap::map(&file) } {
+                Ok(m) => {
+                    log::info!("Mapped {} bytes of {f}", m.len());
+                    return Ok(InputBytes::Mapped(m));
+                }
+                Err(e) => log::debug!("Unable to map {f}, so reading it: {e}"),
+            }
+        }
+    }
+    let bytes = read_input(config)?
+        .into_iter()
+        .flat_map(|(_, b)| b)
+        .collect();
+    Ok(InputBytes::Read(bytes))
+}
+
+// Read a whole text file, such as an object file
+pub fn read_text(f: &str) -> Result<String, SasmError> {
+    match std::fs::read_to_string(f) {
+        Ok(t) => Ok(t),
+        Err(e) => Err(SasmError::io(&format!(
+            "Unable to read input file {f}: {e}"
+        ))),
+    }
+}
+
+pub fn read_binary(f: &str) -> Result<Vec<u8>, SasmError> {
+    match std::fs::read(f) {
+        Ok(b) => Ok(b),
+        Err(e) => Err(SasmError::io(&format!("Unable to read file {f}: {e}"))),
+    }
+}
+
+// Like checksums, the cycle report goes to stderr
+pub fn report_cycles(stretches: &[Stretch]) {
+    for s in stretches {
+        eprintln!("{s}");
+    }
+}
+
+// So are the statistics of each file and line
+pub fn report_stats(stats: &str) {
+    eprint!("{stats}");
+}
+
+// Checksums are reported on stderr so that they never mix with the code itself
+pub fn report_checksum(c: Checksum, value: u32) {
+    eprintln!("{} checksum: {}", c.name(), c.to_hex_string(value));
+}
+
+fn write_code_to_file<T: std::convert::AsRef<[u8]>>(
+    f: &str,
+    c: T,
+    overwrite: Overwrite,
+) -> Result<(), String> {
+    match (std::fs::exists(f), overwrite) {
+        (Ok(true), Overwrite::Never) => return Err(format!("File {f} already exists")),
+        (Ok(true), Overwrite::Backup) => {
+            let backup = format!("{f}.bak");
+            if std::fs::rename(f, &backup).is_err() {
+                return Err(format!("Unable to rename file {f} to {backup}"));
+            }
+        }
+        (Ok(_), _) => (),
+        (Err(_), _) => return Err(format!("Unable to check existence of file {f}")),
+    }
+
+    match std::fs::write(f, c) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("Unable to write to file {f}")),
+    }
+}
+
+// Size and modification time (in seconds since 1970) of a file, or zeros if it cannot be read
+pub fn file_info(f: &str) -> (u64, u64) {
+    let Ok(metadata) = std::fs::metadata(f) else {
+        return (0, 0);
+    };
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    (metadata.len(), mtime)
+}
+
+// Write a text file other than the code, such as a symbol file
+pub fn write_text(text: &str, f: &str, overwrite: Overwrite) -> Result<(), SasmError> {
+    if let Err(e) = write_code_to_file(f, text, overwrite) {
+        return Err(SasmError::io(&format!("Error: {e}")));
+    }
+
+    Ok(())
+}
+
+impl<W: Write> OutputWriter for W {
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), SasmError> {
+        self.write_all(bytes)
+            .map_err(|_| SasmError::io("Unable to write output"))
+    }
+}
+
+// Binary output to STDOUT would garble a terminal, so it is refused unless forced or redirected
+pub fn check_binary_stdout(config: &Config) -> Result<(), SasmError> {
+    if matches!(config.otype, OType::Stdout)
+        && !config.cformat.is_text()
+        && !config.force_binary_stdout
+        && std::io::stdout().is_terminal()
+    {
+        let message = "Binary output to a terminal (use -o FILE or --force-binary-stdout)";
+        return Err(SasmError::io(message));
+    }
+
+    Ok(())
+}
+
+pub fn write_code(code: &Code, otype: &mut OType, overwrite: Overwrite) -> Result<(), SasmError> {
+    let written = match (code, &mut *otype) {
+        (Code::String(s), OType::Writer(w)) => writeln!(w, "{s}"),
+        (Code::Bytes(b), OType::Writer(w)) => w.write_all(b),
+        _ => Ok(()),
+    };
+    if written.is_err() {
+        return Err(SasmError::io("Unable to write output"));
+    }
+
+    match code {
+        Code::String(ref s) => match &otype {
+            OType::Stdout => println!("{s}"),
+            OType::File(f) => {
+                if let Err(e) = write_code_to_file(f, s, overwrite) {
+                    return Err(SasmError::io(&format!("Error: {e}")));
+                }
+            }
+            OType::None | OType::Writer(_) => (),
+        },
+        Code::Bytes(ref b) => match &otype {
+            OType::Stdout => {
+                if let Err(e) = std::io::stdout().write_all(b) {
+                    return Err(SasmError::io(&format!("Error: {e}")));
+                }
+            }
+            OType::File(f) => {
+                if let Err(e) = write_code_to_file(f, b, overwrite) {
+                    return Err(SasmError::io(&format!("Error: {e}")));
+                }
+            }
+            OType::None | OType::Writer(_) => (),
+        },
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overwrite_policies() {
+        let path = std::env::temp_dir().join("sasm2_overwrite_test.hex");
+        let backup = std::env::temp_dir().join("sasm2_overwrite_test.hex.bak");
+        let f = path.to_str().unwrap();
+        std::fs::write(&path, "old").unwrap();
+
+        assert_eq!(
+            write_code_to_file(f, "new", Overwrite::Never),
+            Err(format!("File {f} already exists"))
+        );
+        assert_eq!(write_code_to_file(f, "new", Overwrite::Backup), Ok(()));
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "old");
+        assert_eq!(write_code_to_file(f, "newer", Overwrite::Force), Ok(()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "newer");
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&backup).unwrap();
+    }
+}
@@ -0,0 +1,403 @@
+// Formats accepted as disassembler input, and conversion of each to blocks of bytes along with
+// their load addresses
+
+use crate::error::SasmError;
+use crate::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputFormat {
+    // Detect the format from the input
+    Auto,
+
+    // String of hex digits, possibly broken up by whitespace (as from "xxd -p")
+    Hex,
+
+    // Raw bytes
+    Binary,
+
+    // Intel HEX records
+    IntelHex,
+
+    // Motorola S-records
+    SRecord,
+
+    // Apple II system monitor memory dump, as output by SASM or the monitor itself
+    AppleSM,
+
+    // Apple DOS 3.3 binary ('B') file, which starts with its load address and length
+    Dos33,
+}
+
+// A block of contiguous bytes and the address where it is loaded
+pub type Segment = (u16, Vec<u8>);
+
+impl InputFormat {
+    // Attempt to create a variant from a string
+    pub fn new(format: &str) -> Result<Self, &str> {
+        match format.to_ascii_lowercase().as_str() {
+            "auto" => Ok(InputFormat::Auto),
+            "hex" => Ok(InputFormat::Hex),
+            "bin" => Ok(InputFormat::Binary),
+            "ihex" => Ok(InputFormat::IntelHex),
+            "srec" => Ok(InputFormat::SRecord),
+            "apple" => Ok(InputFormat::AppleSM),
+            "dos" => Ok(InputFormat::Dos33),
+            _ => Err("input format must be auto, hex, bin, ihex, srec, apple, or dos"),
+        }
+    }
+
+    // Resolve Auto to the format that the input appears to be in
+    pub fn detect(self, input: &[u8]) -> Self {
+        if self != InputFormat::Auto {
+            return self;
+        }
+
+        // A DOS 3.3 header gives the exact length of the rest of the file, which is unlikely to
+        // happen by chance
+        if input.len() >= 4 && u16::from_le_bytes([input[2], input[3]]) as usize == input.len() - 4
+        {
+            return InputFormat::Dos33;
+        }
+
+        let text = match core::str::from_utf8(input) {
+            Ok(t) => t.trim_start(),
+            Err(_) => return InputFormat::Binary,
+        };
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(':'), _) => InputFormat::IntelHex,
+            (Some('S'), Some(c)) if c.is_ascii_digit() => InputFormat::SRecord,
+            _ if text
+                .lines()
+                .next()
+                .is_some_and(|l| split_apple_sm_line(l).is_some()) =>
+            {
+                InputFormat::AppleSM
+            }
+            _ if is_hex_text(input) => InputFormat::Hex,
+            _ => InputFormat::Binary,
+        }
+    }
+
+    // Convert the input to segments sorted by address. Formats without addresses are loaded at
+    // start_addr.
+    pub fn decode(self, input: &[u8], start_addr: u16) -> Result<Vec<Segment>, SasmError> {
+        let format = self.detect(input);
+        let text = match format {
+            InputFormat::Auto | InputFormat::Binary => {
+                return Ok(vec![(start_addr, input.to_vec())])
+            }
+            InputFormat::Dos33 => return decode_dos33(input),
+            _ => match core::str::from_utf8(input) {
+                Ok(t) => t,
+                Err(_) => return Err(SasmError::syntax("Input is not text")),
+            },
+        };
+
+        match format {
+            InputFormat::Hex => {
+                let digits: String = text.split_ascii_whitespace().collect();
+                match hex::decode(digits) {
+                    Ok(b) => Ok(vec![(start_addr, b)]),
+                    Err(_) => Err(SasmError::syntax("Cannot decode hex input")),
+                }
+            }
+            InputFormat::IntelHex => merge(decode_intel_hex(text)?),
+            InputFormat::SRecord => merge(decode_srecords(text)?),
+            InputFormat::AppleSM => merge(decode_apple_sm(text)?),
+            InputFormat::Auto | InputFormat::Binary | InputFormat::Dos33 => {
+                Err(SasmError::internal("binary input decoded as text"))
+            }
+        }
+    }
+}
+
+// Input is hex text if it has an even number of hex digits and nothing else but whitespace
+fn is_hex_text(input: &[u8]) -> bool {
+    let mut num_digits = 0;
+    for b in input {
+        if b.is_ascii_hexdigit() {
+            num_digits += 1;
+        } else if !b.is_ascii_whitespace() {
+            return false;
+        }
+    }
+    num_digits > 0 && num_digits % 2 == 0
+}
+
+// Decode a record made of hex digit pairs whose bytes, including the final checksum byte, sum to
+// the given value
+fn decode_record(digits: &str, sum: u8, line: &str, line_num: usize) -> Result<Vec<u8>, SasmError> {
+    let bytes = match hex::decode(digits) {
+        Ok(b) if b.len() >= 2 => b,
+        _ => return Err(SasmError::syntax("invalid record").at_line(line_num, line)),
+    };
+    if bytes.iter().fold(0u8, |s, b| s.wrapping_add(*b)) != sum {
+        return Err(SasmError::syntax("record checksum does not match").at_line(line_num, line));
+    }
+    Ok(bytes)
+}
+
+// Records are ":LLAAAATTDD..CC" with byte count, address, record type, data, and checksum
+fn decode_intel_hex(text: &str) -> Result<Vec<(u32, Vec<u8>)>, SasmError> {
+    let mut blocks = Vec::new();
+    let mut base_addr: u32 = 0;
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let line_num = line_idx + 1;
+        let err = |m: &str| SasmError::syntax(m).at_line(line_num, line);
+        let record = line.trim();
+        if record.is_empty() {
+            continue;
+        }
+
+        let digits = match record.strip_prefix(':') {
+            Some(d) => d,
+            None => return Err(err("Intel HEX records must start with ':'")),
+        };
+        let bytes = decode_record(digits, 0, line, line_num)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(err("Intel HEX record has the wrong length"));
+        }
+
+        let addr = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match (bytes[3], data.len()) {
+            (0x00, _) => blocks.push((base_addr + addr, data.to_vec())),
+            (0x01, _) => break,
+            (0x02, 2) => base_addr = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            (0x04, 2) => base_addr = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+
+            // Start addresses do not affect the memory image
+            (0x03, _) | (0x05, _) => (),
+            _ => return Err(err("unsupported Intel HEX record")),
+        }
+    }
+
+    Ok(blocks)
+}
+
+// The header is the load address and length, both little-endian. Files copied off a disk often
+// have extra bytes at the end, which are ignored.
+fn decode_dos33(input: &[u8]) -> Result<Vec<Segment>, SasmError> {
+    if input.len() < 4 {
+        return Err(SasmError::syntax("Input is too short for a DOS 3.3 header"));
+    }
+    let addr = u16::from_le_bytes([input[0], input[1]]);
+    let len = u16::from_le_bytes([input[2], input[3]]) as usize;
+    match input.get(4..4 + len) {
+        Some(bytes) => Ok(vec![(addr, bytes.to_vec())]),
+        None => Err(SasmError::syntax(
+            "DOS 3.3 header length is longer than the input",
+        )),
+    }
+}
+
+// Records are "STCCAAAADD..SS" with type, byte count, address (2-4 bytes), data, and checksum
+fn decode_srecords(text: &str) -> Result<Vec<(u32, Vec<u8>)>, SasmError> {
+    let mut blocks = Vec::new();
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let line_num = line_idx + 1;
+        let err = |m: &str| SasmError::syntax(m).at_line(line_num, line);
+        let record = line.trim();
+        if record.is_empty() {
+            continue;
+        }
+
+        let (record_type, digits) = match record.strip_prefix('S') {
+            Some(r) if !r.is_empty() && r.is_char_boundary(1) => r.split_at(1),
+            _ => return Err(err("S-records must start with 'S'")),
+        };
+        let bytes = decode_record(digits, 0xff, line, line_num)?;
+        if bytes.len() != bytes[0] as usize + 1 {
+            return Err(err("S-record has the wrong length"));
+        }
+
+        let addr_size = match record_type {
+            "0" | "1" | "5" | "9" => 2,
+            "2" | "6" | "8" => 3,
+            "3" | "7" => 4,
+            _ => return Err(err("unsupported S-record type")),
+        };
+        if bytes.len() < addr_size + 2 {
+            return Err(err("S-record has the wrong length"));
+        }
+        let addr = bytes[1..1 + addr_size]
+            .iter()
+            .fold(0u32, |a, b| (a << 8) | *b as u32);
+        let data = &bytes[1 + addr_size..bytes.len() - 1];
+
+        match record_type {
+            "1" | "2" | "3" => blocks.push((addr, data.to_vec())),
+            "7" | "8" | "9" => break,
+
+            // Header and record counts
+            _ => (),
+        }
+    }
+
+    Ok(blocks)
+}
+
+// Split a memory dump line such as "4000:a9 01" (SASM) or "4000- A9 01" (the monitor itself)
+// into its address and bytes
+fn split_apple_sm_line(line: &str) -> Option<(&str, &str)> {
+    let (addr, bytes) = line.trim().split_once([':', '-'])?;
+    if addr.is_empty() || addr.len() > 4 || !addr.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some((addr, bytes))
+}
+
+fn decode_apple_sm(text: &str) -> Result<Vec<(u32, Vec<u8>)>, SasmError> {
+    let mut blocks = Vec::new();
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let line_num = line_idx + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let decoded = split_apple_sm_line(line).and_then(|(addr, bytes)| {
+            let addr = u32::from_str_radix(addr, 16).ok()?;
+            let bytes = bytes
+                .split_ascii_whitespace()
+                .map(|b| match b.len() {
+                    1 | 2 => u8::from_str_radix(b, 16).ok(),
+                    _ => None,
+                })
+                .collect::<Option<Vec<u8>>>()?;
+            Some((addr, bytes))
+        });
+        match decoded {
+            Some(block) => blocks.push(block),
+            None => {
+                let e = SasmError::syntax("invalid memory dump line");
+                return Err(e.at_line(line_num, line));
+            }
+        }
+    }
+
+    Ok(blocks)
+}
+
+// Sort blocks by address and join the ones that are adjacent
+fn merge(mut blocks: Vec<(u32, Vec<u8>)>) -> Result<Vec<Segment>, SasmError> {
+    blocks.sort_by_key(|b| b.0);
+
+    let mut segments: Vec<Segment> = Vec::new();
+    for (addr, bytes) in blocks {
+        if addr as usize + bytes.len() > 0x10000 {
+            return Err(SasmError::range("input has data beyond address ffff"));
+        }
+        if bytes.is_empty() {
+            continue;
+        }
+
+        match segments.last_mut() {
+            Some((last_addr, last_bytes)) => {
+                let last_end = *last_addr as usize + last_bytes.len();
+                if (addr as usize) < last_end {
+                    let message = format!("input has overlapping data at address {:04x}", addr);
+                    return Err(SasmError::range(&message));
+                } else if addr as usize == last_end {
+                    last_bytes.extend(bytes);
+                } else {
+                    segments.push((addr as u16, bytes));
+                }
+            }
+            None => segments.push((addr as u16, bytes)),
+        }
+    }
+
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_formats() {
+        assert_eq!(
+            InputFormat::Auto.detect(b"a9 01\n8d00c0\n"),
+            InputFormat::Hex
+        );
+        assert_eq!(InputFormat::Auto.detect(&[0xa9, 0x01]), InputFormat::Binary);
+        assert_eq!(InputFormat::Auto.detect(b"a90"), InputFormat::Binary);
+        assert_eq!(InputFormat::Binary.detect(b"a901"), InputFormat::Binary);
+        assert_eq!(
+            InputFormat::Auto.detect(b":00000001FF\n"),
+            InputFormat::IntelHex
+        );
+        assert_eq!(
+            InputFormat::Auto.detect(b"S9030000FC\n"),
+            InputFormat::SRecord
+        );
+        assert_eq!(
+            InputFormat::Auto.detect(b"0300- A9 01\n"),
+            InputFormat::AppleSM
+        );
+        assert_eq!(
+            InputFormat::Auto.detect(&[0x00, 0x03, 0x01, 0x00, 0x60]),
+            InputFormat::Dos33
+        );
+    }
+
+    #[test]
+    fn decode_dos33_with_padding() {
+        assert_eq!(
+            InputFormat::Dos33.decode(&[0x00, 0x03, 0x03, 0x00, 0xa9, 0x01, 0x60, 0x00], 0),
+            Ok(vec![(0x300, vec![0xa9, 0x01, 0x60])])
+        );
+        assert!(InputFormat::Dos33
+            .decode(&[0x00, 0x03, 0x09, 0x00, 0x60], 0)
+            .is_err());
+    }
+
+    #[test]
+    fn decode_hex_with_whitespace() {
+        assert_eq!(
+            InputFormat::Auto.decode(b"a9 01\n60\n", 0x300),
+            Ok(vec![(0x300, vec![0xa9, 0x01, 0x60])])
+        );
+    }
+
+    #[test]
+    fn decode_intel_hex_with_gap() {
+        let text = b":03C00000A9016033\n:01C0100060CF\n:00000001FF\n";
+        assert_eq!(
+            InputFormat::Auto.decode(text, 0),
+            Ok(vec![(0xc000, vec![0xa9, 0x01, 0x60]), (0xc010, vec![0x60])])
+        );
+    }
+
+    #[test]
+    fn decode_srecords() {
+        let text = b"S00600004844521B\nS106C000A901602F\nS9030000FC\n";
+        assert_eq!(
+            InputFormat::Auto.decode(text, 0),
+            Ok(vec![(0xc000, vec![0xa9, 0x01, 0x60])])
+        );
+    }
+
+    #[test]
+    fn decode_apple_sm_dump() {
+        let text = b"4000:a9 01\n4002:60\n0300- EA\n";
+        assert_eq!(
+            InputFormat::Auto.decode(text, 0),
+            Ok(vec![(0x300, vec![0xea]), (0x4000, vec![0xa9, 0x01, 0x60])])
+        );
+    }
+
+    #[test]
+    fn bad_checksum() {
+        assert_eq!(
+            InputFormat::IntelHex
+                .decode(b":03C00000A9016034\n", 0)
+                .map_err(|e| e.to_string()),
+            Err("1: record checksum does not match".to_string())
+        );
+    }
+}
@@ -0,0 +1,39 @@
+// Minimal helpers for writing JSON by hand, which is all that SASM needs
+
+use crate::prelude::*;
+
+// Quote and escape a string
+pub fn string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    return quoted;
+}
+
+// Join already-formatted values into an array, one per line
+pub fn array(values: &[String]) -> String {
+    if values.is_empty() {
+        return "[]".to_string();
+    }
+    return format!("[\n    {}\n  ]", values.join(",\n    "));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_string() {
+        assert_eq!(string("a\"b\\c\n\u{1}"), "\"a\\\"b\\\\c\\n\\u0001\"");
+    }
+}
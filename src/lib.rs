@@ -1,18 +1,75 @@
+// Explicit returns are the preferred style in this crate
+#![allow(clippy::needless_return)]
+// Without the std feature, the crate builds with no_std and alloc
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
 // Top-level public modules
 pub mod assemble;
 pub mod config;
 pub mod disassemble;
+pub mod emu;
+pub mod error;
+#[cfg(feature = "std")]
+pub mod logger;
+pub mod version;
+pub mod warning;
 
 // Internal modules used by assemble and config
+mod bank;
+mod ca65;
+mod checksum;
 mod data;
+mod diagnostic;
+mod diff;
+mod flow;
+mod formatter;
+mod hints;
+mod input;
+mod json;
+mod object;
 mod output;
+mod prelude;
+mod ram;
+mod standard;
+mod symbol;
 mod syntax;
+mod toml;
 mod zpm;
 
+// Input and output through the operating system, or only in memory without std
+#[cfg(feature = "std")]
+mod host;
+#[cfg(not(feature = "std"))]
+#[path = "memory_host.rs"]
+mod host;
+
 // Value returned to user
 pub use output::Code;
 
+// Output formats added by library users
+pub use output::OutputFormat;
+
+// Destination of output written as it is made
+pub use output::OutputWriter;
+
+// Policy for existing output files
+pub use output::Overwrite;
+
 // Simplify the interface for users
-pub use assemble::assemble;
+pub use assemble::{
+    assemble, assemble_str, assemble_to, export_ca65, run, test, verify, AsmOptions, Assembled,
+    Assembler, Stretch,
+};
+pub use ca65::{import_ca65, import_ca65_str};
 pub use config::Config;
-pub use disassemble::disassemble;
+pub use diagnostic::{ColorChoice, Diagnostic, ErrorFormat};
+pub use diff::{diff, Change};
+pub use disassemble::{disassemble, DisassemblyWriter};
+pub use error::SasmError;
+pub use formatter::{format_line, format_source, format_str};
+pub use input::InputFormat;
+pub use standard::Syntax;
+pub use version::version;
+pub use warning::SasmWarning;
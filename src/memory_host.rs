@@ -0,0 +1,92 @@
+// Input and output without the std feature, which has no files, STDIN, or STDOUT. Only input and
+// output in memory work, and what "host.rs" reports on STDERR is logged instead, so that it
+// reaches whatever logger the program installs.
+
+use crate::assemble::Stretch;
+use crate::checksum::Checksum;
+use crate::config::{Config, IType, InputPart, OType};
+use crate::error::SasmError;
+use crate::output::{Code, OutputWriter, Overwrite};
+use crate::prelude::*;
+
+// Without a file system there is nowhere to search, so a file is left as it is given
+pub fn find_file(f: &str, _include_paths: &[String]) -> String {
+    f.to_string()
+}
+
+pub fn read_input(config: &mut Config) -> Result<Vec<InputPart>, SasmError> {
+    match config.itype {
+        IType::String(ref s) => Ok(vec![(None, s.as_bytes().to_vec())]),
+        IType::Stdin => Err(SasmError::io("Unable to read from stdin without std")),
+        IType::File(_) | IType::Files(_) => {
+            Err(SasmError::io("Unable to read input files without std"))
+        }
+    }
+}
+
+pub type InputBytes = Vec<u8>;
+
+pub fn read_input_bytes(config: &mut Config) -> Result<InputBytes, SasmError> {
+    Ok(read_input(config)?
+        .into_iter()
+        .flat_map(|(_, b)| b)
+        .collect())
+}
+
+pub fn read_text(f: &str) -> Result<String, SasmError> {
+    Err(SasmError::io(&format!(
+        "Unable to read input file {f} without std"
+    )))
+}
+
+pub fn read_binary(f: &str) -> Result<Vec<u8>, SasmError> {
+    Err(SasmError::io(&format!(
+        "Unable to read file {f} without std"
+    )))
+}
+
+pub fn report_cycles(stretches: &[Stretch]) {
+    for s in stretches {
+        log::info!("{s}");
+    }
+}
+
+pub fn report_stats(stats: &str) {
+    log::info!("{stats}");
+}
+
+pub fn report_checksum(c: Checksum, value: u32) {
+    log::info!("{} checksum: {}", c.name(), c.to_hex_string(value));
+}
+
+pub fn file_info(_f: &str) -> (u64, u64) {
+    (0, 0)
+}
+
+pub fn write_text(_text: &str, f: &str, _overwrite: Overwrite) -> Result<(), SasmError> {
+    Err(SasmError::io(&format!(
+        "Unable to write to file {f} without std"
+    )))
+}
+
+// There is no terminal to garble
+pub fn check_binary_stdout(_config: &Config) -> Result<(), SasmError> {
+    Ok(())
+}
+
+impl OutputWriter for Vec<u8> {
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), SasmError> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+pub fn write_code(_code: &Code, otype: &mut OType, _overwrite: Overwrite) -> Result<(), SasmError> {
+    match otype {
+        OType::None => Ok(()),
+        OType::Stdout => Err(SasmError::io("Unable to write to stdout without std")),
+        OType::File(f) => Err(SasmError::io(&format!(
+            "Unable to write to file {f} without std"
+        ))),
+    }
+}
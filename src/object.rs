@@ -0,0 +1,352 @@
+// Object files, which hold assembled code whose references to labels in other files are not yet
+// resolved. Linking combines several of them into the final image. Each line of an object file is
+// one of the following, with addresses and values in hex and comments starting with ';':
+//   block ADDR BYTES        Code at an org address
+//   export NAME VALUE       Label defined in this file (two hex digits for zero-page labels)
+//   ref ADDR KIND NAME      Operand at ADDR that refers to a label in another file
+// The kind of a reference is zp (a single byte), abs (two bytes), or rel (a branch offset). The
+// bytes of every reference hold any offset, which is added to the label's value. For rel, the
+// offset is a signed byte, as branch offsets are.
+
+use alloc::collections::BTreeMap;
+
+use crate::error::SasmError;
+use crate::output::org_blocks;
+use crate::prelude::*;
+use crate::syntax::UInt;
+use crate::warning::{SasmWarning, WarningKind};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RefKind {
+    Zp,
+    Abs,
+    Rel,
+}
+
+impl RefKind {
+    fn new(kind: &str) -> Result<Self, String> {
+        match kind {
+            "zp" => Ok(RefKind::Zp),
+            "abs" => Ok(RefKind::Abs),
+            "rel" => Ok(RefKind::Rel),
+            _ => Err(format!("invalid reference kind {kind}")),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            RefKind::Zp => "zp",
+            RefKind::Abs => "abs",
+            RefKind::Rel => "rel",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Object {
+    pub blocks: Vec<(u16, Vec<u8>)>,
+    pub exports: Vec<(String, UInt)>,
+    pub refs: Vec<(u16, RefKind, String)>,
+}
+
+fn parse_hex(h: &str) -> Result<u16, String> {
+    match u16::from_str_radix(h, 16) {
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("invalid hex value {h}")),
+    }
+}
+
+fn parse_bytes(b: &str) -> Result<Vec<u8>, String> {
+    hex::decode(b).map_err(|_| format!("invalid bytes {b}"))
+}
+
+impl Object {
+    // Split assembled code into blocks at each org address
+    pub fn new(
+        bytes: &[u8],
+        org_to_code_pos: &BTreeMap<u16, usize>,
+        labels: &BTreeMap<String, UInt>,
+        refs: Vec<(u16, RefKind, String)>,
+    ) -> Object {
+        let blocks = org_blocks(bytes, org_to_code_pos);
+        let mut exports: Vec<(String, UInt)> =
+            labels.iter().map(|(l, u)| (l.clone(), *u)).collect();
+        exports.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Object {
+            blocks,
+            exports,
+            refs,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("; SASM2 object\n");
+        for (addr, bytes) in &self.blocks {
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            text.push_str(&format!("block {:04x} {hex}\n", addr));
+        }
+        for (name, value) in &self.exports {
+            let value = match value {
+                UInt::U8(u) => format!("{:02x}", u),
+                UInt::U16(u) => format!("{:04x}", u),
+            };
+            text.push_str(&format!("export {name} {value}\n"));
+        }
+        for (addr, kind, name) in &self.refs {
+            text.push_str(&format!("ref {:04x} {} {name}\n", addr, kind.name()));
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Object, String> {
+        let mut object = Object::default();
+
+        for (line_idx, line) in text.lines().enumerate() {
+            let line_num = line_idx + 1;
+            let words: Vec<&str> = line
+                .split(';')
+                .next()
+                .unwrap_or("")
+                .split_ascii_whitespace()
+                .collect();
+
+            let result = match words.as_slice() {
+                [] => Ok(()),
+                ["block", a, b] => {
+                    parse_hex(a).and_then(|a| parse_bytes(b).map(|b| object.blocks.push((a, b))))
+                }
+                ["export", n, v] => parse_hex(v).map(|u| {
+                    let value = match v.len() {
+                        1 | 2 => UInt::U8(u as u8),
+                        _ => UInt::U16(u),
+                    };
+                    object.exports.push((n.to_string(), value));
+                }),
+                ["ref", a, k, n] => parse_hex(a)
+                    .and_then(|a| RefKind::new(k).map(|k| object.refs.push((a, k, n.to_string())))),
+                _ => Err(
+                    "expected block ADDR BYTES, export NAME VALUE, or ref ADDR KIND NAME"
+                        .to_string(),
+                ),
+            };
+            result.map_err(|e| format!("{line_num}: {e}"))?;
+        }
+
+        Ok(object)
+    }
+}
+
+// Bytes of a linked image, their org addresses, and its labels
+type Image = (Vec<u8>, BTreeMap<u16, usize>, BTreeMap<String, UInt>);
+
+// Position in the combined bytes of an address, if the address holds code, and the end of the
+// block that holds it
+fn code_pos(
+    org_to_code_pos: &BTreeMap<u16, usize>,
+    len: usize,
+    addr: u16,
+) -> Option<(usize, usize)> {
+    let (start, pos) = org_to_code_pos.range(..=addr).next_back()?;
+    let end = org_to_code_pos
+        .range(addr..)
+        .find(|(a, _)| **a > *start)
+        .map_or(len, |(_, p)| *p);
+    let pos = pos + (addr - start) as usize;
+    match pos < end {
+        true => Some((pos, end)),
+        false => None,
+    }
+}
+
+// Combine objects into one image and resolve their references. Returns the bytes, their org
+// addresses, and the labels of every object. Labels are named by the file that defines them in
+// errors and warnings, so each object comes with its file name.
+pub fn link(
+    objects: &[(String, Object)],
+    warnings: &mut Vec<SasmWarning>,
+) -> Result<Image, Vec<SasmError>> {
+    let mut errors = Vec::new();
+
+    // Blocks of all objects in address order, which must not overlap
+    let mut blocks: Vec<&(u16, Vec<u8>)> = objects.iter().flat_map(|(_, o)| &o.blocks).collect();
+    blocks.sort_by_key(|b| b.0);
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut org_to_code_pos = BTreeMap::new();
+    let mut next_addr = 0;
+    for (addr, b) in blocks {
+        if (*addr as usize) < next_addr {
+            let message = format!("objects overlap at {:04x}", addr);
+            return Err(vec![SasmError::range(&message)]);
+        }
+        org_to_code_pos.insert(*addr, bytes.len());
+        bytes.extend(b);
+        next_addr = *addr as usize + b.len();
+    }
+
+    // Labels of all objects. A label defined in more than one of them is a warning, and an error
+    // if another object refers to it.
+    let mut labels = BTreeMap::new();
+    let mut defined_in: BTreeMap<&String, Vec<&String>> = BTreeMap::new();
+    for (f, o) in objects {
+        for (name, value) in &o.exports {
+            labels.insert(name.clone(), *value);
+            defined_in.entry(name).or_default().push(f);
+        }
+    }
+    for (name, files) in &defined_in {
+        if let [first, rest @ ..] = files.as_slice() {
+            for f in rest {
+                let message = format!("label {name} also defined in {first}");
+                let mut w = SasmWarning::new(WarningKind::DuplicateExports, &message);
+                w.file = Some(f.to_string());
+                warnings.push(w);
+            }
+        }
+    }
+
+    for (f, o) in objects {
+        for (addr, kind, name) in &o.refs {
+            let mut error = |message: String| {
+                let mut e = SasmError::label(&message);
+                e.file = Some(f.clone());
+                errors.push(e);
+            };
+            let value = match (defined_in.get(name).map(|d| d.len()), labels.get(name)) {
+                (Some(1), Some(v)) => *v,
+                (Some(_), Some(_)) => {
+                    error(format!("label {name} defined in more than one object"));
+                    continue;
+                }
+                _ => {
+                    error(format!("label {name} not defined"));
+                    continue;
+                }
+            };
+            let pos = match code_pos(&org_to_code_pos, bytes.len(), *addr) {
+                Some((p, block_end)) if *kind == RefKind::Abs && p + 1 >= block_end => {
+                    error(format!("reference at {:04x} is outside the code", addr));
+                    continue;
+                }
+                Some((p, _)) => p,
+                None => {
+                    error(format!("reference at {:04x} is outside the code", addr));
+                    continue;
+                }
+            };
+
+            match (kind, value) {
+                (RefKind::Zp, UInt::U8(u)) => match bytes[pos].checked_add(u) {
+                    Some(b) => bytes[pos] = b,
+                    None => error(format!("label {name} plus offset is > 0xff")),
+                },
+                (RefKind::Abs, UInt::U16(u)) => {
+                    let stored = u16::from_le_bytes([bytes[pos], bytes[pos + 1]]);
+                    let b = u.wrapping_add(stored).to_le_bytes();
+                    bytes[pos] = b[0];
+                    bytes[pos + 1] = b[1];
+                }
+                (RefKind::Rel, UInt::U16(u)) => {
+                    // The branch offset is from the address after the operand
+                    let stored = bytes[pos] as i8 as isize;
+                    match i8::try_from(u as isize + stored - (*addr as isize + 1)) {
+                        Ok(o) => bytes[pos] = o as u8,
+                        Err(_) => error(format!("branch to {name} is too far")),
+                    }
+                }
+                (RefKind::Zp, UInt::U16(_)) => error(format!("label {name} must be a single byte")),
+                _ => error(format!("label {name} must be two bytes")),
+            }
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok((bytes, org_to_code_pos, labels)),
+        false => Err(errors),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_text_round_trip() {
+        let object = Object {
+            blocks: vec![(0xc000, vec![0x20, 0x00, 0x00, 0x60])],
+            exports: vec![
+                ("ptr".to_string(), UInt::U8(0xfe)),
+                ("start".to_string(), UInt::U16(0xc000)),
+            ],
+            refs: vec![(0xc001, RefKind::Abs, "print".to_string())],
+        };
+        let text = object.to_text();
+        assert_eq!(
+            text,
+            "; SASM2 object\nblock c000 20000060\nexport ptr fe\nexport start c000\n\
+             ref c001 abs print\n"
+        );
+        assert_eq!(Object::parse(&text), Ok(object));
+    }
+
+    #[test]
+    fn link_resolves_references() {
+        let main = Object::parse(
+            "block c000 2000004c0000d000\nref c001 abs print\nref c004 abs loop\nref c007 rel loop",
+        )
+        .unwrap();
+        let lib = Object::parse("block c100 60\nexport print c100\nexport loop c000").unwrap();
+        let objects = [("main.o".to_string(), main), ("lib.o".to_string(), lib)];
+        let mut warnings = Vec::new();
+        let (bytes, org_to_code_pos, _) = link(&objects, &mut warnings).unwrap();
+        assert_eq!(
+            bytes,
+            vec![0x20, 0x00, 0xc1, 0x4c, 0x00, 0xc0, 0xd0, 0xf8, 0x60]
+        );
+        assert_eq!(org_to_code_pos, BTreeMap::from([(0xc000, 0), (0xc100, 8)]));
+        assert!(warnings.is_empty());
+
+        // Branch offsets in the operand are added to the label, as for the other kinds
+        let main = Object::parse("block c000 d002\nref c001 rel loop").unwrap();
+        let lib = Object::parse("block c002 ea\nexport loop c000").unwrap();
+        let objects = [("main.o".to_string(), main), ("lib.o".to_string(), lib)];
+        let (bytes, _, _) = link(&objects, &mut warnings).unwrap();
+        assert_eq!(bytes, vec![0xd0, 0x00, 0xea]);
+    }
+
+    #[test]
+    fn link_duplicate_exports() {
+        let a = Object::parse("block c000 60\nexport print c000").unwrap();
+        let b = Object::parse("block c001 60\nexport print c001").unwrap();
+        let objects = [("a.o".to_string(), a), ("b.o".to_string(), b)];
+        let mut warnings = Vec::new();
+        assert!(link(&objects, &mut warnings).is_ok());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].to_string(),
+            "b.o: warning: label print also defined in a.o"
+        );
+    }
+
+    #[test]
+    fn link_errors() {
+        let a = Object::parse("block c000 a500\nref c001 zp ptr\nexport x 10").unwrap();
+        let b = Object::parse("block c001 ea\nexport ptr c000\nexport x 20").unwrap();
+        let objects = [("a.o".to_string(), a.clone()), ("b.o".to_string(), b)];
+        let errors = link(&objects, &mut Vec::new()).unwrap_err();
+        assert_eq!(errors[0].message, "objects overlap at c001");
+
+        let b = Object::parse("block c002 ea\nexport ptr c000").unwrap();
+        let objects = [("a.o".to_string(), a), ("b.o".to_string(), b)];
+        let errors = link(&objects, &mut Vec::new()).unwrap_err();
+        assert_eq!(errors[0].message, "label ptr must be a single byte");
+        assert_eq!(errors[0].file, Some("a.o".to_string()));
+
+        // A two-byte operand must not run into the next block
+        let a = Object::parse("block c000 20\nref c000 abs print\nexport print c100").unwrap();
+        let b = Object::parse("block c100 60").unwrap();
+        let objects = [("a.o".to_string(), a), ("b.o".to_string(), b)];
+        let errors = link(&objects, &mut Vec::new()).unwrap_err();
+        assert_eq!(errors[0].message, "reference at c000 is outside the code");
+    }
+}
@@ -1,7 +1,11 @@
-use std::collections::BTreeMap;
-use std::io::Write;
+use alloc::collections::BTreeMap;
 
-use crate::config::OType;
+use crate::data::{get_instr_cycles, Isa};
+use crate::diagnostic::Location;
+use crate::error::SasmError;
+use crate::json;
+use crate::prelude::*;
+use crate::syntax::{strip_comment, UInt};
 
 #[derive(Clone, Copy)]
 pub enum CodeFormat {
@@ -13,6 +17,43 @@ pub enum CodeFormat {
 
     // Binary code
     Binary,
+
+    // JSON document with the code, org blocks, symbols, and line addresses
+    Json,
+
+    // Source with the address and bytes of each line
+    Listing,
+
+    // iNES file for NES emulators, with the code as PRG ROM
+    Ines,
+
+    // Commodore program file: the load address followed by the code
+    Prg,
+
+    // Atari DOS executable, with a segment for each org block
+    Xex,
+
+    // Code that a loader can place anywhere, followed by a table of the operands to adjust
+    Reloc,
+}
+
+// Output format that library users can add without changing SASM2. It is given the address and
+// bytes of each org block, in order of address, and the filler byte for gaps between blocks.
+pub trait OutputFormat {
+    fn output(&self, blocks: &[(u16, Vec<u8>)], fill: u8) -> Code;
+}
+
+// What to do when an output file already exists
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overwrite {
+    // Refuse to write the file (default)
+    Never,
+
+    // Replace the existing file
+    Force,
+
+    // Rename the existing file with a ".bak" extension before writing
+    Backup,
 }
 
 #[derive(Debug, PartialEq)]
@@ -24,53 +65,129 @@ pub enum Code {
     Bytes(Vec<u8>),
 }
 
+impl Code {
+    // The output as it is written, which for strings is their text
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Code::String(s) => s.as_bytes(),
+            Code::Bytes(b) => b,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.as_bytes().is_empty()
+    }
+
+    // Bytes as a string of hex digits, as in the hex format. Strings are already text and are
+    // returned as they are.
+    pub fn to_hex_string(&self) -> String {
+        match self {
+            Code::String(s) => s.clone(),
+            Code::Bytes(b) => hex::encode(b),
+        }
+    }
+
+    // Machine code, decoding strings in the hex format. Other strings (such as listings) are not
+    // machine code, so there is nothing to return for them.
+    pub fn to_machine_code(&self) -> Option<Vec<u8>> {
+        match self {
+            Code::String(s) => hex::decode(s.trim_end()).ok(),
+            Code::Bytes(b) => Some(b.clone()),
+        }
+    }
+
+    // The same code as the other variant: bytes as hex, or hex as bytes
+    pub fn convert(&self) -> Option<Code> {
+        match self {
+            Code::String(_) => self.to_machine_code().map(Code::Bytes),
+            Code::Bytes(_) => Some(Code::String(self.to_hex_string())),
+        }
+    }
+}
+
+impl From<Code> for Vec<u8> {
+    fn from(code: Code) -> Self {
+        match code {
+            Code::String(s) => s.into_bytes(),
+            Code::Bytes(b) => b,
+        }
+    }
+}
+
 impl CodeFormat {
     // Attempt to create a variant from a string.
     // Since first letters are currently all unique, just rely on them for now.
     pub fn new(format: &str) -> Result<Self, &str> {
-        match format
-            .to_ascii_lowercase()
-            .chars()
-            .next()
-            .expect("Internal error: Empty CLI argument")
-        {
+        match format.to_ascii_lowercase().chars().next().unwrap_or(' ') {
             'h' => Ok(CodeFormat::Hex),
             'a' => Ok(CodeFormat::AppleSM),
             'b' => Ok(CodeFormat::Binary),
+            'j' => Ok(CodeFormat::Json),
+            'l' => Ok(CodeFormat::Listing),
+            'i' => Ok(CodeFormat::Ines),
+            'p' => Ok(CodeFormat::Prg),
+            'x' => Ok(CodeFormat::Xex),
+            'r' => Ok(CodeFormat::Reloc),
             _ => Err("Unrecognized code format"),
         }
     }
 
-    fn code_for_org_block(&self, start_addr: usize, end_addr: usize, bytes: &[u8]) -> Code {
+    // Whether the output is text rather than binary
+    pub fn is_text(&self) -> bool {
+        matches!(
+            self,
+            CodeFormat::Hex | CodeFormat::AppleSM | CodeFormat::Json | CodeFormat::Listing
+        )
+    }
+
+    fn code_for_org_block(
+        &self,
+        start_addr: usize,
+        end_addr: usize,
+        bytes: &[u8],
+        fill: u8,
+    ) -> Code {
         match self {
-            CodeFormat::Hex => Self::org_block_for_hex(start_addr, end_addr, bytes),
+            // JSON is built from hex blocks (see json_output). Listings are never built here.
+            CodeFormat::Hex | CodeFormat::Json | CodeFormat::Listing => {
+                Self::org_block_for_hex(start_addr, end_addr, bytes, fill)
+            }
             CodeFormat::AppleSM => Self::org_block_for_apple_sm(start_addr, bytes),
-            CodeFormat::Binary => Self::org_block_for_binary(start_addr, end_addr, bytes),
+            CodeFormat::Binary
+            | CodeFormat::Ines
+            | CodeFormat::Prg
+            | CodeFormat::Xex
+            | CodeFormat::Reloc => Self::org_block_for_binary(start_addr, end_addr, bytes, fill),
         }
     }
 
-    fn org_block_for_hex(start_addr: usize, end_addr: usize, bytes: &[u8]) -> Code {
+    fn org_block_for_hex(start_addr: usize, end_addr: usize, bytes: &[u8], fill: u8) -> Code {
         // Encode bytes as a string of hex values
         let mut code_as_string = hex::encode(bytes);
 
-        // Fill remaining space with the filler hex value (0xff)
+        // Fill remaining space with the filler hex value
         let gap_size = end_addr - start_addr - bytes.len();
-        code_as_string.push_str(&std::iter::repeat("ff").take(gap_size).collect::<String>());
+        code_as_string.push_str(&hex::encode([fill]).repeat(gap_size));
 
         return Code::String(code_as_string);
     }
 
-    fn org_block_for_binary(start_addr: usize, end_addr: usize, bytes: &[u8]) -> Code {
+    fn org_block_for_binary(start_addr: usize, end_addr: usize, bytes: &[u8], fill: u8) -> Code {
         // Nothing to do for code except copy it
         let mut code_as_bytes = bytes.to_vec();
 
-        // Fill remaining space with the filler byte (255)
+        // Fill remaining space with the filler byte
         let gap_size = end_addr - start_addr - bytes.len();
-        code_as_bytes.extend(std::iter::repeat(255).take(gap_size));
+        code_as_bytes.extend(core::iter::repeat_n(fill, gap_size));
 
         return Code::Bytes(code_as_bytes);
     }
 
+    // Every line starts with its address, so gaps between orgs are skipped rather than filled
     fn org_block_for_apple_sm(start_addr: usize, bytes: &[u8]) -> Code {
         let bytes_per_line = 83;
         let mut code_as_string = "".to_string();
@@ -79,54 +196,98 @@ impl CodeFormat {
             // Start a new line
             if i % bytes_per_line == 0 {
                 // Create address string
+                // Code never extends past ffff, as the assembler reports it as an error
                 let current_addr = start_addr + i;
                 if current_addr > 0xffff {
-                    panic!("Internal error: found address > 0xffff while building output string");
+                    break;
                 }
                 let addr_string = hex::encode((current_addr as u16).to_be_bytes());
 
                 // Print line beginning
                 if i > 0 {
-                    code_as_string.push_str("\n");
+                    code_as_string.push('\n');
                 }
                 code_as_string.push_str(&addr_string);
-                code_as_string.push_str(":");
+                code_as_string.push(':');
                 code_as_string.push_str(&hex::encode(&bytes[i..i + 1]));
 
             // Append byte to current line
             } else {
-                code_as_string.push_str(" ");
+                code_as_string.push(' ');
                 code_as_string.push_str(&hex::encode(&bytes[i..i + 1]));
             }
         }
 
         // No filler bytes for this format
-        code_as_string.push_str("\n");
+        code_as_string.push('\n');
         return Code::String(code_as_string);
     }
 }
 
+// Destination of output that is written a piece at a time as it is made, rather than returned
+// whole (see assemble_to). Every std::io::Write is one, and without std so is a Vec<u8>.
+pub trait OutputWriter {
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), SasmError>;
+}
+
+// Write the code in the hex or binary format a piece at a time, with filler bytes between orgs, so
+// that the whole image is never held in memory
+pub fn write_image(
+    out: &mut dyn OutputWriter,
+    bytes: &[u8],
+    org_to_code_pos: &BTreeMap<u16, usize>,
+    format: CodeFormat,
+    fill: u8,
+) -> Result<(), SasmError> {
+    const PIECE: usize = 0x1000;
+    let mut emit = |piece: &[u8]| match format {
+        CodeFormat::Hex => out.emit(hex::encode(piece).as_bytes()),
+        _ => out.emit(piece),
+    };
+    let filler = [fill; PIECE];
+    let mut starts = org_to_code_pos.iter().peekable();
+    while let Some((addr, pos)) = starts.next() {
+        // Blocks are filled up to the next org, but the last one ends with its code
+        let (end, mut gap) = match starts.peek() {
+            Some((next, p)) => (**p, (**next - *addr) as usize - (**p - *pos)),
+            None => (bytes.len(), 0),
+        };
+        for piece in bytes[*pos..end].chunks(PIECE) {
+            emit(piece)?;
+        }
+        while gap > 0 {
+            let n = gap.min(PIECE);
+            emit(&filler[..n])?;
+            gap -= n;
+        }
+    }
+    Ok(())
+}
+
 // Convert assembled bytes to the proper output format (a string to be printed)
 // This function iterates through pairs of orgs, while the format-specific code resides in
 // separate functions.
 pub fn bytes_to_output(
     bytes: &[u8],
-    org_to_code_pos: BTreeMap<u16, usize>,
+    org_to_code_pos: &BTreeMap<u16, usize>,
     format: CodeFormat,
+    fill: u8,
 ) -> Code {
     let mut org_blocks = Vec::new();
 
     // Convert values to usize for array indexing
     let mut org_iter = org_to_code_pos.iter().map(|x| (*x.0 as usize, *x.1));
 
-    // Get first org
-    let (mut prev_org, mut prev_pos) = org_iter
-        .next()
-        .expect("Internal error: no org found for assembled code");
+    // Get first org. Without one there is no code to output.
+    let (mut prev_org, mut prev_pos) = match org_iter.next() {
+        Some(o) => o,
+        None if format.is_text() => return Code::String(String::new()),
+        None => return Code::Bytes(Vec::new()),
+    };
 
     for (org, pos) in org_iter {
         // Generate code blocks between orgs
-        org_blocks.push(format.code_for_org_block(prev_org, org, &bytes[prev_pos..pos]));
+        org_blocks.push(format.code_for_org_block(prev_org, org, &bytes[prev_pos..pos], fill));
 
         prev_org = org;
         prev_pos = pos;
@@ -135,70 +296,710 @@ pub fn bytes_to_output(
     // Generate code block after last org.
     // Length is the size of the remaining bytes to ensure no filler bytes are printed.
     let end_org = prev_org + bytes.len() - prev_pos;
-    org_blocks.push(format.code_for_org_block(prev_org, end_org, &bytes[prev_pos..]));
+    org_blocks.push(format.code_for_org_block(prev_org, end_org, &bytes[prev_pos..], fill));
 
     // Join org blocks
-    match format {
-        CodeFormat::Hex | CodeFormat::AppleSM => {
+    match format.is_text() {
+        true => {
             let code_as_string = org_blocks
                 .iter()
-                .fold(String::new(), |code, block| match block {
-                    Code::String(s) => code + &s,
-                    _ => panic!("Internal error: wrong output type encountered"),
-                });
+                .fold(String::new(), |code, block| code + &block.to_hex_string());
             return Code::String(code_as_string);
         }
-        CodeFormat::Binary => {
-            let code_as_bytes = org_blocks
-                .iter()
-                .fold(Vec::new(), |mut code, block| match block {
-                    Code::Bytes(b) => {
-                        code.extend(b);
-                        code
-                    }
-                    _ => panic!("Internal error: wrong output type encountered"),
-                });
+        false => {
+            let code_as_bytes = org_blocks.iter().fold(Vec::new(), |mut code, block| {
+                code.extend(block.as_bytes());
+                code
+            });
             return Code::Bytes(code_as_bytes);
         }
     }
 }
 
-// Functions for outputting the final result
+// The complete memory image, including filler bytes, regardless of output format
+pub fn binary_image(bytes: &[u8], org_to_code_pos: &BTreeMap<u16, usize>, fill: u8) -> Vec<u8> {
+    Vec::from(bytes_to_output(
+        bytes,
+        org_to_code_pos,
+        CodeFormat::Binary,
+        fill,
+    ))
+}
+
+// Bytes of an existing image starting at base_addr, with blocks of bytes written over them. Space
+// between the end of the image and a block after it is filled.
+pub fn patch_image(
+    base: &[u8],
+    base_addr: u16,
+    blocks: &[(u16, Vec<u8>)],
+    fill: u8,
+) -> Result<Vec<u8>, SasmError> {
+    let start = base_addr as usize;
+    if start + base.len() > 0x10000 {
+        let message = format!("patched image at {base_addr:04x} extends past ffff");
+        return Err(SasmError::syntax(&message));
+    }
+    let mut image = base.to_vec();
+    for (addr, block) in blocks {
+        let Some(pos) = (*addr as usize).checked_sub(start) else {
+            let message =
+                format!("org block at {addr:04x} is before the patched image at {base_addr:04x}");
+            return Err(SasmError::syntax(&message));
+        };
+        if image.len() < pos + block.len() {
+            image.resize(pos + block.len(), fill);
+        }
+        image[pos..pos + block.len()].copy_from_slice(block);
+    }
+    Ok(image)
+}
 
-fn write_code_to_file<T: std::convert::AsRef<[u8]>>(f: &str, c: T) -> Result<(), String> {
-    match std::fs::exists(f) {
-        Ok(true) => Err(format!("File {f} already exists")),
-        Ok(false) => match std::fs::write(f, c) {
-            Ok(_) => Ok(()),
-            Err(_) => Err(format!("Unable to write to file {f}")),
-        },
-        Err(_) => Err(format!("Unable to check existence of file {f}")),
+// Bytes of each org block, without filler. Blocks without bytes are left out.
+pub fn org_blocks(bytes: &[u8], org_to_code_pos: &BTreeMap<u16, usize>) -> Vec<(u16, Vec<u8>)> {
+    let mut blocks = Vec::new();
+    let mut starts = org_to_code_pos.iter().peekable();
+    while let Some((addr, pos)) = starts.next() {
+        let end = starts.peek().map_or(bytes.len(), |(_, p)| **p);
+        if end > *pos {
+            blocks.push((*addr, bytes[*pos..end].to_vec()));
+        }
     }
+    blocks
 }
 
-pub fn write_code(code: &Code, otype: &OType) -> Result<(), String> {
-    match code {
-        Code::String(ref s) => match &otype {
-            OType::Stdout => println!("{s}"),
-            OType::File(f) => {
-                if let Err(e) = write_code_to_file(f, &s) {
-                    return Err(format!("Error: {e}"));
-                }
-            }
-            OType::None => (),
-        },
-        Code::Bytes(ref b) => match &otype {
-            OType::Stdout => std::io::stdout()
-                .write_all(&b)
-                .expect("Unable to write binary to stdout"),
-            OType::File(f) => {
-                if let Err(e) = write_code_to_file(f, &b) {
-                    return Err(format!("Error: {e}"));
-                }
+// Address of the first org, or 0000 if there is no code at all
+fn first_org(org_to_code_pos: &BTreeMap<u16, usize>) -> u16 {
+    org_to_code_pos.keys().next().copied().unwrap_or(0)
+}
+
+// Size of each PRG ROM bank in an iNES file
+const INES_BANK_SIZE: usize = 0x4000;
+
+// Build an iNES file: a 16-byte header followed by one or two PRG ROM banks, which the NES maps so
+// that they end at ffff. The code is padded with the filler byte to fill the banks. There is no
+// CHR ROM, so the program must load its graphics into CHR RAM.
+pub fn ines_output(
+    bytes: &[u8],
+    org_to_code_pos: &BTreeMap<u16, usize>,
+    fill: u8,
+) -> Result<Code, SasmError> {
+    let start = first_org(org_to_code_pos) as usize;
+    let prg_start = match start {
+        0xc000.. => 0xc000,
+        0x8000.. => 0x8000,
+        _ => {
+            return Err(SasmError::range(
+                "iNES output requires code from 8000 to ffff",
+            ))
+        }
+    };
+
+    let image = binary_image(bytes, org_to_code_pos, fill);
+    let mut prg = vec![fill; start - prg_start];
+    prg.extend(image);
+    if prg.len() > 0x10000 - prg_start {
+        return Err(SasmError::range(
+            "iNES output requires code from 8000 to ffff",
+        ));
+    }
+    prg.resize(0x10000 - prg_start, fill);
+
+    // Header: magic number, PRG ROM banks, CHR ROM banks, and flags left at zero (mapper 0 with
+    // horizontal mirroring)
+    let mut ines = b"NES\x1a".to_vec();
+    ines.push((prg.len() / INES_BANK_SIZE) as u8);
+    ines.push(0);
+    ines.resize(16, 0);
+    ines.extend(prg);
+    Ok(Code::Bytes(ines))
+}
+
+// Build a Commodore program file, which is loaded at the address in its first two bytes
+pub fn prg_output(bytes: &[u8], org_to_code_pos: &BTreeMap<u16, usize>, fill: u8) -> Code {
+    let start = first_org(org_to_code_pos);
+    let mut prg = start.to_le_bytes().to_vec();
+    prg.extend(binary_image(bytes, org_to_code_pos, fill));
+    Code::Bytes(prg)
+}
+
+// Build relocatable code: the address it was assembled for and its length, the memory image, and
+// the number of fixups followed by the fixups themselves. A fixup is the position in the image of
+// a two-byte operand that holds an address in the code. A loader that places the code elsewhere
+// adds the difference between the addresses to each of them. All values are little-endian words.
+pub fn reloc_output(
+    bytes: &[u8],
+    org_to_code_pos: &BTreeMap<u16, usize>,
+    fill: u8,
+    fixups: &[u16],
+) -> Code {
+    let start = first_org(org_to_code_pos);
+    let image = binary_image(bytes, org_to_code_pos, fill);
+
+    let mut reloc = start.to_le_bytes().to_vec();
+    reloc.extend((image.len() as u16).to_le_bytes());
+    reloc.extend(image);
+    reloc.extend((fixups.len() as u16).to_le_bytes());
+    for f in fixups {
+        reloc.extend(f.wrapping_sub(start).to_le_bytes());
+    }
+    Code::Bytes(reloc)
+}
+
+// Address where Atari DOS finds the address to run a loaded program
+const RUNAD: u16 = 0x02e0;
+
+// Build an Atari DOS executable: ffff followed by a segment for each org block, without filler
+// bytes. A segment is its first and last address, then its bytes. Unless the code sets the run
+// address itself, a last segment sets it to the first org.
+pub fn xex_output(bytes: &[u8], org_to_code_pos: &BTreeMap<u16, usize>) -> Code {
+    let mut xex = vec![0xff, 0xff];
+    let mut sets_runad = false;
+    let mut org_iter = org_to_code_pos.iter().peekable();
+    while let Some((org, pos)) = org_iter.next() {
+        let end_pos = match org_iter.peek() {
+            Some((_, next_pos)) => **next_pos,
+            None => bytes.len(),
+        };
+        if end_pos == *pos {
+            continue;
+        }
+        let last = *org as usize + end_pos - pos - 1;
+        sets_runad |= (*org as usize..=last).contains(&(RUNAD as usize));
+        xex.extend(org.to_le_bytes());
+        xex.extend((last as u16).to_le_bytes());
+        xex.extend(&bytes[*pos..end_pos]);
+    }
+
+    if !sets_runad {
+        let start = first_org(org_to_code_pos);
+        xex.extend(RUNAD.to_le_bytes());
+        xex.extend((RUNAD + 1).to_le_bytes());
+        xex.extend(start.to_le_bytes());
+    }
+    Code::Bytes(xex)
+}
+
+// Build a JSON document for tools that need more than the raw code. It contains the code in hex
+// (identical to the hex format), each org block separately, the symbol table, and the address
+// and size of every source line that generated code.
+pub fn json_output(
+    bytes: &[u8],
+    org_to_code_pos: &BTreeMap<u16, usize>,
+    fill: u8,
+    labels: &BTreeMap<String, UInt>,
+    line_map: &[(usize, usize, usize)],
+) -> Code {
+    let hex = bytes_to_output(bytes, org_to_code_pos, CodeFormat::Hex, fill).to_hex_string();
+
+    // Org blocks without filler bytes
+    let mut blocks = Vec::new();
+    let mut org_iter = org_to_code_pos.iter().peekable();
+    while let Some((org, pos)) = org_iter.next() {
+        let end_pos = match org_iter.peek() {
+            Some((_, next_pos)) => **next_pos,
+            None => bytes.len(),
+        };
+        blocks.push(format!(
+            "{{\"address\": {org}, \"hex\": \"{}\"}}",
+            hex::encode(&bytes[*pos..end_pos])
+        ));
+    }
+
+    let symbols: Vec<String> = sorted_symbols(labels)
+        .iter()
+        .map(|(value, name)| format!("{{\"name\": {}, \"value\": {value}}}", json::string(name)))
+        .collect();
+
+    let lines: Vec<String> = line_map
+        .iter()
+        .map(|(line, addr, size)| {
+            format!("{{\"line\": {line}, \"address\": {addr}, \"size\": {size}}}")
+        })
+        .collect();
+
+    return Code::String(format!(
+        "{{\n  \"hex\": \"{hex}\",\n  \"blocks\": {},\n  \"symbols\": {},\n  \"lines\": {}\n}}",
+        json::array(&blocks),
+        json::array(&symbols),
+        json::array(&lines)
+    ));
+}
+
+// One line of a listing, with the address and first three bytes of the line's code in front of its
+// source. Lines without code are indented to match.
+pub fn listing_line(addr: usize, bytes: &[u8], source: &str) -> String {
+    if bytes.is_empty() {
+        return format!("{:16}{source}\n", "");
+    }
+    let bytes: Vec<String> = bytes.iter().take(3).map(|b| format!("{b:02x}")).collect();
+    format!("{addr:04x}  {:8}  {source}\n", bytes.join(" "))
+}
+
+// Column of a listing with the cycle count of a SASM source line's instruction, if it has one.
+// Comments start with ';' or any of the other markers.
+pub fn cycles_column(line: &str, markers: &[String], isa: Isa) -> String {
+    let cycles = strip_comment(line, markers)
+        .split_ascii_whitespace()
+        .next()
+        .and_then(|m| get_instr_cycles(m, isa));
+    format!("{:4}", cycles.unwrap_or_default())
+}
+
+// Labels sorted by value and then by name so that output is stable from run to run
+fn sorted_symbols(labels: &BTreeMap<String, UInt>) -> Vec<(u16, &String)> {
+    let mut symbols: Vec<(u16, &String)> = labels
+        .iter()
+        .map(|(name, value)| match value {
+            UInt::U8(u) => (*u as u16, name),
+            UInt::U16(u) => (*u, name),
+        })
+        .collect();
+    symbols.sort();
+    return symbols;
+}
+
+// Formats of the symbol file, as set by the --symbol-format flag
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolFormat {
+    // VICE monitor labels ("al C:4000 .start")
+    Vice,
+
+    // AppleWin symbol table ("4000 start")
+    AppleWin,
+
+    // DASM symbol file, which Stella loads along with the ROM
+    Stella,
+}
+
+impl SymbolFormat {
+    // Attempt to create a variant from a string
+    pub fn new(format: &str) -> Result<Self, &str> {
+        match format.to_ascii_lowercase().as_str() {
+            "vice" => Ok(SymbolFormat::Vice),
+            "applewin" => Ok(SymbolFormat::AppleWin),
+            "stella" => Ok(SymbolFormat::Stella),
+            _ => Err("symbol format must be vice, applewin, or stella"),
+        }
+    }
+
+    pub fn write(&self, labels: &BTreeMap<String, UInt>) -> String {
+        match self {
+            SymbolFormat::Vice => labels_to_vice_symbols(labels),
+            SymbolFormat::AppleWin => labels_to_applewin_symbols(labels),
+            SymbolFormat::Stella => labels_to_stella_symbols(labels),
+        }
+    }
+}
+
+// Convert the label map to a symbol file in VICE label format ("al C:xxxx .name"), which is also
+// understood by other emulators and debuggers.
+pub fn labels_to_vice_symbols(labels: &BTreeMap<String, UInt>) -> String {
+    return sorted_symbols(labels)
+        .iter()
+        .map(|(addr, name)| format!("al C:{:04x} .{name}\n", addr))
+        .collect();
+}
+
+// AppleWin symbol table: the address in uppercase hex, then the name
+pub fn labels_to_applewin_symbols(labels: &BTreeMap<String, UInt>) -> String {
+    return sorted_symbols(labels)
+        .iter()
+        .map(|(addr, name)| format!("{:04X} {name}\n", addr))
+        .collect();
+}
+
+// Symbol file as written by DASM, sorted by name between its header and footer lines. Stella reads
+// the name and the hex value of each line.
+pub fn labels_to_stella_symbols(labels: &BTreeMap<String, UInt>) -> String {
+    let mut symbols = sorted_symbols(labels);
+    symbols.sort_by_key(|(_, name)| *name);
+    let mut text = "--- Symbol List (sorted by symbol)\n".to_string();
+    for (addr, name) in symbols {
+        text.push_str(&format!("{name:24} {:04x}\n", addr));
+    }
+    text.push_str("--- End of Symbol List.\n");
+    return text;
+}
+
+// Source map for debuggers: the address range of each line that generated code, then its file
+// and line number. The file of input from STDIN or a string is shown as "-".
+pub fn source_map(lines: &[(usize, usize, &Location)]) -> String {
+    return lines
+        .iter()
+        .map(|(addr, size, location)| {
+            let file = location.file.as_deref().unwrap_or("-");
+            let line = location.line;
+            format!("{:04x}-{:04x} {file}:{line}\n", addr, addr + size - 1)
+        })
+        .collect();
+}
+
+// Line of the source in debug info, with the address and size of its code if it has any
+pub type DebugLine<'a> = (&'a Location, Option<(usize, usize)>);
+
+// Debug info in the format that the cc65 linker writes with --dbgfile: the source files with their
+// sizes and modification times, the org blocks as segments, the span of the code of each line, and
+// the labels with the line (an index into lines) that defines each one. Everything is in a single
+// module and scope.
+pub fn debug_info(
+    files: &[(String, u64, u64)],
+    segments: &[(u16, usize)],
+    lines: &[DebugLine],
+    symbols: &[(&str, UInt, Option<usize>)],
+) -> String {
+    let segment_of = |addr: usize| {
+        segments
+            .iter()
+            .position(|(a, size)| addr >= *a as usize && addr < *a as usize + size)
+    };
+    let spans: Vec<(usize, usize, usize)> = lines
+        .iter()
+        .filter_map(|(_, span)| *span)
+        .filter_map(|(addr, size)| {
+            let seg = segment_of(addr)?;
+            Some((seg, addr - segments[seg].0 as usize, size))
+        })
+        .collect();
+    let code_size: usize = segments.iter().map(|(_, size)| size).sum();
+
+    let mut text = "version\tmajor=2,minor=0\n".to_string();
+    text.push_str(&format!(
+        "info\tcsym=0,file={},lib=0,line={},mod=1,scope=1,seg={},span={},sym={},type=0\n",
+        files.len(),
+        lines.len(),
+        segments.len(),
+        spans.len(),
+        symbols.len()
+    ));
+    for (id, (name, size, mtime)) in files.iter().enumerate() {
+        text.push_str(&format!(
+            "file\tid={id},name=\"{name}\",size={size},mtime=0x{mtime:08X},mod=0\n"
+        ));
+    }
+    let first_file = files.first().map_or("-", |(name, ..)| name.as_str());
+    text.push_str(&format!("mod\tid=0,name=\"{first_file}\",file=0\n"));
+    for (id, (addr, size)) in segments.iter().enumerate() {
+        text.push_str(&format!(
+            "seg\tid={id},name=\"ORG{addr:04X}\",start=0x{addr:06X},size=0x{size:04X},\
+             addrsize=absolute,type=rw\n"
+        ));
+    }
+    text.push_str(&format!("scope\tid=0,name=\"\",mod=0,size={code_size}\n"));
+
+    let mut span_id = 0;
+    let mut line_records = String::new();
+    for (id, (location, span)) in lines.iter().enumerate() {
+        let file = location.file.as_deref().unwrap_or("-");
+        let file_id = files.iter().position(|(f, ..)| f == file).unwrap_or(0);
+        line_records.push_str(&format!(
+            "line\tid={id},file={file_id},line={}",
+            location.line
+        ));
+        if span.is_some_and(|(addr, _)| segment_of(addr).is_some()) {
+            line_records.push_str(&format!(",span={span_id}"));
+            span_id += 1;
+        }
+        line_records.push('\n');
+    }
+    for (id, (seg, start, size)) in spans.iter().enumerate() {
+        text.push_str(&format!(
+            "span\tid={id},seg={seg},start={start},size={size}\n"
+        ));
+    }
+    text.push_str(&line_records);
+
+    for (id, (name, value, def)) in symbols.iter().enumerate() {
+        let (value, addrsize) = match value {
+            UInt::U8(u) => (*u as u16, "zeropage"),
+            UInt::U16(u) => (*u, "absolute"),
+        };
+        text.push_str(&format!(
+            "sym\tid={id},name=\"{name}\",addrsize={addrsize},scope=0"
+        ));
+        if let Some(line) = def {
+            text.push_str(&format!(",def={line}"));
+        }
+        text.push_str(&format!(",val=0x{value:X}"));
+        // Labels of code and data are in a segment, and the rest are constants
+        match segment_of(value as usize) {
+            Some(seg) => text.push_str(&format!(",seg={seg},type=lab\n")),
+            None => text.push_str(",type=equ\n"),
+        }
+    }
+    return text;
+}
+
+// Line of the statistics report with its location, source, bytes, and fewest cycles, and whether
+// it may take more
+pub type LineStats<'a> = (&'a Location, &'a str, usize, usize, bool);
+
+// Bytes and cycles of each input file, then of each source line with code from the most bytes to
+// the fewest, for finding what takes up the most room
+pub fn stats(lines: &[LineStats]) -> String {
+    let cycles = |c: usize, more: bool| format!("{c}{}", if more { "+" } else { "" });
+
+    // Files in the order they are read, then the whole program
+    let mut files: Vec<(&str, usize, usize, bool)> = Vec::new();
+    for (location, _, bytes, c, more) in lines {
+        let file = location.file.as_deref().unwrap_or("-");
+        let i = match files.iter().position(|f| f.0 == file) {
+            Some(i) => i,
+            None => {
+                files.push((file, 0, 0, false));
+                files.len() - 1
             }
-            OType::None => (),
-        },
+        };
+        files[i].1 += bytes;
+        files[i].2 += c;
+        files[i].3 |= more;
     }
+    let total = files.iter().fold(("total", 0, 0, false), |t, f| {
+        (t.0, t.1 + f.1, t.2 + f.2, t.3 | f.3)
+    });
 
-    Ok(())
+    let mut text = format!("{:>6} {:>7}  file\n", "bytes", "cycles");
+    for (file, bytes, c, more) in files.iter().chain([&total]) {
+        text.push_str(&format!("{bytes:6} {:>7}  {file}\n", cycles(*c, *more)));
+    }
+
+    let mut code_lines: Vec<&LineStats> = lines.iter().filter(|l| l.2 > 0).collect();
+    code_lines.sort_by_key(|l| core::cmp::Reverse(l.2));
+    text.push_str(&format!("\n{:>6} {:>7}  line\n", "bytes", "cycles"));
+    for (location, source, bytes, c, more) in code_lines {
+        let file = location.file.as_deref().unwrap_or("-");
+        text.push_str(&format!(
+            "{bytes:6} {:>7}  {file}:{}  {}\n",
+            cycles(*c, *more),
+            location.line,
+            source.trim()
+        ));
+    }
+    return text;
+}
+
+// Label of a cross-reference with its value (if it has one), the location that defines it (if
+// the source does), and the address and location of each reference
+pub type XrefEntry<'a> = (
+    &'a str,
+    Option<UInt>,
+    Option<&'a Location>,
+    Vec<(usize, &'a Location)>,
+);
+
+// Cross-reference of labels, sorted by name. Each label's line has its value and definition, and
+// each reference follows on an indented line. Labels without values are left for the linker, and
+// labels that the source does not define come from the configuration.
+pub fn xref(entries: &[XrefEntry]) -> String {
+    let place = |location: &Location| {
+        let file = location.file.as_deref().unwrap_or("-");
+        format!("{file}:{}", location.line)
+    };
+    let mut entries: Vec<&XrefEntry> = entries.iter().collect();
+    entries.sort_by_key(|e| e.0);
+
+    let mut text = String::new();
+    for (name, value, definition, refs) in entries {
+        let (value, definition) = match (value, definition) {
+            (None, _) => ("----".to_string(), "external".to_string()),
+            (Some(UInt::U8(u)), d) => (
+                format!("{u:02x}"),
+                d.map_or("configuration".to_string(), place),
+            ),
+            (Some(UInt::U16(u)), d) => (
+                format!("{u:04x}"),
+                d.map_or("configuration".to_string(), place),
+            ),
+        };
+        text.push_str(&format!("{name} {value} {definition}\n"));
+        for (addr, location) in refs {
+            text.push_str(&format!("  {addr:04x} {}\n", place(location)));
+        }
+    }
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vice_symbols_sorted_by_value() {
+        let labels = BTreeMap::from([
+            ("start".to_string(), UInt::U16(0x4000)),
+            ("ptr".to_string(), UInt::U8(0xfe)),
+            ("loop".to_string(), UInt::U16(0x4002)),
+        ]);
+        assert_eq!(
+            labels_to_vice_symbols(&labels),
+            "al C:00fe .ptr\nal C:4000 .start\nal C:4002 .loop\n"
+        );
+    }
+
+    // Labels for the tests of the other symbol formats
+    fn symbol_test_labels() -> BTreeMap<String, UInt> {
+        BTreeMap::from([
+            ("start".to_string(), UInt::U16(0x4000)),
+            ("ptr".to_string(), UInt::U8(0xfe)),
+            ("loop".to_string(), UInt::U16(0x4002)),
+        ])
+    }
+
+    #[test]
+    fn applewin_symbols_sorted_by_value() {
+        let labels = symbol_test_labels();
+        assert_eq!(
+            SymbolFormat::new("AppleWin").map(|f| f.write(&labels)),
+            Ok("00FE ptr\n4000 start\n4002 loop\n".to_string())
+        );
+    }
+
+    #[test]
+    fn stella_symbols_sorted_by_name() {
+        let labels = symbol_test_labels();
+        assert_eq!(
+            SymbolFormat::Stella.write(&labels),
+            "--- Symbol List (sorted by symbol)\n\
+             loop                     4002\n\
+             ptr                      00fe\n\
+             start                    4000\n\
+             --- End of Symbol List.\n"
+        );
+    }
+
+    #[test]
+    fn code_helpers() {
+        let hex = Code::String("a90160".to_string());
+        let bin = Code::Bytes(vec![0xa9, 0x01, 0x60]);
+        assert_eq!(hex.to_machine_code(), Some(vec![0xa9, 0x01, 0x60]));
+        assert_eq!(bin.to_hex_string(), "a90160");
+        assert_eq!(hex.convert(), Some(Code::Bytes(vec![0xa9, 0x01, 0x60])));
+        assert_eq!(bin.convert(), Some(hex));
+        assert_eq!((bin.len(), bin.is_empty()), (3, false));
+        assert_eq!(
+            Code::String("c000  ea  nop\n".to_string()).to_machine_code(),
+            None
+        );
+        assert_eq!(Vec::from(bin), vec![0xa9, 0x01, 0x60]);
+    }
+
+    #[test]
+    fn output_without_code() {
+        assert!(CodeFormat::new("").is_err());
+        let orgs = BTreeMap::new();
+        assert_eq!(
+            bytes_to_output(&[], &orgs, CodeFormat::Hex, 0xff),
+            Code::String(String::new())
+        );
+        assert_eq!(prg_output(&[], &orgs, 0xff), Code::Bytes(vec![0x00, 0x00]));
+    }
+
+    #[test]
+    fn source_map_lines() {
+        let main = Location {
+            file: Some("main.asm".to_string()),
+            line: 2,
+        };
+        let lines = [
+            (0xc000, 3, &main),
+            (
+                0xc003,
+                1,
+                &Location {
+                    file: None,
+                    line: 5,
+                },
+            ),
+        ];
+        assert_eq!(source_map(&lines), "c000-c002 main.asm:2\nc003-c003 -:5\n");
+    }
+
+    #[test]
+    fn cc65_debug_info() {
+        let label = Location {
+            file: Some("main.asm".to_string()),
+            line: 1,
+        };
+        let code = Location {
+            file: Some("main.asm".to_string()),
+            line: 2,
+        };
+        let files = [("main.asm".to_string(), 20, 0)];
+        let lines = [(&label, None), (&code, Some((0xc000, 3)))];
+        let symbols = [("start", UInt::U16(0xc000), Some(0))];
+        let text = debug_info(&files, &[(0xc000, 3)], &lines, &symbols);
+        let records: Vec<&str> = text.lines().skip(6).collect();
+        assert_eq!(
+            records,
+            [
+                "span\tid=0,seg=0,start=0,size=3",
+                "line\tid=0,file=0,line=1",
+                "line\tid=1,file=0,line=2,span=0",
+                "sym\tid=0,name=\"start\",addrsize=absolute,scope=0,def=0,val=0xC000,seg=0,\
+                 type=lab",
+            ]
+        );
+        assert!(text.starts_with("version\tmajor=2,minor=0\ninfo\tcsym=0,file=1,lib=0,line=2"));
+    }
+
+    #[test]
+    fn stats_by_file_and_line() {
+        let main = |line| Location {
+            file: Some("main.asm".to_string()),
+            line,
+        };
+        let lib = Location {
+            file: Some("lib.asm".to_string()),
+            line: 1,
+        };
+        let (org, lda, table) = (main(1), main(2), main(3));
+        let lines = [
+            (&org, "org 4000", 0, 0, false),
+            (&lda, "ldaax .table", 3, 4, true),
+            (&table, "data 00010203", 4, 0, false),
+            (&lib, "rts ; done", 1, 6, false),
+        ];
+        let report = [
+            " bytes  cycles  file",
+            "     7      4+  main.asm",
+            "     1       6  lib.asm",
+            "     8     10+  total",
+            "",
+            " bytes  cycles  line",
+            "     4       0  main.asm:3  data 00010203",
+            "     3      4+  main.asm:2  ldaax .table",
+            "     1       6  lib.asm:1  rts ; done",
+        ];
+        assert_eq!(stats(&lines), report.join("\n") + "\n");
+    }
+
+    #[test]
+    fn xref_sorted_by_name() {
+        let def = Location {
+            file: Some("main.asm".to_string()),
+            line: 3,
+        };
+        let use1 = Location {
+            file: Some("main.asm".to_string()),
+            line: 6,
+        };
+        let use2 = Location {
+            file: None,
+            line: 9,
+        };
+        let entries = [
+            (
+                "loop",
+                Some(UInt::U16(0x4002)),
+                Some(&def),
+                vec![(0x4007, &use1)],
+            ),
+            ("print", None, None, vec![(0x400a, &use2)]),
+            ("ptr", Some(UInt::U8(0xfe)), None, vec![]),
+        ];
+        assert_eq!(
+            xref(&entries),
+            "loop 4002 main.asm:3\n  4007 main.asm:6\nprint ---- external\n  400a -:9\n\
+             ptr fe configuration\n"
+        );
+    }
 }
@@ -0,0 +1,8 @@
+// Names from alloc that the std prelude puts in scope, so that every module builds the same way
+// with or without std
+
+pub use alloc::boxed::Box;
+pub use alloc::format;
+pub use alloc::string::{String, ToString};
+pub use alloc::vec;
+pub use alloc::vec::Vec;
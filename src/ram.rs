@@ -0,0 +1,67 @@
+// Allocation of variables in main memory, outside of zero page. Each system has a block of RAM that
+// its OS and BASIC leave free for programs, and the manager hands it out in order of address.
+// Programs must not place code there.
+
+#[derive(Debug)]
+pub struct Ram {
+    // Address of the next free byte and the address after the block
+    next: usize,
+    end: usize,
+}
+
+impl Ram {
+    // The block of RAM for a system, as named by Zpm::system. The Atari 2600 has no RAM outside
+    // zero page.
+    pub fn new(system: &str) -> Option<Self> {
+        let (start, end) = match system {
+            // Page 3, apart from the DOS and monitor vectors at its end
+            "apple" => (0x0300, 0x03d0),
+
+            // After the stack in page 1 and the sprite buffer in page 2
+            "nes" => (0x0300, 0x0800),
+
+            // The 4K between BASIC ROM and the I/O area
+            "c64" => (0xc000, 0xd000),
+
+            // The cassette buffer
+            "vic20" => (0x033c, 0x03fc),
+
+            // Page 6, which the OS leaves free
+            "atari8" => (0x0600, 0x0700),
+
+            _ => return None,
+        };
+        Some(Ram { next: start, end })
+    }
+
+    pub fn alloc(&mut self, size: u16) -> Result<u16, &'static str> {
+        if size == 0 {
+            return Err("var size must not be zero");
+        }
+        if self.next + size as usize > self.end {
+            return Err("RAM for variables exhausted");
+        }
+        let addr = self.next as u16;
+        self.next += size as usize;
+        Ok(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ram_alloc_in_order() {
+        let mut ram = Ram::new("atari8").unwrap();
+        assert_eq!(ram.alloc(0x10), Ok(0x0600));
+        assert_eq!(ram.alloc(1), Ok(0x0610));
+        assert_eq!(ram.alloc(0xf0), Err("RAM for variables exhausted"));
+        assert_eq!(ram.alloc(0xef), Ok(0x0611));
+    }
+
+    #[test]
+    fn ram_none_for_atari_2600() {
+        assert!(Ram::new("atari").is_none());
+    }
+}
@@ -0,0 +1,108 @@
+// Translation of disassembled SASM source into the standard 6502 syntax used by most other
+// assemblers, such as "LDA #$00" and "STA $0600,X". The directives are those of ca65.
+
+use crate::prelude::*;
+
+// Syntax of disassembled source, as set by the --syntax flag
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Syntax {
+    Sasm,
+    Standard,
+}
+
+impl Syntax {
+    // Attempt to create a variant from a string
+    pub fn new(syntax: &str) -> Result<Self, &str> {
+        match syntax.to_ascii_lowercase().as_str() {
+            "sasm" => Ok(Syntax::Sasm),
+            "standard" => Ok(Syntax::Standard),
+            _ => Err("syntax must be sasm or standard"),
+        }
+    }
+}
+
+// Labels made by the disassembler are addresses, which other assemblers would read as numbers
+pub(crate) fn label(name: &str) -> String {
+    match name.len() == 4 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => format!("L{}", name.to_ascii_uppercase()),
+        false => name.to_string(),
+    }
+}
+
+fn operand(op: &str) -> String {
+    match op.strip_prefix('.') {
+        Some(l) => label(l),
+        None => format!("${}", op.to_ascii_uppercase()),
+    }
+}
+
+// Translate one line of disassembled source. Lines match one to one, so that warnings refer to
+// the same line numbers.
+pub fn translate(line: &str) -> String {
+    // Keep comments after code, such as cycle counts
+    if let Some((code, comment)) = line.split_once(';').filter(|(c, _)| !c.trim().is_empty()) {
+        return format!("{:23};{comment}", translate(code.trim_end()));
+    }
+
+    let words: Vec<&str> = line.split_ascii_whitespace().collect();
+    match words.as_slice() {
+        [c, ..] if c.starts_with(';') => line.to_string(),
+        ["org", a] => format!("        .org {}", operand(a)),
+        ["label", n, a] => format!("{} = {}", label(n), operand(a)),
+        ["data", l] if l.starts_with('.') => format!("        .word {}", label(&l[1..])),
+        ["data", d] => {
+            let bytes: Vec<String> = (0..d.len())
+                .step_by(2)
+                .map(|i| operand(&d[i..i + 2]))
+                .collect();
+            format!("        .byte {}", bytes.join(","))
+        }
+        [l] if l.starts_with('.') => format!("{}:", label(&l[1..])),
+        [m] => format!("        {}", m.to_ascii_uppercase()),
+        [m, op] => {
+            let mnemonic = m[..3].to_ascii_uppercase();
+            let op = operand(op);
+
+            // Absolute addresses in zero page must stay absolute to keep the same opcode
+            let abs = if op.len() == 5 && op.starts_with("$00") {
+                "a:"
+            } else {
+                ""
+            };
+            let op = match &m[3..] {
+                "i" => format!("#{op}"),
+                "a" => format!("{abs}{op}"),
+                "zx" => format!("{op},X"),
+                "ax" => format!("{abs}{op},X"),
+                "zy" => format!("{op},Y"),
+                "ay" => format!("{abs}{op},Y"),
+                "n" => format!("({op})"),
+                "nx" => format!("({op},X)"),
+                "ny" => format!("({op}),Y"),
+                _ => op,
+            };
+            format!("        {mnemonic} {op}")
+        }
+        _ => line.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translate_addressing_modes() {
+        assert_eq!(translate("ldai  00"), "        LDA #$00");
+        assert_eq!(translate("staax 0600"), "        STA $0600,X");
+        assert_eq!(translate("ldaa  0012"), "        LDA a:$0012");
+        assert_eq!(translate("ldany 12"), "        LDA ($12),Y");
+        assert_eq!(translate("jmpn  .vector"), "        JMP (vector)");
+        assert_eq!(translate("jmpn  0012"), "        JMP ($0012)");
+        assert_eq!(translate("bne   .c00a"), "        BNE LC00A");
+        assert_eq!(translate(".c00a"), "LC00A:");
+        assert_eq!(translate("data a901"), "        .byte $A9,$01");
+        assert_eq!(translate("data .reset"), "        .word reset");
+        assert_eq!(translate("ldaax 0600 ; 4+"), "        LDA $0600,X    ; 4+");
+    }
+}
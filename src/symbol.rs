@@ -0,0 +1,60 @@
+// Interned label names. Each name is stored once, and the tables of the passes refer to it by a
+// small number, so that they copy and compare numbers rather than strings.
+
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+
+use crate::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SymbolId(u32);
+
+#[derive(Default)]
+pub struct SymbolTable {
+    names: Vec<Rc<str>>,
+    ids: BTreeMap<Rc<str>, SymbolId>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Number of a name, which is added if it is new
+    pub fn intern(&mut self, name: &str) -> SymbolId {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = SymbolId(self.names.len() as u32);
+        let name: Rc<str> = Rc::from(name);
+        self.names.push(name.clone());
+        self.ids.insert(name, id);
+        id
+    }
+
+    // Number of a name, if it has one
+    pub fn get(&self, name: &str) -> Option<SymbolId> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: SymbolId) -> &str {
+        &self.names[id.0 as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_name_same_id() {
+        let mut t = SymbolTable::new();
+        let a = t.intern("start");
+        let b = t.intern("loop");
+        assert_eq!(t.intern("start"), a);
+        assert_ne!(a, b);
+        assert_eq!(t.get("loop"), Some(b));
+        assert_eq!(t.get("end"), None);
+        assert_eq!((t.name(a), t.name(b)), ("start", "loop"));
+    }
+}
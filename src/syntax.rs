@@ -1,40 +1,236 @@
 // Enums for tokenizing source code lines
 
-#[derive(Copy, Clone)]
+use crate::checksum::Checksum;
+use crate::prelude::*;
+
+// Remove the comment from a source line. Comments start with ';' or any of the other markers,
+// such as "//" or "#". A marker inside a quoted string does not start one.
+pub fn strip_comment<'a>(line: &'a str, markers: &[String]) -> &'a str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ if !quoted && markers.iter().any(|m| line[i..].starts_with(m.as_str())) => {
+                return &line[..i]
+            }
+            _ => (),
+        }
+    }
+    line
+}
+
+// Split text at separators outside quoted strings, dropping empty pieces. Each piece comes with
+// its byte offset in the text.
+pub fn split_unquoted(text: &str, is_separator: impl Fn(char) -> bool) -> Vec<(usize, &str)> {
+    let mut pieces = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        match (is_separator(c) && !quoted, start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                pieces.push((s, &text[s..i]));
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    if let Some(s) = start {
+        pieces.push((s, &text[s..]));
+    }
+    pieces
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum UInt {
     U8(u8),
     U16(u16),
 }
 
+#[derive(Clone)]
 pub enum Op {
     UInt(UInt),
     Label(String),
+
+    // Low or high byte of a label's value, written "<.label" or ">.label"
+    Low(String),
+    High(String),
     None,
 }
 
+// Added to the operand. A value is a single byte, which is negative when written with a minus sign.
 pub enum Offset {
-    U8(u8),
+    Value(i16),
     Label(String),
 }
 
 pub enum Rawdata {
     Bytes(Vec<u8>),
-    Label(String),
+    Label(String, Layout),
+}
+
+impl Rawdata {
+    // Number of bytes, assuming that labels are two-byte values
+    pub fn size(&self) -> usize {
+        match self {
+            Rawdata::Bytes(b) => b.len(),
+            Rawdata::Label(_, layout) => layout.size(),
+        }
+    }
+}
+
+// How a label's value is laid out in data
+#[derive(Clone, Copy, PartialEq)]
+pub enum Layout {
+    // Two bytes in little-endian order, as written by "data" and "word"
+    Word,
+
+    // Two bytes in big-endian order, as written by "wordbe"
+    WordBe,
+
+    // Four bytes in little-endian order, as written by "dword"
+    Dword,
+}
+
+impl Layout {
+    pub fn size(&self) -> usize {
+        match self {
+            Layout::Word | Layout::WordBe => 2,
+            Layout::Dword => 4,
+        }
+    }
+
+    // Lay out a value, which must fit in the size
+    pub fn bytes(&self, value: u32) -> Vec<u8> {
+        match self {
+            Layout::Word => (value as u16).to_le_bytes().to_vec(),
+            Layout::WordBe => (value as u16).to_be_bytes().to_vec(),
+            Layout::Dword => value.to_le_bytes().to_vec(),
+        }
+    }
+}
+
+// How long zero-page bytes stay allocated
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scope {
+    // For the whole program, unless freed with zfree
+    Program,
+
+    // Until the next code marker
+    Local,
+}
+
+// Sections of the program, as set by the segment command
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Segment {
+    // Instructions, and everything before the first segment command
+    Code,
+
+    // Tables and other initialized data
+    Data,
+
+    // Uninitialized memory, which takes up addresses but is not output
+    Bss,
 }
 
 pub enum SourceLine {
     // Empty lines after removing comments
     Blank,
 
-    // Keywords
-    Org(u16),
+    // Keywords. An org has its address and whether it may be lower than the code address, and a
+    // bank has its number, origin, and size.
+    Org(u16, bool),
+    Bank(u16, u16, u16),
+
+    // Entry address of the program, as a value or a label
+    End(Op),
     Label(String, UInt),
-    ZByte(String, u8),
-    Data(Rawdata),
+    ZByte(String, u8, u8, Scope),
+    ZWord(String, Scope),
+    ZFree(String),
+    ZpReserve(u8, u8),
+    Var(String, u16),
+    Segment(Segment),
+    // Items of data, each with the word of the line that it is in
+    Data(Vec<(usize, Rawdata)>),
+    Checksum(Checksum),
+
+    // Bytes written over the image at an address after assembling, outside of the code
+    Patch(u16, Vec<u8>),
 
     // Isolated labels
     CodeMarker(String),
 
     // Instruction lines
     Instr(String, Op, Offset),
+
+    // Relative branch whose target is too far, assembled as the opposite branch over a jmpa
+    LongBranch(String, Op, Offset),
+
+    // Directives for "sasm test": the start of a named test at the line's address, and a check
+    // after the test runs that a register or memory does (or does not) hold a value
+    Test(String),
+    Assert(Checked, u8, bool),
+}
+
+// What an assert directive checks: a register, named by its letter, or a byte of memory
+pub enum Checked {
+    Register(char),
+    Memory(Op),
+}
+
+// Label and offset of a label in data, such as "table+4" or "handler-1", with the offset in hex
+// like every other value. Text without such an offset is all label.
+pub fn split_offset(label: &str) -> (&str, i32) {
+    let Some(i) = label.rfind(['+', '-']).filter(|i| *i > 0) else {
+        return (label, 0);
+    };
+    let digits = &label[i + 1..];
+    if digits.is_empty() || digits.len() > 4 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return (label, 0);
+    }
+    let offset = i32::from_str_radix(digits, 16).unwrap_or(0);
+    match &label[i..i + 1] {
+        "-" => (&label[..i], -offset),
+        _ => (&label[..i], offset),
+    }
+}
+
+impl Op {
+    // Label that the operand refers to, if any
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            Op::Label(l) | Op::Low(l) | Op::High(l) => Some(l),
+            _ => None,
+        }
+    }
+}
+
+impl SourceLine {
+    // Labels that the line refers to, as opposed to those that it defines
+    pub fn label_refs(&self) -> Vec<&str> {
+        match self {
+            SourceLine::End(op) => op.label().into_iter().collect(),
+            SourceLine::Instr(_, op, offset) | SourceLine::LongBranch(_, op, offset) => {
+                let mut refs: Vec<&str> = op.label().into_iter().collect();
+                if let Offset::Label(l) = offset {
+                    refs.push(l);
+                }
+                refs
+            }
+            SourceLine::Assert(Checked::Memory(op), ..) => op.label().into_iter().collect(),
+            SourceLine::Data(items) => items
+                .iter()
+                .filter_map(|(_, d)| match d {
+                    Rawdata::Label(l, _) => Some(split_offset(l).0),
+                    Rawdata::Bytes(_) => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
 }
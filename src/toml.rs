@@ -0,0 +1,283 @@
+// Minimal reader for the subset of TOML used by SASM project files: "key = value" pairs, optional
+// [table] headers, and values that are strings, integers, booleans, or arrays of strings on one
+// line. Comments start with '#'. Anything else that TOML allows, such as dotted keys, inline tables,
+// and arrays of tables, is an error rather than being read differently than TOML would.
+
+use alloc::collections::BTreeSet;
+
+use crate::prelude::*;
+
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<String>),
+}
+
+// A single setting along with the table it appeared in ("" for the top level)
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub table: String,
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+// Character of a \u or \U escape sequence with the given number of hex digits, and the text
+// after it
+fn parse_unicode_escape(s: &str, digits: usize) -> Result<(char, &str), String> {
+    let hex = s.get(..digits).unwrap_or(s);
+    match u32::from_str_radix(hex, 16).ok().and_then(char::from_u32) {
+        Some(c) if hex.len() == digits => Ok((c, &s[digits..])),
+        _ => Err(format!("invalid unicode escape sequence {hex}")),
+    }
+}
+
+// Parse a quoted string at the start of s, returning the string and the remaining text. Basic
+// strings ("...") have the escape sequences of TOML, and literal strings ('...') have none.
+fn parse_string(s: &str) -> Result<(String, &str), String> {
+    if s.starts_with("\"\"\"") || s.starts_with("'''") {
+        return Err("multi-line strings are not supported".to_string());
+    }
+    if let Some(rest) = s.strip_prefix('\'') {
+        return match rest.split_once('\'') {
+            Some((value, after)) => Ok((value.to_string(), after)),
+            None => Err("unterminated string".to_string()),
+        };
+    }
+    let mut rest = match s.strip_prefix('"') {
+        Some(r) => r,
+        None => return Err("expected a quoted string".to_string()),
+    };
+
+    let mut value = String::new();
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '"' => return Ok((value, rest)),
+            '\\' => {
+                let e = rest.chars().next().ok_or("unterminated string")?;
+                rest = &rest[e.len_utf8()..];
+                match e {
+                    'b' => value.push('\u{8}'),
+                    't' => value.push('\t'),
+                    'n' => value.push('\n'),
+                    'f' => value.push('\u{c}'),
+                    'r' => value.push('\r'),
+                    '"' | '\\' => value.push(e),
+                    'u' | 'U' => {
+                        let (u, after) = parse_unicode_escape(rest, if e == 'u' { 4 } else { 8 })?;
+                        value.push(u);
+                        rest = after;
+                    }
+                    _ => return Err(format!("invalid escape sequence \\{e}")),
+                }
+            }
+            _ => value.push(c),
+        }
+    }
+
+    Err("unterminated string".to_string())
+}
+
+// Remove a trailing comment, ignoring '#' inside strings
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some('"'), '\\') => escaped = true,
+            (Some(q), _) if c == q => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+// Bare key (letters, digits, '-', and '_') or quoted key. Dotted keys are not supported.
+fn parse_key(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    if s.starts_with('"') || s.starts_with('\'') {
+        return match parse_string(s)? {
+            (key, rest) if rest.trim().is_empty() => Ok(key),
+            _ => Err(format!("invalid key {s}")),
+        };
+    }
+    if s.contains('.') {
+        return Err(format!("dotted keys are not supported: {s}"));
+    }
+    match !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        true => Ok(s.to_string()),
+        false => Err(format!("invalid key {s}")),
+    }
+}
+
+fn parse_value(s: &str) -> Result<Value, String> {
+    let s = s.trim();
+    if s.starts_with('"') || s.starts_with('\'') {
+        let (value, rest) = parse_string(s)?;
+        if !rest.trim().is_empty() {
+            return Err("unexpected text after string".to_string());
+        }
+        return Ok(Value::String(value));
+    }
+
+    if let Some(inner) = s.strip_prefix('[') {
+        let inner = match inner.strip_suffix(']') {
+            Some(i) => i,
+            None => return Err("arrays must be on a single line".to_string()),
+        };
+        // Strings separated by commas, with an optional comma after the last one
+        let mut values = Vec::new();
+        let mut rest = inner.trim();
+        while !rest.is_empty() {
+            let (value, after) = parse_string(rest)?;
+            values.push(value);
+            rest = after.trim();
+            rest = match rest.strip_prefix(',') {
+                Some(r) => r.trim(),
+                None if rest.is_empty() => rest,
+                None => return Err("expected a comma between array values".to_string()),
+            };
+        }
+        return Ok(Value::Array(values));
+    }
+    if s.starts_with('{') {
+        return Err("inline tables are not supported".to_string());
+    }
+
+    match s {
+        "true" => Ok(Value::Boolean(true)),
+        "false" => Ok(Value::Boolean(false)),
+        _ => match s.replace('_', "").parse() {
+            Ok(n) => Ok(Value::Integer(n)),
+            Err(_) => Err(format!("invalid value: {s}")),
+        },
+    }
+}
+
+pub fn parse(text: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let mut table = String::new();
+    let mut tables = BTreeSet::new();
+
+    for (line_idx, raw_line) in text.lines().enumerate() {
+        let line_num = line_idx + 1;
+        let line = strip_comment(raw_line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with("[[") {
+            return Err(format!("{line_num}: arrays of tables are not supported"));
+        }
+        if let Some(name) = line.strip_prefix('[') {
+            let name = match name.strip_suffix(']') {
+                Some(n) => parse_key(n).map_err(|e| format!("{line_num}: {e}"))?,
+                None => return Err(format!("{line_num}: invalid table header")),
+            };
+            if !tables.insert(name.clone()) {
+                return Err(format!("{line_num}: table [{name}] defined twice"));
+            }
+            table = name;
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some(kv) => kv,
+            None => return Err(format!("{line_num}: expected key = value")),
+        };
+        let key = parse_key(key).map_err(|e| format!("{line_num}: {e}"))?;
+        let value = parse_value(value).map_err(|e| format!("{line_num}: {e}"))?;
+        if entries
+            .iter()
+            .any(|e: &Entry| e.table == table && e.key == key)
+        {
+            return Err(format!("{line_num}: {key} defined twice"));
+        }
+        entries.push(Entry {
+            table: table.clone(),
+            key,
+            value,
+            line: line_num,
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_values_and_tables() {
+        let text = "# project\n\
+                    input = \"main.asm\" # trailing comment\n\
+                    illegal = true\n\
+                    min_region = 12\n\
+                    include_paths = [\"lib\", \"#inc\"]\n\
+                    [defines]\n\
+                    screen = \"0400\"\n";
+        let entries = parse(text).unwrap();
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0].value, Value::String("main.asm".to_string()));
+        assert_eq!(entries[1].value, Value::Boolean(true));
+        assert_eq!(entries[2].value, Value::Integer(12));
+        assert_eq!(
+            entries[3].value,
+            Value::Array(vec!["lib".to_string(), "#inc".to_string()])
+        );
+        assert_eq!(entries[4].table, "defines");
+        assert_eq!(entries[4].key, "screen");
+    }
+
+    #[test]
+    fn parse_strings() {
+        let text = "a = 'C:\\dir' # literal\n\
+                    b = \"tab\\there \\u00e9\\\"\"\n\
+                    c = [\"x\", 'y',]\n";
+        let entries = parse(text).unwrap();
+        assert_eq!(entries[0].value, Value::String("C:\\dir".to_string()));
+        assert_eq!(
+            entries[1].value,
+            Value::String("tab\there \u{e9}\"".to_string())
+        );
+        assert_eq!(
+            entries[2].value,
+            Value::Array(vec!["x".to_string(), "y".to_string()])
+        );
+    }
+
+    #[test]
+    fn parse_rejects_unsupported_toml() {
+        let error = |text: &str| parse(text).unwrap_err();
+        assert_eq!(
+            error("a = [\"x\" \"y\"]"),
+            "1: expected a comma between array values"
+        );
+        assert_eq!(error("a = [\n\"x\"]"), "1: arrays must be on a single line");
+        assert_eq!(error("a = \"\\q\""), "1: invalid escape sequence \\q");
+        assert_eq!(error("[[bin]]"), "1: arrays of tables are not supported");
+        assert_eq!(error("a.b = 1"), "1: dotted keys are not supported: a.b");
+        assert_eq!(error("[a.b]"), "1: dotted keys are not supported: a.b");
+        assert_eq!(error("a = { b = 1 }"), "1: inline tables are not supported");
+        assert_eq!(error("a = 1\na = 2"), "2: a defined twice");
+        assert_eq!(error("[t]\n[t]"), "2: table [t] defined twice");
+    }
+
+    #[test]
+    fn parse_error_has_line() {
+        assert_eq!(
+            parse("a = 1\nb = \"oops\n"),
+            Err("2: unterminated string".to_string())
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use core::fmt;
+
+use crate::prelude::*;
+
+// Version of the crate along with the capabilities compiled into it, so that tools can check
+// what the assembler supports before relying on it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Version {
+    pub version: &'static str,
+
+    // CPU instruction sets that can be assembled and disassembled
+    pub cpus: Vec<&'static str>,
+
+    // Code output formats accepted by the -f flag
+    pub formats: Vec<&'static str>,
+
+    // Target systems accepted by the -s flag
+    pub systems: Vec<&'static str>,
+}
+
+pub fn version() -> Version {
+    Version {
+        version: env!("CARGO_PKG_VERSION"),
+        cpus: vec!["6502", "6502-illegal"],
+        formats: vec![
+            "hex", "apple", "bin", "json", "listing", "ines", "prg", "xex", "reloc",
+        ],
+        systems: vec!["apple", "atari", "nes", "c64", "vic20", "atari8"],
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "SASM2 {}", self.version)?;
+        writeln!(f, "CPUs:    {}", self.cpus.join(", "))?;
+        writeln!(f, "Formats: {}", self.formats.join(", "))?;
+        write!(f, "Systems: {}", self.systems.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_matches_manifest() {
+        let v = version();
+        assert_eq!(v.version, env!("CARGO_PKG_VERSION"));
+        assert!(v.to_string().starts_with(&format!("SASM2 {}\n", v.version)));
+    }
+}
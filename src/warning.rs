@@ -0,0 +1,237 @@
+use alloc::collections::BTreeSet;
+use core::fmt;
+
+use crate::diagnostic;
+use crate::diagnostic::Location;
+use crate::prelude::*;
+
+// Non-fatal issues found during assembly or disassembly. Each kind has a name used to enable or
+// disable it from the command line.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum WarningKind {
+    // An offset is added to the target of a relative branch
+    BranchOffset,
+
+    // A relative branch crosses a page boundary, costing an extra cycle when taken
+    BranchPage,
+
+    // The disassembler found a labeled address inside another instruction or data line
+    LabelInsideLine,
+
+    // A label, code marker, zbyte, zword, or var is never referred to
+    UnusedLabels,
+
+    // More than one linked object file defines the same label
+    DuplicateExports,
+}
+
+const ALL_WARNINGS: [WarningKind; 5] = [
+    WarningKind::BranchOffset,
+    WarningKind::BranchPage,
+    WarningKind::LabelInsideLine,
+    WarningKind::UnusedLabels,
+    WarningKind::DuplicateExports,
+];
+
+impl WarningKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WarningKind::BranchOffset => "branch-offset",
+            WarningKind::BranchPage => "branch-page",
+            WarningKind::LabelInsideLine => "label-inside-line",
+            WarningKind::UnusedLabels => "unused-labels",
+            WarningKind::DuplicateExports => "duplicate-exports",
+        }
+    }
+
+    // Page crossings are normal in most programs and only matter for cycle-exact code, and
+    // libraries define labels that a program may not use, so those warnings must be requested
+    // explicitly.
+    fn on_by_default(&self) -> bool {
+        !matches!(self, WarningKind::BranchPage | WarningKind::UnusedLabels)
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        ALL_WARNINGS.into_iter().find(|w| w.name() == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SasmWarning {
+    pub kind: WarningKind,
+    pub message: String,
+
+    // Input file, line number (starting at 1), and text of the source line, if known
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub source_line: Option<String>,
+
+    // Index of the word in the source line that caused the warning, if known
+    pub word: Option<usize>,
+}
+
+impl SasmWarning {
+    pub fn new(kind: WarningKind, message: &str) -> Self {
+        SasmWarning {
+            kind,
+            message: message.to_string(),
+            file: None,
+            line: None,
+            source_line: None,
+            word: None,
+        }
+    }
+
+    pub fn at_line(mut self, line: usize, source: &str) -> Self {
+        self.line = Some(line);
+        self.source_line = Some(source.to_string());
+        self
+    }
+
+    pub fn at(mut self, location: &Location, source: &str) -> Self {
+        self.file = location.file.clone();
+        self.line = Some(location.line);
+        self.source_line = Some(source.to_string());
+        self
+    }
+
+    pub fn in_word(mut self, word: usize) -> Self {
+        self.word = Some(word);
+        self
+    }
+
+    // Column (starting at 1) and length of the text that caused the warning, if known
+    pub fn span(&self) -> Option<(usize, usize)> {
+        diagnostic::span(self.source_line.as_deref()?, self.word)
+    }
+
+    // The warning followed by the source line with the relevant text underlined
+    pub fn render(&self, color: bool) -> String {
+        diagnostic::render(
+            &self.to_string(),
+            self.source_line.as_deref(),
+            self.span(),
+            diagnostic::YELLOW,
+            color,
+        )
+    }
+
+    // A single-line JSON object for editors and other tools
+    pub fn to_json(&self) -> String {
+        diagnostic::json(
+            self.file.as_deref(),
+            self.line,
+            self.span(),
+            "warning",
+            &self.message,
+            self.kind.name(),
+        )
+    }
+}
+
+impl fmt::Display for SasmWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(l)) => write!(f, "{file}:{l}: warning: {}", self.message),
+            (None, Some(l)) => write!(f, "{l}: warning: {}", self.message),
+            (Some(file), None) => write!(f, "{file}: warning: {}", self.message),
+            _ => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+// Warning settings plus the warnings collected so far. Only enabled warnings are collected.
+pub struct Warnings {
+    enabled: BTreeSet<WarningKind>,
+    pub as_errors: bool,
+    pub list: Vec<SasmWarning>,
+}
+
+impl Default for Warnings {
+    fn default() -> Self {
+        Warnings {
+            enabled: ALL_WARNINGS
+                .into_iter()
+                .filter(|w| w.on_by_default())
+                .collect(),
+            as_errors: false,
+            list: Vec::new(),
+        }
+    }
+}
+
+impl Warnings {
+    // Apply a comma-separated list of settings, such as "all,no-branch-offset,error"
+    pub fn apply(&mut self, spec: &str) -> Result<(), String> {
+        for setting in spec.split(',') {
+            match setting {
+                "all" => self.enabled = ALL_WARNINGS.into_iter().collect(),
+                "none" => self.enabled.clear(),
+                "error" => self.as_errors = true,
+                s => {
+                    let (name, enable) = match s.strip_prefix("no-") {
+                        Some(n) => (n, false),
+                        None => (s, true),
+                    };
+                    let kind = match WarningKind::from_name(name) {
+                        Some(k) => k,
+                        None => return Err(format!("Unknown warning: {s}")),
+                    };
+                    if enable {
+                        self.enabled.insert(kind);
+                    } else {
+                        self.enabled.remove(&kind);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_enabled(&self, kind: WarningKind) -> bool {
+        self.enabled.contains(&kind)
+    }
+
+    pub fn warn(&mut self, w: SasmWarning) {
+        if self.is_enabled(w.kind) {
+            self.list.push(w);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_warnings() {
+        let w = Warnings::default();
+        assert!(w.is_enabled(WarningKind::BranchOffset));
+        assert!(!w.is_enabled(WarningKind::BranchPage));
+        assert!(!w.as_errors);
+    }
+
+    #[test]
+    fn apply_warning_list() {
+        let mut w = Warnings::default();
+        w.apply("all,no-branch-offset,error").unwrap();
+        assert!(!w.is_enabled(WarningKind::BranchOffset));
+        assert!(w.is_enabled(WarningKind::BranchPage));
+        assert!(w.as_errors);
+    }
+
+    #[test]
+    fn apply_unknown_warning() {
+        let mut w = Warnings::default();
+        assert_eq!(w.apply("bogus"), Err("Unknown warning: bogus".to_string()));
+    }
+
+    #[test]
+    fn disabled_warnings_not_collected() {
+        let mut w = Warnings::default();
+        w.apply("none").unwrap();
+        w.warn(SasmWarning::new(WarningKind::BranchOffset, "ignored"));
+        assert!(w.list.is_empty());
+    }
+}
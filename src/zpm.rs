@@ -1,59 +1,270 @@
-#[derive(Debug)]
+// Each variant keeps a free list of the zero-page bytes that can still be allocated, as sorted,
+// half-open ranges. Bytes return to it when they are freed, so that temporary bytes can be
+// reused. Reserved ranges, such as bytes used by DOS, the monitor, or hardware, are never free.
+// Only bytes within the bounds, an inclusive range given by --zp-range or else the system's
+// default, are ever allocated.
+
+use alloc::collections::BTreeSet;
+
+use crate::prelude::*;
+
+// Sorted, half-open ranges of free bytes
+type FreeList = Vec<(u16, u16)>;
+
+#[derive(Clone, Debug)]
 pub enum Zpm {
-    Apple { bytes_remaining: u16 },
-    Atari2600 { next_free_byte: u16 },
+    Apple {
+        free: FreeList,
+        reserved: Vec<(u8, u8)>,
+        bounds: Option<(u8, u8)>,
+    },
+    Atari2600 {
+        free: FreeList,
+        reserved: Vec<(u8, u8)>,
+        bounds: Option<(u8, u8)>,
+    },
+    Nes {
+        free: FreeList,
+        reserved: Vec<(u8, u8)>,
+        bounds: Option<(u8, u8)>,
+    },
+    C64 {
+        free: FreeList,
+        reserved: Vec<(u8, u8)>,
+        bounds: Option<(u8, u8)>,
+    },
+    Vic20 {
+        free: FreeList,
+        reserved: Vec<(u8, u8)>,
+        bounds: Option<(u8, u8)>,
+    },
+    Atari8 {
+        free: FreeList,
+        reserved: Vec<(u8, u8)>,
+        bounds: Option<(u8, u8)>,
+    },
     None,
 }
 
+// Add the bytes from start to end (exclusive) to a free list, joining neighboring ranges
+fn add_free(free: &mut FreeList, start: u16, end: u16) {
+    free.push((start, end));
+    free.sort();
+    let mut joined: FreeList = Vec::new();
+    for r in free.drain(..) {
+        match joined.last_mut() {
+            Some(last) if last.1 >= r.0 => last.1 = last.1.max(r.1),
+            _ => joined.push(r),
+        }
+    }
+    *free = joined;
+}
+
+// Remove the bytes from start to end (exclusive) from a free list
+fn remove_free(free: &mut FreeList, start: u16, end: u16) {
+    *free = free
+        .iter()
+        .flat_map(|r| [(r.0, r.1.min(start)), (r.0.max(end), r.1)])
+        .filter(|r| r.0 < r.1)
+        .collect();
+}
+
+// Inclusive ranges of zero page that are not among the given bytes
+pub fn ranges_outside(addrs: &BTreeSet<u8>) -> Vec<(u8, u8)> {
+    let mut ranges = Vec::new();
+    let mut start = 0u16;
+    for a in addrs.iter().map(|a| *a as u16).chain([0x100]) {
+        if a > start {
+            ranges.push((start as u8, (a - 1) as u8));
+        }
+        start = a + 1;
+    }
+    ranges
+}
+
+// Error when no free range is large enough, which the assembler reports with the line and size
+pub const ZP_EXHAUSTED: &str = "zero page exhausted";
+
 impl Zpm {
     // Allow creating specific variants without needing to check for failure
     pub fn new_for_apple() -> Self {
-        Self::new("apple").expect("Internal error: Unable to create an AppleII ZPM")
+        Self::new("apple", None).expect("Internal error: Unable to create an AppleII ZPM")
     }
 
     pub fn new_for_atari() -> Self {
-        Self::new("atari").expect("Internal error: Unable to create an Atari 2600 ZPM")
+        Self::new("atari", None).expect("Internal error: Unable to create an Atari 2600 ZPM")
+    }
+
+    // Attempt to create a variant from a string. Without bounds, the manager allocates all the
+    // zero page that the system leaves for programs.
+    pub fn new(arch: &str, bounds: Option<(u8, u8)>) -> Result<Self, &str> {
+        let start = bounds.map(|b| b.0);
+        if bounds.is_some_and(|b| b.1 < b.0) {
+            return Err("Zero-page range ends before it starts");
+        }
+
+        let arch = arch.to_ascii_lowercase();
+        let mut zpm = if arch.starts_with("apple") {
+            Zpm::Apple {
+                free: Vec::new(),
+                reserved: Vec::new(),
+                bounds,
+            }
+        } else if arch.starts_with("atari8") {
+            Zpm::Atari8 {
+                free: Vec::new(),
+                reserved: Vec::new(),
+                bounds,
+            }
+        } else if arch.starts_with("atari") {
+            if start.is_some_and(|s| s < 0x80) {
+                return Err("Atari 2600 zero-page range must be within 80-ff");
+            }
+            Zpm::Atari2600 {
+                free: Vec::new(),
+                reserved: Vec::new(),
+                bounds,
+            }
+        } else if arch.starts_with("nes") {
+            Zpm::Nes {
+                free: Vec::new(),
+                reserved: Vec::new(),
+                bounds,
+            }
+        } else if arch.starts_with("c64") {
+            if start.is_some_and(|s| s < 0x02) {
+                return Err("C64 zero-page range must be within 02-ff");
+            }
+            Zpm::C64 {
+                free: Vec::new(),
+                reserved: Vec::new(),
+                bounds,
+            }
+        } else if arch.starts_with("vic20") {
+            Zpm::Vic20 {
+                free: Vec::new(),
+                reserved: Vec::new(),
+                bounds,
+            }
+        } else {
+            return Err("Unrecognized or unsupported system");
+        };
+        zpm.reset();
+        Ok(zpm)
+    }
+
+    // Name of the system, as accepted by new
+    pub fn system(&self) -> &'static str {
+        match self {
+            Zpm::Apple { .. } => "apple",
+            Zpm::Atari2600 { .. } => "atari",
+            Zpm::Nes { .. } => "nes",
+            Zpm::C64 { .. } => "c64",
+            Zpm::Vic20 { .. } => "vic20",
+            Zpm::Atari8 { .. } => "atari8",
+            Zpm::None => "none",
+        }
+    }
+
+    fn bounds(&self) -> (u8, u8) {
+        match self {
+            Zpm::Apple { bounds, .. } | Zpm::Nes { bounds, .. } => bounds.unwrap_or((0, 0xff)),
+            Zpm::Atari2600 { bounds, .. } => bounds.unwrap_or((0x80, 0xff)),
+            Zpm::C64 { bounds, .. } => bounds.unwrap_or((0x02, 0xfe)),
+            Zpm::Vic20 { bounds, .. } => bounds.unwrap_or((0xfb, 0xfe)),
+            Zpm::Atari8 { bounds, .. } => bounds.unwrap_or((0xcb, 0xd1)),
+            Zpm::None => (0, 0xff),
+        }
+    }
+
+    // Free list and reserved ranges, which a manager set to none does not have
+    fn lists(&mut self) -> Option<(&mut FreeList, &mut Vec<(u8, u8)>)> {
+        match self {
+            Zpm::Apple { free, reserved, .. }
+            | Zpm::Atari2600 { free, reserved, .. }
+            | Zpm::Nes { free, reserved, .. }
+            | Zpm::C64 { free, reserved, .. }
+            | Zpm::Vic20 { free, reserved, .. }
+            | Zpm::Atari8 { free, reserved, .. } => Some((free, reserved)),
+            Zpm::None => None,
+        }
     }
 
-    // Attempt to create a variant from a string
-    pub fn new(arch: &str) -> Result<Self, &str> {
-        if arch.to_ascii_lowercase().starts_with("apple") {
-            return Ok(Zpm::Apple {
-                bytes_remaining: 0x100,
-            });
+    // Keep a range of zero page from being allocated. Bytes that are already allocated cannot be
+    // reserved.
+    pub fn reserve(&mut self, start: u8, end: u8) -> Result<(), &'static str> {
+        // Bytes outside the bounds, such as the lower half of zero page on the Atari 2600, are
+        // not allocated either
+        let (low, high) = self.bounds();
+        let allocatable = low as u16..=high as u16;
+        let (free, reserved) = self.lists().ok_or("no zero-page manager")?;
+        if (start as u16..=end as u16).any(|a| {
+            allocatable.contains(&a)
+                && !free.iter().any(|r| r.0 <= a && a < r.1)
+                && !reserved.iter().any(|r| r.0 as u16 <= a && a <= r.1 as u16)
+        }) {
+            return Err("zero-page bytes in this range are already allocated");
         }
+        remove_free(free, start as u16, end as u16 + 1);
+        reserved.push((start, end));
+        Ok(())
+    }
+
+    // Free all allocated bytes and reserved ranges, so that the same manager can be used for
+    // another assembly
+    pub fn reset(&mut self) {
+        let (low, high) = self.bounds();
+
+        // BASIC and the KERNAL use all of the C64's zero page except 02 and fb-fe. Programs that
+        // do not return to BASIC can claim more of it with explicit bounds.
+        let kernal = matches!(self, Zpm::C64 { bounds: None, .. });
 
-        if arch.to_ascii_lowercase().starts_with("atari") {
-            return Ok(Zpm::Atari2600 {
-                next_free_byte: 0x80,
-            });
+        let Some((free, reserved)) = self.lists() else {
+            return;
+        };
+        *free = vec![(low as u16, high as u16 + 1)];
+        reserved.clear();
+        if kernal {
+            remove_free(free, 0x03, 0xfb);
+            reserved.push((0x03, 0xfa));
         }
+    }
 
-        Err("Unrecognized or unsupported system")
+    pub fn alloc(&mut self, size: u8) -> Result<u8, &'static str> {
+        self.alloc_aligned(size, 1)
     }
 
-    pub fn alloc(&mut self, size: u8) -> u8 {
-        // Convert size to u16 for calculations
+    // Allocate bytes starting at a multiple of align, such as a pointer pair at an even address
+    pub fn alloc_aligned(&mut self, size: u8, align: u8) -> Result<u8, &'static str> {
+        // Convert size and align to u16 for calculations
         let size = size as u16;
+        let align = align as u16;
+        if align == 0 {
+            return Err("zero-page alignment must not be zero");
+        }
+        if size == 0 {
+            return Err("zero-page size must not be zero");
+        }
 
-        match self {
+        let addr = match self {
             // Apple II system-level programs, like the monitor and DOS, use the
             // lower addresses first and leave the higher addresses for user
             // programs. Thus, this simple manager allocates bytes in order from
-            // high to low memory. A program that uses lots of zero-page bytes
-            // will need a more sophisticated manager. It also will have to
-            // consider the specific Apple II model being used.
-            Zpm::Apple { bytes_remaining: b } => {
-                if size == 0 {
-                    panic!("Request to allocate zero bytes of zero page memory")
-                }
-
-                if size > *b {
-                    panic!("Zero page memory exhausted");
-                }
-
-                *b -= size;
-                return *b as u8;
+            // high to low memory, taking the highest free range that is large
+            // enough once aligned. A program that uses lots of zero-page bytes will need a
+            // more sophisticated manager. It also will have to consider the
+            // specific Apple II model being used.
+            //
+            // The free zero page of the C64 and the VIC-20 is also at the
+            // top, at fb-fe. The VIC-20 leaves no other bytes free.
+            Zpm::Apple { free, .. } | Zpm::C64 { free, .. } | Zpm::Vic20 { free, .. } => {
+                free.iter()
+                    .rev()
+                    .filter(|r| r.1 - r.0 >= size)
+                    .map(|r| ((r.1 - size) / align * align, r.0))
+                    .find(|(addr, start)| addr >= start)
+                    .ok_or(ZP_EXHAUSTED)?
+                    .0
             }
 
             // The upper half of zero page (0x80 - 0xff) is the ONLY memory,
@@ -61,21 +272,51 @@ impl Zpm {
             // available. Furthermore, the stack is mapped to zero page as well!
             // The stack normally starts at ff and grows down, which means that
             // the lower addresses should be preferred. Accordingly, this
-            // manager allocates memory in order from 0x80 to 0xff.
-            Zpm::Atari2600 { next_free_byte: b } => {
-                if size == 0 {
-                    panic!("Request to allocate zero bytes of zero page memory");
-                }
-
-                if *b + size > 0x100 {
-                    panic!("Zero page memory exhausted");
-                }
-
-                *b += size;
-                return (*b - size) as u8;
+            // manager allocates memory in order from 0x80 to 0xff, taking the
+            // lowest free range that is large enough once aligned.
+            //
+            // The NES has 2K of RAM, all of zero page is free for programs,
+            // and the stack is in page 1. Programs conventionally fill zero
+            // page from the bottom, so the NES follows the Atari.
+            //
+            // The Atari 400 and 800 OS uses 00-7f and BASIC uses 80-ca,
+            // which leaves cb-d1 for programs. A program without BASIC can
+            // claim 80-ff with explicit bounds, which this manager fills from
+            // the bottom as on the Atari 2600.
+            Zpm::Atari2600 { free, .. } | Zpm::Nes { free, .. } | Zpm::Atari8 { free, .. } => {
+                free.iter()
+                    .map(|r| (r.0.div_ceil(align) * align, r.1))
+                    .find(|(addr, end)| addr + size <= *end)
+                    .ok_or(ZP_EXHAUSTED)?
+                    .0
             }
 
-            Zpm::None => panic!("Internal error: zero-page manager set to none"),
+            Zpm::None => return Err("no zero-page manager"),
+        };
+
+        if let Some((free, _)) = self.lists() {
+            remove_free(free, addr, addr + size);
+        }
+        Ok(addr as u8)
+    }
+
+    // Bytes among the given ones that zbyte allocates once the rest of zero page is reserved, in
+    // the order that it allocates them. Bytes that the manager never allocates, such as those of
+    // the operating system, are left out.
+    pub fn alloc_order(&self, addrs: &BTreeSet<u8>) -> Vec<u8> {
+        let mut zpm = self.clone();
+        zpm.reset();
+        for (start, end) in ranges_outside(addrs) {
+            // Bytes outside the bounds are not allocated anyway
+            let _ = zpm.reserve(start, end);
+        }
+        core::iter::from_fn(|| zpm.alloc(1).ok()).collect()
+    }
+
+    // Return allocated bytes, so that later allocations can reuse them
+    pub fn free(&mut self, addr: u8, size: u8) {
+        if let Some((free, _)) = self.lists() {
+            add_free(free, addr as u16, addr as u16 + size as u16);
         }
     }
 }
@@ -85,50 +326,178 @@ mod tests {
     use super::*;
 
     #[test]
-    #[should_panic(expected = "Request to allocate zero bytes of zero page memory")]
+    fn zpm_reset() {
+        let mut z = Zpm::new_for_atari();
+        z.alloc(4).unwrap();
+        z.reset();
+        assert_eq!(z.alloc(1), Ok(0x80));
+    }
+
+    #[test]
+    fn zpm_skip_reserved() {
+        let mut apple = Zpm::new_for_apple();
+        apple.reserve(0xf0, 0xf7).unwrap();
+        assert_eq!(apple.alloc(8), Ok(0xf8));
+        assert_eq!(apple.alloc(2), Ok(0xee));
+
+        let mut atari = Zpm::new_for_atari();
+        atari.reserve(0x81, 0x81).unwrap();
+        assert_eq!(atari.alloc(2), Ok(0x82));
+
+        // The byte skipped before the reserved range is still free
+        assert_eq!(atari.alloc(1), Ok(0x80));
+    }
+
+    #[test]
+    fn zpm_free_and_reuse() {
+        let mut zpm = Zpm::new_for_apple();
+        let a = zpm.alloc(2).unwrap();
+        let b = zpm.alloc(1).unwrap();
+        zpm.free(a, 2);
+        assert_eq!(zpm.alloc(1), Ok(0xff));
+        zpm.free(b, 1);
+        assert_eq!(zpm.alloc(2), Ok(0xfd));
+    }
+
+    #[test]
+    fn zpm_alloc_aligned() {
+        let mut apple = Zpm::new_for_apple();
+        assert_eq!(apple.alloc(1), Ok(0xff));
+        assert_eq!(apple.alloc_aligned(2, 2), Ok(0xfc));
+        assert_eq!(apple.alloc_aligned(1, 4), Ok(0xf8));
+
+        // Bytes skipped for alignment are still free
+        assert_eq!(apple.alloc(1), Ok(0xfe));
+
+        let mut atari = Zpm::new_for_atari();
+        assert_eq!(atari.alloc(1), Ok(0x80));
+        assert_eq!(atari.alloc_aligned(4, 8), Ok(0x88));
+        assert_eq!(atari.alloc(1), Ok(0x81));
+    }
+
+    #[test]
+    fn zpm_bounds() {
+        let mut zpm = Zpm::new("apple", Some((0xd0, 0xef))).unwrap();
+        assert_eq!(zpm.alloc(2), Ok(0xee));
+        zpm.reset();
+        assert_eq!(zpm.alloc(1), Ok(0xef));
+        assert!(zpm.reserve(0xf0, 0xff).is_ok());
+
+        assert!(Zpm::new("atari", Some((0x70, 0x8f))).is_err());
+        assert!(Zpm::new("apple", Some((0xef, 0xd0))).is_err());
+    }
+
+    #[test]
+    fn zpm_alloc_outside_bounds() {
+        let mut zpm = Zpm::new("atari", Some((0x80, 0x83))).unwrap();
+        assert_eq!(zpm.alloc(2), Ok(0x80));
+        assert_eq!(zpm.alloc(3), Err(ZP_EXHAUSTED));
+    }
+
+    #[test]
+    fn zpm_nes() {
+        let mut zpm = Zpm::new("nes", None).unwrap();
+        assert_eq!(zpm.alloc(2), Ok(0x00));
+        assert_eq!(zpm.alloc(1), Ok(0x02));
+        zpm.reset();
+        assert_eq!(zpm.system(), "nes");
+        assert_eq!(zpm.alloc(1), Ok(0x00));
+    }
+
+    #[test]
+    fn zpm_c64() {
+        let mut zpm = Zpm::new("c64", None).unwrap();
+        assert_eq!(zpm.alloc(4), Ok(0xfb));
+        assert_eq!(zpm.alloc(1), Ok(0x02));
+        assert!(zpm.reserve(0x03, 0x10).is_ok());
+        zpm.reset();
+        assert_eq!(zpm.alloc(2), Ok(0xfd));
+
+        let mut zpm = Zpm::new("c64", Some((0x02, 0x8f))).unwrap();
+        assert_eq!(zpm.alloc(1), Ok(0x8f));
+        assert!(Zpm::new("c64", Some((0x00, 0x8f))).is_err());
+    }
+
+    #[test]
+    fn zpm_atari8_and_vic20() {
+        let mut atari8 = Zpm::new("atari800", None).unwrap();
+        assert_eq!(atari8.system(), "atari8");
+        assert_eq!(atari8.alloc(2), Ok(0xcb));
+        assert_eq!(atari8.alloc(5), Ok(0xcd));
+
+        let mut vic20 = Zpm::new("vic20", None).unwrap();
+        assert_eq!(vic20.alloc(1), Ok(0xfe));
+        assert_eq!(vic20.alloc(3), Ok(0xfb));
+    }
+
+    #[test]
+    fn zpm_alloc_order() {
+        let addrs = BTreeSet::from([0x10, 0x80, 0xfb]);
+        assert_eq!(
+            ranges_outside(&addrs),
+            vec![(0x00, 0x0f), (0x11, 0x7f), (0x81, 0xfa), (0xfc, 0xff)]
+        );
+        assert_eq!(
+            Zpm::new_for_apple().alloc_order(&addrs),
+            vec![0xfb, 0x80, 0x10]
+        );
+        assert_eq!(Zpm::new_for_atari().alloc_order(&addrs), vec![0x80, 0xfb]);
+        assert_eq!(
+            Zpm::new("c64", None).unwrap().alloc_order(&addrs),
+            vec![0xfb]
+        );
+    }
+
+    #[test]
+    fn zpm_reserve_allocated() {
+        let mut zpm = Zpm::new_for_atari();
+        zpm.alloc(4).unwrap();
+        assert!(zpm.reserve(0x83, 0x90).is_err());
+        assert!(zpm.reserve(0x84, 0x90).is_ok());
+    }
+
+    #[test]
     fn zpm_alloc_0_apple() {
-        Zpm::new_for_apple().alloc(0);
+        assert!(Zpm::new_for_apple().alloc(0).is_err());
+        assert!(Zpm::new_for_apple().alloc_aligned(1, 0).is_err());
     }
 
     #[test]
-    #[should_panic(expected = "Request to allocate zero bytes of zero page memory")]
     fn zpm_alloc_0_atari() {
-        Zpm::new_for_atari().alloc(0);
+        assert!(Zpm::new_for_atari().alloc(0).is_err());
     }
 
     #[test]
-    #[should_panic(expected = "Zero page memory exhausted")]
     fn zpm_alloc_too_much_apple() {
         let mut zpm = Zpm::new_for_apple();
-        zpm.alloc(100);
-        zpm.alloc(100);
-        zpm.alloc(57);
+        zpm.alloc(100).unwrap();
+        zpm.alloc(100).unwrap();
+        assert_eq!(zpm.alloc(57), Err(ZP_EXHAUSTED));
     }
 
     #[test]
-    #[should_panic(expected = "Zero page memory exhausted")]
     fn zpm_alloc_too_much_atari() {
         let mut zpm = Zpm::new_for_atari();
-        zpm.alloc(50);
-        zpm.alloc(50);
-        zpm.alloc(29);
+        zpm.alloc(50).unwrap();
+        zpm.alloc(50).unwrap();
+        assert_eq!(zpm.alloc(29), Err(ZP_EXHAUSTED));
     }
 
     #[test]
     fn zpm_alloc_all_available_apple() {
         let mut zpm = Zpm::new_for_apple();
-        let addr1 = zpm.alloc(100);
-        let addr2 = zpm.alloc(100);
-        let addr3 = zpm.alloc(56);
+        let addr1 = zpm.alloc(100).unwrap();
+        let addr2 = zpm.alloc(100).unwrap();
+        let addr3 = zpm.alloc(56).unwrap();
         assert!(addr1 == 0xff - 99 && addr2 == 0xff - 199 && addr3 == 0);
     }
 
     #[test]
     fn zpm_alloc_all_available_atari() {
         let mut zpm = Zpm::new_for_atari();
-        let addr1 = zpm.alloc(50);
-        let addr2 = zpm.alloc(50);
-        let addr3 = zpm.alloc(28);
+        let addr1 = zpm.alloc(50).unwrap();
+        let addr2 = zpm.alloc(50).unwrap();
+        let addr3 = zpm.alloc(28).unwrap();
         assert!(addr1 == 0x80 && addr2 == 0x80 + 50 && addr3 == 0x80 + 100);
     }
 }
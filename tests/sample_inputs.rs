@@ -1,17 +1,24 @@
+#![allow(clippy::needless_return)]
+
 // Create a string of s repeated n times for creating blocks of repeated code.
 fn build_rep_string(s: &str, n: usize) -> String {
-    return std::iter::repeat(s).take(n).collect::<String>();
+    return s.repeat(n);
+}
+
+// Errors print as "line: message", which is what most tests check against
+fn error_strings(errors: Vec<sasm2::SasmError>) -> Vec<String> {
+    return errors.iter().map(|e| e.to_string()).collect();
 }
 
 // Boilerplate for running an integration test
 fn run_string_test(assembly: &str, should_pass: bool, output: &str) {
     let mut c = sasm2::Config::build_string_test(assembly);
-    let result = sasm2::assemble(&mut c);
+    let result = sasm2::assemble(&mut c).map_err(error_strings);
 
     if should_pass {
         assert_eq!(result, Ok(sasm2::Code::String(output.to_string())));
     } else {
-        assert_eq!(result, Err(output.to_string()));
+        assert_eq!(result, Err(vec![output.to_string()]));
     }
 }
 
@@ -48,7 +55,8 @@ fn data_non_hex() {
 
 #[test]
 fn data_with_spaces() {
-    run_string_test("data cafe dad", false, "1: data takes one argument");
+    run_string_test("data cafe dad", false, "1: data must be a valid hex string");
+    run_string_test("data", false, "1: data takes at least one argument");
 }
 
 #[test]
@@ -326,7 +334,11 @@ fn org_one_less_than_code_addr() {
                      beq   .loop_2_start\n\
                      beq   .loop_1_start\n"].join("");
 
-    run_string_test(&assembly, false, "6: org smaller than code address");
+    run_string_test(
+        &assembly,
+        false,
+        "6: org smaller than code address (use org! for a separate block)",
+    );
 }
 
 #[test]
@@ -339,3 +351,1252 @@ fn data_from_one_byte_label() {
     run_string_test("label addr ed\ndata  .addr", false, "2: labels used for data must be two bytes");
 }
 
+
+#[test]
+fn org_gap_with_custom_fill() {
+    let assembly = "org 4000\n\
+                    nop\n\
+                    org 4004\n\
+                    nop\n";
+
+    let mut c = sasm2::Config::build_string_test(assembly);
+    c.fill = 0x00;
+    let result = sasm2::assemble(&mut c);
+    assert_eq!(result, Ok(sasm2::Code::String("ea000000ea".to_string())));
+}
+
+#[test]
+fn all_errors_reported() {
+    let assembly = "org 88\n\
+                    ldai  00\n\
+                    dec\n\
+                    label l 10\n\
+                    label l 20\n\
+                    jmpa  .nowhere\n";
+
+    let mut c = sasm2::Config::build_string_test(assembly);
+    let result = sasm2::assemble(&mut c).map_err(error_strings);
+    assert_eq!(
+        result,
+        Err(vec![
+            "1: org must be a 2-byte address".to_string(),
+            "3: mnemonic not found".to_string(),
+            "5: label l repeated (first defined on line 4)".to_string(),
+            "6: label nowhere not defined".to_string(),
+        ])
+    );
+}
+
+#[test]
+fn error_has_kind_and_location() {
+    let mut c = sasm2::Config::build_string_test("nop\nldai  100\n");
+    let errors = sasm2::assemble(&mut c).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].kind, sasm2::error::ErrorKind::Syntax);
+    assert_eq!(errors[0].line, Some(2));
+    assert_eq!(errors[0].source_line, Some("ldai  100".to_string()));
+}
+
+#[test]
+fn branch_page_crossing_warning() {
+    let assembly = ["org 40fc\n\
+                     .loop_start\n\
+                     inx\n\
+                     bne   .loop_start\n\
+                     bne   .loop_start\n"].join("");
+
+    let mut c = sasm2::Config::build_string_test(&assembly);
+    c.warnings.apply("branch-page").unwrap();
+    let result = sasm2::assemble(&mut c);
+    assert_eq!(result, Ok(sasm2::Code::String("e8d0fdd0fb".to_string())));
+    let warnings: Vec<String> = c.warnings.list.iter().map(|w| w.to_string()).collect();
+    assert_eq!(warnings, vec!["5: warning: branch to 40fc crosses a page boundary"]);
+}
+
+#[test]
+fn warnings_as_errors() {
+    let mut c = sasm2::Config::build_string_test(".start\nbne   .start 1\n");
+    c.warnings.apply("error").unwrap();
+    let result = sasm2::assemble(&mut c).map_err(error_strings);
+    assert_eq!(result, Err(vec!["2: offset added to relative branch target".to_string()]));
+}
+
+#[test]
+fn illegal_opcode_not_enabled() {
+    run_string_test("laxz  10", false, "1: undocumented instruction (enable with -u)");
+}
+
+#[test]
+fn illegal_opcode_enabled() {
+    let mut c = sasm2::Config::build_string_test("laxz  10\ndcpay 4000\nsbxi  05\n");
+    c.illegal = true;
+    let result = sasm2::assemble(&mut c);
+    assert_eq!(result, Ok(sasm2::Code::String("a710db0040cb05".to_string())));
+}
+
+#[test]
+fn json_format() {
+    let args: Vec<String> = ["sasm", "-f", "json"].iter().map(|s| s.to_string()).collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String("org 4000\n.start\njmpa  .start\n".to_string());
+    c.otype = sasm2::config::OType::None;
+
+    let json = "{\n  \"hex\": \"4c0040\",\n  \
+                \"blocks\": [\n    {\"address\": 16384, \"hex\": \"4c0040\"}\n  ],\n  \
+                \"symbols\": [\n    {\"name\": \"start\", \"value\": 16384}\n  ],\n  \
+                \"lines\": [\n    {\"line\": 3, \"address\": 16384, \"size\": 3}\n  ]\n}";
+    assert_eq!(sasm2::assemble(&mut c), Ok(sasm2::Code::String(json.to_string())));
+}
+
+#[test]
+fn checksum_sum_embedded() {
+    run_string_test("org 4000\nldai  01\nchecksum sum\n", true, "a901aa");
+}
+
+#[test]
+fn checksum_crc16_includes_filler() {
+    // CRC-16/CCITT-FALSE of 31 32 ff 00 00 is 0xb951
+    run_string_test(
+        "org 4000\ndata 3132\norg 4003\nchecksum crc16\n",
+        true,
+        "3132ff51b9",
+    );
+}
+
+#[test]
+fn checksum_repeated() {
+    run_string_test(
+        "checksum sum\nchecksum crc32\n",
+        false,
+        "2: only one checksum directive is allowed",
+    );
+}
+
+#[test]
+fn defines_from_command_line() {
+    let args: Vec<String> = ["sasm", "-D", "color=1e", "--define=wsync=02"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String("org 4000\nldai .color\nstaz .wsync\n".to_string());
+    assert_eq!(sasm2::assemble(&mut c), Ok(sasm2::Code::String("a91e8502".to_string())));
+}
+
+#[test]
+fn error_span_points_at_operand() {
+    let mut c = sasm2::Config::build_string_test("org 4000\n  jmpa .nowhere ; far away\n");
+    let errors = sasm2::assemble(&mut c).unwrap_err();
+    assert_eq!(errors[0].span(), Some((8, 8)));
+    assert_eq!(
+        errors[0].render(false),
+        "2: label nowhere not defined\n      jmpa .nowhere ; far away\n           ^^^^^^^^"
+    );
+}
+
+#[test]
+fn listing_with_cycles() {
+    let args: Vec<String> = ["sasm", "-f", "listing", "--cycles"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String("org 4000\n.loop\nldaax 0600\nbne   .loop\n".to_string());
+    c.otype = sasm2::config::OType::None;
+
+    let listing = [
+        "                    org 4000",
+        "                    .loop",
+        "4000  bd 00 06  4+  ldaax 0600",
+        "4003  d0 fb     2+  bne   .loop",
+    ];
+    let result = sasm2::assemble(&mut c);
+    assert_eq!(result, Ok(sasm2::Code::String(listing.join("\n") + "\n")));
+
+    // Other comment markers end the mnemonic too
+    let args: Vec<String> = ["sasm", "-f", "listing", "--cycles", "--comments", "//"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String("org 4000\nrts// return\n".to_string());
+    c.otype = sasm2::config::OType::None;
+    let listing = "                    org 4000\n4000  60        6   rts// return\n";
+    assert_eq!(sasm2::assemble(&mut c), Ok(sasm2::Code::String(listing.to_string())));
+}
+
+#[test]
+fn zpreserve_skips_range() {
+    run_string_test("zpreserve f8 ff\nzbyte a 2\nldaz .a\n", true, "a5f6");
+}
+
+#[test]
+fn zpreserve_after_zbyte() {
+    run_string_test(
+        "zbyte a\nzpreserve f0 ff\n",
+        false,
+        "2: zero-page bytes in this range are already allocated",
+    );
+}
+
+#[test]
+fn zbyte_local_freed_at_code_marker() {
+    run_string_test("zbyte a local\n.sub\nzbyte b\nldaz .b\n", true, "a5ff");
+}
+
+#[test]
+fn zfree_reuses_bytes() {
+    run_string_test("zbyte a\nzfree a\nzbyte b\nldaz .b\n", true, "a5ff");
+}
+
+#[test]
+fn zfree_twice() {
+    run_string_test(
+        "zbyte a\nzfree a\nzfree a\n",
+        false,
+        "3: not an allocated zbyte",
+    );
+}
+
+#[test]
+fn zbyte_aligned() {
+    run_string_test("zbyte a\nzbyte b 2 align 2 local\nldaz .b\n", true, "a5fc");
+}
+
+#[test]
+fn zbyte_align_zero() {
+    run_string_test(
+        "zbyte a 2 align 0\n",
+        false,
+        "1: zbyte alignment must be from 1 to ff",
+    );
+    run_string_test(
+        "zbyte a 0 0\n",
+        false,
+        "1: expected zbyte NAME, zbyte NAME SIZE, or either with align N or local",
+    );
+}
+
+#[test]
+fn zword_pointer_pair() {
+    run_string_test("zword p\nldany .p\nstaz .p+1\n", true, "b1fe85ff");
+}
+
+#[test]
+fn zword_repeated() {
+    run_string_test("zbyte p\nzword p\n", false, "2: label p repeated (first defined on line 1)");
+}
+
+#[test]
+fn zword_bad_arguments() {
+    run_string_test(
+        "zword p 2\n",
+        false,
+        "1: expected zword NAME or zword NAME local",
+    );
+}
+
+#[test]
+fn ines_format() {
+    let args: Vec<String> = ["sasm", "-s", "nes", "-f", "ines", "-F", "00"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String("org c000\n.reset\njmpa .reset\n".to_string());
+    c.otype = sasm2::config::OType::None;
+
+    let mut ines = vec![0x4e, 0x45, 0x53, 0x1a, 0x01];
+    ines.resize(16, 0);
+    ines.extend([0x4c, 0x00, 0xc0]);
+    ines.resize(16 + 0x4000, 0);
+    assert_eq!(sasm2::assemble(&mut c), Ok(sasm2::Code::Bytes(ines)));
+}
+
+#[test]
+fn ines_format_below_8000() {
+    let args: Vec<String> = ["sasm", "-s", "nes", "-f", "ines"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String("org 0300\nrts\n".to_string());
+    c.otype = sasm2::config::OType::None;
+    assert_eq!(
+        sasm2::assemble(&mut c).map_err(error_strings),
+        Err(vec!["iNES output requires code from 8000 to ffff".to_string()])
+    );
+}
+
+#[test]
+fn prg_format() {
+    let args: Vec<String> = ["sasm", "-s", "c64", "-f", "prg"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String("org c000\nzbyte p\nldaz .p\nrts\n".to_string());
+    c.otype = sasm2::config::OType::None;
+    assert_eq!(
+        sasm2::assemble(&mut c),
+        Ok(sasm2::Code::Bytes(vec![0x00, 0xc0, 0xa5, 0xfe, 0x60]))
+    );
+}
+
+#[test]
+fn xex_format() {
+    let args: Vec<String> = ["sasm", "-s", "atari8", "-f", "xex"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String("org 2000\nzbyte p\nldaz .p\nrts\n".to_string());
+    c.otype = sasm2::config::OType::None;
+    assert_eq!(
+        sasm2::assemble(&mut c),
+        Ok(sasm2::Code::Bytes(vec![
+            0xff, 0xff, 0x00, 0x20, 0x02, 0x20, 0xa5, 0xcb, 0x60, 0xe0, 0x02, 0xe1, 0x02, 0x00,
+            0x20
+        ]))
+    );
+}
+
+#[test]
+fn xex_format_requires_atari8() {
+    let args: Vec<String> = ["sasm", "-s", "c64", "-f", "xex"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    assert_eq!(
+        sasm2::Config::build(&args).err(),
+        Some("XEX output requires the Atari 8-bit system".to_string())
+    );
+}
+
+#[test]
+fn var_allocates_ram() {
+    run_string_test("var buf 10\nvar count\nstaa .count\n", true, "8d1003");
+}
+
+#[test]
+fn var_ram_exhausted() {
+    run_string_test("var buf d1\n", false, "1: RAM for variables exhausted");
+}
+
+#[test]
+fn segments_grouped() {
+    let assembly = "org 4000\nldaa .table\nstaa .buf\n\
+                    segment data\n.table\ndata 0102\n\
+                    segment bss\n.buf\ndata 00000000\n\
+                    segment code\nrts\n";
+    run_string_test(assembly, true, "ad07408d0940600102");
+}
+
+#[test]
+fn segments_overlap() {
+    run_string_test(
+        "org 4000\nnop\nsegment data\norg 4000\ndata 01\n",
+        false,
+        "segments overlap at 4000",
+    );
+}
+
+#[test]
+fn bss_without_instructions() {
+    run_string_test(
+        "segment bss\nnop\n",
+        false,
+        "2: bss segment cannot contain instructions",
+    );
+}
+
+#[test]
+fn emit_obj_with_external_labels() {
+    let assembly = "org c000\n.start\njsra .print\nbne .start\nldaz .ptr 1\n";
+    let mut c = sasm2::Config::build_string_test(assembly);
+    c.emit_obj = true;
+    let object = "; SASM2 object\nblock c000 200000d0fba501\nexport start c000\n\
+                  ref c001 abs print\nref c006 zp ptr\n";
+    assert_eq!(sasm2::assemble(&mut c), Ok(sasm2::Code::String(object.to_string())));
+}
+
+#[test]
+fn link_objects() {
+    let dir = std::env::temp_dir();
+    let main = dir.join("sasm2_link_main.o");
+    let lib = dir.join("sasm2_link_lib.o");
+    std::fs::write(&main, "block c000 20000060\nref c001 abs print\n").unwrap();
+    std::fs::write(&lib, "block c004 60\nexport print c004\n").unwrap();
+
+    let mut c = sasm2::Config::build_string_test("");
+    c.itype = sasm2::config::IType::Files(vec![
+        main.to_string_lossy().to_string(),
+        lib.to_string_lossy().to_string(),
+    ]);
+    c.link = true;
+    let result = sasm2::assemble(&mut c);
+    std::fs::remove_file(main).unwrap();
+    std::fs::remove_file(lib).unwrap();
+    assert_eq!(result, Ok(sasm2::Code::String("2004c06060".to_string())));
+}
+
+#[test]
+fn reloc_format() {
+    let args: Vec<String> = ["sasm", "-f", "reloc"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    let assembly = "org 4000\n.start\njsra .sub\nbne .start\njsra fded\n.sub\nrts\ndata .start\n";
+    c.itype = sasm2::config::IType::String(assembly.to_string());
+    c.otype = sasm2::config::OType::None;
+    assert_eq!(
+        sasm2::assemble(&mut c),
+        Ok(sasm2::Code::Bytes(vec![
+            0x00, 0x40, 0x0b, 0x00, 0x20, 0x08, 0x40, 0xd0, 0xfb, 0x20, 0xed, 0xfd, 0x60, 0x00,
+            0x40, 0x02, 0x00, 0x01, 0x00, 0x09, 0x00
+        ]))
+    );
+}
+
+#[test]
+fn assemble_str_in_memory() {
+    let options = sasm2::AsmOptions {
+        system: "atari".to_string(),
+        ..Default::default()
+    };
+    let a = sasm2::assemble_str("org f000\n.start\nzbyte p\nstaz .p\norg f004\nrts\n", &options)
+        .unwrap();
+    assert_eq!(a.bytes, vec![0x85, 0x80, 0xff, 0xff, 0x60]);
+    assert_eq!(a.blocks, vec![(0xf000, vec![0x85, 0x80]), (0xf004, vec![0x60])]);
+    assert_eq!(a.symbols.get("start"), Some(&0xf000));
+    assert_eq!(a.symbols.get("p"), Some(&0x80));
+    assert_eq!(a.block(0xf004), Some(&[0x60][..]));
+    assert_eq!(a.symbol("start"), Some(0xf000));
+
+    let e = sasm2::assemble_str("bogus\n", &options).unwrap_err();
+    assert_eq!(e.to_string(), "1: mnemonic not found");
+}
+
+// Writer that keeps what is written where the test can see it
+struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn reader_and_writer() {
+    let buffer = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let mut c = sasm2::Config::build_string_test("");
+    c.itype = sasm2::config::IType::Reader(Box::new(std::io::Cursor::new("ldai 01\nrts\n")));
+    c.otype = sasm2::config::OType::Writer(Box::new(SharedBuffer(buffer.clone())));
+    assert_eq!(sasm2::assemble(&mut c), Ok(sasm2::Code::String("a90160".to_string())));
+    assert_eq!(*buffer.borrow(), b"a90160\n".to_vec());
+}
+
+#[test]
+fn assembler_reused() {
+    let mut assembler = sasm2::Assembler::new(&sasm2::AsmOptions::default()).unwrap();
+    assembler.define("screen", "0400").unwrap();
+    for _ in 0..2 {
+        let a = assembler.assemble("zbyte p\nstaz .p\nstaa .screen\n").unwrap();
+        assert_eq!(a.bytes, vec![0x85, 0xff, 0x8d, 0x00, 0x04]);
+    }
+    assert!(assembler.assemble("staa .missing\n").is_err());
+}
+
+#[test]
+fn diagnostic_callback() {
+    let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let mut assembler = sasm2::Assembler::new(&sasm2::AsmOptions::default()).unwrap();
+    let s = seen.clone();
+    assembler.on_diagnostic(move |d| {
+        s.borrow_mut().push(match d {
+            sasm2::Diagnostic::Error(e) => format!("error {e}"),
+            sasm2::Diagnostic::Warning(w) => format!("warning {w}"),
+        })
+    });
+    let source = "org 4000\n.l\nnop\nbne .l 1\nbogus\nstaa .missing\n";
+    assert!(assembler.assemble(source).is_err());
+    assert_eq!(
+        *seen.borrow(),
+        vec![
+            "error 5: mnemonic not found",
+            "warning 4: warning: offset added to relative branch target",
+            "error 6: label missing not defined"
+        ]
+    );
+}
+
+#[test]
+fn streamed_output() {
+    let source = "org 4000\nnop\norg 4004\nrts\n";
+    for format in ["hex", "binary", "listing", "apple"] {
+        let args: Vec<String> = ["sasm", "-f", format].iter().map(|s| s.to_string()).collect();
+        let mut c = sasm2::Config::build(&args).unwrap();
+        c.itype = sasm2::config::IType::String(source.to_string());
+        c.otype = sasm2::config::OType::None;
+        let mut expected = Vec::from(sasm2::assemble(&mut c).unwrap());
+        if format != "binary" {
+            expected.push(b'\n');
+        }
+
+        let mut c = sasm2::Config::build(&args).unwrap();
+        c.itype = sasm2::config::IType::String(source.to_string());
+        let mut out = Vec::new();
+        assert_eq!(sasm2::assemble_to(&mut c, &mut out), Ok(()));
+        assert_eq!(out, expected);
+    }
+}
+
+#[test]
+fn code_past_ffff() {
+    run_string_test(
+        "org ffff\ndata 0102",
+        false,
+        "segment at ffff extends past ffff",
+    );
+}
+
+#[test]
+fn floating_segment_past_ffff() {
+    run_string_test(
+        "segment data\ndata 0102\n.tbl\ndata 03\nsegment code\norg fff0\nldaa .tbl\n\
+         data 0000000000000000000000\n",
+        false,
+        "segment at fffe extends past ffff",
+    );
+    run_string_test(
+        "segment data\ndata 01\n.end\nsegment code\norg fffe\nnop\n",
+        false,
+        "label end at 10000 is past ffff",
+    );
+}
+
+#[test]
+fn zbyte_zero_page_exhausted() {
+    run_string_test(
+        "zbyte buf ff\nzword ptr\n",
+        false,
+        "2: zero page exhausted at line 2 requesting 2 bytes",
+    );
+    run_string_test("zbyte buf 00\n", false, "1: zbyte array size must not be zero");
+}
+
+#[test]
+fn unreadable_input_files() {
+    let missing = "tests/no_such_file.asm".to_string();
+    let mut c = sasm2::Config::build_string_test("");
+    c.itype = sasm2::config::IType::File(missing.clone());
+    let errors = sasm2::assemble(&mut c).unwrap_err();
+    assert!(errors[0]
+        .message
+        .starts_with("Unable to read input file tests/no_such_file.asm: "));
+
+    let mut c = sasm2::Config::build_string_test("");
+    c.itype = sasm2::config::IType::File(missing);
+    let e = sasm2::disassemble(&mut c).unwrap_err();
+    assert!(e
+        .message
+        .starts_with("Unable to read input file tests/no_such_file.asm: "));
+
+    // A directory exists but cannot be read as a file
+    let mut c = sasm2::Config::build_string_test("");
+    c.itype = sasm2::config::IType::File("tests".to_string());
+    let e = sasm2::disassemble(&mut c).unwrap_err();
+    assert!(e.message.starts_with("Unable to read input file tests: "));
+}
+
+#[test]
+fn cycles_between_labels() {
+    let source = "org 4000\n.start\nldai 00\n.loop\nstaax 0600\ninx\nbne .loop\nrts\ndata 0102\n";
+    let assembled = sasm2::assemble_str(source, &sasm2::AsmOptions::default()).unwrap();
+    let report: Vec<String> = assembled.stretches.iter().map(|s| s.to_string()).collect();
+    assert_eq!(
+        report,
+        vec![
+            ".start           4000     2 bytes      2 cycles",
+            ".loop            4002     9 bytes    15+ cycles"
+        ]
+    );
+}
+
+#[test]
+fn low_and_high_bytes() {
+    run_string_test(
+        "org 4000\nldai <.msg\nldxi >.msg\nldyi >1234\nldai <.ptr\n.msg\ndata 00\nzbyte ptr\n",
+        true,
+        "a908a240a012a9ff00",
+    );
+    run_string_test("ldai <.missing", false, "1: label missing not defined");
+    run_string_test("ldaa >.msg\n.msg", false, "1: instruction requires a two-byte operand");
+}
+
+#[test]
+fn word_and_dword_data() {
+    run_string_test(
+        "org 4000\n.table\nword .table\nwordbe .table\nword 12\ndword 12345678\ndword .table\n",
+        true,
+        "0040400012007856341200400000",
+    );
+    run_string_test("word 12345", false, "1: word must be a label or up to 4 hex digits");
+    run_string_test("dword", false, "1: dword takes at least one argument");
+}
+
+#[test]
+fn data_with_several_items() {
+    run_string_test(
+        "org 4000\n.ptr\ndata 01 02,.ptr , ff00\nword .ptr,1234 .ptr\n",
+        true,
+        "01020040ff00004034120040",
+    );
+    run_string_test("data 01 .ptr", false, "1: label ptr not defined");
+}
+
+#[test]
+fn data_with_strings() {
+    run_string_test(
+        "data \"HI; A,B\" 0d,\"!\",00 ; comment\n",
+        true,
+        "48493b20412c420d2100",
+    );
+    run_string_test("data \"HI 00", false, "1: data string is missing its closing quote");
+    run_string_test("word \"HI\"", false, "1: word must be a label or up to 4 hex digits");
+}
+
+#[test]
+fn backwards_org() {
+    run_string_test(
+        "org 4004\ndata 0102\norg! 4000\n.b\ndata 03\njmpa .b\n",
+        true,
+        "034c00400102",
+    );
+    run_string_test(
+        "org 4000\ndata 0102\norg! 4001\ndata 03\n",
+        false,
+        "segments overlap at 4001",
+    );
+}
+
+#[test]
+fn banks() {
+    run_string_test(
+        "bank 0 origin f000 size 8\n.start\nldai 01\nbank 1 origin f000 size 8\nldai 02\norg f005\njmpa .start\n",
+        true,
+        "a901ffffffffffffa902ffffff4c00f0",
+    );
+    run_string_test(
+        "bank 0 origin f000 size 4\nldai 01\nldai 02\nldai 03\n",
+        false,
+        "code at f000-f005 is outside bank 0 (f000-f003)",
+    );
+    run_string_test(
+        "bank 1 origin f000 size 4\nldai 01\n",
+        false,
+        "banks must be numbered from 0 without gaps",
+    );
+    run_string_test("bank 0 origin f000\n", false, "1: expected bank N origin ADDR size SIZE");
+}
+
+#[test]
+fn output_file_per_org_block() {
+    let dir = std::env::temp_dir();
+    let pattern = dir.join("sasm2_block_%04x.hex");
+    let mut c = sasm2::Config::build_string_test("org 4000\ndata 0102\norg 5000\ndata 03\n");
+    c.otype = sasm2::config::OType::File(pattern.to_string_lossy().to_string());
+    let result = sasm2::assemble(&mut c);
+    let first = std::fs::read_to_string(dir.join("sasm2_block_4000.hex"));
+    let second = std::fs::read_to_string(dir.join("sasm2_block_5000.hex"));
+    std::fs::remove_file(dir.join("sasm2_block_4000.hex")).unwrap();
+    std::fs::remove_file(dir.join("sasm2_block_5000.hex")).unwrap();
+    assert!(result.is_ok());
+    assert_eq!(first.unwrap(), "0102");
+    assert_eq!(second.unwrap(), "03");
+}
+
+#[test]
+fn apple_run_command() {
+    let run = |flags: &[&str], assembly: &str| {
+        let args: Vec<String> = ["sasm", "-f", "apple"]
+            .iter()
+            .chain(flags)
+            .map(|s| s.to_string())
+            .collect();
+        let mut c = sasm2::Config::build(&args).unwrap();
+        c.itype = sasm2::config::IType::String(assembly.to_string());
+        c.otype = sasm2::config::OType::None;
+        sasm2::assemble(&mut c)
+    };
+    let code = |s: &str| Ok(sasm2::Code::String(s.to_string()));
+
+    let assembly = "org 0a00\nnop\n.start\nrts\nend .start\n";
+    assert_eq!(run(&[], assembly), code("0a00:ea 60\n0a01G\n"));
+    assert_eq!(run(&["-e", "a00"], assembly), code("0a00:ea 60\n0a00G\n"));
+    assert_eq!(run(&[], "org 0a00\nnop\n"), code("0a00:ea\n"));
+}
+
+#[test]
+fn custom_output_format() {
+    // Each block as its address and length
+    struct Summary;
+    impl sasm2::OutputFormat for Summary {
+        fn output(&self, blocks: &[(u16, Vec<u8>)], _fill: u8) -> sasm2::Code {
+            let lines: Vec<String> = blocks
+                .iter()
+                .map(|(addr, bytes)| format!("{addr:04x}:{}", bytes.len()))
+                .collect();
+            sasm2::Code::String(lines.join(","))
+        }
+    }
+
+    let mut c = sasm2::Config::build_string_test("org 4000\nnop\nnop\norg 5000\nrts\n");
+    c.custom_format = Some(Box::new(Summary));
+    assert_eq!(
+        sasm2::assemble(&mut c),
+        Ok(sasm2::Code::String("4000:2,5000:1".to_string()))
+    );
+}
+
+#[test]
+fn custom_disassembly_writer() {
+    use sasm2::disassemble::{Item, Operand};
+
+    // Each instruction as its mnemonic, with the address of any label it refers to
+    struct Links;
+    impl sasm2::DisassemblyWriter for Links {
+        fn write(&self, items: &[Item]) -> sasm2::Code {
+            let lines: Vec<String> = items
+                .iter()
+                .map(|item| match item {
+                    Item::Org(a) => format!("org {a:04x}"),
+                    Item::Label(l, a) => format!("{l}@{a:04x}"),
+                    Item::Instr {
+                        mnemonic,
+                        operand: Operand::Label(l, a),
+                        ..
+                    } => format!("{mnemonic}->{l}@{a:04x}"),
+                    Item::Instr { mnemonic, .. } => mnemonic.to_string(),
+                    Item::Data { bytes, .. } => format!("{} bytes", bytes.len()),
+                    _ => "other".to_string(),
+                })
+                .collect();
+            sasm2::Code::String(lines.join(","))
+        }
+    }
+
+    // 4000: lda #00; bne 4000; rts; data 01 02
+    let mut c = sasm2::Config::build_string_test("a900d0fc600102");
+    c.addr = 0x4000;
+    c.entries = vec![0x4000];
+    c.custom_writer = Some(Box::new(Links));
+    assert_eq!(
+        sasm2::disassemble(&mut c),
+        Ok(sasm2::Code::String(
+            "org 4000,4000@4000,ldai,bne->4000@4000,rts,2 bytes".to_string()
+        ))
+    );
+}
+
+#[test]
+fn repeated_label_names_first_definition() {
+    run_string_test(
+        "org 4000\n.loop\nnop\n.loop\n",
+        false,
+        "4: label loop repeated (first defined on line 2)",
+    );
+
+    let args: Vec<String> = ["sasm", "-D", "screen=0400"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String("label screen 0400\n".to_string());
+    c.otype = sasm2::config::OType::None;
+    let errors = sasm2::assemble(&mut c).unwrap_err();
+    assert_eq!(
+        errors[0].message,
+        "label screen repeated (first defined in the configuration)"
+    );
+
+    let dir = std::env::temp_dir();
+    let main = dir.join("sasm2_repeated_main.asm");
+    let lib = dir.join("sasm2_repeated_lib.asm");
+    std::fs::write(&main, "org 4000\n.print\nrts\n").unwrap();
+    std::fs::write(&lib, ".print\nrts\n").unwrap();
+    let mut c = sasm2::Config::build_string_test("");
+    c.itype = sasm2::config::IType::Files(vec![
+        main.to_string_lossy().to_string(),
+        lib.to_string_lossy().to_string(),
+    ]);
+    let errors = sasm2::assemble(&mut c).unwrap_err();
+    std::fs::remove_file(&main).unwrap();
+    std::fs::remove_file(lib).unwrap();
+    assert_eq!(
+        errors[0].message,
+        format!(
+            "label print repeated (first defined at {}:2)",
+            main.to_string_lossy()
+        )
+    );
+}
+
+#[test]
+fn error_in_second_input_file() {
+    let dir = std::env::temp_dir();
+    let main = dir.join("sasm2_location_main.asm");
+    let lib = dir.join("sasm2_location_lib.asm");
+    std::fs::write(&main, "org 4000\njsra .print\nrts\n").unwrap();
+    std::fs::write(&lib, ".print\nldxi 00\nldai zz\nrts\n").unwrap();
+    let files = format!("{},{}", main.to_string_lossy(), lib.to_string_lossy());
+    let args: Vec<String> = ["sasm", "-i", &files].iter().map(|s| s.to_string()).collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.otype = sasm2::config::OType::None;
+    let errors = sasm2::assemble(&mut c).unwrap_err();
+    std::fs::remove_file(main).unwrap();
+    std::fs::remove_file(&lib).unwrap();
+    assert_eq!(errors[0].file, Some(lib.to_string_lossy().to_string()));
+    assert_eq!(errors[0].line, Some(3));
+    assert_eq!(
+        errors[0].to_string(),
+        format!("{}:3: not a valid hexadecimal number", lib.to_string_lossy())
+    );
+}
+
+#[test]
+fn unused_label_warnings() {
+    let assembly = "org 4000\nzbyte p\nzword q\nzbyte r\nvar buf 10\nlabel k 10\n\
+                    .start\nldaz .q+1\nstaz .r\njmpa .start\n.unused\nrts\n";
+    let args: Vec<String> = ["sasm", "-Wunused-labels"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String(assembly.to_string());
+    c.otype = sasm2::config::OType::None;
+    assert!(sasm2::assemble(&mut c).is_ok());
+    let warnings: Vec<String> = c.warnings.list.iter().map(|w| w.to_string()).collect();
+    assert_eq!(
+        warnings,
+        vec![
+            "2: warning: zbyte p is never used",
+            "5: warning: var buf is never used",
+            "6: warning: label k is never used",
+            "11: warning: label unused is never used",
+        ]
+    );
+
+    // Off by default
+    let mut c = sasm2::Config::build_string_test(assembly);
+    assert!(sasm2::assemble(&mut c).is_ok());
+    assert!(c.warnings.list.is_empty());
+}
+
+#[test]
+fn zero_page_optimization() {
+    let assembly = "label io 00f0\nzbyte p\norg 4000\nldaa 0012\nstaax .io 2\nldaa .p\n\
+                    ldaay 0012\njmpa .start\n.start\nldaa .later\nrts\nlabel later 0020\n";
+    let args: Vec<String> = ["sasm", "--zp-optimize"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String(assembly.to_string());
+    c.otype = sasm2::config::OType::None;
+    assert_eq!(
+        sasm2::assemble(&mut c),
+        Ok(sasm2::Code::String(
+            "a51295f2a5ffb912004c0c40ad200060".to_string()
+        ))
+    );
+
+    // Without the flag, instructions keep the form they are written in
+    run_string_test(
+        "org 4000\nldaa 0012\nstaax 00f0 2\n",
+        true,
+        "ad12009df200",
+    );
+}
+
+#[test]
+fn long_branches() {
+    let assembly = ["org 4000\n.start\nbeq .far\nbne .start\n",
+                    &build_rep_string("nop\n", 200),
+                    ".far\nbcc .start\nrts\n"].join("");
+    let mut c = sasm2::Config::build_string_test(&assembly);
+    let errors = sasm2::assemble(&mut c).map_err(error_strings).unwrap_err();
+    assert_eq!(errors[0], "3: relative branch is too far from target");
+
+    let mut c = sasm2::Config::build_string_test(&assembly);
+    c.long_branches = true;
+    let expected = ["d0034ccf40d0f9", &build_rep_string("ea", 200), "b0034c004060"].join("");
+    assert_eq!(sasm2::assemble(&mut c), Ok(sasm2::Code::String(expected)));
+
+    // Only the diagnostics of the last attempt reach the callback
+    let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let s = seen.clone();
+    let mut c = sasm2::Config::build_string_test(&(assembly + "bogus\n"));
+    c.long_branches = true;
+    c.on_diagnostic = Some(Box::new(move |d| s.borrow_mut().push(format!("{d:?}"))));
+    assert!(sasm2::assemble(&mut c).is_err());
+    assert_eq!(seen.borrow().len(), 1);
+}
+
+// The forms chosen in the first pass (zero page and long branches) must be the ones the second
+// pass assembles, at the same addresses. Each part is the source of zero_page_optimization and
+// long_branches, the second at its own org.
+#[test]
+fn zero_page_and_long_branches_together() {
+    let assembly = ["label io 00f0\nzbyte p\norg 4000\nldaa 0012\nstaax .io 2\nldaa .p\n\
+                     ldaay 0012\njmpa .start\n.start\nldaa .later\nrts\nlabel later 0020\n",
+                    "org 5000\n.s2\nbeq .far\nbne .s2\n",
+                    &build_rep_string("nop\n", 200),
+                    ".far\nbcc .s2\nrts\n"].join("");
+    let mut c = sasm2::Config::build_string_test(&assembly);
+    c.zp_optimize = true;
+    c.long_branches = true;
+    let expected = ["a51295f2a5ffb912004c0c40ad200060",
+                    &build_rep_string("ff", 0x1000 - 0x10),
+                    "d0034ccf50d0f9",
+                    &build_rep_string("ea", 200),
+                    "b0034c005060"].join("");
+    assert_eq!(sasm2::assemble(&mut c), Ok(sasm2::Code::String(expected)));
+
+    // Addresses and sizes of the lines whose forms changed
+    let args: Vec<String> = ["sasm", "-f", "listing", "--zp-optimize", "--long-branches"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String(assembly);
+    c.otype = sasm2::config::OType::None;
+    let listing = match sasm2::assemble(&mut c) {
+        Ok(sasm2::Code::String(s)) => s,
+        other => panic!("unexpected result {other:?}"),
+    };
+    let lines: Vec<&str> = listing.lines().filter(|l| !l.starts_with(' ')).collect();
+    assert_eq!(
+        lines[..6],
+        [
+            "4000  a5 12     ldaa 0012",
+            "4002  95 f2     staax .io 2",
+            "4004  a5 ff     ldaa .p",
+            "4006  b9 12 00  ldaay 0012",
+            "4009  4c 0c 40  jmpa .start",
+            "400c  ad 20 00  ldaa .later",
+        ]
+    );
+    assert_eq!(lines[7..9], ["5000  d0 03 4c  beq .far", "5005  d0 f9     bne .s2"]);
+    assert_eq!(lines[lines.len() - 2..], ["50cf  b0 03 4c  bcc .s2", "50d4  60        rts"]);
+}
+
+#[test]
+fn indirect_jump() {
+    run_string_test(
+        "org 4000\njmpn 0300\njmpn .vector\n.vector\ndata .vector\n",
+        true,
+        "6c00036c06400640",
+    );
+    run_string_test("jmpn 03\n", false, "1: instruction requires a two-byte operand");
+
+    // The disassembler reads the two-byte pointer address
+    let mut c = sasm2::Config::build_string_test("6c0003");
+    c.addr = 0x4000;
+    c.entries = vec![0x4000];
+    assert_eq!(
+        sasm2::disassemble(&mut c),
+        Ok(sasm2::Code::String("org   4000\njmpn  0300\n".to_string()))
+    );
+}
+
+#[test]
+fn data_label_offsets() {
+    run_string_test(
+        "org 4000\n.table\ndata .table+4 .handler-1\nword .handler-10\nwordbe .table+1\n\
+         .handler\nrts\n",
+        true,
+        "04400740f83f400160",
+    );
+    run_string_test(
+        "org 4000\ndata .missing+2\n",
+        false,
+        "2: label missing not defined",
+    );
+}
+
+#[test]
+fn negative_offsets() {
+    run_string_test(
+        "org 4000\nlabel end 4100\nzbyte p\nldaa .end -1\nldaz .p -5\nldaa 0002 -5\n",
+        true,
+        "adff40a5faadfdff",
+    );
+    run_string_test("ldaz 02 -5\n", false, "1: operand minus offset is < 0");
+    run_string_test("ldaz 02 -100\n", false, "1: offset must be a single byte (< 0x100)");
+}
+
+#[test]
+fn comment_markers() {
+    let assembly = "org 4000 // start\n# setup\nldai 01 # one\n\
+                    data \"a#b//c\" // text\nrts ; return\n";
+    let args: Vec<String> = ["sasm", "--comments", "//,#"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String(assembly.to_string());
+    c.otype = sasm2::config::OType::None;
+    assert_eq!(
+        sasm2::assemble(&mut c),
+        Ok(sasm2::Code::String("a9016123622f2f6360".to_string()))
+    );
+
+    // Without the flag, only ';' starts a comment
+    run_string_test("org 4000\nrts # return\n", false, "2: not a valid hexadecimal number");
+
+    let args: Vec<String> = ["sasm", "--comments", "/ /"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    assert_eq!(
+        sasm2::Config::build(&args).err(),
+        Some("Invalid comment marker: / /".to_string())
+    );
+}
+
+#[test]
+fn run_on_emulator() {
+    let assembly = "org 0600\nzbyte count\nldai 03\nstaz .count\nldyi 00\n.loop\n\
+                    ldaay .text\nstaa f001\niny\ndecz .count\nbne .loop\nbrk\n\
+                    .text\ndata \"abc\"\n";
+    let args: Vec<String> = ["sasm", "run", "--max-cycles", "1000", "--dump", "00fe-00ff"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    assert!(c.run);
+    assert_eq!(c.dump, Some((0x00fe, 0x00ff)));
+    c.itype = sasm2::config::IType::String(assembly.to_string());
+    let (cpu, stop) = sasm2::run(&mut c).unwrap();
+    assert_eq!(stop, sasm2::emu::Stop::Brk(0x0611));
+    assert_eq!(cpu.output, b"abc");
+    assert_eq!(cpu.y, 3);
+    assert_eq!(cpu.cycles, 2 + 3 + 2 + 3 * (4 + 4 + 2 + 5) + 2 * 3 + 2);
+
+    // A program that never stops is stopped at the cycle limit
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String("org 0600\n.a\nnop\njmpa .a\n".to_string());
+    assert_eq!(sasm2::run(&mut c).unwrap().1, sasm2::emu::Stop::CycleLimit);
+}
+
+#[test]
+fn tests_on_emulator() {
+    let assembly = "org 0600\nzbyte result\ntest add_five\nldai 03\njsra .add_five\nbrk\n\
+                    assert a == 08\nassert mem .result == 08\nassert x != 00\n\
+                    test spin\n.spin\njmpa .next\n.next\njmpa .spin\n\
+                    .add_five\nclc\nadci 05\nstaz .result\nrts\n";
+    let args: Vec<String> = ["sasm", "test", "--max-cycles", "100"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    assert!(c.test);
+    c.itype = sasm2::config::IType::String(assembly.to_string());
+    let results = sasm2::test(&mut c).unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].name, "add_five");
+    assert_eq!(results[0].stop, sasm2::emu::Stop::Brk(0x0605));
+    assert_eq!(error_strings(results[0].failures.clone()), ["9: assert failed: x is 00"]);
+    assert_eq!(
+        error_strings(results[1].failures.clone()),
+        ["10: test did not stop: cycle limit reached"]
+    );
+
+    // Test directives add no bytes to the program
+    run_string_test(assembly, true, "a903200c06004c09064c060618690585ff60");
+
+    run_string_test("assert a == 01\n", false, "1: assert must follow a test directive");
+    run_string_test("test t\nassert a = 01\n", false, "2: assert must compare with == or !=");
+    let message = "2: assert takes a, x, y, s, p, or mem and an address, then == or != and a value";
+    run_string_test("test t\nassert q == 01\n", false, message);
+    run_string_test("test t\ntest t\n", false, "2: test t repeated");
+}
+
+#[test]
+fn formatted_source_assembles_the_same() {
+    let source = "ORG  F000   ; start\n  .Start  \nLDAI   0A\n    zbyte P\nSTAZ .P;save\n\
+                  data   \"12;  34\"  00\n.loop\n  JMPA .loop\n";
+    let formatted = sasm2::format_str(source);
+    assert_eq!(
+        formatted,
+        "org   f000      ; start\n.Start\nldai  0a\nzbyte P\nstaz  .P        ;save\n\
+         data \"12;  34\" 00\n.loop\njmpa  .loop\n"
+    );
+    assert_eq!(sasm2::format_str(&formatted), formatted);
+
+    let options = sasm2::AsmOptions::default();
+    // Uppercase mnemonics and commands are not assembled, so compare with all lowercase source
+    let original = sasm2::assemble_str(&source.to_lowercase(), &options).unwrap();
+    let formatted = sasm2::assemble_str(&formatted, &options).unwrap();
+    assert_eq!(formatted.bytes, original.bytes);
+}
+
+#[test]
+fn verify_against_disassembly() {
+    let assembly = "org 0300\n.start\nldai 01\nstaa .table\nldxi 00\n.loop\ninx\nbne .loop\n\
+                    jmpa .start\n.table\ndata 01 02 03\norg fffa\nword 0300 0300 0300\n";
+    let args: Vec<String> = ["sasm", "verify"].iter().map(|s| s.to_string()).collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    assert!(c.verify);
+    c.itype = sasm2::config::IType::String(assembly.to_string());
+    assert_eq!(sasm2::verify(&mut c), Ok(22));
+
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String(assembly.to_string());
+    c.emit_obj = true;
+    let errors = sasm2::verify(&mut c).map_err(error_strings);
+    assert_eq!(errors, Err(vec!["object files cannot be verified".to_string()]));
+}
+
+#[test]
+fn window_of_large_file() {
+    // Large files are mapped into memory rather than read
+    let mut rom = vec![0xff; 0x80000];
+    rom[0x70000..0x70003].copy_from_slice(&[0xa9, 0x01, 0x60]);
+    let path = std::env::temp_dir().join("sasm2_large_rom.bin");
+    std::fs::write(&path, &rom).unwrap();
+
+    let mut c = sasm2::Config::build_string_test("");
+    c.itype = sasm2::config::IType::File(path.to_string_lossy().to_string());
+    c.offset = 0x70000;
+    c.length = Some(3);
+    c.addr = 0x0300;
+    c.min_region_size = 0;
+    let result = sasm2::disassemble(&mut c);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        result,
+        Ok(sasm2::Code::String("org   0300\nldai  01\nrts\n".to_string()))
+    );
+}
+
+#[test]
+fn patch_existing_image() {
+    let path = std::env::temp_dir().join("sasm2_patch_base.bin");
+    std::fs::write(&path, [0xea; 8]).unwrap();
+
+    let base = path.to_string_lossy().to_string();
+    let args: Vec<String> = ["sasm", "-f", "bin", "--patch", &base, "-a", "0300"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String("org 0302\nldai 01\norg 0309\nrts\n".to_string());
+    c.otype = sasm2::config::OType::None;
+    let patched = sasm2::assemble(&mut c);
+
+    let mut c = sasm2::Config::build(&args).unwrap();
+    c.itype = sasm2::config::IType::String("org 02ff\nrts\n".to_string());
+    c.otype = sasm2::config::OType::None;
+    let before = sasm2::assemble(&mut c).map_err(error_strings);
+    std::fs::remove_file(&path).unwrap();
+
+    let expected = vec![0xea, 0xea, 0xa9, 0x01, 0xea, 0xea, 0xea, 0xea, 0xff, 0x60];
+    assert_eq!(patched, Ok(sasm2::Code::Bytes(expected)));
+    assert_eq!(
+        before,
+        Err(vec!["org block at 02ff is before the patched image at 0300".to_string()])
+    );
+}
+
+#[test]
+fn patch_directive() {
+    let assembly = "org 0300\nrts\npatch 0302 eaea 60\norg 0306\nrts\n";
+    let mut c = sasm2::Config::build_string_test(assembly);
+    let result = sasm2::assemble(&mut c);
+    assert_eq!(result, Ok(sasm2::Code::String("60ffeaea60ff60".to_string())));
+
+    let assembly = "org 0300\nldai 01\npatch 0301 02\npatch 0310 ea\n";
+    let mut c = sasm2::Config::build_string_test(assembly);
+    let errors = sasm2::assemble(&mut c).map_err(error_strings);
+    assert_eq!(
+        errors,
+        Err(vec!["3: patch at 0301 overlaps code at 0300".to_string()])
+    );
+}
+
+#[test]
+fn diff_disassemblies() {
+    let a = std::env::temp_dir().join("sasm2_diff_a.bin");
+    let b = std::env::temp_dir().join("sasm2_diff_b.bin");
+    std::fs::write(&a, [0xa9, 0x01, 0x8d, 0x00, 0x04, 0xe8, 0xd0, 0xfd, 0x20, 0x00, 0x40, 0x60])
+        .unwrap();
+    std::fs::write(
+        &b,
+        [0xa9, 0x02, 0xea, 0x8d, 0x00, 0x04, 0xe8, 0xd0, 0xfd, 0x4c, 0x00, 0x40, 0x60],
+    )
+    .unwrap();
+
+    let (a, b) = (a.to_string_lossy().to_string(), b.to_string_lossy().to_string());
+    let args: Vec<String> = ["sasm", "diff", &a, &b, "-a", "0300"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut c = sasm2::Config::build(&args).unwrap();
+    assert!(c.diff);
+    let changes = sasm2::diff(&mut c).map(|c| c.iter().map(|c| c.to_string()).collect());
+    std::fs::remove_file(&a).unwrap();
+    std::fs::remove_file(&b).unwrap();
+    assert_eq!(
+        changes,
+        Ok(vec![
+            "0300: operand ldai 01 -> ldai 02".to_string(),
+            "0302: added nop".to_string(),
+            "0306/0307: operand bne 0305 -> bne 0306".to_string(),
+            "0308/0309: instruction jsra 4000 -> jmpa 4000".to_string(),
+        ])
+    );
+}
+
+#[test]
+fn imported_ca65_assembles() {
+    let source = "        .org $0300\nstart:  ldx #0\n@next:  lda msg,x\n        beq @done\n\
+                  \x20       sta $0400,x\n        inx\n        bne @next\n@done:  rts\n\
+                  msg:    .byte \"HI\", 0\n";
+    let imported = sasm2::import_ca65_str(source).unwrap();
+    let mut c = sasm2::Config::build_string_test(&imported);
+    assert_eq!(
+        sasm2::assemble(&mut c),
+        Ok(sasm2::Code::String(
+            "a200bd0e03f0069d0004e8d0f560484900".to_string()
+        ))
+    );
+}
+
+#[test]
+fn exported_ca65_imports_back() {
+    let source = "zbyte ptr\nlabel io d000\norg 0300\n.start\nldai 01 ; one\nstaz .ptr\n\
+                  ldaax .table 02\nstaa .io\nbne .start\nldai <.table\nrts\n.table\n\
+                  data 01 ff\nword .start .table-1\n";
+    let mut c = sasm2::Config::build_string_test(source);
+    c.otype = sasm2::config::OType::None;
+    let exported = match sasm2::export_ca65(&mut c) {
+        Ok(sasm2::Code::String(s)) => s,
+        _ => panic!("export failed"),
+    };
+    assert!(exported.contains("ptr = $FF\n"));
+    assert!(exported.contains("        STA z:ptr\n"));
+    assert!(exported.contains("        .word table-$01\n"));
+
+    let mut original = sasm2::Config::build_string_test(source);
+    let imported = sasm2::import_ca65_str(&exported).unwrap();
+    let mut imported = sasm2::Config::build_string_test(&imported);
+    assert_eq!(sasm2::assemble(&mut imported), sasm2::assemble(&mut original));
+}